@@ -0,0 +1,336 @@
+//! A minimal flattened device tree (FDT) parser.
+//!
+//! The parser works directly on the blob handed over by the firmware and never
+//! allocates, so it can be used before the kernel heap is ready.
+#![cfg_attr(not(test), no_std)]
+
+mod node;
+mod standard;
+
+pub use node::{AllNodes, Cells, ChildIter, Node, Property, PropertyIter, RegIter, StrList};
+pub use standard::{Chosen, Cpu, MemRegion};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FdtError {
+    BadMagic,
+    Truncated,
+    UnsupportedVersion(u32),
+}
+
+/// A parsed view of a flattened device tree blob.
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    data: &'a [u8],
+    struct_off: usize,
+    struct_size: usize,
+    strings_off: usize,
+    strings_size: usize,
+    rsvmap_off: usize,
+    boot_cpuid: u32,
+}
+
+pub(crate) enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(&'a str, &'a [u8]),
+    End,
+}
+
+impl<'a> Fdt<'a> {
+    /// Parses the device tree contained in `data`.
+    pub fn new(data: &'a [u8]) -> Result<Self, FdtError> {
+        if data.len() < FDT_HEADER_SIZE {
+            return Err(FdtError::Truncated);
+        }
+        let header = |index: usize| be_u32(data, index * 4).unwrap_or(0) as usize;
+        if header(0) as u32 != FDT_MAGIC {
+            return Err(FdtError::BadMagic);
+        }
+        let total_size = header(1);
+        if data.len() < total_size {
+            return Err(FdtError::Truncated);
+        }
+        if (header(6) as u32) > FDT_LAST_COMP_VERSION {
+            return Err(FdtError::UnsupportedVersion(header(5) as u32));
+        }
+        let fdt = Self {
+            data: &data[..total_size],
+            struct_off: header(2),
+            struct_size: header(9),
+            strings_off: header(3),
+            strings_size: header(8),
+            rsvmap_off: header(4),
+            boot_cpuid: header(7) as u32,
+        };
+        if fdt.struct_off + fdt.struct_size > total_size
+            || fdt.strings_off + fdt.strings_size > total_size
+            || fdt.rsvmap_off > total_size
+        {
+            return Err(FdtError::Truncated);
+        }
+        Ok(fdt)
+    }
+
+    /// Parses the device tree located at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a readable device tree blob that stays valid and
+    /// unmodified for `'a`.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self, FdtError> {
+        let header = core::slice::from_raw_parts(ptr, FDT_HEADER_SIZE);
+        if be_u32(header, 0) != Some(FDT_MAGIC) {
+            return Err(FdtError::BadMagic);
+        }
+        let total_size = be_u32(header, 4).unwrap_or(0) as usize;
+        Self::new(core::slice::from_raw_parts(ptr, total_size))
+    }
+
+    /// Size of the whole blob in bytes.
+    pub fn total_size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Physical id of the CPU the firmware booted on.
+    pub fn boot_cpuid(&self) -> u32 {
+        self.boot_cpuid
+    }
+
+    /// Entries of the memory reservation block (`/memreserve/`).
+    pub fn mem_reservations(&self) -> impl Iterator<Item = MemRegion> + 'a {
+        let data = self.data;
+        let mut off = self.rsvmap_off;
+        core::iter::from_fn(move || {
+            let start = be_u64(data, off)? as usize;
+            let size = be_u64(data, off + 8)? as usize;
+            off += 16;
+            if start == 0 && size == 0 {
+                None
+            } else {
+                Some(MemRegion { start, size })
+            }
+        })
+    }
+
+    /// The root node `/`.
+    pub fn root(&self) -> Node<'a> {
+        match self.token(0) {
+            Some((Token::BeginNode(name), next)) => Node::new(*self, name, next, Cells::default()),
+            _ => Node::new(*self, "", self.struct_size, Cells::default()),
+        }
+    }
+
+    /// Looks up a node by its absolute path, e.g. `/cpus/cpu@0`.
+    ///
+    /// A path component without a unit address matches any unit address,
+    /// so `/memory` finds `/memory@80000000`.
+    pub fn find_node(&self, path: &str) -> Option<Node<'a>> {
+        let mut node = self.root();
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node = node.children().find(|child| child.matches(component))?;
+        }
+        Some(node)
+    }
+
+    /// Iterates over every node of the tree in depth-first order.
+    pub fn all_nodes(&self) -> AllNodes<'a> {
+        AllNodes::new(*self)
+    }
+
+    /// Iterates over all nodes compatible with `compatible`.
+    pub fn compatible_nodes<'b>(&self, compatible: &'b str) -> impl Iterator<Item = Node<'a>> + 'b
+    where
+        'a: 'b,
+    {
+        self.all_nodes()
+            .filter(move |node| node.is_compatible(compatible))
+    }
+
+    /// Finds the first node compatible with any of `compatibles`.
+    pub fn find_compatible(&self, compatibles: &[&str]) -> Option<Node<'a>> {
+        self.all_nodes()
+            .find(|node| compatibles.iter().any(|c| node.is_compatible(c)))
+    }
+
+    /// Finds the node whose `phandle` property equals `phandle`.
+    pub fn find_phandle(&self, phandle: u32) -> Option<Node<'a>> {
+        self.all_nodes().find(|node| node.phandle() == Some(phandle))
+    }
+
+    /// Decodes the structure block token at `off`, skipping `FDT_NOP`s.
+    ///
+    /// Returns the token and the offset of the next one.
+    pub(crate) fn token(&self, mut off: usize) -> Option<(Token<'a>, usize)> {
+        let block = self
+            .data
+            .get(self.struct_off..self.struct_off + self.struct_size)?;
+        loop {
+            match be_u32(block, off)? {
+                FDT_BEGIN_NODE => {
+                    let name = cstr(block, off + 4)?;
+                    return Some((Token::BeginNode(name), align4(off + 4 + name.len() + 1)));
+                }
+                FDT_END_NODE => return Some((Token::EndNode, off + 4)),
+                FDT_PROP => {
+                    let len = be_u32(block, off + 4)? as usize;
+                    let name_off = be_u32(block, off + 8)? as usize;
+                    let value = block.get(off + 12..off + 12 + len)?;
+                    let name = self.string(name_off)?;
+                    return Some((Token::Prop(name, value), align4(off + 12 + len)));
+                }
+                FDT_NOP => off += 4,
+                FDT_END => return Some((Token::End, off + 4)),
+                _ => return None,
+            }
+        }
+    }
+
+    fn string(&self, off: usize) -> Option<&'a str> {
+        let strings = self
+            .data
+            .get(self.strings_off..self.strings_off + self.strings_size)?;
+        cstr(strings, off)
+    }
+}
+
+fn align4(off: usize) -> usize {
+    (off + 3) & !3
+}
+
+fn cstr(data: &[u8], off: usize) -> Option<&str> {
+    let bytes = data.get(off..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+pub(crate) fn be_u32(data: &[u8], off: usize) -> Option<u32> {
+    let bytes = data.get(off..off + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+pub(crate) fn be_u64(data: &[u8], off: usize) -> Option<u64> {
+    let bytes = data.get(off..off + 8)?;
+    Some(u64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Run on the host, with `cargo test -p fdt --target <host triple>`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Built from `testdata/test.dts`.
+    const BLOB: &[u8] = include_bytes!("../testdata/test.dtb");
+
+    fn fdt() -> Fdt<'static> {
+        Fdt::new(BLOB).unwrap()
+    }
+
+    fn names<'a>(nodes: impl Iterator<Item = Node<'a>>) -> Vec<&'a str> {
+        nodes.map(|node| node.name()).collect()
+    }
+
+    #[test]
+    fn validates_header() {
+        let fdt = fdt();
+        assert_eq!(fdt.total_size(), BLOB.len());
+        assert_eq!(fdt.boot_cpuid(), 0);
+
+        let mut bad_magic = BLOB.to_vec();
+        bad_magic[0] ^= 0xff;
+        assert_eq!(Fdt::new(&bad_magic).err(), Some(FdtError::BadMagic));
+        assert_eq!(Fdt::new(&BLOB[..FDT_HEADER_SIZE - 1]).err(), Some(FdtError::Truncated));
+        assert_eq!(Fdt::new(&BLOB[..BLOB.len() - 4]).err(), Some(FdtError::Truncated));
+
+        let mut too_new = BLOB.to_vec();
+        too_new[24..28].copy_from_slice(&17u32.to_be_bytes());
+        assert_eq!(Fdt::new(&too_new).err(), Some(FdtError::UnsupportedVersion(17)));
+
+        let mut struct_past_end = BLOB.to_vec();
+        struct_past_end[36..40].copy_from_slice(&(BLOB.len() as u32).to_be_bytes());
+        assert_eq!(Fdt::new(&struct_past_end).err(), Some(FdtError::Truncated));
+    }
+
+    #[test]
+    fn reads_memory_reservations() {
+        let reserved: Vec<_> = fdt().mem_reservations().collect();
+        assert_eq!(reserved, [MemRegion { start: 0x8000_0000, size: 0x20_0000 }]);
+    }
+
+    #[test]
+    fn walks_nodes() {
+        let fdt = fdt();
+        let root = fdt.root();
+        assert_eq!(root.name(), "");
+        assert!(root.is_compatible("test,soc"));
+        assert_eq!(names(root.children()), ["chosen", "cpus", "memory@80000000", "soc"]);
+        assert_eq!(
+            names(fdt.all_nodes()),
+            [
+                "",
+                "chosen",
+                "cpus",
+                "cpu@0",
+                "interrupt-controller",
+                "cpu@1",
+                "memory@80000000",
+                "soc",
+                "interrupt-controller@c000000",
+                "serial@10000000",
+            ]
+        );
+
+        let memory = fdt.find_node("/memory").unwrap();
+        assert_eq!(memory.name(), "memory@80000000");
+        assert_eq!(memory.base_name(), "memory");
+        assert_eq!(memory.unit_address(), Some("80000000"));
+        assert!(fdt.find_node("/memory@90000000").is_none());
+        assert!(fdt.find_node("/cpus/cpu@2").is_none());
+
+        let cpus: Vec<_> = fdt.cpus().filter(|cpu| cpu.is_available()).filter_map(|cpu| cpu.id()).collect();
+        assert_eq!(cpus, [0]);
+        assert_eq!(fdt.timebase_frequency(), Some(10_000_000));
+        assert_eq!(fdt.chosen().unwrap().bootargs(), Some("console=ttyS0 root=/dev/vda"));
+        assert_eq!(names(fdt.compatible_nodes("ns16550a")), ["serial@10000000"]);
+    }
+
+    #[test]
+    fn decodes_reg_with_the_parent_cells() {
+        let fdt = fdt();
+        let memory: Vec<_> = fdt.memory().collect();
+        assert_eq!(memory, [MemRegion { start: 0x8000_0000, size: 0x800_0000 }]);
+
+        let soc = fdt.find_node("/soc").unwrap();
+        assert_eq!(soc.child_cells(), Cells { address: 1, size: 1 });
+        let serial = fdt.find_node("/soc/serial@10000000").unwrap();
+        let reg: Vec<_> = serial.reg().collect();
+        assert_eq!(reg, [MemRegion { start: 0x1000_0000, size: 0x100 }]);
+        assert_eq!(serial.interrupts().collect::<Vec<_>>(), [10]);
+
+        let cpu = fdt.find_node("/cpus/cpu@1").unwrap();
+        let reg: Vec<_> = cpu.reg().collect();
+        assert_eq!(reg, [MemRegion { start: 1, size: 0 }]);
+    }
+
+    #[test]
+    fn finds_nodes_by_phandle() {
+        let fdt = fdt();
+        assert_eq!(fdt.find_phandle(1).unwrap().name(), "interrupt-controller");
+        let plic = fdt.find_phandle(2).unwrap();
+        assert_eq!(plic.name(), "interrupt-controller@c000000");
+        assert_eq!(plic.compatible().collect::<Vec<_>>(), ["sifive,plic-1.0.0", "riscv,plic0"]);
+        let serial = fdt.find_compatible(&["ns16550a"]).unwrap();
+        let parent = serial.property("interrupt-parent").and_then(|prop| prop.as_u32());
+        assert_eq!(parent, Some(2));
+        assert!(fdt.find_phandle(3).is_none());
+    }
+}
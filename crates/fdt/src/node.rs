@@ -0,0 +1,344 @@
+use crate::{be_u32, be_u64, standard::MemRegion, Fdt, Token};
+
+/// Maximum nesting depth tracked when walking the whole tree.
+const MAX_DEPTH: usize = 16;
+
+/// `#address-cells` and `#size-cells` in effect for a node's `reg`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cells {
+    pub address: usize,
+    pub size: usize,
+}
+
+impl Default for Cells {
+    /// Defaults mandated by the devicetree specification.
+    fn default() -> Self {
+        Self { address: 2, size: 1 }
+    }
+}
+
+/// A node of the device tree.
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    /// Offset of the first token after the node name.
+    body_off: usize,
+    /// Cells of the parent, used to decode this node's `reg`.
+    cells: Cells,
+}
+
+/// A property of a node.
+#[derive(Clone, Copy)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+impl<'a> Node<'a> {
+    pub(crate) fn new(fdt: Fdt<'a>, name: &'a str, body_off: usize, cells: Cells) -> Self {
+        Self {
+            fdt,
+            name,
+            body_off,
+            cells,
+        }
+    }
+
+    /// Full node name, including the unit address.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Node name without the unit address.
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+
+    /// Unit address part of the name, if any.
+    pub fn unit_address(&self) -> Option<&'a str> {
+        self.name.split_once('@').map(|(_, addr)| addr)
+    }
+
+    pub(crate) fn matches(&self, component: &str) -> bool {
+        if component.contains('@') {
+            self.name == component
+        } else {
+            self.base_name() == component
+        }
+    }
+
+    pub fn properties(&self) -> PropertyIter<'a> {
+        PropertyIter {
+            fdt: self.fdt,
+            off: self.body_off,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<Property<'a>> {
+        self.properties().find(|prop| prop.name == name)
+    }
+
+    /// Direct children of this node.
+    pub fn children(&self) -> ChildIter<'a> {
+        ChildIter {
+            fdt: self.fdt,
+            off: self.body_off,
+            cells: self.child_cells(),
+        }
+    }
+
+    /// Cells this node imposes on its children.
+    pub fn child_cells(&self) -> Cells {
+        let default = Cells::default();
+        let cells = |name, default| {
+            self.property(name)
+                .and_then(|prop| prop.as_u32())
+                .map_or(default, |v| v as usize)
+        };
+        Cells {
+            address: cells("#address-cells", default.address),
+            size: cells("#size-cells", default.size),
+        }
+    }
+
+    /// Entries of the `reg` property, decoded with the parent's cells.
+    pub fn reg(&self) -> RegIter<'a> {
+        RegIter {
+            data: self.property("reg").map_or(&[][..], |prop| prop.value),
+            cells: self.cells,
+        }
+    }
+
+    pub fn compatible(&self) -> StrList<'a> {
+        StrList::new(self.property("compatible").map_or(&[][..], |prop| prop.value))
+    }
+
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.compatible().any(|c| c == compatible)
+    }
+
+    pub fn device_type(&self) -> Option<&'a str> {
+        self.property("device_type").and_then(|prop| prop.as_str())
+    }
+
+    /// Whether the `status` property is absent, `"okay"` or `"ok"`.
+    pub fn is_available(&self) -> bool {
+        self.property("status")
+            .and_then(|prop| prop.as_str())
+            .is_none_or(|status| status == "okay" || status == "ok")
+    }
+
+    pub fn phandle(&self) -> Option<u32> {
+        self.property("phandle")
+            .or_else(|| self.property("linux,phandle"))
+            .and_then(|prop| prop.as_u32())
+    }
+
+    /// Cells of the `interrupts` property.
+    pub fn interrupts(&self) -> impl Iterator<Item = u32> + 'a {
+        self.property("interrupts")
+            .map_or(&[][..], |prop| prop.value)
+            .chunks_exact(4)
+            .map(|cell| u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))
+    }
+}
+
+impl<'a> Property<'a> {
+    pub fn as_u32(&self) -> Option<u32> {
+        be_u32(self.value, 0)
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        be_u64(self.value, 0)
+    }
+
+    /// Reads a one or two cell integer.
+    pub fn as_usize(&self) -> Option<usize> {
+        match self.value.len() {
+            4 => self.as_u32().map(|v| v as usize),
+            8 => self.as_u64().map(|v| v as usize),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        let bytes = self.value.strip_suffix(&[0]).unwrap_or(self.value);
+        core::str::from_utf8(bytes).ok()
+    }
+
+    pub fn as_str_list(&self) -> StrList<'a> {
+        StrList::new(self.value)
+    }
+}
+
+pub struct PropertyIter<'a> {
+    fdt: Fdt<'a>,
+    off: usize,
+}
+
+impl<'a> Iterator for PropertyIter<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.fdt.token(self.off)? {
+            (Token::Prop(name, value), next) => {
+                self.off = next;
+                Some(Property { name, value })
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct ChildIter<'a> {
+    fdt: Fdt<'a>,
+    off: usize,
+    cells: Cells,
+}
+
+impl<'a> Iterator for ChildIter<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (token, next) = self.fdt.token(self.off)?;
+            match token {
+                Token::Prop(..) => self.off = next,
+                Token::BeginNode(name) => {
+                    let child = Node::new(self.fdt, name, next, self.cells);
+                    self.off = skip_subtree(&self.fdt, next)?;
+                    return Some(child);
+                }
+                Token::EndNode | Token::End => return None,
+            }
+        }
+    }
+}
+
+/// Returns the offset right after the `FDT_END_NODE` closing the node whose
+/// body starts at `off`.
+fn skip_subtree(fdt: &Fdt, mut off: usize) -> Option<usize> {
+    let mut depth = 1;
+    while depth > 0 {
+        let (token, next) = fdt.token(off)?;
+        match token {
+            Token::BeginNode(_) => depth += 1,
+            Token::EndNode => depth -= 1,
+            Token::Prop(..) => {}
+            Token::End => return None,
+        }
+        off = next;
+    }
+    Some(off)
+}
+
+/// Depth-first iterator over every node of the tree.
+pub struct AllNodes<'a> {
+    fdt: Fdt<'a>,
+    off: usize,
+    depth: usize,
+    cells: [Cells; MAX_DEPTH],
+}
+
+impl<'a> AllNodes<'a> {
+    pub(crate) fn new(fdt: Fdt<'a>) -> Self {
+        Self {
+            fdt,
+            off: 0,
+            depth: 0,
+            cells: [Cells::default(); MAX_DEPTH],
+        }
+    }
+}
+
+impl<'a> Iterator for AllNodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (token, next) = self.fdt.token(self.off)?;
+            self.off = next;
+            match token {
+                Token::BeginNode(name) => {
+                    let parent_cells = match self.depth {
+                        0 => Cells::default(),
+                        depth => self.cells[(depth - 1).min(MAX_DEPTH - 1)],
+                    };
+                    let node = Node::new(self.fdt, name, next, parent_cells);
+                    self.cells[self.depth.min(MAX_DEPTH - 1)] = node.child_cells();
+                    self.depth += 1;
+                    return Some(node);
+                }
+                Token::EndNode => self.depth = self.depth.saturating_sub(1),
+                Token::Prop(..) => {}
+                Token::End => return None,
+            }
+        }
+    }
+}
+
+/// Iterator over the `(address, size)` pairs of a `reg` property.
+pub struct RegIter<'a> {
+    data: &'a [u8],
+    cells: Cells,
+}
+
+impl<'a> Iterator for RegIter<'a> {
+    type Item = MemRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry_size = (self.cells.address + self.cells.size) * 4;
+        if entry_size == 0 || self.data.len() < entry_size {
+            return None;
+        }
+        let (entry, rest) = self.data.split_at(entry_size);
+        self.data = rest;
+        let (address, size) = entry.split_at(self.cells.address * 4);
+        Some(MemRegion {
+            start: read_cells(address),
+            size: read_cells(size),
+        })
+    }
+}
+
+/// Reads a big-endian number spanning any number of cells, keeping the low
+/// bits if it does not fit.
+fn read_cells(cells: &[u8]) -> usize {
+    cells
+        .chunks_exact(4)
+        .fold(0u64, |acc, cell| {
+            (acc << 32) | u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]) as u64
+        }) as usize
+}
+
+/// Iterator over a NUL separated string list such as `compatible`.
+pub struct StrList<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StrList<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+}
+
+impl<'a> Iterator for StrList<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.data.is_empty() {
+            let len = self
+                .data
+                .iter()
+                .position(|&b| b == 0)
+                .unwrap_or(self.data.len());
+            let (s, rest) = self.data.split_at(len);
+            self.data = rest.get(1..).unwrap_or(&[]);
+            if let Ok(s) = core::str::from_utf8(s) {
+                return Some(s);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,109 @@
+//! Helpers for the standard nodes every RISC-V device tree carries.
+
+use crate::{Fdt, Node};
+
+/// A physical memory range described by the device tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemRegion {
+    pub start: usize,
+    pub size: usize,
+}
+
+impl MemRegion {
+    pub fn end(&self) -> usize {
+        self.start + self.size
+    }
+}
+
+/// A `/cpus/cpu@*` node.
+#[derive(Clone, Copy)]
+pub struct Cpu<'a> {
+    node: Node<'a>,
+}
+
+impl<'a> Cpu<'a> {
+    /// The hart id, taken from `reg`.
+    pub fn id(&self) -> Option<usize> {
+        self.node.reg().next().map(|reg| reg.start)
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.node.is_available()
+    }
+
+    pub fn isa(&self) -> Option<&'a str> {
+        self.node
+            .property("riscv,isa")
+            .and_then(|prop| prop.as_str())
+    }
+
+    pub fn timebase_frequency(&self) -> Option<usize> {
+        self.node
+            .property("timebase-frequency")
+            .and_then(|prop| prop.as_usize())
+    }
+
+    pub fn node(&self) -> Node<'a> {
+        self.node
+    }
+}
+
+/// The `/chosen` node.
+#[derive(Clone, Copy)]
+pub struct Chosen<'a> {
+    node: Node<'a>,
+}
+
+impl<'a> Chosen<'a> {
+    pub fn bootargs(&self) -> Option<&'a str> {
+        self.node
+            .property("bootargs")
+            .and_then(|prop| prop.as_str())
+            .filter(|args| !args.is_empty())
+    }
+
+    pub fn stdout_path(&self) -> Option<&'a str> {
+        self.node
+            .property("stdout-path")
+            .and_then(|prop| prop.as_str())
+    }
+}
+
+impl<'a> Fdt<'a> {
+    /// Ranges of all `device_type = "memory"` nodes.
+    pub fn memory(&self) -> impl Iterator<Item = MemRegion> + 'a {
+        self.root()
+            .children()
+            .filter(|node| node.device_type() == Some("memory"))
+            .flat_map(|node| node.reg())
+    }
+
+    /// Ranges of the children of `/reserved-memory`.
+    pub fn reserved_memory(&self) -> impl Iterator<Item = MemRegion> + 'a {
+        self.find_node("/reserved-memory")
+            .into_iter()
+            .flat_map(|node| node.children())
+            .flat_map(|node| node.reg())
+    }
+
+    /// All `cpu` nodes below `/cpus`.
+    pub fn cpus(&self) -> impl Iterator<Item = Cpu<'a>> + 'a {
+        self.find_node("/cpus")
+            .into_iter()
+            .flat_map(|node| node.children())
+            .filter(|node| node.device_type() == Some("cpu"))
+            .map(|node| Cpu { node })
+    }
+
+    /// The timer frequency, from `/cpus` or else from the first cpu.
+    pub fn timebase_frequency(&self) -> Option<usize> {
+        self.find_node("/cpus")
+            .and_then(|node| node.property("timebase-frequency"))
+            .and_then(|prop| prop.as_usize())
+            .or_else(|| self.cpus().find_map(|cpu| cpu.timebase_frequency()))
+    }
+
+    pub fn chosen(&self) -> Option<Chosen<'a>> {
+        self.find_node("/chosen").map(|node| Chosen { node })
+    }
+}
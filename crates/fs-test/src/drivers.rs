@@ -0,0 +1,4 @@
+//! The kernel's block device layer.
+
+#[path = "../../../kernel/src/drivers/block/mod.rs"]
+pub mod block;
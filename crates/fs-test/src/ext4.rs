@@ -0,0 +1,190 @@
+//! ext4 file systems made by mkfs.ext4, and checked by e2fsck.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use std::{fs, path::PathBuf, process::Command};
+
+use crate::{
+    fs::{ext4::Ext4, FsError, Inode, InodeType, SuperBlock},
+    image::{self, Image},
+};
+
+/// Enough names in one directory for it to span blocks and get an index.
+const MANY: usize = 400;
+
+fn path(image: &Image) -> &str {
+    image.path().to_str().unwrap()
+}
+
+/// A file system with checksummed metadata, filled from `root` if given.
+fn format(image: &Image, root: Option<&PathBuf>) {
+    let mut args = vec!["-q", "-F", "-b", "4096", "-O", "metadata_csum"];
+    if let Some(root) = root {
+        args.extend(["-d", root.to_str().unwrap()]);
+    }
+    args.push(path(image));
+    image::run("mkfs.ext4", &args);
+}
+
+/// Run e2fsck on `image` with `args`, returning its exit status.
+fn e2fsck(image: &Image, args: &[&str]) -> i32 {
+    let output = Command::new("e2fsck").args(args).arg(path(image)).output().unwrap();
+    let status = output.status.code().unwrap();
+    if status != 0 {
+        eprintln!("{}", String::from_utf8_lossy(&output.stdout));
+    }
+    status
+}
+
+fn mount(image: &Image) -> Arc<Ext4> {
+    Ext4::new(image.device()).unwrap()
+}
+
+fn read_all(inode: &Arc<dyn Inode>) -> Vec<u8> {
+    let mut data = vec![0; inode.metadata().unwrap().size as usize];
+    let mut done = 0;
+    while done < data.len() {
+        let read = inode.read_at(done, &mut data[done..]).unwrap();
+        assert!(read > 0, "file ends early at {}", done);
+        done += read;
+    }
+    data
+}
+
+fn names(dir: &Arc<dyn Inode>) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut pos = 0;
+    while let Some((entry, next)) = dir.read_dir(pos).unwrap() {
+        names.push(entry.name);
+        pos = next;
+    }
+    names
+}
+
+fn many_name(i: usize) -> String {
+    format!("entry number {} of the directory", i)
+}
+
+/// Data spanning several blocks, different in each.
+fn contents(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + i / 4096) as u8).collect()
+}
+
+/// A directory tree for mkfs.ext4 to copy in, removed when dropped.
+struct Tree(PathBuf);
+
+impl Tree {
+    fn new(name: &str) -> Self {
+        let root = std::env::temp_dir().join(format!("fs-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(root.join("many")).unwrap();
+        fs::write(root.join("big"), contents(300_000)).unwrap();
+        for i in 0..MANY {
+            fs::write(root.join("many").join(many_name(i)), many_name(i)).unwrap();
+        }
+        std::os::unix::fs::symlink("many/entry number 7 of the directory", root.join("link")).unwrap();
+        Self(root)
+    }
+}
+
+impl Drop for Tree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn reads_files_made_by_mkfs() {
+    let tree = Tree::new("ext4-read");
+    let image = Image::new("ext4-read", 64 << 20);
+    format(&image, Some(&tree.0));
+    // Index the directories, for lookups to go through the hash tree.
+    assert!(e2fsck(&image, &["-f", "-y", "-D"]) <= 1);
+
+    let fs = mount(&image);
+    let root = fs.root();
+    assert_eq!(read_all(&root.lookup("big").unwrap()), contents(300_000));
+    let link = root.lookup("link").unwrap();
+    assert_eq!(link.metadata().unwrap().kind, InodeType::Symlink);
+    assert_eq!(link.read_link().unwrap(), "many/entry number 7 of the directory");
+
+    let many = root.lookup("many").unwrap();
+    let mut found = names(&many);
+    found.sort();
+    let mut expected: Vec<String> = (0..MANY).map(many_name).chain([".".into(), "..".into()]).collect();
+    expected.sort();
+    assert_eq!(found, expected);
+    for i in 0..MANY {
+        let file = many.lookup(&many_name(i)).unwrap();
+        assert_eq!(read_all(&file), many_name(i).as_bytes());
+    }
+    assert!(matches!(many.lookup("missing"), Err(FsError::NotFound)));
+}
+
+#[test]
+fn writes_what_e2fsck_accepts() {
+    let image = Image::new("ext4-write", 64 << 20);
+    format(&image, None);
+    {
+        let fs = mount(&image);
+        let root = fs.root();
+        let dir = root.create("many", InodeType::Dir, 0o755).unwrap();
+        for i in 0..MANY {
+            let file = dir.create(&many_name(i), InodeType::File, 0o644).unwrap();
+            file.write_at(0, many_name(i).as_bytes()).unwrap();
+        }
+        for i in (0..MANY).step_by(3) {
+            dir.unlink(&many_name(i)).unwrap();
+        }
+        let big = root.create("big", InodeType::File, 0o644).unwrap();
+        let data = contents(300_000);
+        let mut done = 0;
+        // Uneven writes, to cross block boundaries in the middle of one.
+        for chunk in data.chunks(5000) {
+            assert_eq!(big.write_at(done, chunk).unwrap(), chunk.len());
+            done += chunk.len();
+        }
+        root.symlink("link", "big").unwrap();
+        fs.sync().unwrap();
+    }
+
+    let fs = mount(&image);
+    let root = fs.root();
+    assert_eq!(read_all(&root.lookup("big").unwrap()), contents(300_000));
+    assert_eq!(root.lookup("link").unwrap().read_link().unwrap(), "big");
+    let dir = root.lookup("many").unwrap();
+    for i in 0..MANY {
+        match dir.lookup(&many_name(i)) {
+            Ok(file) => assert_eq!(read_all(&file), many_name(i).as_bytes()),
+            Err(e) => assert!(i % 3 == 0 && matches!(e, FsError::NotFound), "{}: {:?}", i, e),
+        }
+    }
+    drop((dir, root, fs));
+
+    assert_eq!(e2fsck(&image, &["-f", "-n"]), 0);
+}
+
+#[test]
+fn rejects_corrupted_directory_blocks() {
+    let tree = Tree::new("ext4-corrupt");
+    let image = Image::new("ext4-corrupt", 64 << 20);
+    format(&image, Some(&tree.0));
+    // Change a name in a leaf of the directory, keeping the entries
+    // well-formed so that only the checksum tells.
+    let blocks = image::run("debugfs", &["-R", "blocks /many", path(&image)]);
+    let mut raw = fs::read(image.path()).unwrap();
+    let name = blocks
+        .split_whitespace()
+        .find_map(|block| {
+            let data = &mut raw[block.parse::<usize>().unwrap() * 4096..][..4096];
+            let at = data.windows(6).position(|window| window == b"entry ")?;
+            let len = data[at..].windows(9).position(|window| window == b"directory")? + 9;
+            let name = String::from_utf8(data[at..at + len].to_vec()).unwrap();
+            data[at] = b'E';
+            Some(name)
+        })
+        .unwrap();
+    fs::write(image.path(), raw).unwrap();
+
+    let fs = mount(&image);
+    let many = fs.root().lookup("many").unwrap();
+    assert!(matches!(many.lookup(&name), Err(FsError::Corrupted)));
+}
@@ -0,0 +1,99 @@
+//! FAT32 volumes made by mkfs.vfat.
+//!
+//! dosfstools and mtools are seldom installed, so these tests only run with
+//! `cargo test -- --ignored`.
+
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::{
+    fs::{fat32::Fat32, Inode, InodeType, SuperBlock},
+    image::{self, Image},
+};
+
+const LONG_NAME: &str = "A file with a rather long name.txt";
+const DIR_NAME: &str = "Some directory";
+
+/// A freshly formatted volume.
+fn format(name: &str) -> Image {
+    let image = Image::new(name, 64 << 20);
+    image::run("mkfs.vfat", &["-F", "32", "-n", "TEST", path(&image)]);
+    image
+}
+
+fn path(image: &Image) -> &str {
+    image.path().to_str().unwrap()
+}
+
+fn mount(image: &Image) -> Arc<Fat32> {
+    Fat32::new(image.device()).unwrap()
+}
+
+fn read_all(inode: &Arc<dyn Inode>) -> Vec<u8> {
+    let mut data = vec![0; inode.metadata().unwrap().size as usize];
+    let mut done = 0;
+    while done < data.len() {
+        let read = inode.read_at(done, &mut data[done..]).unwrap();
+        assert!(read > 0, "file ends early at {}", done);
+        done += read;
+    }
+    data
+}
+
+/// Text spanning several clusters, for the host tools to print.
+fn contents() -> Vec<u8> {
+    (0..20_000).map(|i| b"0123456789abcdefghijklmnopqrstuvwxyz\n"[i % 37]).collect()
+}
+
+#[test]
+#[ignore = "needs dosfstools and mtools"]
+fn finds_long_names_written_by_mtools() {
+    let image = format("fat32-mtools");
+    let source = Image::new("fat32-mtools-source", 0);
+    std::fs::write(source.path(), contents()).unwrap();
+    let target = format!("::{}/{}", DIR_NAME, LONG_NAME);
+    image::run("mmd", &["-i", path(&image), &format!("::{}", DIR_NAME)]);
+    image::run("mcopy", &["-i", path(&image), path(&source), &target]);
+
+    let fs = mount(&image);
+    let dir = fs.root().lookup(DIR_NAME).unwrap();
+    assert_eq!(dir.metadata().unwrap().kind, InodeType::Dir);
+    let mut names = Vec::new();
+    let mut pos = 0;
+    while let Some((entry, next)) = dir.read_dir(pos).unwrap() {
+        names.push(entry.name);
+        pos = next;
+    }
+    assert!(names.iter().any(|name| name == LONG_NAME), "{:?}", names);
+    let file = dir.lookup(LONG_NAME).unwrap();
+    assert_eq!(read_all(&file), contents());
+}
+
+#[test]
+#[ignore = "needs dosfstools and mtools"]
+fn reads_back_created_files() {
+    let image = format("fat32-create");
+    {
+        let fs = mount(&image);
+        let dir = fs.root().create(DIR_NAME, InodeType::Dir, 0o755).unwrap();
+        let file = dir.create(LONG_NAME, InodeType::File, 0o644).unwrap();
+        let data = contents();
+        let mut done = 0;
+        // Uneven writes, to cross cluster boundaries in the middle of one.
+        for chunk in data.chunks(3000) {
+            assert_eq!(file.write_at(done, chunk).unwrap(), chunk.len());
+            done += chunk.len();
+        }
+        fs.sync().unwrap();
+    }
+
+    let fs = mount(&image);
+    let file = fs.root().lookup(DIR_NAME).unwrap().lookup(LONG_NAME).unwrap();
+    assert_eq!(file.metadata().unwrap().size, contents().len() as u64);
+    assert_eq!(read_all(&file), contents());
+    drop(fs);
+
+    image::run("fsck.vfat", &["-n", path(&image)]);
+    let target = format!("::{}/{}", DIR_NAME, LONG_NAME);
+    let printed = image::run("mtype", &["-i", path(&image), &target]);
+    assert_eq!(printed.as_bytes(), contents());
+}
@@ -0,0 +1,31 @@
+//! The kernel's disk file systems, and what they use of the VFS.
+
+#[path = "../../../kernel/src/fs/error.rs"]
+mod error;
+#[path = "../../../kernel/src/fs/ext4/mod.rs"]
+pub mod ext4;
+#[path = "../../../kernel/src/fs/fat32/mod.rs"]
+pub mod fat32;
+#[path = "../../../kernel/src/fs/flags.rs"]
+mod flags;
+#[path = "../../../kernel/src/fs/inode.rs"]
+mod inode;
+
+pub use self::{
+    error::{FsError, FsResult},
+    flags::OpenFlags,
+    inode::{DirEntry, Inode, InodeType, Metadata, SuperBlock, TimeSpec},
+};
+
+/// Open files, which the drivers only name.
+pub trait File: Send + Sync {}
+
+pub mod mount {
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    static NEXT_DEV: AtomicU64 = AtomicU64::new(1);
+
+    pub fn new_dev() -> u64 {
+        NEXT_DEV.fetch_add(1, Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,87 @@
+//! Disk images for the drivers to mount.
+
+use std::{
+    fs::{self, File},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+
+use crate::{
+    drivers::block::BlockDevice,
+    fs::{FsError, FsResult},
+};
+
+/// A file holding a disk image, removed when dropped.
+pub struct Image {
+    path: PathBuf,
+}
+
+impl Image {
+    /// An empty image of `size` bytes, named after the test making it.
+    pub fn new(name: &str, size: u64) -> Self {
+        let path = std::env::temp_dir().join(format!("fs-test-{}-{}.img", std::process::id(), name));
+        File::create(&path).unwrap().set_len(size).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The image as a block device, as a driver would see a disk.
+    pub fn device(&self) -> Arc<dyn BlockDevice> {
+        let file = File::options().read(true).write(true).open(&self.path).unwrap();
+        let num_blocks = file.metadata().unwrap().len() / 512;
+        Arc::new(FileDevice { file, num_blocks })
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct FileDevice {
+    file: File,
+    num_blocks: u64,
+}
+
+impl BlockDevice for FileDevice {
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn read_blocks(&self, start: u64, buf: &mut [u8]) -> FsResult<()> {
+        self.file.read_exact_at(buf, start * 512).map_err(|_| FsError::Io)
+    }
+
+    fn write_blocks(&self, start: u64, buf: &[u8]) -> FsResult<()> {
+        self.file.write_all_at(buf, start * 512).map_err(|_| FsError::Io)
+    }
+
+    fn flush(&self) -> FsResult<()> {
+        self.file.sync_data().map_err(|_| FsError::Io)
+    }
+}
+
+/// Run `program` with `args`, returning what it printed. Panics if it
+/// fails or is not installed.
+pub fn run(program: &str, args: &[&str]) -> String {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .unwrap_or_else(|e| panic!("cannot run {}: {}", program, e));
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        output.status.success(),
+        "{} {:?} failed:\n{}{}",
+        program,
+        args,
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
@@ -0,0 +1,52 @@
+//! Tests of the kernel's disk file systems, run on the host against images
+//! made by the host's own tools.
+//!
+//! The drivers are built from the kernel's sources, with what they use of
+//! the rest of the kernel stubbed out. This crate is not part of the
+//! workspace, run `cargo test` from its directory.
+
+#![cfg(test)]
+#![allow(dead_code)]
+
+extern crate alloc;
+
+mod drivers;
+mod ext4;
+mod fat32;
+mod fs;
+mod image;
+
+mod sched {
+    pub use spin::Mutex as SleepMutex;
+
+    /// Spins instead of blocking, as there are no other tasks to run.
+    pub struct WaitQueue;
+
+    impl WaitQueue {
+        pub const fn new() -> Self {
+            Self
+        }
+
+        pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+            while !condition() {
+                std::thread::yield_now();
+            }
+        }
+
+        pub fn wake_one(&self) {}
+
+        pub fn wake_all(&self) {}
+    }
+}
+
+mod timer {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    pub fn get_ticks() -> usize {
+        get_time_ns()
+    }
+
+    pub fn get_time_ns() -> usize {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as usize
+    }
+}
@@ -7,6 +7,13 @@ const FID_HART_STOP: u64 = 1;
 const FID_HART_GET_STATUS: u64 = 2;
 const FID_HART_SUSPEND: u64 = 3;
 
+pub const HART_STATE_STARTED: u64 = 0;
+pub const HART_STATE_STOPPED: u64 = 1;
+pub const HART_STATE_START_PENDING: u64 = 2;
+pub const HART_STATE_STOP_PENDING: u64 = 3;
+pub const HART_STATE_SUSPENDED: u64 = 4;
+pub const HART_STATE_SUSPEND_PENDING: u64 = 5;
+pub const HART_STATE_RESUME_PENDING: u64 = 6;
 
 pub fn sbi_hart_start(hartid: u64, start_addr: u64, opaque: u64) -> Sbiret {
     sbi_call(EID_BASE, FID_HART_START, hartid, start_addr, opaque)
@@ -4,6 +4,7 @@ use core::arch::asm;
 pub mod hsm;
 pub mod base;
 pub mod legacy;
+pub mod rfence;
 
 
 #[inline(always)]
@@ -23,19 +24,31 @@ pub fn sbi_call(eid: u64, fid: u64, arg0: u64, arg1: u64, arg2: u64) -> Sbiret {
         };
     }
     Sbiret {
-        error: match error {
-            0 => SbiError::SbiSuccess,
-            -1 => SbiError::SbiErrFailed,
-            -2 => SbiError::SbiErrNotSupported,
-            -3 => SbiError::SbiErrInvalidParam,
-            -4 => SbiError::SbiErrDenied,
-            -5 => SbiError::SbiErrInvalidAddress,
-            -6 => SbiError::SbiErrAlreadyAvailable,
-            -7 => SbiError::SbiErrAlreadyStarted,
-            -8 => SbiError::SbiErrAlreadyStopped,
-            -9 => SbiError::SbiErrNoShrem,
-            _ => panic!("Unknown SBI error code: {}", error),
-        },
+        error: SbiError::from_code(error),
+        value,
+    }
+}
+
+/// `sbi_call` for functions taking four arguments.
+#[inline(always)]
+pub fn sbi_call_4(eid: u64, fid: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64) -> Sbiret {
+    let error : i64;
+    let value : u64;
+    unsafe {
+        asm! {
+            "ecall",
+            in("x17") eid,
+            in("x16") fid,
+            in("x10") arg0,
+            in("x11") arg1,
+            in("x12") arg2,
+            in("x13") arg3,
+            lateout("x10") error,
+            lateout("x11") value,
+        };
+    }
+    Sbiret {
+        error: SbiError::from_code(error),
         value,
     }
 }
@@ -74,6 +87,24 @@ pub enum SbiError {
     SbiErrAlreadyStopped = -8,
     SbiErrNoShrem = -9
 }
+impl SbiError {
+    fn from_code(error: i64) -> Self {
+        match error {
+            0 => SbiError::SbiSuccess,
+            -1 => SbiError::SbiErrFailed,
+            -2 => SbiError::SbiErrNotSupported,
+            -3 => SbiError::SbiErrInvalidParam,
+            -4 => SbiError::SbiErrDenied,
+            -5 => SbiError::SbiErrInvalidAddress,
+            -6 => SbiError::SbiErrAlreadyAvailable,
+            -7 => SbiError::SbiErrAlreadyStarted,
+            -8 => SbiError::SbiErrAlreadyStopped,
+            -9 => SbiError::SbiErrNoShrem,
+            _ => panic!("Unknown SBI error code: {}", error),
+        }
+    }
+}
+
 impl Sbiret {
     pub fn is_success(&self) -> bool {
         self.error == SbiError::SbiSuccess
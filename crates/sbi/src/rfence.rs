@@ -0,0 +1,14 @@
+use crate::{sbi_call_4, Sbiret};
+
+const EID_RFENCE: u64 = 0x52464E43;
+
+const FID_REMOTE_FENCE_I: u64 = 0;
+const FID_REMOTE_SFENCE_VMA: u64 = 1;
+
+pub fn sbi_remote_fence_i(hart_mask: u64, hart_mask_base: u64) -> Sbiret {
+    sbi_call_4(EID_RFENCE, FID_REMOTE_FENCE_I, hart_mask, hart_mask_base, 0, 0)
+}
+
+pub fn sbi_remote_sfence_vma(hart_mask: u64, hart_mask_base: u64, start_addr: u64, size: u64) -> Sbiret {
+    sbi_call_4(EID_RFENCE, FID_REMOTE_SFENCE_VMA, hart_mask, hart_mask_base, start_addr, size)
+}
@@ -4,7 +4,8 @@
 use core::arch::asm;
 
 use log::trace;
-use sbi::hsm::sbi_hart_get_status;
+use riscv::register::sstatus;
+use sbi::{hsm::sbi_hart_get_status, rfence::sbi_remote_sfence_vma};
 
 use crate::mm::consts::PAGE_SIZE_BITS;
 
@@ -35,11 +36,16 @@ pub fn sp() -> usize {
     value
 }
 
-#[inline]
-pub fn get_hart_id() -> usize {
-    let hart_id;
-    unsafe { core::arch::asm!("mv {0}, tp", out(reg) hart_id) };
-    hart_id
+#[inline(always)]
+pub fn tp() -> usize {
+    let value: usize;
+    unsafe { core::arch::asm!("mv {0}, tp", out(reg) value) };
+    value
+}
+
+#[inline(always)]
+pub unsafe fn set_tp(value: usize) {
+    core::arch::asm!("mv tp, {0}", in(reg) value);
 }
 
 #[inline]
@@ -58,6 +64,12 @@ pub fn get_hart_count() -> usize {
     hart_cnt
 }
 
+/// The `satp` value selecting the Sv39 page table rooted at `pa`.
+#[inline]
+pub fn satp_of(pa: usize) -> usize {
+    (8 << 60) | (pa >> PAGE_SIZE_BITS)
+}
+
 #[inline(always)]
 pub fn switch_page_table(pa: usize) -> usize {
     trace!("Switching to pagetable: 0x{:x}", pa);
@@ -74,6 +86,38 @@ pub fn switch_page_table(pa: usize) -> usize {
     old_page_table_ptr.ppn() << PAGE_SIZE_BITS
 }
 
+/// Flush the TLB entries of `va` on the current hart.
+#[inline(always)]
+pub fn flush_tlb(va: usize) {
+    unsafe { riscv::asm::sfence_vma(0, va) };
+}
+
+/// Flush the whole TLB of the current hart.
+#[inline(always)]
+pub fn flush_tlb_all() {
+    riscv::asm::sfence_vma_all();
+}
+
+/// Flush the TLB entries of `[va, va + size)` on the harts in `hart_mask`.
+pub fn flush_tlb_remote(hart_mask: usize, va: usize, size: usize) {
+    if hart_mask != 0 {
+        sbi_remote_sfence_vma(hart_mask as u64, 0, va as u64, size as u64);
+    }
+}
+
+/// Run `f` with supervisor interrupts disabled on the current hart,
+/// restoring the previous state afterwards.
+#[inline]
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = sstatus::read().sie();
+    unsafe { sstatus::clear_sie() };
+    let result = f();
+    if enabled {
+        unsafe { sstatus::set_sie() };
+    }
+    result
+}
+
 #[inline]
 pub fn wfi() {
     unsafe {
@@ -2,7 +2,7 @@
 
 use core::arch::{asm, global_asm};
 
-use crate::mm::{addr::{kva2pa, PhysAddr, VirtAddr}, consts::PAGE_TABLE_ENTRY_COUNT};
+use crate::{config::{MAX_HART_COUNT, PHYS_VIRT_OFFSET}, mm::addr::{kva2pa, PhysAddr, VirtAddr}};
 
 #[naked]
 #[link_section = ".init.boot"]
@@ -25,27 +25,59 @@ unsafe extern "C" fn _entry(hartid: usize) -> ! {
         "   call {set_stack}",
         "   call {set_boot_page_table}",
         "   la   t0, kernel_init
-            li   t1, 0xffffffff00000000
+            li   t1, {offset}
             add  t0, t0, t1
             add  sp, sp, t1
             jr   t0
         ",
         set_stack   = sym set_stack,
         set_boot_page_table = sym set_boot_page_table,
+        offset = const PHYS_VIRT_OFFSET,
         options(noreturn),
     )
 }
 
+/// Entry of secondary harts started through SBI HSM.
+///
+/// Like `_entry`, this runs at its physical address with paging disabled.
+#[naked]
+#[export_name = "_secondary_entry"]
+unsafe extern "C" fn _secondary_entry(hartid: usize) -> ! {
+    core::arch::asm!(
+        "   mv   tp, a0",
+        "   call {set_stack}",
+        "   call {set_boot_page_table}",
+        "   la   t0, secondary_init
+            li   t1, {offset}
+            add  t0, t0, t1
+            add  sp, sp, t1
+            jr   t0
+        ",
+        set_stack   = sym set_stack,
+        set_boot_page_table = sym set_boot_page_table,
+        offset = const PHYS_VIRT_OFFSET,
+        options(noreturn),
+    )
+}
+
+/// Physical address secondary harts should start executing at.
+pub fn secondary_entry_pa() -> PhysAddr {
+    kva2pa(VirtAddr(_secondary_entry as usize))
+}
+
 global_asm!(
     "   .section .data
         .align 12
     __boot_page_table_sv39:
-        .quad (0x00000 << 10) | 0xcf
+        # 0x0000_0000_8000_0000 -> 0x8000_0000, only used to jump to the high half
+        .quad 0
         .quad 0
         .quad (0x80000 << 10) | 0xcf
-        .zero 8 * 507
+        .zero 8 * 255
+        # 0xffff_ffc0_8000_0000 -> 0x8000_0000 - 0x1_0000_0000, the boot direct map
         .quad (0x80000 << 10) | 0xcf
-        .quad 0
+        .quad (0xc0000 << 10) | 0xcf
+        .zero 8 * 252
     "
 );
 
@@ -53,7 +85,7 @@ global_asm!(
 struct KernelStack([u8; 1024 * 1024]); // 1MiB stack
 
 #[link_section = ".bss.stack"]
-static mut KERNEL_STACK: core::mem::MaybeUninit<[KernelStack; 8]> =
+static mut KERNEL_STACK: core::mem::MaybeUninit<[KernelStack; MAX_HART_COUNT]> =
     core::mem::MaybeUninit::uninit();
 
 
@@ -61,7 +93,7 @@ static mut KERNEL_STACK: core::mem::MaybeUninit<[KernelStack; 8]> =
 unsafe extern "C" fn set_stack(hartid: usize) {
     asm!(
         "   add  t0, a0, 1
-            slli t0, t0, 18
+            slli t0, t0, 20
             la   sp, {stack}
             add  sp, sp, t0
             ret
@@ -86,15 +118,3 @@ unsafe extern "C" fn set_boot_page_table(hartid: usize) {
     )
 
 }
-
-extern "C" {
-    fn __boot_page_table_sv39();
-}
-
-pub fn boot_page_table_pa() -> PhysAddr {
-    kva2pa(VirtAddr(__boot_page_table_sv39 as usize))
-}
-
-pub fn boot_pagetable() -> &'static mut [usize] {
-    unsafe { core::slice::from_raw_parts_mut(boot_page_table_pa().0 as *mut _, PAGE_TABLE_ENTRY_COUNT) }
-}
\ No newline at end of file
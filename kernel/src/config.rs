@@ -2,11 +2,37 @@
 
 pub const KERNEL_HEAP_SIZE : usize = 0x1_000_000; // 16 MiB
 
-const MEMORY_SIZE: usize = 0x1_0000_0000; // 4 GiB
+pub const MAX_HART_COUNT: usize = 8;
 
 pub const PHYSICAL_MEMORY_START: usize = 0x8000_0000;
-pub const PHYSICAL_MEMORY_END: usize = PHYSICAL_MEMORY_START + MEMORY_SIZE;
 
-pub const KERNEL_VIRTUAL_MEMORY_START: usize = 0xFFFF_FFFF_8000_0000;  
-pub const KERNEL_VIRTUAL_MEMORY_END: usize = 0xFFFF_FFFF_FFFF_FFFF;
- 
\ No newline at end of file
+/// Physical memory is mapped into kernel space at `pa + PHYS_VIRT_OFFSET`.
+/// The kernel image itself is linked inside this direct map.
+pub const PHYS_VIRT_OFFSET: usize = 0xFFFF_FFC0_0000_0000;
+
+/// The direct map cannot cover physical memory above this address.
+pub const DIRECT_MAP_MAX_PA: usize = 0x20_0000_0000; // 128 GiB
+
+/// Physical memory covered by the boot page table, usable before the device
+/// tree tells us how much memory there is.
+pub const BOOT_DIRECT_MAP_END: usize = 0x1_0000_0000;
+
+/// End of the user half of the address space.
+pub const USER_SPACE_END: usize = 0x40_0000_0000;
+
+/// Where mappings without an address hint are placed from.
+pub const USER_MMAP_BASE: usize = 0x10_0000_0000;
+
+/// Position-independent executables are loaded here.
+pub const USER_PIE_BASE: usize = 0x1000_0000;
+
+/// The main thread stack of a new program ends here.
+pub const USER_STACK_TOP: usize = USER_SPACE_END;
+
+pub const USER_STACK_SIZE: usize = 0x80_0000; // 8 MiB
+
+/// Size of the kernel stack of every task.
+pub const TASK_KERNEL_STACK_SIZE: usize = 0x10000; // 64 KiB
+
+/// Process and thread ids are below this.
+pub const PID_MAX: usize = 32768;
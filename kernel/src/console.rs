@@ -1,5 +1,12 @@
 use core::fmt::{self, Write};
-use sbi::legacy::sbi_console_putchar;
+use sbi::legacy::{sbi_console_getchar, sbi_console_putchar};
+use spin::Mutex;
+
+use crate::{arch, drivers::uart};
+
+/// Keeps lines printed by different harts from interleaving.
+static CONSOLE_LOCK: Mutex<()> = Mutex::new(());
+
 struct Stdout;
 
 impl Write for Stdout {
@@ -13,16 +20,7 @@ impl Write for Stdout {
     ///
     /// Returns `Ok(())` if the write operation is successful.
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        let mut buffer = [0u8; 4];
-        for c in s.chars() {
-            if c.is_ascii() {
-                sbi_console_putchar(c as u8);
-            } else {
-                for &code in c.encode_utf8(&mut buffer).as_bytes() {
-                    sbi_console_putchar(code);
-                }
-            }
-        }
+        put_bytes(s.as_bytes());
         Ok(())
     }
 }
@@ -32,7 +30,57 @@ impl Write for Stdout {
 /// You may not need to use this function directly.
 /// Instead, you can use the `print!` and `println!` macros.
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    // Interrupts are masked so a handler that prints cannot deadlock on the
+    // lock held by the code it interrupted.
+    arch::without_interrupts(|| {
+        let _guard = CONSOLE_LOCK.lock();
+        Stdout.write_fmt(args).unwrap();
+    });
+}
+
+/// Write raw bytes to the console, as the terminal device does.
+pub fn write_bytes(bytes: &[u8]) {
+    arch::without_interrupts(|| {
+        let _guard = CONSOLE_LOCK.lock();
+        put_bytes(bytes);
+    });
+}
+
+/// Send `bytes` to the UART, or through the firmware until the UART driver
+/// has taken over.
+fn put_bytes(bytes: &[u8]) {
+    match uart::get() {
+        Some(uart) => uart.write(bytes),
+        None => {
+            for &byte in bytes {
+                sbi_console_putchar(byte);
+            }
+        }
+    }
+}
+
+/// Wait until everything written has been sent, before the machine stops.
+pub fn flush() {
+    if let Some(uart) = uart::get() {
+        uart.flush();
+    }
+}
+
+/// A byte typed on the console, if one is waiting.
+pub fn getchar() -> Option<u8> {
+    if let Some(uart) = uart::get() {
+        return uart.getchar();
+    }
+    match sbi_console_getchar() {
+        byte @ 0..=0xff => Some(byte as u8),
+        _ => None,
+    }
+}
+
+/// Call `handler` whenever something is typed on the console, returning
+/// whether it will be. Input through the firmware has to be polled for.
+pub fn set_input_handler(handler: fn()) -> bool {
+    uart::get().is_some_and(|uart| uart.set_input_handler(handler))
 }
 
 /// Print a formatted string to the console, like the one in the standard library.
@@ -0,0 +1,129 @@
+//! Per-hart kernel state.
+//!
+//! Every hart owns one [`Cpu`]. While running in the kernel, `tp` holds a
+//! pointer to it, so [`current`] is a single register read.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use crate::{arch, config::MAX_HART_COUNT, mm::memory_set::AddressSpace, task::Task};
+
+pub mod smp;
+
+pub struct Cpu {
+    hart_id: AtomicUsize,
+    online: AtomicBool,
+    ticks: AtomicUsize,
+    /// The user address space this hart runs on, if any.
+    memory_set: Mutex<Option<Arc<AddressSpace>>>,
+    /// `satp` of `memory_set`, readable without taking its lock.
+    user_satp: AtomicUsize,
+    /// The task running on this hart, if any.
+    task: Mutex<Option<Arc<Task>>>,
+}
+
+impl Cpu {
+    const fn new() -> Self {
+        Self {
+            hart_id: AtomicUsize::new(0),
+            online: AtomicBool::new(false),
+            ticks: AtomicUsize::new(0),
+            memory_set: Mutex::new(None),
+            user_satp: AtomicUsize::new(0),
+            task: Mutex::new(None),
+        }
+    }
+
+    pub fn hart_id(&self) -> usize {
+        self.hart_id.load(Ordering::Relaxed)
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    /// Timer interrupts taken by this hart.
+    pub fn ticks(&self) -> usize {
+        self.ticks.load(Ordering::Relaxed)
+    }
+
+    pub fn tick(&self) -> usize {
+        self.ticks.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn memory_set(&self) -> Option<Arc<AddressSpace>> {
+        self.memory_set.lock().clone()
+    }
+
+    pub fn set_memory_set(&self, memory_set: Option<Arc<AddressSpace>>) {
+        let satp = memory_set.as_ref().map_or(0, |memory_set| memory_set.satp());
+        let old = core::mem::replace(&mut *self.memory_set.lock(), memory_set);
+        self.user_satp.store(satp, Ordering::Release);
+        // Only now, as dropping the last reference may sleep.
+        drop(old);
+    }
+
+    pub fn task(&self) -> Option<Arc<Task>> {
+        self.task.lock().clone()
+    }
+
+    pub fn set_task(&self, task: Option<Arc<Task>>) {
+        *self.task.lock() = task;
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU_INIT: Cpu = Cpu::new();
+static CPUS: [Cpu; MAX_HART_COUNT] = [CPU_INIT; MAX_HART_COUNT];
+
+static BOOT_HART: AtomicUsize = AtomicUsize::new(0);
+
+/// Point `tp` at the `Cpu` of `hart_id`.
+///
+/// This must be the first thing every hart does in Rust code.
+pub fn init(hart_id: usize) {
+    let cpu = &CPUS[hart_id];
+    cpu.hart_id.store(hart_id, Ordering::Relaxed);
+    unsafe { arch::set_tp(cpu as *const Cpu as usize) };
+}
+
+/// Mark the boot hart, which owns global bookkeeping such as wall-clock ticks.
+pub fn set_boot_hart(hart_id: usize) {
+    BOOT_HART.store(hart_id, Ordering::Relaxed);
+}
+
+/// Announce that the current hart has finished its initialization.
+pub fn set_online() {
+    current().online.store(true, Ordering::Release);
+}
+
+/// The `Cpu` of the current hart.
+#[inline]
+pub fn current() -> &'static Cpu {
+    unsafe { &*(arch::tp() as *const Cpu) }
+}
+
+#[inline]
+pub fn hart_id() -> usize {
+    current().hart_id()
+}
+
+pub fn is_boot_hart() -> bool {
+    hart_id() == BOOT_HART.load(Ordering::Relaxed)
+}
+
+pub fn get(hart_id: usize) -> &'static Cpu {
+    &CPUS[hart_id]
+}
+
+/// Harts other than the current one running on the user address space
+/// selected by `satp`, as a mask of hart ids.
+pub fn harts_on_satp(satp: usize) -> usize {
+    let current = hart_id();
+    CPUS.iter()
+        .enumerate()
+        .filter(|&(id, cpu)| id != current && cpu.user_satp.load(Ordering::Acquire) == satp)
+        .fold(0, |mask, (id, _)| mask | 1 << id)
+}
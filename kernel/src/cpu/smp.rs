@@ -0,0 +1,57 @@
+//! Secondary hart bring-up through the SBI HSM extension.
+
+use log::{info, warn};
+use riscv::register::time;
+use sbi::hsm::{sbi_hart_get_status, sbi_hart_start, HART_STATE_STOPPED};
+
+use crate::{boot, config::MAX_HART_COUNT, dtb, timer};
+
+/// How long to wait for a started hart to come online.
+const START_TIMEOUT_MS: usize = 1000;
+
+/// Start every hart listed in the device tree except the current one.
+///
+/// Harts are brought up one at a time, so their early logs do not interleave.
+pub fn start_secondary_harts() {
+    let entry = boot::secondary_entry_pa();
+    let boot_hart = super::hart_id();
+    for &hart_id in dtb::machine().harts() {
+        if hart_id == boot_hart {
+            continue;
+        }
+        // It would have no `Cpu` to point `tp` at.
+        if hart_id >= MAX_HART_COUNT {
+            warn!("Hart {} exceeds MAX_HART_COUNT, skipped.", hart_id);
+            continue;
+        }
+        let status = sbi_hart_get_status(hart_id as u64);
+        if !status.is_success() || status.value != HART_STATE_STOPPED {
+            warn!("Hart {} is not startable, skipped.", hart_id);
+            continue;
+        }
+        if !sbi_hart_start(hart_id as u64, entry.0 as u64, 0).is_success() {
+            warn!("Failed to start hart {}.", hart_id);
+            continue;
+        }
+        if !wait_online(hart_id) {
+            warn!("Hart {} did not come online in time.", hart_id);
+        }
+    }
+    let online = dtb::machine()
+        .harts()
+        .iter()
+        .filter(|&&id| super::get(id).is_online())
+        .count();
+    info!("{} harts online.", online);
+}
+
+fn wait_online(hart_id: usize) -> bool {
+    let deadline = time::read() + timer::clock_freq() / 1000 * START_TIMEOUT_MS;
+    while time::read() < deadline {
+        if super::get(hart_id).is_online() {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
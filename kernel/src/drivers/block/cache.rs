@@ -0,0 +1,265 @@
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use spin::Mutex;
+
+use crate::{
+    fs::{FsError, FsResult},
+    sched::WaitQueue,
+};
+
+use super::BlockDevice;
+
+/// Blocks cached when no capacity is given.
+const DEFAULT_CAPACITY: usize = 1024;
+
+struct CachedBlock {
+    data: Box<[u8]>,
+    dirty: bool,
+    /// When the block was last used, to evict the least recently used one.
+    used: u64,
+    /// The block is being read in or written back. Others wait on
+    /// [`BlockCache::idle`] instead of touching it meanwhile.
+    busy: bool,
+}
+
+struct CacheInner {
+    blocks: BTreeMap<u64, CachedBlock>,
+    clock: u64,
+}
+
+/// Byte-addressed access to a block device, through a write-back cache of
+/// its blocks.
+///
+/// Written data reaches the device when its block is evicted or on
+/// [`flush`](Self::flush). The device is never accessed with the lock on
+/// the cache held, so it may sleep.
+pub struct BlockCache {
+    device: Arc<dyn BlockDevice>,
+    block_size: usize,
+    capacity: usize,
+    inner: Mutex<CacheInner>,
+    /// Woken when I/O on a block finishes.
+    idle: WaitQueue,
+}
+
+impl BlockCache {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        Self::with_capacity(device, DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(device: Arc<dyn BlockDevice>, capacity: usize) -> Self {
+        Self {
+            block_size: device.block_size(),
+            device,
+            capacity: capacity.max(1),
+            inner: Mutex::new(CacheInner {
+                blocks: BTreeMap::new(),
+                clock: 0,
+            }),
+            idle: WaitQueue::new(),
+        }
+    }
+
+    pub fn device(&self) -> &Arc<dyn BlockDevice> {
+        &self.device
+    }
+
+    /// Size of the device in bytes.
+    pub fn len(&self) -> u64 {
+        self.device.num_blocks() * self.block_size as u64
+    }
+
+    /// Read `buf.len()` bytes at byte `pos` of the device.
+    pub fn read_at(&self, pos: u64, buf: &mut [u8]) -> FsResult<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let (block, offset) = self.locate(pos + done as u64);
+            let len = (self.block_size - offset).min(buf.len() - done);
+            self.with_block(block, false, |data| {
+                buf[done..done + len].copy_from_slice(&data[offset..offset + len]);
+            })?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Write `buf` at byte `pos` of the device.
+    pub fn write_at(&self, pos: u64, buf: &[u8]) -> FsResult<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let (block, offset) = self.locate(pos + done as u64);
+            let len = (self.block_size - offset).min(buf.len() - done);
+            self.with_block(block, true, |data| {
+                data[offset..offset + len].copy_from_slice(&buf[done..done + len]);
+            })?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Set `len` bytes at `pos` to zero.
+    pub fn zero(&self, pos: u64, len: usize) -> FsResult<()> {
+        let zeroes = vec![0u8; self.block_size];
+        let mut done = 0;
+        while done < len {
+            let chunk = (self.block_size - self.locate(pos + done as u64).1).min(len - done);
+            self.write_at(pos + done as u64, &zeroes[..chunk])?;
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    pub fn read_u16(&self, pos: u64) -> FsResult<u16> {
+        let mut bytes = [0; 2];
+        self.read_at(pos, &mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&self, pos: u64) -> FsResult<u32> {
+        let mut bytes = [0; 4];
+        self.read_at(pos, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn write_u32(&self, pos: u64, value: u32) -> FsResult<()> {
+        self.write_at(pos, &value.to_le_bytes())
+    }
+
+    /// Write every dirty block back and flush the device.
+    pub fn flush(&self) -> FsResult<()> {
+        let dirty: Vec<u64> = {
+            let inner = self.inner.lock();
+            inner.blocks.iter().filter(|(_, cached)| cached.dirty).map(|(&block, _)| block).collect()
+        };
+        for block in dirty {
+            self.write_back(block)?;
+        }
+        self.device.flush()
+    }
+
+    fn locate(&self, pos: u64) -> (u64, usize) {
+        let block_size = self.block_size as u64;
+        (pos / block_size, (pos % block_size) as usize)
+    }
+
+    /// Run `f` on the cached data of `block`, reading it in first if needed.
+    fn with_block(&self, block: u64, write: bool, f: impl FnOnce(&mut [u8])) -> FsResult<()> {
+        if block >= self.device.num_blocks() {
+            return Err(FsError::Io);
+        }
+        loop {
+            let mut inner = self.inner.lock();
+            inner.clock += 1;
+            let now = inner.clock;
+            match inner.blocks.get_mut(&block) {
+                Some(cached) if cached.busy => {
+                    drop(inner);
+                    self.wait_idle(block);
+                }
+                Some(cached) => {
+                    cached.used = now;
+                    cached.dirty |= write;
+                    f(&mut cached.data);
+                    return Ok(());
+                }
+                None => {
+                    let victim = if inner.blocks.len() >= self.capacity {
+                        inner
+                            .blocks
+                            .iter()
+                            .filter(|(_, cached)| !cached.busy)
+                            .min_by_key(|(_, cached)| cached.used)
+                            .map(|(&victim, _)| victim)
+                    } else {
+                        None
+                    };
+                    // Claim the block, so that others wait for it to be read
+                    // in instead of reading it too.
+                    inner.blocks.insert(
+                        block,
+                        CachedBlock {
+                            data: Box::default(),
+                            dirty: false,
+                            used: now,
+                            busy: true,
+                        },
+                    );
+                    drop(inner);
+                    if let Some(victim) = victim {
+                        if let Err(e) = self.evict(victim) {
+                            self.finish_io(block, None);
+                            return Err(e);
+                        }
+                    }
+                    let mut data = vec![0u8; self.block_size].into_boxed_slice();
+                    let read = self.device.read_blocks(block, &mut data);
+                    self.finish_io(block, read.is_ok().then_some(data));
+                    read?;
+                }
+            }
+        }
+    }
+
+    /// Write `block` back if it is dirty, after waiting for I/O already
+    /// going on.
+    fn write_back(&self, block: u64) -> FsResult<()> {
+        let data = loop {
+            let mut inner = self.inner.lock();
+            match inner.blocks.get_mut(&block) {
+                Some(cached) if cached.busy => {
+                    drop(inner);
+                    self.wait_idle(block);
+                }
+                Some(cached) if cached.dirty => {
+                    cached.busy = true;
+                    cached.dirty = false;
+                    break cached.data.clone();
+                }
+                _ => return Ok(()),
+            }
+        };
+        let written = self.device.write_blocks(block, &data);
+        let mut inner = self.inner.lock();
+        let cached = inner.blocks.get_mut(&block).unwrap();
+        cached.busy = false;
+        if written.is_err() {
+            cached.dirty = true;
+        }
+        drop(inner);
+        self.idle.wake_all();
+        written
+    }
+
+    /// Drop `block`, writing it back first if dirty.
+    fn evict(&self, block: u64) -> FsResult<()> {
+        self.write_back(block)?;
+        let mut inner = self.inner.lock();
+        // It may have been written to again while it was written back.
+        if inner.blocks.get(&block).is_some_and(|cached| !cached.dirty && !cached.busy) {
+            inner.blocks.remove(&block);
+        }
+        Ok(())
+    }
+
+    /// Mark the I/O reading `block` in as done, with `data` if it
+    /// succeeded, and wake those waiting for it.
+    fn finish_io(&self, block: u64, data: Option<Box<[u8]>>) {
+        let mut inner = self.inner.lock();
+        match data {
+            Some(data) => {
+                let cached = inner.blocks.get_mut(&block).unwrap();
+                cached.data = data;
+                cached.busy = false;
+            }
+            None => {
+                inner.blocks.remove(&block);
+            }
+        }
+        drop(inner);
+        self.idle.wake_all();
+    }
+
+    fn wait_idle(&self, block: u64) {
+        self.idle
+            .wait_until(|| self.inner.lock().blocks.get(&block).is_none_or(|cached| !cached.busy));
+    }
+}
@@ -0,0 +1,67 @@
+//! Block devices, which file systems are mounted from.
+//!
+//! Drivers register their devices by name, such as `vda`, and file systems
+//! access them through a [`BlockCache`].
+
+mod cache;
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use log::info;
+use spin::Mutex;
+
+use crate::fs::FsResult;
+
+pub use self::cache::BlockCache;
+
+/// A device storing data in fixed-size blocks.
+///
+/// Transfers may sleep, so no spin lock may be held across them.
+pub trait BlockDevice: Send + Sync {
+    /// Bytes per block.
+    fn block_size(&self) -> usize {
+        512
+    }
+
+    fn num_blocks(&self) -> u64;
+
+    /// Read blocks from `start` on into `buf`, whose length is a multiple of
+    /// the block size.
+    fn read_blocks(&self, start: u64, buf: &mut [u8]) -> FsResult<()>;
+
+    /// Write `buf`, whose length is a multiple of the block size, to blocks
+    /// from `start` on.
+    fn write_blocks(&self, start: u64, buf: &[u8]) -> FsResult<()>;
+
+    /// Make written blocks durable.
+    fn flush(&self) -> FsResult<()> {
+        Ok(())
+    }
+}
+
+static DEVICES: Mutex<BTreeMap<String, Arc<dyn BlockDevice>>> = Mutex::new(BTreeMap::new());
+
+/// Make `device` available as `name`.
+pub fn register(name: &str, device: Arc<dyn BlockDevice>) {
+    info!(
+        "Block device {}: {} blocks of {} bytes",
+        name,
+        device.num_blocks(),
+        device.block_size()
+    );
+    DEVICES.lock().insert(name.to_string(), device);
+}
+
+/// The device registered as `name`.
+pub fn get(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().get(name).cloned()
+}
+
+/// Names of every registered device, in order.
+pub fn names() -> Vec<String> {
+    DEVICES.lock().keys().cloned().collect()
+}
@@ -0,0 +1,82 @@
+//! Devices that make data up rather than store it.
+
+use spin::Mutex;
+
+use crate::{fs::FsResult, timer};
+
+use super::CharDevice;
+
+/// Reads nothing and discards what is written.
+pub struct Null;
+
+impl CharDevice for Null {
+    fn read(&self, _buf: &mut [u8], _nonblock: bool) -> FsResult<usize> {
+        Ok(0)
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// Reads zeroes and discards what is written.
+pub struct Zero;
+
+impl CharDevice for Zero {
+    fn read(&self, buf: &mut [u8], _nonblock: bool) -> FsResult<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        Ok(buf.len())
+    }
+}
+
+/// Reads pseudo-random bytes from an xorshift generator, stirred with the
+/// time of every read. Nothing here is fit for cryptography.
+pub struct Random {
+    state: Mutex<u64>,
+}
+
+impl Random {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(0x9e37_79b9_7f4a_7c15),
+        }
+    }
+}
+
+impl CharDevice for Random {
+    fn read(&self, buf: &mut [u8], _nonblock: bool) -> FsResult<usize> {
+        let mut state = self.state.lock();
+        *state ^= (timer::get_time_ns() as u64).wrapping_mul(0x2545_f491_4f6c_dd1d);
+        if *state == 0 {
+            *state = 1;
+        }
+        for chunk in buf.chunks_mut(8) {
+            let mut x = *state;
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            *state = x;
+            let bytes = x.wrapping_mul(0x2545_f491_4f6c_dd1d).to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+
+    /// Written data is mixed into the state, as Linux does.
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        let mut state = self.state.lock();
+        for chunk in buf.chunks(8) {
+            let mut bytes = [0; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            *state = (*state ^ u64::from_le_bytes(bytes)).rotate_left(23);
+        }
+        if *state == 0 {
+            *state = 1;
+        }
+        Ok(buf.len())
+    }
+}
@@ -0,0 +1,84 @@
+//! Character devices, which are read and written as streams of bytes.
+//!
+//! Drivers register their devices by name and device number, and `/dev`
+//! shows every registered device as a device file.
+
+mod mem;
+mod tty;
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+
+use crate::fs::{FsResult, OpenFlags};
+
+pub use self::{
+    mem::{Null, Random, Zero},
+    tty::{ControllingTty, Termios, Tty, WinSize},
+};
+
+/// A device transferring bytes in order, without offsets.
+pub trait CharDevice: Send + Sync {
+    /// Read into `buf`, waiting for at least one byte unless `nonblock`.
+    fn read(&self, buf: &mut [u8], nonblock: bool) -> FsResult<usize>;
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize>;
+
+    /// The device an open of the device file reaches, if not this one.
+    fn open(&self, _flags: OpenFlags) -> FsResult<Option<Arc<dyn CharDevice>>> {
+        Ok(None)
+    }
+
+    /// The terminal the device is, for the terminal `ioctl`s.
+    fn tty(&self) -> Option<Arc<Tty>> {
+        None
+    }
+}
+
+/// A registered device and its device number.
+#[derive(Clone)]
+pub struct Registered {
+    pub rdev: u64,
+    pub device: Arc<dyn CharDevice>,
+}
+
+static DEVICES: Mutex<BTreeMap<String, Registered>> = Mutex::new(BTreeMap::new());
+
+/// The device number of `major` and `minor`, encoded as Linux does.
+pub fn makedev(major: u32, minor: u32) -> u64 {
+    let (major, minor) = (major as u64, minor as u64);
+    (major & 0xfff) << 8 | (major & !0xfff) << 32 | (minor & 0xff) | (minor & !0xff) << 12
+}
+
+/// Make `device` available as `name` with the device number `major:minor`.
+pub fn register(name: &str, major: u32, minor: u32, device: Arc<dyn CharDevice>) {
+    let rdev = makedev(major, minor);
+    DEVICES.lock().insert(name.to_string(), Registered { rdev, device });
+}
+
+/// The device registered as `name`.
+pub fn get(name: &str) -> Option<Registered> {
+    DEVICES.lock().get(name).cloned()
+}
+
+/// Names of every registered device, in order.
+pub fn names() -> Vec<String> {
+    DEVICES.lock().keys().cloned().collect()
+}
+
+/// Register the devices every system has.
+pub fn init() {
+    register("null", 1, 3, Arc::new(Null));
+    register("zero", 1, 5, Arc::new(Zero));
+    let random = Arc::new(Random::new());
+    register("random", 1, 8, random.clone());
+    register("urandom", 1, 9, random);
+    let console = tty::init_console();
+    register("tty", 5, 0, Arc::new(ControllingTty));
+    register("console", 5, 1, console.clone());
+    register("ttyS0", 4, 64, console);
+}
@@ -0,0 +1,645 @@
+//! Terminals, with the line discipline between the console and the
+//! processes of a session.
+//!
+//! In canonical mode input is edited a line at a time and read a line at a
+//! time; in raw mode it is read as it comes. With `ISIG`, the interrupt,
+//! quit and suspend characters are not read but signal the foreground
+//! process group. A terminal becomes the controlling terminal of the first
+//! session whose leader opens it.
+
+use alloc::{
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::{Mutex, Once};
+
+use crate::{
+    console,
+    fs::{FsError, FsResult, OpenFlags},
+    sched::{self, WaitQueue},
+    task::{
+        self,
+        signal::{SIGCONT, SIGHUP, SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU, SIGWINCH, SIG_IGN},
+        Process,
+    },
+};
+
+use super::{makedev, CharDevice};
+
+const NCCS: usize = 19;
+
+// Indices of the control characters.
+const VINTR: usize = 0;
+const VQUIT: usize = 1;
+const VERASE: usize = 2;
+const VKILL: usize = 3;
+const VEOF: usize = 4;
+const VTIME: usize = 5;
+const VMIN: usize = 6;
+const VSTART: usize = 8;
+const VSTOP: usize = 9;
+const VSUSP: usize = 10;
+const VEOL: usize = 11;
+const VREPRINT: usize = 12;
+const VDISCARD: usize = 13;
+const VWERASE: usize = 14;
+const VLNEXT: usize = 15;
+const VEOL2: usize = 16;
+
+// Input flags.
+const ISTRIP: u32 = 0o40;
+const INLCR: u32 = 0o100;
+const IGNCR: u32 = 0o200;
+const ICRNL: u32 = 0o400;
+const IXON: u32 = 0o2000;
+const IUTF8: u32 = 0o40000;
+
+// Output flags.
+const OPOST: u32 = 0o1;
+const ONLCR: u32 = 0o4;
+
+/// 38400 baud, 8 data bits, receiver on, hang up on last close.
+const DEFAULT_CFLAG: u32 = 0o17 | 0o60 | 0o200 | 0o2000;
+
+// Local flags.
+const ISIG: u32 = 0o1;
+const ICANON: u32 = 0o2;
+const ECHO: u32 = 0o10;
+const ECHOE: u32 = 0o20;
+const ECHOK: u32 = 0o40;
+const ECHONL: u32 = 0o100;
+const NOFLSH: u32 = 0o200;
+const TOSTOP: u32 = 0o400;
+const ECHOCTL: u32 = 0o1000;
+const ECHOKE: u32 = 0o4000;
+const IEXTEN: u32 = 0o100000;
+
+/// Longest line canonical mode edits; characters beyond are dropped.
+const MAX_CANON: usize = 4095;
+/// Bytes raw mode holds for readers; more are dropped.
+const MAX_INPUT: usize = 4096;
+
+const BACKSPACE: u8 = 0x08;
+
+/// `struct termios` as the `TCGETS` family of `ioctl`s passes it.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+}
+
+impl Default for Termios {
+    /// The settings of a freshly opened Linux terminal.
+    fn default() -> Self {
+        let mut cc = [0; NCCS];
+        cc[VINTR] = 0x03;
+        cc[VQUIT] = 0x1c;
+        cc[VERASE] = 0x7f;
+        cc[VKILL] = 0x15;
+        cc[VEOF] = 0x04;
+        cc[VTIME] = 0;
+        cc[VMIN] = 1;
+        cc[VSTART] = 0x11;
+        cc[VSTOP] = 0x13;
+        cc[VSUSP] = 0x1a;
+        cc[VREPRINT] = 0x12;
+        cc[VDISCARD] = 0x0f;
+        cc[VWERASE] = 0x17;
+        cc[VLNEXT] = 0x16;
+        Self {
+            iflag: ICRNL | IXON | IUTF8,
+            oflag: OPOST | ONLCR,
+            cflag: DEFAULT_CFLAG,
+            lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            line: 0,
+            cc,
+        }
+    }
+}
+
+impl Termios {
+    /// Whether `c` is the control character at `index`, which 0 disables.
+    fn is(&self, c: u8, index: usize) -> bool {
+        self.cc[index] != 0 && self.cc[index] == c
+    }
+
+    fn canonical(&self) -> bool {
+        self.lflag & ICANON != 0
+    }
+}
+
+/// `struct winsize`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct WinSize {
+    pub row: u16,
+    pub col: u16,
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
+
+struct TtyInner {
+    termios: Termios,
+    winsize: WinSize,
+    /// The line being edited, in canonical mode.
+    line: Vec<u8>,
+    /// Lines ready to be read in canonical mode. An empty one is an end of
+    /// file.
+    lines: VecDeque<Vec<u8>>,
+    /// Bytes ready to be read in raw mode.
+    raw: VecDeque<u8>,
+    /// The session this is the controlling terminal of.
+    session: Option<usize>,
+    /// The foreground process group of the session.
+    pgrp: usize,
+}
+
+/// A terminal on the console.
+pub struct Tty {
+    this: Weak<Tty>,
+    rdev: u64,
+    inner: Mutex<TtyInner>,
+    readable: WaitQueue,
+    /// Whether input is pushed by the console interrupt. Otherwise readers
+    /// poll for it.
+    input_irq: AtomicBool,
+}
+
+/// What taking in a character asks for besides editing the input.
+#[derive(Default)]
+struct Effects {
+    echo: Vec<u8>,
+    signals: Vec<usize>,
+}
+
+impl TtyInner {
+    /// Take in a character typed, as the line discipline says.
+    fn receive(&mut self, byte: u8, effects: &mut Effects) {
+        let termios = self.termios;
+        let mut c = byte;
+        if termios.iflag & ISTRIP != 0 {
+            c &= 0x7f;
+        }
+        match c {
+            b'\r' if termios.iflag & IGNCR != 0 => return,
+            b'\r' if termios.iflag & ICRNL != 0 => c = b'\n',
+            b'\n' if termios.iflag & INLCR != 0 => c = b'\r',
+            _ => {}
+        }
+
+        if termios.lflag & ISIG != 0 {
+            let sig = if termios.is(c, VINTR) {
+                Some(SIGINT)
+            } else if termios.is(c, VQUIT) {
+                Some(SIGQUIT)
+            } else if termios.is(c, VSUSP) {
+                Some(SIGTSTP)
+            } else {
+                None
+            };
+            if let Some(sig) = sig {
+                if termios.lflag & NOFLSH == 0 {
+                    self.flush_input();
+                }
+                self.echo(c, effects);
+                effects.signals.push(sig);
+                return;
+            }
+        }
+
+        if !termios.canonical() {
+            if self.raw.len() < MAX_INPUT {
+                self.raw.push_back(c);
+                self.echo(c, effects);
+            }
+            return;
+        }
+
+        // Terminals send either DEL or ^H for backspace.
+        if termios.is(c, VERASE) || c == BACKSPACE {
+            self.erase(effects);
+        } else if termios.is(c, VKILL) {
+            if termios.lflag & (ECHOKE | ECHOE) != 0 {
+                while !self.line.is_empty() {
+                    self.erase(effects);
+                }
+            } else {
+                self.line.clear();
+                self.echo(c, effects);
+                if termios.lflag & ECHOK != 0 {
+                    self.echo_raw(b'\n', effects);
+                }
+            }
+        } else if termios.is(c, VWERASE) && termios.lflag & IEXTEN != 0 {
+            while self.line.last().is_some_and(u8::is_ascii_whitespace) {
+                self.erase(effects);
+            }
+            while self.line.last().is_some_and(|c| !c.is_ascii_whitespace()) {
+                self.erase(effects);
+            }
+        } else if termios.is(c, VEOF) {
+            // Ends the line without being part of it, so at the start of a
+            // line it is read as the end of file.
+            let line = core::mem::take(&mut self.line);
+            self.lines.push_back(line);
+        } else if c == b'\n' || termios.is(c, VEOL) || termios.is(c, VEOL2) {
+            self.line.push(c);
+            let line = core::mem::take(&mut self.line);
+            self.lines.push_back(line);
+            if c == b'\n' && termios.lflag & ECHONL != 0 {
+                self.echo_raw(c, effects);
+            } else {
+                self.echo(c, effects);
+            }
+        } else if self.line.len() < MAX_CANON {
+            self.line.push(c);
+            self.echo(c, effects);
+        }
+    }
+
+    /// Whether `c` echoes as `^` and a letter.
+    fn echoes_as_control(&self, c: u8) -> bool {
+        self.termios.lflag & ECHOCTL != 0 && (c < 0x20 || c == 0x7f) && c != b'\t' && c != b'\n'
+    }
+
+    fn echo(&self, c: u8, effects: &mut Effects) {
+        if self.termios.lflag & ECHO == 0 {
+            return;
+        }
+        if self.echoes_as_control(c) {
+            effects.echo.push(b'^');
+            effects.echo.push(c ^ 0x40);
+        } else {
+            effects.echo.push(c);
+        }
+    }
+
+    /// Echo `c` even without `ECHO`, as `ECHONL` and `ECHOK` do.
+    fn echo_raw(&self, c: u8, effects: &mut Effects) {
+        effects.echo.push(c);
+    }
+
+    /// Remove the last character of the line, and from the screen.
+    fn erase(&mut self, effects: &mut Effects) {
+        let Some(mut c) = self.line.pop() else {
+            return;
+        };
+        if self.termios.iflag & IUTF8 != 0 {
+            // Take the continuation bytes and the lead byte of a UTF-8
+            // character, which takes one column.
+            while c & 0xc0 == 0x80 {
+                match self.line.pop() {
+                    Some(prev) => c = prev,
+                    None => break,
+                }
+            }
+        }
+        if self.termios.lflag & ECHO == 0 {
+            return;
+        }
+        if self.termios.lflag & ECHOE == 0 {
+            self.echo(self.termios.cc[VERASE], effects);
+            return;
+        }
+        let columns = if self.echoes_as_control(c) { 2 } else { 1 };
+        for _ in 0..columns {
+            effects.echo.extend_from_slice(b"\x08 \x08");
+        }
+    }
+
+    fn flush_input(&mut self) {
+        self.line.clear();
+        self.lines.clear();
+        self.raw.clear();
+    }
+
+    /// Whether a read of up to `len` bytes would return now.
+    fn ready(&self, len: usize) -> bool {
+        if self.termios.canonical() {
+            !self.lines.is_empty()
+        } else {
+            self.raw.len() >= (self.termios.cc[VMIN] as usize).min(len)
+        }
+    }
+
+    /// Read into `buf` if a read would return now: a line, or what there is
+    /// of it, in canonical mode; `VMIN` bytes or more in raw mode. `VTIME`
+    /// is not supported, so reads wait for `VMIN` bytes however long that
+    /// takes.
+    fn take_input(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if !self.ready(buf.len()) {
+            return None;
+        }
+        if self.termios.canonical() {
+            let line = self.lines.front_mut().unwrap();
+            let len = line.len().min(buf.len());
+            buf[..len].copy_from_slice(&line[..len]);
+            line.drain(..len);
+            if line.is_empty() {
+                self.lines.pop_front();
+            }
+            Some(len)
+        } else {
+            let len = self.raw.len().min(buf.len());
+            for (dst, src) in buf.iter_mut().zip(self.raw.drain(..len)) {
+                *dst = src;
+            }
+            Some(len)
+        }
+    }
+
+    /// Output processing of `bytes`.
+    fn output(&self, bytes: &[u8]) -> Vec<u8> {
+        let oflag = self.termios.oflag;
+        if oflag & OPOST == 0 || oflag & ONLCR == 0 {
+            return bytes.to_vec();
+        }
+        let mut out = Vec::with_capacity(bytes.len());
+        for &byte in bytes {
+            if byte == b'\n' {
+                out.push(b'\r');
+            }
+            out.push(byte);
+        }
+        out
+    }
+}
+
+impl Tty {
+    fn new(rdev: u64) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            rdev,
+            inner: Mutex::new(TtyInner {
+                termios: Termios::default(),
+                winsize: WinSize { row: 24, col: 80, xpixel: 0, ypixel: 0 },
+                line: Vec::new(),
+                lines: VecDeque::new(),
+                raw: VecDeque::new(),
+                session: None,
+                pgrp: 0,
+            }),
+            readable: WaitQueue::new(),
+            input_irq: AtomicBool::new(false),
+        })
+    }
+
+    pub fn rdev(&self) -> u64 {
+        self.rdev
+    }
+
+    /// Take what was typed on the console through the line discipline.
+    fn poll_input(&self) {
+        let mut effects = Effects::default();
+        let mut received = false;
+        let (echo, pgrp) = {
+            let mut inner = self.inner.lock();
+            while let Some(byte) = console::getchar() {
+                inner.receive(byte, &mut effects);
+                received = true;
+            }
+            let echo = inner.output(&effects.echo);
+            (echo, inner.session.map(|_| inner.pgrp))
+        };
+        if !received {
+            return;
+        }
+        console::write_bytes(&echo);
+        if let Some(pgrp) = pgrp {
+            for &sig in effects.signals.iter() {
+                task::signal_group(pgrp, sig);
+            }
+        }
+        // Readers also wake to see the signals.
+        self.readable.wake_all();
+    }
+
+    /// Let the current process go on with a read, or a write with `sig`
+    /// `SIGTTOU`, only if it is not in a background group of the session
+    /// this terminal controls. A background group gets `sig` instead,
+    /// unless it ignores or blocks it.
+    fn check_foreground(&self, sig: usize) -> FsResult<()> {
+        let task = task::current();
+        let process = task.process().clone();
+        let (pgid, sid) = {
+            let inner = process.inner();
+            (inner.pgid, inner.sid)
+        };
+        let (session, pgrp) = {
+            let inner = self.inner.lock();
+            (inner.session, inner.pgrp)
+        };
+        if session != Some(sid) || pgid == pgrp {
+            return Ok(());
+        }
+        let ignored = task.inner().sig_blocked.contains(sig)
+            || process.inner().sig_actions.lock().get(sig).handler == SIG_IGN;
+        match (ignored, sig) {
+            (true, SIGTTOU) => Ok(()),
+            (true, _) => Err(FsError::Io),
+            (false, _) => {
+                task::signal_group(pgid, sig);
+                Err(FsError::Interrupted)
+            }
+        }
+    }
+
+    /// Make this the controlling terminal of the session `process` leads,
+    /// unless the session has one or, without `steal`, the terminal
+    /// controls another session. Returns whether it is the controlling
+    /// terminal now.
+    pub fn attach(&self, process: &Process, steal: bool) -> bool {
+        let Some(this) = self.this.upgrade() else {
+            return false;
+        };
+        let mut process_inner = process.inner();
+        if let Some(tty) = process_inner.tty.as_ref() {
+            return Arc::ptr_eq(tty, &this) && self.controls(process_inner.sid);
+        }
+        if process_inner.sid != process.pid() {
+            return false;
+        }
+        let mut inner = self.inner.lock();
+        if inner.session.is_some_and(|sid| sid != process_inner.sid) && !steal {
+            return false;
+        }
+        inner.session = Some(process_inner.sid);
+        inner.pgrp = process_inner.pgid;
+        process_inner.tty = Some(this);
+        true
+    }
+
+    /// Whether this is the controlling terminal of session `sid`.
+    pub fn controls(&self, sid: usize) -> bool {
+        self.inner.lock().session == Some(sid)
+    }
+
+    /// The foreground process group.
+    pub fn foreground(&self) -> usize {
+        self.inner.lock().pgrp
+    }
+
+    pub fn set_foreground(&self, pgrp: usize) {
+        self.inner.lock().pgrp = pgrp;
+    }
+
+    /// Stop controlling session `sid`, whose leader exited. The foreground
+    /// group is told with `SIGHUP`.
+    pub fn hangup(&self, sid: usize) {
+        let pgrp = {
+            let mut inner = self.inner.lock();
+            if inner.session != Some(sid) {
+                return;
+            }
+            inner.session = None;
+            core::mem::take(&mut inner.pgrp)
+        };
+        task::signal_group(pgrp, SIGHUP);
+        task::signal_group(pgrp, SIGCONT);
+        self.readable.wake_all();
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.inner.lock().termios
+    }
+
+    /// Change the settings, discarding pending input if `flush`. Input
+    /// pending when canonical mode changes moves to where the new mode
+    /// reads it from.
+    pub fn set_termios(&self, termios: Termios, flush: bool) {
+        {
+            let mut inner = self.inner.lock();
+            if flush {
+                inner.flush_input();
+            }
+            match (inner.termios.canonical(), termios.canonical()) {
+                (true, false) => {
+                    let mut raw: VecDeque<u8> = inner.lines.drain(..).flatten().collect();
+                    raw.extend(inner.line.drain(..));
+                    raw.extend(inner.raw.drain(..));
+                    inner.raw = raw;
+                }
+                (false, true) => {
+                    let pending: Vec<u8> = inner.raw.drain(..).collect();
+                    inner.line.extend(pending);
+                    inner.line.truncate(MAX_CANON);
+                }
+                _ => {}
+            }
+            inner.termios = termios;
+        }
+        self.readable.wake_all();
+    }
+
+    pub fn winsize(&self) -> WinSize {
+        self.inner.lock().winsize
+    }
+
+    /// Change the window size, telling the foreground group with `SIGWINCH`
+    /// if it changed.
+    pub fn set_winsize(&self, winsize: WinSize) {
+        let pgrp = {
+            let mut inner = self.inner.lock();
+            let changed = inner.winsize != winsize;
+            inner.winsize = winsize;
+            inner.session.filter(|_| changed).map(|_| inner.pgrp)
+        };
+        if let Some(pgrp) = pgrp {
+            task::signal_group(pgrp, SIGWINCH);
+        }
+    }
+}
+
+impl CharDevice for Tty {
+    fn read(&self, buf: &mut [u8], nonblock: bool) -> FsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.check_foreground(SIGTTIN)?;
+        loop {
+            self.poll_input();
+            if let Some(len) = self.inner.lock().take_input(buf) {
+                return Ok(len);
+            }
+            if nonblock {
+                return Err(FsError::WouldBlock);
+            }
+            if task::signal_pending() {
+                return Err(FsError::Interrupted);
+            }
+            if self.input_irq.load(Ordering::Acquire) {
+                self.readable
+                    .wait_until(|| self.inner.lock().ready(buf.len()) || task::signal_pending());
+            } else {
+                sched::yield_now();
+            }
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        if self.termios().lflag & TOSTOP != 0 {
+            self.check_foreground(SIGTTOU)?;
+        }
+        let out = self.inner.lock().output(buf);
+        console::write_bytes(&out);
+        Ok(buf.len())
+    }
+
+    fn open(&self, flags: OpenFlags) -> FsResult<Option<Arc<dyn CharDevice>>> {
+        if !flags.contains(OpenFlags::NOCTTY) {
+            self.attach(task::current().process(), false);
+        }
+        Ok(None)
+    }
+
+    fn tty(&self) -> Option<Arc<Tty>> {
+        self.this.upgrade()
+    }
+}
+
+/// `/dev/tty`, which opens the controlling terminal of the process opening
+/// it.
+pub struct ControllingTty;
+
+impl CharDevice for ControllingTty {
+    fn read(&self, _buf: &mut [u8], _nonblock: bool) -> FsResult<usize> {
+        Err(FsError::NoDevice)
+    }
+
+    fn write(&self, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::NoDevice)
+    }
+
+    fn open(&self, _flags: OpenFlags) -> FsResult<Option<Arc<dyn CharDevice>>> {
+        let process = task::current().process().clone();
+        let inner = process.inner();
+        match inner.tty.clone() {
+            Some(tty) if tty.controls(inner.sid) => Ok(Some(tty)),
+            _ => Err(FsError::NoDevice),
+        }
+    }
+}
+
+static CONSOLE: Once<Arc<Tty>> = Once::new();
+
+fn console_input() {
+    if let Some(tty) = CONSOLE.get() {
+        tty.poll_input();
+    }
+}
+
+/// Create the terminal on the system console, `ttyS0`.
+pub fn init_console() -> Arc<Tty> {
+    let tty = CONSOLE.call_once(|| Tty::new(makedev(4, 64))).clone();
+    let input_irq = console::set_input_handler(console_input);
+    tty.input_irq.store(input_irq, Ordering::Release);
+    tty
+}
@@ -0,0 +1,22 @@
+//! Device drivers.
+
+pub mod block;
+pub mod chardev;
+pub mod plic;
+pub mod uart;
+pub mod virtio;
+
+/// Register the devices every system has and probe for those the device
+/// tree describes, on the boot hart.
+pub fn init() {
+    plic::init();
+    plic::init_hart();
+    uart::init();
+    chardev::init();
+    virtio::probe();
+}
+
+/// Let a secondary hart take device interrupts.
+pub fn init_hart() {
+    plic::init_hart();
+}
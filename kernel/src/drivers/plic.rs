@@ -0,0 +1,201 @@
+//! The platform-level interrupt controller, which routes the interrupts of
+//! devices to the S-mode external interrupt of harts.
+//!
+//! Drivers register a handler for the source their device raises; a hart
+//! taking the external interrupt claims the source, runs its handler and
+//! completes it. Every source is enabled on every hart, and the claim makes
+//! sure only one of them handles it.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::ptr::{read_volatile, write_volatile};
+
+use log::{info, warn};
+use spin::{Mutex, Once};
+
+use crate::{
+    config::MAX_HART_COUNT,
+    cpu, dtb,
+    mm::{addr::PhysAddr, kernel_space},
+};
+
+const PRIORITY_BASE: usize = 0;
+const ENABLE_BASE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_BASE: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const CONTEXT_THRESHOLD: usize = 0;
+const CONTEXT_CLAIM: usize = 4;
+
+/// The most sources a PLIC can have; source 0 means none.
+const MAX_SOURCES: u32 = 1024;
+/// The cause of the S-mode external interrupt, as it appears in
+/// `interrupts-extended`.
+const IRQ_S_EXT: u32 = 9;
+
+pub type IrqHandler = Arc<dyn Fn() + Send + Sync>;
+
+struct Plic {
+    base: usize,
+    sources: u32,
+    /// The S-mode context of every hart.
+    contexts: [Option<usize>; MAX_HART_COUNT],
+}
+
+impl Plic {
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as *mut u32
+    }
+
+    fn set_priority(&self, irq: u32, priority: u32) {
+        unsafe { write_volatile(self.reg(PRIORITY_BASE + 4 * irq as usize), priority) }
+    }
+
+    fn set_enabled(&self, context: usize, irq: u32, enabled: bool) {
+        let reg = self.reg(ENABLE_BASE + ENABLE_STRIDE * context + 4 * (irq as usize / 32));
+        let bit = 1 << (irq % 32);
+        unsafe {
+            let word = read_volatile(reg);
+            write_volatile(reg, if enabled { word | bit } else { word & !bit });
+        }
+    }
+
+    fn set_threshold(&self, context: usize, threshold: u32) {
+        unsafe { write_volatile(self.reg(CONTEXT_BASE + CONTEXT_STRIDE * context + CONTEXT_THRESHOLD), threshold) }
+    }
+
+    fn claim(&self, context: usize) -> u32 {
+        unsafe { read_volatile(self.reg(CONTEXT_BASE + CONTEXT_STRIDE * context + CONTEXT_CLAIM)) }
+    }
+
+    fn complete(&self, context: usize, irq: u32) {
+        unsafe { write_volatile(self.reg(CONTEXT_BASE + CONTEXT_STRIDE * context + CONTEXT_CLAIM), irq) }
+    }
+
+    fn contexts(&self) -> impl Iterator<Item = usize> + '_ {
+        self.contexts.iter().filter_map(|context| *context)
+    }
+}
+
+static PLIC: Once<Plic> = Once::new();
+static HANDLERS: Mutex<BTreeMap<u32, IrqHandler>> = Mutex::new(BTreeMap::new());
+
+/// Find the PLIC in the device tree, map it and mask every source. Without
+/// one, handlers can still be registered but never run.
+pub fn init() {
+    let fdt = dtb::machine().fdt();
+    let Some(node) = fdt.find_compatible(&["riscv,plic0", "sifive,plic-1.0.0"]) else {
+        warn!("PLIC: not in the device tree, external interrupts disabled");
+        return;
+    };
+    let Some(reg) = node.reg().next() else {
+        warn!("PLIC: {} has no registers", node.name());
+        return;
+    };
+    let base = match kernel_space::map_mmio(PhysAddr(reg.start), reg.size) {
+        Ok(base) => base.0,
+        Err(e) => {
+            warn!("PLIC: failed to map {}: {}", node.name(), e);
+            return;
+        }
+    };
+    let sources = node
+        .property("riscv,ndev")
+        .and_then(|prop| prop.as_u32())
+        .map_or(MAX_SOURCES, |ndev| (ndev + 1).min(MAX_SOURCES));
+
+    let mut plic = Plic {
+        base,
+        sources,
+        contexts: [None; MAX_HART_COUNT],
+    };
+    let extended = node.property("interrupts-extended").map_or(&[][..], |prop| prop.value);
+    if extended.is_empty() {
+        // QEMU `virt` gives every hart an M-mode and then an S-mode context.
+        for &hart in dtb::machine().harts() {
+            plic.contexts[hart] = Some(2 * hart + 1);
+        }
+    } else {
+        // Pairs of the phandle of a hart's interrupt controller and the
+        // interrupt the context raises there, one pair per context.
+        let cells: Vec<u32> = extended
+            .chunks_exact(4)
+            .map(|cell| u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))
+            .collect();
+        for (context, pair) in cells.chunks_exact(2).enumerate() {
+            if pair[1] != IRQ_S_EXT {
+                continue;
+            }
+            let hart = fdt.cpus().find_map(|cpu| {
+                let controls = cpu.node().children().any(|child| child.phandle() == Some(pair[0]));
+                if controls { cpu.id() } else { None }
+            });
+            match hart {
+                Some(hart) if hart < MAX_HART_COUNT => plic.contexts[hart] = Some(context),
+                _ => {}
+            }
+        }
+    }
+
+    for irq in 1..plic.sources {
+        plic.set_priority(irq, 0);
+    }
+    for context in plic.contexts() {
+        plic.set_threshold(context, 1);
+        for irq in 1..plic.sources {
+            plic.set_enabled(context, irq, false);
+        }
+    }
+    info!("PLIC: {} sources at 0x{:x}", plic.sources - 1, reg.start);
+    PLIC.call_once(|| plic);
+}
+
+/// Let the current hart take the sources that have a handler.
+pub fn init_hart() {
+    let Some(plic) = PLIC.get() else {
+        return;
+    };
+    if let Some(context) = plic.contexts[cpu::hart_id()] {
+        plic.set_threshold(context, 0);
+    }
+}
+
+/// Run `handler` whenever source `irq` is raised, replacing any handler it
+/// had. The handler runs in the trap handler with interrupts disabled.
+pub fn register(irq: u32, handler: IrqHandler) {
+    HANDLERS.lock().insert(irq, handler);
+    let Some(plic) = PLIC.get() else {
+        return;
+    };
+    if irq == 0 || irq >= plic.sources {
+        warn!("PLIC: no source {}", irq);
+        return;
+    }
+    plic.set_priority(irq, 1);
+    for context in plic.contexts() {
+        plic.set_enabled(context, irq, true);
+    }
+}
+
+/// Handle the S-mode external interrupt of the current hart: run the
+/// handlers of every source it claims.
+pub fn handle_interrupt() {
+    let Some(plic) = PLIC.get() else {
+        return;
+    };
+    let Some(context) = plic.contexts[cpu::hart_id()] else {
+        return;
+    };
+    loop {
+        let irq = plic.claim(context);
+        if irq == 0 {
+            break;
+        }
+        // Not called with the lock held, so handlers may register others.
+        let handler = HANDLERS.lock().get(&irq).cloned();
+        match handler {
+            Some(handler) => handler(),
+            None => warn!("PLIC: source {} has no handler", irq),
+        }
+        plic.complete(context, irq);
+    }
+}
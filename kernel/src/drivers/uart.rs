@@ -0,0 +1,249 @@
+//! The NS16550A UART, the serial port of QEMU `virt` and of most boards.
+//!
+//! Received bytes are taken off the FIFO by the interrupt handler into a
+//! ring buffer. Written bytes are queued and fed to the transmitter as its
+//! FIFO empties. Without an interrupt, both directions are polled instead.
+
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    hint,
+    ptr::{read_volatile, write_volatile},
+};
+
+use log::{info, warn};
+use spin::{Mutex, Once};
+
+use crate::{
+    arch, dtb,
+    mm::{addr::PhysAddr, kernel_space},
+};
+
+use super::plic;
+
+/// Receive buffer when read, transmit holding register when written.
+const REG_DATA: usize = 0;
+const REG_IER: usize = 1;
+/// FIFO control, written.
+const REG_FCR: usize = 2;
+const REG_LCR: usize = 3;
+const REG_MCR: usize = 4;
+const REG_LSR: usize = 5;
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+/// Enable the FIFOs and clear the receive one. The transmit one may still
+/// hold what the firmware printed.
+const FCR_ENABLE_CLEAR_RX: u8 = 0x03;
+/// 8 data bits, no parity, 1 stop bit.
+const LCR_8N1: u8 = 0x03;
+/// DTR, RTS and OUT2, which gates the interrupt on PC-style ports.
+const MCR_DTR_RTS_OUT2: u8 = 0x0b;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_TX_EMPTY: u8 = 1 << 5;
+
+const FIFO_SIZE: usize = 16;
+/// Bytes received and not read yet; more are dropped.
+const RX_BUFFER_SIZE: usize = 1024;
+/// Bytes written and not sent yet; writers wait beyond that.
+const TX_BUFFER_SIZE: usize = 4096;
+
+struct Buffers {
+    rx: VecDeque<u8>,
+    tx: VecDeque<u8>,
+}
+
+pub struct Uart {
+    base: usize,
+    /// Registers are `1 << shift` bytes apart and `width` bytes wide.
+    shift: usize,
+    width: usize,
+    irq: Option<u32>,
+    buffers: Mutex<Buffers>,
+    /// Told when input arrives, from the interrupt handler.
+    input_handler: Once<fn()>,
+}
+
+impl Uart {
+    fn read_reg(&self, reg: usize) -> u8 {
+        let addr = self.base + (reg << self.shift);
+        unsafe {
+            match self.width {
+                4 => read_volatile(addr as *const u32) as u8,
+                _ => read_volatile(addr as *const u8),
+            }
+        }
+    }
+
+    fn write_reg(&self, reg: usize, value: u8) {
+        let addr = self.base + (reg << self.shift);
+        unsafe {
+            match self.width {
+                4 => write_volatile(addr as *mut u32, value as u32),
+                _ => write_volatile(addr as *mut u8, value),
+            }
+        }
+    }
+
+    fn setup(&self) {
+        self.write_reg(REG_IER, 0);
+        // The baud rate is left as the firmware set it.
+        self.write_reg(REG_LCR, LCR_8N1);
+        self.write_reg(REG_FCR, FCR_ENABLE_CLEAR_RX);
+        self.write_reg(REG_MCR, MCR_DTR_RTS_OUT2);
+        if self.irq.is_some() {
+            self.write_reg(REG_IER, IER_RX_AVAILABLE);
+        }
+    }
+
+    /// Take what the receiver has into the ring buffer, returning whether
+    /// there was anything.
+    fn receive(&self, buffers: &mut Buffers) -> bool {
+        let mut received = false;
+        while self.read_reg(REG_LSR) & LSR_DATA_READY != 0 {
+            let byte = self.read_reg(REG_DATA);
+            if buffers.rx.len() < RX_BUFFER_SIZE {
+                buffers.rx.push_back(byte);
+            }
+            received = true;
+        }
+        received
+    }
+
+    /// Fill the transmitter FIFO if it is empty, returning whether it was.
+    /// The transmit interrupt stays enabled while bytes are queued.
+    fn transmit(&self, buffers: &mut Buffers) -> bool {
+        let empty = self.read_reg(REG_LSR) & LSR_TX_EMPTY != 0;
+        if empty {
+            for _ in 0..FIFO_SIZE {
+                let Some(byte) = buffers.tx.pop_front() else {
+                    break;
+                };
+                self.write_reg(REG_DATA, byte);
+            }
+        }
+        if self.irq.is_some() {
+            let ier = if buffers.tx.is_empty() {
+                IER_RX_AVAILABLE
+            } else {
+                IER_RX_AVAILABLE | IER_TX_EMPTY
+            };
+            self.write_reg(REG_IER, ier);
+        }
+        empty
+    }
+
+    /// Queue `bytes` for sending. Without an interrupt to send them later,
+    /// they are sent before returning.
+    pub fn write(&self, bytes: &[u8]) {
+        arch::without_interrupts(|| {
+            let mut buffers = self.buffers.lock();
+            for &byte in bytes {
+                while buffers.tx.len() >= TX_BUFFER_SIZE {
+                    if !self.transmit(&mut buffers) {
+                        hint::spin_loop();
+                    }
+                }
+                buffers.tx.push_back(byte);
+            }
+            self.transmit(&mut buffers);
+            if self.irq.is_none() {
+                self.drain(&mut buffers);
+            }
+        });
+    }
+
+    /// Wait until every queued byte has gone to the transmitter.
+    pub fn flush(&self) {
+        arch::without_interrupts(|| self.drain(&mut self.buffers.lock()));
+    }
+
+    fn drain(&self, buffers: &mut Buffers) {
+        while !buffers.tx.is_empty() {
+            if !self.transmit(buffers) {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    /// The next byte received, if any.
+    pub fn getchar(&self) -> Option<u8> {
+        arch::without_interrupts(|| {
+            let mut buffers = self.buffers.lock();
+            self.receive(&mut buffers);
+            buffers.rx.pop_front()
+        })
+    }
+
+    /// Call `handler` whenever input arrives, returning whether it will be,
+    /// that is whether the UART has an interrupt. Only the first handler set
+    /// is kept.
+    pub fn set_input_handler(&self, handler: fn()) -> bool {
+        self.input_handler.call_once(|| handler);
+        self.irq.is_some()
+    }
+
+    fn handle_irq(&self) {
+        let received = {
+            let mut buffers = self.buffers.lock();
+            let received = self.receive(&mut buffers);
+            self.transmit(&mut buffers);
+            received
+        };
+        // Not called with the lock held, so the handler can read the input
+        // and echo it.
+        if let Some(handler) = self.input_handler.get().filter(|_| received) {
+            handler();
+        }
+    }
+}
+
+static UART: Once<Arc<Uart>> = Once::new();
+
+/// The UART, once [`init`] found one.
+pub fn get() -> Option<&'static Arc<Uart>> {
+    UART.get()
+}
+
+/// Take over the first UART in the device tree from the firmware.
+pub fn init() {
+    let fdt = dtb::machine().fdt();
+    let Some(node) = fdt
+        .compatible_nodes("ns16550a")
+        .chain(fdt.compatible_nodes("ns16550"))
+        .find(|node| node.is_available())
+    else {
+        warn!("UART: not in the device tree, staying on the SBI console");
+        return;
+    };
+    let Some(reg) = node.reg().next() else {
+        warn!("UART: {} has no registers", node.name());
+        return;
+    };
+    let base = match kernel_space::map_mmio(PhysAddr(reg.start), reg.size) {
+        Ok(base) => base.0,
+        Err(e) => {
+            warn!("UART: failed to map {}: {}", node.name(), e);
+            return;
+        }
+    };
+    let cell = |name: &str| node.property(name).and_then(|prop| prop.as_u32());
+    let uart = Arc::new(Uart {
+        base,
+        shift: cell("reg-shift").unwrap_or(0) as usize,
+        width: cell("reg-io-width").unwrap_or(1) as usize,
+        irq: node.interrupts().next(),
+        buffers: Mutex::new(Buffers {
+            rx: VecDeque::with_capacity(RX_BUFFER_SIZE),
+            tx: VecDeque::with_capacity(TX_BUFFER_SIZE),
+        }),
+        input_handler: Once::new(),
+    });
+    uart.setup();
+    if let Some(irq) = uart.irq {
+        let handler = uart.clone();
+        plic::register(irq, Arc::new(move || handler.handle_irq()));
+    }
+    let irq = uart.irq;
+    UART.call_once(|| uart);
+    info!("UART: {} at 0x{:x}, irq {:?}", node.name(), reg.start, irq);
+}
@@ -0,0 +1,285 @@
+//! virtio-blk, the block device QEMU attaches with
+//! `-device virtio-blk-device`.
+
+use core::{
+    hint,
+    ptr::{read_volatile, write_volatile},
+};
+
+use log::{info, warn};
+use spin::Mutex;
+
+use crate::{
+    drivers::block::BlockDevice,
+    fs::{FsError, FsResult},
+    mm::{
+        addr::{kva2pa, PhysPageNum, VirtAddr},
+        frame,
+    },
+    sched::WaitQueue,
+    task,
+};
+
+use super::{
+    mmio::MmioTransport,
+    queue::{Buffer, VirtQueue},
+    VirtioError, VirtioResult, F_VERSION_1,
+};
+
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+const CONFIG_CAPACITY: usize = 0;
+
+const REQ_IN: u32 = 0;
+const REQ_OUT: u32 = 1;
+const REQ_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+
+/// Entries of the request queue. Every request takes two descriptors, or
+/// three with data.
+const QUEUE_SIZE: u16 = 128;
+const SECTOR_SIZE: usize = 512;
+/// Length of a request header: type, reserved and sector.
+const HEADER_SIZE: usize = 16;
+/// Where the status bytes of requests start in the header frame.
+const STATUS_OFFSET: usize = HEADER_SIZE * QUEUE_SIZE as usize;
+
+/// Where a request, known by the head of its descriptor chain, is.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Free,
+    Submitted,
+    /// The device is done, with this status.
+    Done(u8),
+}
+
+struct Inner {
+    queue: VirtQueue,
+    slots: [Slot; QUEUE_SIZE as usize],
+}
+
+impl Inner {
+    /// Take the requests the device is done with off the used ring.
+    fn complete(&mut self, headers: PhysPageNum) {
+        while let Some((head, _)) = self.queue.pop_used() {
+            let status = unsafe { read_volatile(headers.kva().as_ptr::<u8>().add(STATUS_OFFSET + head as usize)) };
+            self.slots[head as usize] = Slot::Done(status);
+        }
+    }
+}
+
+/// A virtio block device with a single request queue.
+///
+/// Requests are completed from the used ring, by the interrupt handler or
+/// by whoever waits for them. Tasks sleep until the interrupt wakes them;
+/// outside of tasks there is nothing to sleep on, and the used ring is
+/// polled instead.
+pub struct VirtioBlk {
+    transport: MmioTransport,
+    irq: Option<u32>,
+    /// Sectors of 512 bytes.
+    capacity: u64,
+    read_only: bool,
+    flush: bool,
+    /// A frame with the header and status byte of every request, indexed by
+    /// the head of its chain.
+    headers: PhysPageNum,
+    inner: Mutex<Inner>,
+    /// Woken when requests complete or free their descriptors.
+    done: WaitQueue,
+}
+
+impl VirtioBlk {
+    pub fn new(transport: MmioTransport, irq: Option<u32>) -> VirtioResult<Self> {
+        let features = transport.begin_init(F_RO | F_FLUSH | F_VERSION_1)?;
+        let size = QUEUE_SIZE.min(transport.queue_max(0));
+        if size == 0 || !size.is_power_of_two() {
+            transport.fail();
+            return Err(VirtioError::Unsupported);
+        }
+        let setup = VirtQueue::new(0, size).and_then(|queue| {
+            transport.set_queue(&queue)?;
+            let headers = frame::alloc().ok_or(VirtioError::OutOfMemory)?;
+            Ok((queue, headers))
+        });
+        let (queue, headers) = match setup {
+            Ok(setup) => setup,
+            Err(e) => {
+                transport.fail();
+                return Err(e);
+            }
+        };
+        transport.finish_init();
+        let blk = Self {
+            capacity: transport.config(CONFIG_CAPACITY, 8),
+            transport,
+            irq,
+            read_only: features & F_RO != 0,
+            flush: features & F_FLUSH != 0,
+            headers,
+            inner: Mutex::new(Inner {
+                queue,
+                slots: [Slot::Free; QUEUE_SIZE as usize],
+            }),
+            done: WaitQueue::new(),
+        };
+        info!(
+            "virtio-blk: {} MiB{}, irq {:?}",
+            blk.capacity * SECTOR_SIZE as u64 / 1024 / 1024,
+            if blk.read_only { ", read-only" } else { "" },
+            irq
+        );
+        Ok(blk)
+    }
+
+    /// The interrupt the device raises when it has used buffers.
+    pub fn irq(&self) -> Option<u32> {
+        self.irq
+    }
+
+    /// Acknowledge the interrupt and complete the requests it signals.
+    pub fn handle_irq(&self) {
+        self.transport.ack_interrupt();
+        self.inner.lock().complete(self.headers);
+        self.done.wake_all();
+    }
+
+    /// Wait until `ready` holds, completing requests before every check.
+    fn wait_until(&self, mut ready: impl FnMut(&mut Inner) -> bool) {
+        let mut check = || {
+            let mut inner = self.inner.lock();
+            inner.complete(self.headers);
+            ready(&mut inner)
+        };
+        if task::try_current().is_some() {
+            self.done.wait_until(check);
+        } else {
+            while !check() {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    /// Queue a request and return the head of its chain, waiting for room
+    /// in the queue.
+    fn submit(&self, kind: u32, sector: u64, data: Option<Buffer>) -> u16 {
+        let needed = if data.is_some() { 3 } else { 2 };
+        let mut head = 0;
+        // Room is made by waiters freeing their requests.
+        self.wait_until(|inner| {
+            if inner.queue.free_count() < needed {
+                return false;
+            }
+            head = self.push(inner, kind, sector, data);
+            true
+        });
+        head
+    }
+
+    /// Add a request to the queue, which has room for it.
+    fn push(&self, inner: &mut Inner, kind: u32, sector: u64, data: Option<Buffer>) -> u16 {
+        // The header and status go where the head of the chain points to,
+        // so they are written before the chain is added.
+        let head = inner.queue.next_head();
+        let header_offset = HEADER_SIZE * head as usize;
+        let status_offset = STATUS_OFFSET + head as usize;
+        let base = self.headers.kva().0;
+        unsafe {
+            write_volatile((base + header_offset) as *mut u32, kind);
+            write_volatile((base + header_offset + 4) as *mut u32, 0);
+            write_volatile((base + header_offset + 8) as *mut u64, sector);
+            write_volatile((base + status_offset) as *mut u8, 0xff);
+        }
+        let header = Buffer {
+            pa: self.headers.addr().0 + header_offset,
+            len: HEADER_SIZE,
+            writable: false,
+        };
+        let status = Buffer {
+            pa: self.headers.addr().0 + status_offset,
+            len: 1,
+            writable: true,
+        };
+        let added = match data {
+            Some(data) => inner.queue.add(&[header, data, status]),
+            None => inner.queue.add(&[header, status]),
+        };
+        debug_assert_eq!(added, Some(head));
+        inner.slots[head as usize] = Slot::Submitted;
+        if inner.queue.should_notify() {
+            self.transport.notify(0);
+        }
+        head
+    }
+
+    /// Wait for the request at `head` to complete and free it.
+    fn wait(&self, head: u16) -> FsResult<()> {
+        let mut status = STATUS_OK;
+        self.wait_until(|inner| match inner.slots[head as usize] {
+            Slot::Done(done) => {
+                status = done;
+                inner.slots[head as usize] = Slot::Free;
+                inner.queue.recycle(head);
+                true
+            }
+            _ => false,
+        });
+        // Its descriptors may be what a submitter waits for.
+        self.done.wake_all();
+        match status {
+            STATUS_OK => Ok(()),
+            status => {
+                warn!("virtio-blk: request failed with status {}", status);
+                Err(FsError::Io)
+            }
+        }
+    }
+
+    /// Transfer the sectors from `sector` on to or from `data`, which must
+    /// be kernel memory in the direct map.
+    fn transfer(&self, kind: u32, sector: u64, data: usize, len: usize) -> FsResult<()> {
+        if len % SECTOR_SIZE != 0 || sector + (len / SECTOR_SIZE) as u64 > self.capacity {
+            return Err(FsError::InvalidInput);
+        }
+        let buffer = Buffer {
+            pa: kva2pa(VirtAddr(data)).0,
+            len,
+            writable: kind == REQ_IN,
+        };
+        let head = self.submit(kind, sector, Some(buffer));
+        self.wait(head)
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn num_blocks(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read_blocks(&self, start: u64, buf: &mut [u8]) -> FsResult<()> {
+        self.transfer(REQ_IN, start, buf.as_mut_ptr() as usize, buf.len())
+    }
+
+    fn write_blocks(&self, start: u64, buf: &[u8]) -> FsResult<()> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        self.transfer(REQ_OUT, start, buf.as_ptr() as usize, buf.len())
+    }
+
+    fn flush(&self) -> FsResult<()> {
+        if !self.flush {
+            return Ok(());
+        }
+        let head = self.submit(REQ_FLUSH, 0, None);
+        self.wait(head)
+    }
+}
+
+impl Drop for VirtioBlk {
+    fn drop(&mut self) {
+        frame::dealloc(self.headers);
+    }
+}
@@ -0,0 +1,199 @@
+//! The virtio-mmio transport, in both its legacy (version 1) and modern
+//! (version 2) register layouts.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use super::{queue::VirtQueue, VirtioError, VirtioResult, F_VERSION_1};
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+const REG_GUEST_PAGE_SIZE: usize = 0x028;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03c;
+const REG_QUEUE_PFN: usize = 0x040;
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_QUEUE_DESC: usize = 0x080;
+const REG_QUEUE_DRIVER: usize = 0x090;
+const REG_QUEUE_DEVICE: usize = 0x0a0;
+const REG_CONFIG_GENERATION: usize = 0x0fc;
+const REG_CONFIG: usize = 0x100;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+/// Legacy devices locate queues by page frame number, in pages of this size.
+const LEGACY_PAGE_SIZE: u32 = 4096;
+
+/// The registers of one virtio-mmio device.
+pub struct MmioTransport {
+    /// Kernel address the registers are mapped at.
+    base: usize,
+    version: u32,
+    device_id: u32,
+}
+
+impl MmioTransport {
+    /// The device whose registers are mapped at `base`. Slots without a
+    /// device behind them have device id 0.
+    pub fn new(base: usize) -> VirtioResult<Self> {
+        let mut transport = Self {
+            base,
+            version: 0,
+            device_id: 0,
+        };
+        if transport.read(REG_MAGIC) != MAGIC {
+            return Err(VirtioError::NotVirtio);
+        }
+        transport.version = transport.read(REG_VERSION);
+        if !matches!(transport.version, 1 | 2) {
+            return Err(VirtioError::NotVirtio);
+        }
+        transport.device_id = transport.read(REG_DEVICE_ID);
+        Ok(transport)
+    }
+
+    pub fn device_id(&self) -> u32 {
+        self.device_id
+    }
+
+    fn is_legacy(&self) -> bool {
+        self.version == 1
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    fn add_status(&self, status: u32) {
+        self.write(REG_STATUS, self.read(REG_STATUS) | status);
+    }
+
+    /// Reset the device and agree on the features of `supported` it offers,
+    /// which are returned. Queues are set up next, then [`Self::finish_init`].
+    pub fn begin_init(&self, supported: u64) -> VirtioResult<u64> {
+        self.write(REG_STATUS, 0);
+        self.add_status(STATUS_ACKNOWLEDGE);
+        self.add_status(STATUS_DRIVER);
+
+        let mut offered = 0;
+        for half in 0..2 {
+            self.write(REG_DEVICE_FEATURES_SEL, half);
+            offered |= (self.read(REG_DEVICE_FEATURES) as u64) << (32 * half);
+        }
+        let mut features = offered & supported;
+        if self.is_legacy() {
+            features &= !F_VERSION_1;
+        } else if features & F_VERSION_1 == 0 {
+            self.add_status(STATUS_FAILED);
+            return Err(VirtioError::Unsupported);
+        }
+        for half in 0..2 {
+            self.write(REG_DRIVER_FEATURES_SEL, half);
+            self.write(REG_DRIVER_FEATURES, (features >> (32 * half)) as u32);
+        }
+
+        if self.is_legacy() {
+            self.write(REG_GUEST_PAGE_SIZE, LEGACY_PAGE_SIZE);
+        } else {
+            self.add_status(STATUS_FEATURES_OK);
+            if self.read(REG_STATUS) & STATUS_FEATURES_OK == 0 {
+                self.add_status(STATUS_FAILED);
+                return Err(VirtioError::Rejected);
+            }
+        }
+        Ok(features)
+    }
+
+    /// Let the device start using its queues.
+    pub fn finish_init(&self) {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+
+    /// Give up on the device after a failed initialization.
+    pub fn fail(&self) {
+        self.add_status(STATUS_FAILED);
+    }
+
+    /// The most entries queue `index` can have, 0 if it does not exist.
+    pub fn queue_max(&self, index: u16) -> u16 {
+        self.write(REG_QUEUE_SEL, index as u32);
+        self.read(REG_QUEUE_NUM_MAX).min(u16::MAX as u32) as u16
+    }
+
+    /// Tell the device where `queue` is.
+    pub fn set_queue(&self, queue: &VirtQueue) -> VirtioResult<()> {
+        self.write(REG_QUEUE_SEL, queue.index() as u32);
+        if !self.is_legacy() && self.read(REG_QUEUE_READY) != 0 {
+            return Err(VirtioError::Rejected);
+        }
+        if self.read(REG_QUEUE_NUM_MAX) < queue.size() as u32 {
+            return Err(VirtioError::Rejected);
+        }
+        self.write(REG_QUEUE_NUM, queue.size() as u32);
+        if self.is_legacy() {
+            self.write(REG_QUEUE_ALIGN, LEGACY_PAGE_SIZE);
+            self.write(REG_QUEUE_PFN, (queue.desc_pa() / LEGACY_PAGE_SIZE as usize) as u32);
+        } else {
+            let write_u64 = |offset: usize, value: usize| {
+                self.write(offset, value as u32);
+                self.write(offset + 4, (value >> 32) as u32);
+            };
+            write_u64(REG_QUEUE_DESC, queue.desc_pa());
+            write_u64(REG_QUEUE_DRIVER, queue.avail_pa());
+            write_u64(REG_QUEUE_DEVICE, queue.used_pa());
+            self.write(REG_QUEUE_READY, 1);
+        }
+        Ok(())
+    }
+
+    /// Tell the device queue `index` has new buffers.
+    pub fn notify(&self, index: u16) {
+        self.write(REG_QUEUE_NOTIFY, index as u32);
+    }
+
+    /// Acknowledge the pending interrupt, returning its cause bits.
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.read(REG_INTERRUPT_STATUS);
+        if status != 0 {
+            self.write(REG_INTERRUPT_ACK, status);
+        }
+        status
+    }
+
+    fn config_u8(&self, offset: usize) -> u8 {
+        unsafe { read_volatile((self.base + REG_CONFIG + offset) as *const u8) }
+    }
+
+    /// Read the little-endian integer of `len` bytes at `offset` of the
+    /// device specific configuration. Modern devices are read again if the
+    /// configuration changed meanwhile.
+    pub fn config(&self, offset: usize, len: usize) -> u64 {
+        loop {
+            let generation = if self.is_legacy() { 0 } else { self.read(REG_CONFIG_GENERATION) };
+            let value = (0..len).fold(0, |value, i| value | (self.config_u8(offset + i) as u64) << (8 * i));
+            if self.is_legacy() || self.read(REG_CONFIG_GENERATION) == generation {
+                return value;
+            }
+        }
+    }
+}
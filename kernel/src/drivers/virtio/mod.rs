@@ -0,0 +1,91 @@
+//! Virtio devices behind the virtio-mmio transport, as QEMU `virt` has
+//! them. Devices are found in the device tree; block devices are
+//! registered as `vda`, `vdb` and so on, in the order of the tree.
+
+mod blk;
+mod mmio;
+mod queue;
+
+use alloc::{format, sync::Arc};
+use core::fmt::{self, Display};
+use log::{debug, warn};
+
+use crate::{
+    dtb,
+    mm::{addr::PhysAddr, kernel_space},
+};
+
+use self::{blk::VirtioBlk, mmio::MmioTransport};
+
+use super::{block, plic};
+
+/// The device follows the virtio 1.0 specification rather than the legacy
+/// interface. Only negotiated over the modern transport.
+const F_VERSION_1: u64 = 1 << 32;
+
+const DEVICE_BLOCK: u32 = 2;
+
+/// Errors of setting up a virtio device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VirtioError {
+    /// The registers are not those of a virtio device this driver knows.
+    NotVirtio,
+    /// The device lacks something the driver needs.
+    Unsupported,
+    /// The device did not accept the features or queues offered.
+    Rejected,
+    /// No frames were left for the queues.
+    OutOfMemory,
+}
+
+pub type VirtioResult<T> = Result<T, VirtioError>;
+
+impl Display for VirtioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            VirtioError::NotVirtio => "not a virtio device",
+            VirtioError::Unsupported => "device not supported",
+            VirtioError::Rejected => "rejected by the device",
+            VirtioError::OutOfMemory => "out of memory",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// Set up every virtio-mmio device in the device tree that has a driver.
+pub fn probe() {
+    let mut disks = 0;
+    for device in dtb::machine().devices("virtio,mmio") {
+        let transport = kernel_space::map_mmio(PhysAddr(device.base), device.size)
+            .map_err(|e| warn!("virtio: failed to map {}: {}", device.name, e))
+            .ok()
+            .and_then(|base| match MmioTransport::new(base.0) {
+                Ok(transport) => Some(transport),
+                Err(e) => {
+                    warn!("virtio: {}: {}", device.name, e);
+                    None
+                }
+            });
+        let Some(transport) = transport else {
+            continue;
+        };
+        match transport.device_id() {
+            // An empty slot.
+            0 => {}
+            DEVICE_BLOCK => match VirtioBlk::new(transport, device.irq) {
+                Ok(blk) => {
+                    let blk = Arc::new(blk);
+                    if let Some(irq) = blk.irq() {
+                        let blk = blk.clone();
+                        plic::register(irq, Arc::new(move || blk.handle_irq()));
+                    }
+                    let name = format!("vd{}", (b'a' + disks) as char);
+                    block::register(&name, blk);
+                    disks += 1;
+                }
+                Err(e) => warn!("virtio-blk: {}: {}", device.name, e),
+            },
+            id => debug!("virtio: {}: no driver for device {}", device.name, id),
+        }
+    }
+}
@@ -0,0 +1,247 @@
+//! Split virtqueues, laid out as legacy devices require so that both
+//! transport versions can use them.
+
+use core::{
+    mem::size_of,
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{fence, Ordering},
+};
+
+use crate::mm::{
+    addr::PhysPageNum,
+    consts::PAGE_SIZE,
+    frame,
+};
+
+use super::{VirtioError, VirtioResult};
+
+const DESC_F_NEXT: u16 = 1;
+/// The device writes the buffer rather than reads it.
+const DESC_F_WRITE: u16 = 2;
+/// The device does not need to be notified of new buffers.
+const USED_F_NO_NOTIFY: u16 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// A buffer handed to the device: its physical address, its length, and
+/// whether the device writes it.
+#[derive(Clone, Copy)]
+pub struct Buffer {
+    pub pa: usize,
+    pub len: usize,
+    pub writable: bool,
+}
+
+/// The descriptor table, available ring and used ring of one queue, in
+/// physically contiguous frames.
+///
+/// Chains the device has used stay allocated until [`VirtQueue::recycle`],
+/// so their head can identify a request until its submitter has seen it
+/// complete.
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    frames: PhysPageNum,
+    frame_count: usize,
+    /// Offsets of the rings from the start of the frames.
+    avail_offset: usize,
+    used_offset: usize,
+    /// The first free descriptor, chained through `next`.
+    free_head: u16,
+    free_count: u16,
+    /// Where the next available entry goes, and the next used entry to look
+    /// at, both counting from 0 and wrapping at `u16::MAX`.
+    avail_idx: u16,
+    last_used: u16,
+}
+
+// The queue memory is only reached through the queue, which its owner
+// locks.
+unsafe impl Send for VirtQueue {}
+
+impl VirtQueue {
+    /// Allocate queue `index` with `size` entries, a power of two.
+    pub fn new(index: u16, size: u16) -> VirtioResult<Self> {
+        debug_assert!(size.is_power_of_two());
+        let n = size as usize;
+        let avail_offset = size_of::<Descriptor>() * n;
+        // Flags, index, ring and `used_event`.
+        let avail_len = 2 * (3 + n);
+        let used_offset = (avail_offset + avail_len).next_multiple_of(PAGE_SIZE);
+        let used_len = 2 * 3 + size_of::<UsedElem>() * n;
+        let frame_count = (used_offset + used_len).div_ceil(PAGE_SIZE).next_power_of_two();
+        let frames = frame::alloc_frames(frame_count, frame_count).ok_or(VirtioError::OutOfMemory)?;
+        let mut queue = Self {
+            index,
+            size,
+            frames,
+            frame_count,
+            avail_offset,
+            used_offset,
+            free_head: 0,
+            free_count: size,
+            avail_idx: 0,
+            last_used: 0,
+        };
+        for i in 0..size {
+            queue.set_desc(i, Descriptor {
+                addr: 0,
+                len: 0,
+                flags: 0,
+                next: (i + 1) % size,
+            });
+        }
+        Ok(queue)
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn desc_pa(&self) -> usize {
+        self.frames.addr().0
+    }
+
+    pub fn avail_pa(&self) -> usize {
+        self.desc_pa() + self.avail_offset
+    }
+
+    pub fn used_pa(&self) -> usize {
+        self.desc_pa() + self.used_offset
+    }
+
+    /// Descriptors not in any chain.
+    pub fn free_count(&self) -> u16 {
+        self.free_count
+    }
+
+    /// The head the next chain added gets.
+    pub fn next_head(&self) -> u16 {
+        self.free_head
+    }
+
+    fn base(&self) -> usize {
+        self.frames.kva().0
+    }
+
+    fn desc_ptr(&self, i: u16) -> *mut Descriptor {
+        (self.base() + size_of::<Descriptor>() * i as usize) as *mut Descriptor
+    }
+
+    fn desc(&self, i: u16) -> Descriptor {
+        unsafe { read_volatile(self.desc_ptr(i)) }
+    }
+
+    fn set_desc(&mut self, i: u16, desc: Descriptor) {
+        unsafe { write_volatile(self.desc_ptr(i), desc) }
+    }
+
+    /// The 16-bit field at `offset` of the rings.
+    fn ring_u16(&self, offset: usize) -> *mut u16 {
+        (self.base() + offset) as *mut u16
+    }
+
+    /// Put a chain of `buffers` on the available ring, returning its head,
+    /// or `None` if there are not enough free descriptors. The device still
+    /// has to be notified.
+    pub fn add(&mut self, buffers: &[Buffer]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.free_count as usize {
+            return None;
+        }
+        let head = self.free_head;
+        let mut i = head;
+        for (n, buffer) in buffers.iter().enumerate() {
+            let next = self.desc(i).next;
+            let mut flags = if buffer.writable { DESC_F_WRITE } else { 0 };
+            if n + 1 < buffers.len() {
+                flags |= DESC_F_NEXT;
+            }
+            self.set_desc(i, Descriptor {
+                addr: buffer.pa as u64,
+                len: buffer.len as u32,
+                flags,
+                next,
+            });
+            if n + 1 < buffers.len() {
+                i = next;
+            } else {
+                self.free_head = next;
+            }
+        }
+        self.free_count -= buffers.len() as u16;
+
+        let slot = self.avail_idx % self.size;
+        unsafe {
+            write_volatile(self.ring_u16(self.avail_offset + 4 + 2 * slot as usize), head);
+        }
+        // The entry must be visible before the index that publishes it.
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe { write_volatile(self.ring_u16(self.avail_offset + 2), self.avail_idx) };
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+
+    /// Whether the device wants to be notified of new buffers.
+    pub fn should_notify(&self) -> bool {
+        unsafe { read_volatile(self.ring_u16(self.used_offset)) & USED_F_NO_NOTIFY == 0 }
+    }
+
+    /// The head of the next chain the device is done with, and how many
+    /// bytes it wrote.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        let used_idx = unsafe { read_volatile(self.ring_u16(self.used_offset + 2)) };
+        if used_idx == self.last_used {
+            return None;
+        }
+        // Read the entry only after seeing the index that publishes it.
+        fence(Ordering::SeqCst);
+        let slot = (self.last_used % self.size) as usize;
+        let elem = unsafe {
+            read_volatile((self.base() + self.used_offset + 4 + size_of::<UsedElem>() * slot) as *const UsedElem)
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((elem.id as u16, elem.len))
+    }
+
+    /// Return the chain starting at `head`, which the device has used, to
+    /// the free descriptors.
+    pub fn recycle(&mut self, head: u16) {
+        let mut tail = head;
+        let mut count = 1;
+        while self.desc(tail).flags & DESC_F_NEXT != 0 {
+            tail = self.desc(tail).next;
+            count += 1;
+        }
+        let mut desc = self.desc(tail);
+        desc.flags = 0;
+        desc.next = self.free_head;
+        self.set_desc(tail, desc);
+        self.free_head = head;
+        self.free_count += count;
+    }
+}
+
+impl Drop for VirtQueue {
+    fn drop(&mut self) {
+        frame::dealloc_frames(self.frames, self.frame_count);
+    }
+}
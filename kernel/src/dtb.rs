@@ -0,0 +1,214 @@
+//! Machine description parsed from the device tree.
+//!
+//! The boot hart receives the physical address of the device tree blob in `a1`.
+//! It is parsed once during early boot, before the heap is usable, so
+//! everything is kept in fixed-size arrays.
+
+use fdt::{Fdt, MemRegion};
+use log::{info, warn};
+use spin::Once;
+
+use crate::{
+    config::MAX_HART_COUNT,
+    mm::addr::{pa2kva, PhysAddr},
+};
+
+const MAX_MEMORY_REGIONS: usize = 8;
+const MAX_RESERVED_REGIONS: usize = 16;
+
+static MACHINE: Once<MachineInfo> = Once::new();
+
+pub struct MachineInfo {
+    fdt: Fdt<'static>,
+    memory: Regions<MAX_MEMORY_REGIONS>,
+    reserved: Regions<MAX_RESERVED_REGIONS>,
+    harts: [usize; MAX_HART_COUNT],
+    hart_count: usize,
+    timebase_frequency: Option<usize>,
+    bootargs: Option<&'static str>,
+}
+
+/// A device with memory-mapped registers, as the device tree describes it.
+#[derive(Clone, Copy, Debug)]
+pub struct MmioDevice {
+    pub name: &'static str,
+    /// Physical address of the registers.
+    pub base: usize,
+    pub size: usize,
+    /// The interrupt it raises, if it has one.
+    pub irq: Option<u32>,
+}
+
+#[derive(Clone, Copy)]
+struct Regions<const N: usize> {
+    regions: [MemRegion; N],
+    len: usize,
+}
+
+impl<const N: usize> Regions<N> {
+    const fn new() -> Self {
+        Self {
+            regions: [MemRegion { start: 0, size: 0 }; N],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, region: MemRegion) {
+        if region.size == 0 {
+            return;
+        }
+        if self.len == N {
+            warn!(
+                "Too many memory regions, ignoring 0x{:x} - 0x{:x}",
+                region.start,
+                region.end()
+            );
+            return;
+        }
+        self.regions[self.len] = region;
+        self.len += 1;
+    }
+
+    fn as_slice(&self) -> &[MemRegion] {
+        &self.regions[..self.len]
+    }
+
+    fn sort(&mut self) {
+        self.regions[..self.len].sort_unstable_by_key(|region| region.start);
+    }
+}
+
+impl MachineInfo {
+    fn parse(fdt: Fdt<'static>, dtb_pa: usize) -> Self {
+        let mut memory = Regions::new();
+        fdt.memory().for_each(|region| memory.push(region));
+        memory.sort();
+
+        let mut reserved = Regions::new();
+        reserved.push(MemRegion {
+            start: dtb_pa,
+            size: fdt.total_size(),
+        });
+        fdt.mem_reservations()
+            .chain(fdt.reserved_memory())
+            .for_each(|region| reserved.push(region));
+        reserved.sort();
+
+        let mut harts = [0; MAX_HART_COUNT];
+        let mut hart_count = 0;
+        for id in fdt
+            .cpus()
+            .filter(|cpu| cpu.is_available())
+            .filter_map(|cpu| cpu.id())
+        {
+            if id >= MAX_HART_COUNT {
+                warn!("Hart {} exceeds MAX_HART_COUNT, ignored.", id);
+                continue;
+            }
+            harts[hart_count] = id;
+            hart_count += 1;
+        }
+
+        Self {
+            fdt,
+            memory,
+            reserved,
+            harts,
+            hart_count,
+            timebase_frequency: fdt.timebase_frequency(),
+            bootargs: fdt.chosen().and_then(|chosen| chosen.bootargs()),
+        }
+    }
+
+    pub fn fdt(&self) -> &Fdt<'static> {
+        &self.fdt
+    }
+
+    /// Physical memory ranges, sorted by start address.
+    pub fn memory(&self) -> &[MemRegion] {
+        self.memory.as_slice()
+    }
+
+    /// Ranges that must not be handed out, sorted by start address.
+    ///
+    /// This covers `/memreserve/` entries, `/reserved-memory` children and
+    /// the device tree blob itself.
+    pub fn reserved(&self) -> &[MemRegion] {
+        self.reserved.as_slice()
+    }
+
+    /// End of the highest physical memory range.
+    pub fn memory_end(&self) -> usize {
+        self.memory().iter().map(|r| r.end()).max().unwrap_or(0)
+    }
+
+    /// Ids of all available harts.
+    pub fn harts(&self) -> &[usize] {
+        &self.harts[..self.hart_count]
+    }
+
+    pub fn hart_count(&self) -> usize {
+        self.hart_count
+    }
+
+    pub fn timebase_frequency(&self) -> Option<usize> {
+        self.timebase_frequency
+    }
+
+    pub fn bootargs(&self) -> Option<&'static str> {
+        self.bootargs
+    }
+
+    /// Available devices compatible with `compatible`, in tree order.
+    /// Devices without registers are left out.
+    pub fn devices<'b>(&self, compatible: &'b str) -> impl Iterator<Item = MmioDevice> + 'b {
+        self.fdt
+            .compatible_nodes(compatible)
+            .filter(|node| node.is_available())
+            .filter_map(|node| {
+                let reg = node.reg().next()?;
+                Some(MmioDevice {
+                    name: node.name(),
+                    base: reg.start,
+                    size: reg.size,
+                    irq: node.interrupts().next(),
+                })
+            })
+    }
+}
+
+/// Parse the device tree at `dtb_pa`.
+///
+/// This function should be called only once, by the boot hart.
+pub fn init(dtb_pa: usize) {
+    let ptr = pa2kva(PhysAddr(dtb_pa)).as_ptr::<u8>();
+    let fdt = unsafe { Fdt::from_ptr(ptr) }
+        .unwrap_or_else(|e| panic!("Invalid device tree at 0x{:x}: {:?}", dtb_pa, e));
+    let machine = MACHINE.call_once(|| MachineInfo::parse(fdt, dtb_pa));
+    print_machine_info(machine);
+}
+
+/// The machine description. Panics if called before [`init`].
+pub fn machine() -> &'static MachineInfo {
+    MACHINE.get().expect("Device tree not parsed yet")
+}
+
+fn print_machine_info(machine: &MachineInfo) {
+    for region in machine.memory() {
+        info!(
+            "Memory: 0x{:x} - 0x{:x} ({} MiB)",
+            region.start,
+            region.end(),
+            region.size / 1024 / 1024
+        );
+    }
+    for region in machine.reserved() {
+        info!("Reserved: 0x{:x} - 0x{:x}", region.start, region.end());
+    }
+    info!(
+        "Harts: {:?}, timebase frequency: {:?}, bootargs: {:?}",
+        machine.harts(),
+        machine.timebase_frequency(),
+        machine.bootargs()
+    );
+}
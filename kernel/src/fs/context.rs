@@ -0,0 +1,84 @@
+use alloc::sync::Arc;
+
+use super::{mount, Dentry, File, FsError, FsResult, InodeFile, InodeType, OpenFlags, PathWalker};
+
+/// The root and working directories of a process, shared by processes
+/// created with `CLONE_FS`.
+#[derive(Clone)]
+pub struct FsContext {
+    pub root: Arc<Dentry>,
+    pub cwd: Arc<Dentry>,
+    /// Permission bits cleared from the mode of created files.
+    pub umask: u32,
+}
+
+impl FsContext {
+    /// Both directories at the system root.
+    pub fn new() -> Self {
+        let root = mount::root();
+        Self {
+            cwd: root.clone(),
+            root,
+            umask: 0o022,
+        }
+    }
+
+    pub fn walker(&self) -> PathWalker {
+        PathWalker::new(&self.root)
+    }
+
+    /// Look `path` up relative to `base`, or to the working directory.
+    pub fn lookup(&self, base: Option<&Arc<Dentry>>, path: &str, follow: bool) -> FsResult<Arc<Dentry>> {
+        self.walker().walk(base.unwrap_or(&self.cwd), path, follow)
+    }
+
+    /// Open `path` relative to `base`, or to the working directory.
+    /// Files created with [`OpenFlags::CREAT`] get permissions `mode`
+    /// minus the umask.
+    pub fn open(&self, base: Option<&Arc<Dentry>>, path: &str, flags: OpenFlags, mode: u32) -> FsResult<Arc<dyn File>> {
+        let base = base.unwrap_or(&self.cwd);
+        let mut walker = self.walker();
+        let follow = !flags.contains(OpenFlags::NOFOLLOW);
+        let dentry = if flags.contains(OpenFlags::CREAT) {
+            // With O_EXCL, even a dangling symbolic link counts as existing.
+            let follow = follow && !flags.contains(OpenFlags::EXCL);
+            let (dir, name) = walker.walk_create(base, path, follow)?;
+            if matches!(name.as_str(), "." | "..") || path.ends_with('/') {
+                return Err(FsError::IsDir);
+            }
+            match walker.step(&dir, &name, false) {
+                Ok(_) if flags.contains(OpenFlags::EXCL) => return Err(FsError::AlreadyExists),
+                Ok(dentry) => dentry,
+                Err(FsError::NotFound) => dir.create(&name, InodeType::File, mode & 0o7777 & !self.umask)?,
+                Err(e) => return Err(e),
+            }
+        } else {
+            walker.walk(base, path, follow)?
+        };
+
+        match dentry.kind() {
+            InodeType::Symlink if !flags.contains(OpenFlags::PATH) => return Err(FsError::SymlinkLoop),
+            InodeType::Dir if flags.writable() => return Err(FsError::IsDir),
+            kind if kind != InodeType::Dir && flags.contains(OpenFlags::DIRECTORY) => {
+                return Err(FsError::NotDir)
+            }
+            _ => {}
+        }
+        if flags.contains(OpenFlags::PATH) {
+            return Ok(Arc::new(InodeFile::new(dentry, flags)));
+        }
+        if flags.contains(OpenFlags::TRUNC) && flags.writable() && dentry.kind() == InodeType::File {
+            dentry.inode().truncate(0)?;
+        }
+        match dentry.inode().open(flags)? {
+            Some(file) => Ok(file),
+            None => Ok(Arc::new(InodeFile::new(dentry, flags))),
+        }
+    }
+}
+
+impl Default for FsContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,228 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::Mutex;
+
+use super::{FsError, FsResult, Inode, InodeType};
+
+/// A name in the directory tree, caching the inode it refers to.
+///
+/// Looked up names stay cached for as long as their directory is, until
+/// they are removed or renamed. Directories a file system is mounted on
+/// point to the root of the mounted file system.
+pub struct Dentry {
+    name: String,
+    /// `None` for the root of a file system.
+    parent: Option<Arc<Dentry>>,
+    inode: Arc<dyn Inode>,
+    /// File types never change, so this saves asking the file system.
+    kind: InodeType,
+    children: Mutex<BTreeMap<String, Arc<Dentry>>>,
+    /// The root of the file system mounted here, if any.
+    mounted: Mutex<Option<Arc<Dentry>>>,
+    /// For the root of a mounted file system, the directory it is mounted on.
+    covers: Option<Weak<Dentry>>,
+}
+
+impl Dentry {
+    /// The root of a file system, mounted on `covers` unless it is the
+    /// first one.
+    pub fn new_root(inode: Arc<dyn Inode>, covers: Option<&Arc<Dentry>>) -> FsResult<Arc<Self>> {
+        let kind = inode.metadata()?.kind;
+        if kind != InodeType::Dir {
+            return Err(FsError::NotDir);
+        }
+        Ok(Arc::new(Self {
+            name: "/".to_string(),
+            parent: None,
+            inode,
+            kind,
+            children: Mutex::new(BTreeMap::new()),
+            mounted: Mutex::new(None),
+            covers: covers.map(Arc::downgrade),
+        }))
+    }
+
+    fn new_child(self: &Arc<Self>, name: &str, inode: Arc<dyn Inode>) -> FsResult<Arc<Self>> {
+        let kind = inode.metadata()?.kind;
+        Ok(Arc::new(Self {
+            name: name.to_string(),
+            parent: Some(self.clone()),
+            inode,
+            kind,
+            children: Mutex::new(BTreeMap::new()),
+            mounted: Mutex::new(None),
+            covers: None,
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parent(&self) -> Option<Arc<Dentry>> {
+        self.parent.clone()
+    }
+
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        &self.inode
+    }
+
+    pub fn kind(&self) -> InodeType {
+        self.kind
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == InodeType::Dir
+    }
+
+    pub fn mounted(&self) -> Option<Arc<Dentry>> {
+        self.mounted.lock().clone()
+    }
+
+    pub fn set_mounted(&self, root: Option<Arc<Dentry>>) {
+        *self.mounted.lock() = root;
+    }
+
+    /// The directory this file system root is mounted on.
+    pub fn covers(&self) -> Option<Arc<Dentry>> {
+        self.covers.as_ref().and_then(Weak::upgrade)
+    }
+
+    /// The root of the file system mounted last on this directory, or the
+    /// directory itself.
+    pub fn follow_mounts(self: &Arc<Self>) -> Arc<Dentry> {
+        let mut dentry = self.clone();
+        while let Some(root) = dentry.mounted() {
+            dentry = root;
+        }
+        dentry
+    }
+
+    /// The entry `name` of this directory.
+    pub fn lookup(self: &Arc<Self>, name: &str) -> FsResult<Arc<Dentry>> {
+        if !self.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        if let Some(child) = self.children.lock().get(name) {
+            return Ok(child.clone());
+        }
+        let child = self.new_child(name, self.inode.lookup(name)?)?;
+        // Another lookup may have raced this one, keep what is cached.
+        Ok(self
+            .children
+            .lock()
+            .entry(name.to_string())
+            .or_insert(child)
+            .clone())
+    }
+
+    /// Create `name` in this directory.
+    pub fn create(self: &Arc<Self>, name: &str, kind: InodeType, mode: u32) -> FsResult<Arc<Dentry>> {
+        let child = self.new_child(name, self.inode.create(name, kind, mode)?)?;
+        self.children.lock().insert(name.to_string(), child.clone());
+        Ok(child)
+    }
+
+    /// Create a symbolic link `name` to `target` in this directory.
+    pub fn symlink(self: &Arc<Self>, name: &str, target: &str) -> FsResult<Arc<Dentry>> {
+        let child = self.new_child(name, self.inode.symlink(name, target)?)?;
+        self.children.lock().insert(name.to_string(), child.clone());
+        Ok(child)
+    }
+
+    /// Add `name` to this directory as another link to `target`.
+    pub fn link(self: &Arc<Self>, name: &str, target: &Arc<Dentry>) -> FsResult<()> {
+        if target.is_dir() {
+            return Err(FsError::PermissionDenied);
+        }
+        self.inode.link(name, &target.inode)?;
+        self.children.lock().remove(name);
+        Ok(())
+    }
+
+    /// Remove the entry `name`, a directory if `dir`.
+    pub fn unlink(self: &Arc<Self>, name: &str, dir: bool) -> FsResult<()> {
+        let child = self.lookup(name)?;
+        if child.mounted().is_some() {
+            return Err(FsError::Busy);
+        }
+        match (dir, child.is_dir()) {
+            (true, false) => return Err(FsError::NotDir),
+            (false, true) => return Err(FsError::IsDir),
+            (true, true) => self.inode.rmdir(name)?,
+            (false, false) => self.inode.unlink(name)?,
+        }
+        self.children.lock().remove(name);
+        Ok(())
+    }
+
+    /// Move the entry `old_name` of this directory to `new_name` in
+    /// `new_parent`.
+    pub fn rename(self: &Arc<Self>, old_name: &str, new_parent: &Arc<Dentry>, new_name: &str) -> FsResult<()> {
+        let old = self.lookup(old_name)?;
+        if old.mounted().is_some() {
+            return Err(FsError::Busy);
+        }
+        // A directory cannot move below itself.
+        let mut ancestor = Some(new_parent.clone());
+        while let Some(dentry) = ancestor {
+            if Arc::ptr_eq(&dentry, &old) {
+                return Err(FsError::InvalidInput);
+            }
+            ancestor = dentry.parent();
+        }
+        match new_parent.lookup(new_name) {
+            Ok(new) if Arc::ptr_eq(&new, &old) => return Ok(()),
+            Ok(new) if new.mounted().is_some() => return Err(FsError::Busy),
+            Ok(new) if new.is_dir() && !old.is_dir() => return Err(FsError::IsDir),
+            Ok(new) if !new.is_dir() && old.is_dir() => return Err(FsError::NotDir),
+            Ok(_) | Err(FsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        self.inode.rename(old_name, &new_parent.inode, new_name)?;
+        self.children.lock().remove(old_name);
+        // The replaced entry may be the last link, and freeing it may sleep.
+        let replaced = new_parent.children.lock().remove(new_name);
+        drop(replaced);
+        Ok(())
+    }
+
+    /// Drop every cached name below this directory, so that the file
+    /// system can go away.
+    pub fn forget_children(&self) {
+        let children = core::mem::take(&mut *self.children.lock());
+        for child in children.values() {
+            child.forget_children();
+        }
+    }
+
+    /// The absolute path of this entry, as seen from the directory `root`.
+    pub fn path(self: &Arc<Self>, root: &Arc<Dentry>) -> String {
+        let root = root.follow_mounts();
+        let mut names = Vec::new();
+        let mut dentry = self.clone();
+        while !Arc::ptr_eq(&dentry, &root) {
+            match (dentry.parent(), dentry.covers()) {
+                (Some(parent), _) => {
+                    names.push(dentry.name.clone());
+                    dentry = parent;
+                }
+                (None, Some(covered)) => dentry = covered,
+                // Outside of `root`.
+                (None, None) => break,
+            }
+        }
+        if names.is_empty() {
+            return "/".to_string();
+        }
+        names.iter().rev().fold(String::new(), |mut path, name| {
+            path.push('/');
+            path.push_str(name);
+            path
+        })
+    }
+}
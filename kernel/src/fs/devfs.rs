@@ -0,0 +1,213 @@
+//! The device files of every registered character device, mounted at
+//! `/dev`. The directory lists what the drivers registered, and cannot be
+//! changed through the file system.
+
+use alloc::{string::ToString, sync::Arc};
+use core::any::Any;
+use spin::Mutex;
+
+use crate::drivers::chardev::{self, CharDevice, Registered, Tty};
+
+use super::{mount, DirEntry, File, FsError, FsResult, Inode, InodeType, Metadata, OpenFlags, SeekFrom, SuperBlock, TimeSpec};
+
+const ROOT_INO: u64 = 1;
+
+pub struct DevFs {
+    root: Arc<DevDir>,
+}
+
+impl DevFs {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            root: Arc::new(DevDir {
+                dev: mount::new_dev(),
+                ctime: TimeSpec::now(),
+            }),
+        })
+    }
+}
+
+impl SuperBlock for DevFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+struct DevDir {
+    dev: u64,
+    ctime: TimeSpec,
+}
+
+impl Inode for DevDir {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata {
+            dev: self.dev,
+            ino: ROOT_INO,
+            kind: InodeType::Dir,
+            mode: 0o755,
+            nlink: 2,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            blksize: 4096,
+            blocks: 0,
+            rdev: 0,
+            atime: self.ctime,
+            mtime: self.ctime,
+            ctime: self.ctime,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let registered = chardev::get(name).ok_or(FsError::NotFound)?;
+        Ok(Arc::new(DevInode {
+            dev: self.dev,
+            ctime: self.ctime,
+            registered,
+        }))
+    }
+
+    fn create(&self, _name: &str, _kind: InodeType, _mode: u32) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn read_dir(&self, pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        let dot = |name: &str| DirEntry {
+            ino: ROOT_INO,
+            kind: InodeType::Dir,
+            name: name.to_string(),
+        };
+        let entry = match pos {
+            0 => dot("."),
+            1 => dot(".."),
+            _ => {
+                let Some(name) = chardev::names().into_iter().nth(pos - 2) else {
+                    return Ok(None);
+                };
+                let registered = chardev::get(&name).ok_or(FsError::NotFound)?;
+                DirEntry {
+                    ino: registered.rdev,
+                    kind: InodeType::CharDevice,
+                    name,
+                }
+            }
+        };
+        Ok(Some((entry, pos + 1)))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The device file of a character device, numbered after its device.
+#[derive(Clone)]
+struct DevInode {
+    dev: u64,
+    ctime: TimeSpec,
+    registered: Registered,
+}
+
+impl Inode for DevInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        Ok(Metadata {
+            dev: self.dev,
+            ino: self.registered.rdev,
+            kind: InodeType::CharDevice,
+            mode: 0o666,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            blksize: 4096,
+            blocks: 0,
+            rdev: self.registered.rdev,
+            atime: self.ctime,
+            mtime: self.ctime,
+            ctime: self.ctime,
+        })
+    }
+
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        self.registered.device.read(buf, false)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> FsResult<usize> {
+        self.registered.device.write(buf)
+    }
+
+    fn open(&self, flags: OpenFlags) -> FsResult<Option<Arc<dyn File>>> {
+        let device = self.registered.device.open(flags)?;
+        Ok(Some(Arc::new(DeviceFile {
+            inode: self.clone(),
+            device: device.unwrap_or_else(|| self.registered.device.clone()),
+            flags: Mutex::new(flags - OpenFlags::CREAT - OpenFlags::EXCL - OpenFlags::TRUNC - OpenFlags::CLOEXEC),
+        })))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// An open device file. Devices have no position, so reads and writes at
+/// offsets are plain reads and writes.
+struct DeviceFile {
+    inode: DevInode,
+    /// What opening the device reached, like the terminal `/dev/tty` stands
+    /// for.
+    device: Arc<dyn CharDevice>,
+    flags: Mutex<OpenFlags>,
+}
+
+impl DeviceFile {
+    fn flags(&self) -> OpenFlags {
+        *self.flags.lock()
+    }
+}
+
+impl File for DeviceFile {
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        let flags = self.flags();
+        if !flags.readable() {
+            return Err(FsError::BadDescriptor);
+        }
+        self.device.read(buf, flags.contains(OpenFlags::NONBLOCK))
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        if !self.flags().writable() {
+            return Err(FsError::BadDescriptor);
+        }
+        self.device.write(buf)
+    }
+
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        self.read(buf)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> FsResult<usize> {
+        self.write(buf)
+    }
+
+    fn seek(&self, _pos: SeekFrom) -> FsResult<usize> {
+        Ok(0)
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        self.inode.metadata()
+    }
+
+    fn status_flags(&self) -> OpenFlags {
+        self.flags()
+    }
+
+    fn set_status_flags(&self, flags: OpenFlags) {
+        let mut current = self.flags.lock();
+        *current = (*current - OpenFlags::SETFL_MASK) | (flags & OpenFlags::SETFL_MASK);
+    }
+
+    fn tty(&self) -> Option<Arc<Tty>> {
+        self.device.tty()
+    }
+}
@@ -0,0 +1,87 @@
+use core::fmt::{self, Display};
+
+/// Errors of file operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsError {
+    /// The file does not support the operation.
+    Unsupported,
+    /// The file was not opened for the operation.
+    BadDescriptor,
+    /// An argument is out of range.
+    InvalidInput,
+    /// The process has no descriptor left.
+    TooManyFiles,
+    /// The device failed to transfer data.
+    Io,
+    /// No file has the name looked up.
+    NotFound,
+    /// A file with the name to create exists already.
+    AlreadyExists,
+    /// A directory was expected.
+    NotDir,
+    /// The operation does not apply to directories.
+    IsDir,
+    /// The directory to remove still has entries.
+    NotEmpty,
+    /// Too many symbolic links were followed during a lookup.
+    SymlinkLoop,
+    /// A path component is longer than the file system allows.
+    NameTooLong,
+    /// The operation would link files of different file systems.
+    CrossDevice,
+    /// The file has as many links as the file system allows.
+    TooManyLinks,
+    /// The file is in use, for example as a mount point.
+    Busy,
+    /// The file system is mounted read-only.
+    ReadOnly,
+    /// The file system is full.
+    NoSpace,
+    /// The file has no position to move.
+    NotSeekable,
+    /// The permissions of the file forbid the access.
+    PermissionDenied,
+    /// The on-disk structures are inconsistent.
+    Corrupted,
+    /// Nothing can be transferred without blocking, and the file is
+    /// non-blocking.
+    WouldBlock,
+    /// A signal arrived while waiting.
+    Interrupted,
+    /// The device file stands for no device, like `/dev/tty` without a
+    /// controlling terminal.
+    NoDevice,
+}
+
+pub type FsResult<T> = Result<T, FsError>;
+
+impl Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            FsError::Unsupported => "operation not supported",
+            FsError::BadDescriptor => "bad file descriptor",
+            FsError::InvalidInput => "invalid argument",
+            FsError::TooManyFiles => "too many open files",
+            FsError::Io => "I/O error",
+            FsError::NotFound => "no such file or directory",
+            FsError::AlreadyExists => "file exists",
+            FsError::NotDir => "not a directory",
+            FsError::IsDir => "is a directory",
+            FsError::NotEmpty => "directory not empty",
+            FsError::SymlinkLoop => "too many levels of symbolic links",
+            FsError::NameTooLong => "file name too long",
+            FsError::CrossDevice => "invalid cross-device link",
+            FsError::TooManyLinks => "too many links",
+            FsError::Busy => "device or resource busy",
+            FsError::ReadOnly => "read-only file system",
+            FsError::NoSpace => "no space left on device",
+            FsError::NotSeekable => "illegal seek",
+            FsError::PermissionDenied => "permission denied",
+            FsError::Corrupted => "file system corrupted",
+            FsError::WouldBlock => "resource temporarily unavailable",
+            FsError::Interrupted => "interrupted system call",
+            FsError::NoDevice => "no such device or address",
+        };
+        f.write_str(msg)
+    }
+}
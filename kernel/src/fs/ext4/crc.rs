@@ -0,0 +1,45 @@
+//! The checksums of ext4 metadata.
+
+/// Reversed polynomial of CRC-32C (Castagnoli).
+const CRC32C_POLY: u32 = 0x82f6_3b78;
+/// Reversed polynomial of CRC-16 (IBM).
+const CRC16_POLY: u16 = 0xa001;
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ CRC32C_POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+/// Continue the CRC-32C `crc` over `data`, without the final inversion, as
+/// ext4 chains it.
+pub fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc = CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Continue the CRC-16 `crc` over `data`, for group descriptors of volumes
+/// without `metadata_csum`.
+pub fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ CRC16_POLY } else { crc >> 1 };
+        }
+    }
+    crc
+}
@@ -0,0 +1,447 @@
+//! Directory blocks: linear entries, the checksum tail ending leaf blocks,
+//! and the hash tree index of large directories.
+
+use alloc::vec::Vec;
+
+use crate::fs::{FsError, FsResult, InodeType};
+
+use super::crc::crc32c;
+
+/// Length of the fixed part of an entry, before the name.
+const ENTRY_HEADER: usize = 8;
+/// Length of the fake entry ending leaf blocks with its checksum.
+pub const TAIL_SIZE: usize = 12;
+/// File type of the tail entry.
+const TAIL_FILE_TYPE: u8 = 0xde;
+pub const NAME_MAX: usize = 255;
+
+/// Offset of `dx_root_info` in the root block of an index, past the `.`
+/// and `..` entries.
+const DX_ROOT_INFO: usize = 0x18;
+/// Offset of the entries of an interior node, past its empty entry.
+const DX_NODE_ENTRIES: usize = 8;
+const DX_ENTRY_SIZE: usize = 8;
+/// Hash of the end of a directory, never given to names.
+const HTREE_EOF: u32 = 0x7fff_ffff;
+
+const HASH_LEGACY: u8 = 0;
+const HASH_HALF_MD4: u8 = 1;
+const HASH_TEA: u8 = 2;
+/// Added to the versions above when names hash as unsigned bytes.
+pub const HASH_UNSIGNED: u8 = 3;
+
+/// The `file_type` of entries for `kind`.
+pub fn file_type(kind: InodeType) -> u8 {
+    match kind {
+        InodeType::File => 1,
+        InodeType::Dir => 2,
+        InodeType::CharDevice => 3,
+        InodeType::BlockDevice => 4,
+        InodeType::Fifo => 5,
+        InodeType::Socket => 6,
+        InodeType::Symlink => 7,
+    }
+}
+
+/// The kind of file an entry's `file_type` tells, if it tells.
+pub fn kind(file_type: u8) -> Option<InodeType> {
+    Some(match file_type {
+        1 => InodeType::File,
+        2 => InodeType::Dir,
+        3 => InodeType::CharDevice,
+        4 => InodeType::BlockDevice,
+        5 => InodeType::Fifo,
+        6 => InodeType::Socket,
+        7 => InodeType::Symlink,
+        _ => return None,
+    })
+}
+
+/// An entry of a block, in use when `inode` is not 0.
+#[derive(Clone, Copy)]
+pub struct Record {
+    pub offset: usize,
+    pub rec_len: usize,
+    pub inode: u32,
+    pub name_len: usize,
+    pub file_type: u8,
+}
+
+impl Record {
+    pub fn name<'a>(&self, block: &'a [u8]) -> &'a [u8] {
+        &block[self.offset + ENTRY_HEADER..self.offset + ENTRY_HEADER + self.name_len]
+    }
+
+    /// Bytes the entry needs, leaving the rest of `rec_len` free.
+    fn used(&self) -> usize {
+        match self.inode {
+            0 => 0,
+            _ => entry_len(self.name_len),
+        }
+    }
+}
+
+/// Bytes an entry with a name of `name_len` needs.
+fn entry_len(name_len: usize) -> usize {
+    (ENTRY_HEADER + name_len).next_multiple_of(4)
+}
+
+/// `rec_len` as stored: 64 KiB blocks store their whole length as 65535.
+fn decode_rec_len(raw: u16, block_size: usize) -> usize {
+    match raw {
+        0 | 0xffff if block_size >= 0x10000 => 0x10000,
+        len => len as usize,
+    }
+}
+
+fn encode_rec_len(len: usize) -> u16 {
+    match len {
+        0x10000 => 0xffff,
+        len => len as u16,
+    }
+}
+
+fn write_record(block: &mut [u8], offset: usize, inode: u32, rec_len: usize, name: &[u8], file_type: u8) {
+    block[offset..offset + 4].copy_from_slice(&inode.to_le_bytes());
+    block[offset + 4..offset + 6].copy_from_slice(&encode_rec_len(rec_len).to_le_bytes());
+    block[offset + 6] = name.len() as u8;
+    block[offset + 7] = file_type;
+    block[offset + ENTRY_HEADER..offset + ENTRY_HEADER + name.len()].copy_from_slice(name);
+}
+
+fn set_rec_len(block: &mut [u8], offset: usize, rec_len: usize) {
+    block[offset + 4..offset + 6].copy_from_slice(&encode_rec_len(rec_len).to_le_bytes());
+}
+
+/// The entries of `block`, including unused ones and the tail.
+pub fn records(block: &[u8]) -> FsResult<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < block.len() {
+        if offset + ENTRY_HEADER > block.len() {
+            return Err(FsError::Corrupted);
+        }
+        let raw_len = u16::from_le_bytes([block[offset + 4], block[offset + 5]]);
+        let record = Record {
+            offset,
+            rec_len: decode_rec_len(raw_len, block.len()),
+            inode: u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap()),
+            name_len: block[offset + 6] as usize,
+            file_type: block[offset + 7],
+        };
+        if record.rec_len < ENTRY_HEADER
+            || record.rec_len % 4 != 0
+            || offset + record.rec_len > block.len()
+            || ENTRY_HEADER + record.name_len > record.rec_len
+        {
+            return Err(FsError::Corrupted);
+        }
+        offset += record.rec_len;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// The entry named `name` in `block`.
+pub fn find(block: &[u8], name: &str) -> FsResult<Option<Record>> {
+    Ok(records(block)?
+        .into_iter()
+        .find(|record| record.inode != 0 && record.name(block) == name.as_bytes()))
+}
+
+/// Whether `block` holds no entries but `.` and `..`.
+pub fn is_empty(block: &[u8]) -> FsResult<bool> {
+    Ok(records(block)?
+        .iter()
+        .all(|record| record.inode == 0 || matches!(record.name(block), b"." | b"..")))
+}
+
+/// Add an entry to `block` in the free space of an existing one, if there
+/// is room.
+pub fn insert(block: &mut [u8], inode: u32, name: &str, file_type: u8) -> FsResult<bool> {
+    let needed = entry_len(name.len());
+    for record in records(block)? {
+        // The tail is not free space, whatever it looks like.
+        if record.file_type == TAIL_FILE_TYPE && record.rec_len == TAIL_SIZE && record.offset + TAIL_SIZE == block.len() {
+            continue;
+        }
+        let used = record.used();
+        if record.rec_len - used < needed {
+            continue;
+        }
+        if used > 0 {
+            set_rec_len(block, record.offset, used);
+        }
+        write_record(block, record.offset + used, inode, record.rec_len - used, name.as_bytes(), file_type);
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Remove the entry `record` of `block`, giving its space to the entry
+/// before it.
+pub fn remove(block: &mut [u8], record: &Record) -> FsResult<()> {
+    let previous = records(block)?
+        .into_iter()
+        .take_while(|other| other.offset < record.offset)
+        .last();
+    match previous {
+        Some(previous) => set_rec_len(block, previous.offset, previous.rec_len + record.rec_len),
+        None => block[record.offset..record.offset + 4].fill(0),
+    }
+    Ok(())
+}
+
+/// Point the entry `record` of `block` to another inode.
+pub fn set_inode(block: &mut [u8], record: &Record, inode: u32, file_type: u8) {
+    block[record.offset..record.offset + 4].copy_from_slice(&inode.to_le_bytes());
+    block[record.offset + 7] = file_type;
+}
+
+/// Make `block` one unused entry, followed by a tail if `tail`.
+pub fn init_block(block: &mut [u8], tail: bool) {
+    block.fill(0);
+    let end = if tail { block.len() - TAIL_SIZE } else { block.len() };
+    set_rec_len(block, 0, end);
+    if tail {
+        set_tail(block);
+    }
+}
+
+/// Fill the first block of a new directory with `.` and `..`.
+pub fn init_first_block(block: &mut [u8], ino: u32, parent: u32, tail: bool) {
+    init_block(block, tail);
+    let end = if tail { block.len() - TAIL_SIZE } else { block.len() };
+    write_record(block, 0, ino, entry_len(1), b".", file_type(InodeType::Dir));
+    write_record(block, entry_len(1), parent, end - entry_len(1), b"..", file_type(InodeType::Dir));
+}
+
+fn set_tail(block: &mut [u8]) {
+    let offset = block.len() - TAIL_SIZE;
+    block[offset..].fill(0);
+    set_rec_len(block, offset, TAIL_SIZE);
+    block[offset + 7] = TAIL_FILE_TYPE;
+}
+
+fn has_tail(block: &[u8]) -> bool {
+    let tail = &block[block.len() - TAIL_SIZE..];
+    tail[..4] == [0; 4] && u16::from_le_bytes([tail[4], tail[5]]) as usize == TAIL_SIZE && tail[6] == 0 && tail[7] == TAIL_FILE_TYPE
+}
+
+/// Store the checksum of `block`, a leaf with a tail or a node of the
+/// index whose entries start at `dx_entries`. Blocks with neither have
+/// nowhere to store it.
+pub fn update_checksum(block: &mut [u8], seed: u32, dx_entries: Option<usize>) {
+    let len = block.len();
+    if has_tail(block) {
+        let checksum = crc32c(seed, &block[..len - TAIL_SIZE]);
+        block[len - 4..].copy_from_slice(&checksum.to_le_bytes());
+        return;
+    }
+    let Some(entries) = dx_entries else {
+        return;
+    };
+    let limit = u16::from_le_bytes([block[entries], block[entries + 1]]) as usize;
+    let count = u16::from_le_bytes([block[entries + 2], block[entries + 3]]) as usize;
+    // The tail of an index node: a reserved word, then the checksum.
+    let tail = entries + limit * DX_ENTRY_SIZE;
+    if count > limit || tail + 8 > len {
+        return;
+    }
+    let mut checksum = crc32c(seed, &block[..entries + count * DX_ENTRY_SIZE]);
+    checksum = crc32c(checksum, &block[tail..tail + 4]);
+    checksum = crc32c(checksum, &[0; 4]);
+    block[tail + 4..tail + 8].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Where the entries of an interior node of an index start, if `block` is
+/// one: it starts with an unused entry spanning the whole block.
+pub fn dx_node_entries(block: &[u8]) -> Option<usize> {
+    let rec_len = decode_rec_len(u16::from_le_bytes([block[4], block[5]]), block.len());
+    (block[..4] == [0; 4] && rec_len == block.len()).then_some(DX_NODE_ENTRIES)
+}
+
+/// The root of a directory's index, from its first block.
+pub struct DxRoot {
+    pub hash_version: u8,
+    /// Levels of interior nodes below the root.
+    pub levels: u8,
+    /// Where the entries start.
+    pub entries: usize,
+}
+
+impl DxRoot {
+    /// Parse the root of `block`, if it looks sane: lookups fall back to a
+    /// linear search otherwise.
+    pub fn parse(block: &[u8], max_levels: u8) -> Option<Self> {
+        let info = &block[DX_ROOT_INFO..];
+        let info_len = info[5] as usize;
+        let root = Self {
+            hash_version: info[4],
+            levels: info[6],
+            entries: DX_ROOT_INFO + info_len,
+        };
+        let valid = info[..4] == [0; 4] && info_len == 8 && root.levels < max_levels;
+        valid.then_some(root)
+    }
+}
+
+/// One step down an index: the children of the node `block`, whose entries
+/// start at `entries`, that may hold names hashing to `hash`. The first
+/// covers the hash; the others go on with names of the same hash that did
+/// not fit in it. Also tells whether they run to the end of the node, so
+/// the next node may go on too.
+pub fn dx_lookup(block: &[u8], entries: usize, hash: u32) -> FsResult<(Vec<u32>, bool)> {
+    let count = u16::from_le_bytes([block[entries + 2], block[entries + 3]]) as usize;
+    if count == 0 || entries + count * DX_ENTRY_SIZE > block.len() {
+        return Err(FsError::Corrupted);
+    }
+    let hash_at = |i: usize| u32::from_le_bytes(block[entries + i * DX_ENTRY_SIZE..][..4].try_into().unwrap());
+    let child_at = |i: usize| u32::from_le_bytes(block[entries + i * DX_ENTRY_SIZE + 4..][..4].try_into().unwrap()) & 0x0fff_ffff;
+    // The first entry holds the count instead of a hash, and covers
+    // everything below the second.
+    let first = (1..count).take_while(|&i| hash_at(i) <= hash).last().unwrap_or(0);
+    let last = (first + 1..count).take_while(|&i| hash_at(i) & !1 == hash).last().unwrap_or(first);
+    Ok(((first..=last).map(child_at).collect(), last + 1 == count))
+}
+
+/// The children of the index node `block`, whose entries start at
+/// `entries`.
+pub fn dx_children(block: &[u8], entries: usize) -> Vec<u32> {
+    let count = u16::from_le_bytes([block[entries + 2], block[entries + 3]]) as usize;
+    (0..count)
+        .filter(|i| entries + (i + 1) * DX_ENTRY_SIZE <= block.len())
+        .map(|i| u32::from_le_bytes(block[entries + i * DX_ENTRY_SIZE + 4..][..4].try_into().unwrap()) & 0x0fff_ffff)
+        .collect()
+}
+
+/// Turn the root block of an index back into a plain first block.
+pub fn drop_dx_root(block: &mut [u8], tail: bool) {
+    let dot_dot = entry_len(1);
+    let end = if tail { block.len() - TAIL_SIZE } else { block.len() };
+    set_rec_len(block, dot_dot, end - dot_dot);
+    if tail {
+        set_tail(block);
+    }
+}
+
+/// The hash of `name` with `version`, which includes [`HASH_UNSIGNED`] if
+/// needed, from `seed`. Versions this driver does not know give `None`.
+pub fn hash(name: &[u8], version: u8, seed: &[u32; 4]) -> Option<u32> {
+    if version > HASH_TEA + HASH_UNSIGNED {
+        return None;
+    }
+    let unsigned = version >= HASH_UNSIGNED;
+    let mut buf = match seed.iter().any(|&word| word != 0) {
+        true => *seed,
+        false => [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476],
+    };
+    let hash = match version % HASH_UNSIGNED {
+        HASH_LEGACY => legacy_hash(name, unsigned),
+        HASH_HALF_MD4 => {
+            for start in (0..name.len()).step_by(32) {
+                half_md4(&mut buf, &str_to_words(&name[start..], unsigned));
+            }
+            buf[1]
+        }
+        _ => {
+            for start in (0..name.len()).step_by(16) {
+                tea(&mut buf, &str_to_words(&name[start..], unsigned));
+            }
+            buf[0]
+        }
+    };
+    let hash = hash & !1;
+    Some(if hash == HTREE_EOF << 1 { (HTREE_EOF - 1) << 1 } else { hash })
+}
+
+/// Byte `byte` of a name as the hashes take it, sign extended unless
+/// `unsigned`.
+fn hash_byte(byte: u8, unsigned: bool) -> u32 {
+    match unsigned {
+        true => byte as u32,
+        false => byte as i8 as i32 as u32,
+    }
+}
+
+fn legacy_hash(name: &[u8], unsigned: bool) -> u32 {
+    let (mut hash0, mut hash1) = (0x12a3_fe2d_u32, 0x37ab_e8f9_u32);
+    for &byte in name {
+        let mut hash = hash1.wrapping_add(hash0 ^ hash_byte(byte, unsigned).wrapping_mul(7_152_373));
+        if hash & 0x8000_0000 != 0 {
+            hash = hash.wrapping_sub(0x7fff_ffff);
+        }
+        hash1 = hash0;
+        hash0 = hash;
+    }
+    hash0 << 1
+}
+
+/// Pack the start of `rest`, what is left of a name, into `N` words,
+/// padded with the length left.
+fn str_to_words<const N: usize>(rest: &[u8], unsigned: bool) -> [u32; N] {
+    let mut pad = rest.len() as u32 | (rest.len() as u32) << 8;
+    pad |= pad << 16;
+    let mut words = [pad; N];
+    let mut value = pad;
+    let len = rest.len().min(N * 4);
+    for (i, &byte) in rest[..len].iter().enumerate() {
+        value = hash_byte(byte, unsigned).wrapping_add(value << 8);
+        if i % 4 == 3 {
+            words[i / 4] = value;
+            value = pad;
+        }
+    }
+    if len % 4 != 0 {
+        words[len / 4] = value;
+    }
+    words
+}
+
+fn half_md4(buf: &mut [u32; 4], input: &[u32; 8]) {
+    const K2: u32 = 0o13240474631;
+    const K3: u32 = 0o15666365641;
+    let f = |x: u32, y: u32, z: u32| z ^ (x & (y ^ z));
+    let g = |x: u32, y: u32, z: u32| (x & y).wrapping_add((x ^ y) & z);
+    let h = |x: u32, y: u32, z: u32| x ^ y ^ z;
+    let [mut a, mut b, mut c, mut d] = *buf;
+    let step = |func: &dyn Fn(u32, u32, u32) -> u32, a: u32, b: u32, c: u32, d: u32, x: u32, s: u32| {
+        a.wrapping_add(func(b, c, d)).wrapping_add(x).rotate_left(s)
+    };
+    for round in [[0, 1, 2, 3], [4, 5, 6, 7]] {
+        a = step(&f, a, b, c, d, input[round[0]], 3);
+        d = step(&f, d, a, b, c, input[round[1]], 7);
+        c = step(&f, c, d, a, b, input[round[2]], 11);
+        b = step(&f, b, c, d, a, input[round[3]], 19);
+    }
+    for round in [[1, 3, 5, 7], [0, 2, 4, 6]] {
+        a = step(&g, a, b, c, d, input[round[0]].wrapping_add(K2), 3);
+        d = step(&g, d, a, b, c, input[round[1]].wrapping_add(K2), 5);
+        c = step(&g, c, d, a, b, input[round[2]].wrapping_add(K2), 9);
+        b = step(&g, b, c, d, a, input[round[3]].wrapping_add(K2), 13);
+    }
+    for round in [[3, 7, 2, 6], [1, 5, 0, 4]] {
+        a = step(&h, a, b, c, d, input[round[0]].wrapping_add(K3), 3);
+        d = step(&h, d, a, b, c, input[round[1]].wrapping_add(K3), 9);
+        c = step(&h, c, d, a, b, input[round[2]].wrapping_add(K3), 11);
+        b = step(&h, b, c, d, a, input[round[3]].wrapping_add(K3), 15);
+    }
+    buf[0] = buf[0].wrapping_add(a);
+    buf[1] = buf[1].wrapping_add(b);
+    buf[2] = buf[2].wrapping_add(c);
+    buf[3] = buf[3].wrapping_add(d);
+}
+
+fn tea(buf: &mut [u32; 4], input: &[u32; 4]) {
+    const DELTA: u32 = 0x9e37_79b9;
+    let (mut b0, mut b1) = (buf[0], buf[1]);
+    let [a, b, c, d] = *input;
+    let mut sum = 0u32;
+    for _ in 0..16 {
+        sum = sum.wrapping_add(DELTA);
+        b0 = b0.wrapping_add((b1 << 4).wrapping_add(a) ^ b1.wrapping_add(sum) ^ (b1 >> 5).wrapping_add(b));
+        b1 = b1.wrapping_add((b0 << 4).wrapping_add(c) ^ b0.wrapping_add(sum) ^ (b0 >> 5).wrapping_add(d));
+    }
+    buf[0] = buf[0].wrapping_add(b0);
+    buf[1] = buf[1].wrapping_add(b1);
+}
@@ -0,0 +1,237 @@
+//! Inodes as stored in the inode table.
+
+use alloc::{vec, vec::Vec};
+
+use crate::fs::{InodeType, TimeSpec};
+
+use super::crc::crc32c;
+
+/// Size of `i_block`, which holds the extent tree root, block map, or the
+/// target of a fast symbolic link.
+pub const I_BLOCK_SIZE: usize = 60;
+const I_BLOCK: usize = 0x28;
+/// Inodes this long or shorter have none of the extra fields.
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const CHECKSUM_LO: usize = 0x7c;
+const CHECKSUM_HI: usize = 0x82;
+
+/// `i_blocks` counts file system blocks rather than 512-byte sectors.
+pub const HUGE_FILE_FL: u32 = 0x40000;
+/// The directory has a hash tree index.
+pub const INDEX_FL: u32 = 0x1000;
+/// `i_block` holds an extent tree rather than a block map.
+pub const EXTENTS_FL: u32 = 0x80000;
+
+const S_IFMT: u16 = 0o170000;
+
+pub struct DiskInode(Vec<u8>);
+
+impl DiskInode {
+    pub fn new(raw: Vec<u8>) -> Self {
+        Self(raw)
+    }
+
+    /// A zeroed inode of `size` bytes, whose extra fields take `extra_isize`.
+    pub fn zeroed(size: usize, extra_isize: u16) -> Self {
+        let mut inode = Self(vec![0; size]);
+        if size > GOOD_OLD_INODE_SIZE {
+            inode.set_u16(0x80, extra_isize);
+        }
+        inode
+    }
+
+    pub fn raw(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    fn set_u16(&mut self, offset: usize, value: u16) {
+        self.0[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.0[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn set_u32(&mut self, offset: usize, value: u32) {
+        self.0[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Whether the field of `len` bytes at `offset` exists in this inode.
+    fn has_field(&self, offset: usize, len: usize) -> bool {
+        self.0.len() > GOOD_OLD_INODE_SIZE && offset + len <= GOOD_OLD_INODE_SIZE + self.u16_at(0x80) as usize
+    }
+
+    pub fn mode(&self) -> u16 {
+        self.u16_at(0x0)
+    }
+
+    pub fn set_mode(&mut self, mode: u16) {
+        self.set_u16(0x0, mode);
+    }
+
+    pub fn kind(&self) -> Option<InodeType> {
+        Some(match self.mode() & S_IFMT {
+            0o010000 => InodeType::Fifo,
+            0o020000 => InodeType::CharDevice,
+            0o040000 => InodeType::Dir,
+            0o060000 => InodeType::BlockDevice,
+            0o100000 => InodeType::File,
+            0o120000 => InodeType::Symlink,
+            0o140000 => InodeType::Socket,
+            _ => return None,
+        })
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.u16_at(0x2) as u32 | (self.u16_at(0x78) as u32) << 16
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.u16_at(0x18) as u32 | (self.u16_at(0x7a) as u32) << 16
+    }
+
+    pub fn size(&self) -> u64 {
+        self.u32_at(0x4) as u64 | (self.u32_at(0x6c) as u64) << 32
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        self.set_u32(0x4, size as u32);
+        self.set_u32(0x6c, (size >> 32) as u32);
+    }
+
+    pub fn links(&self) -> u16 {
+        self.u16_at(0x1a)
+    }
+
+    pub fn set_links(&mut self, links: u16) {
+        self.set_u16(0x1a, links);
+    }
+
+    /// Space used, in 512-byte sectors unless [`HUGE_FILE_FL`] is set.
+    pub fn blocks(&self) -> u64 {
+        self.u32_at(0x1c) as u64 | (self.u16_at(0x74) as u64) << 32
+    }
+
+    pub fn set_blocks(&mut self, blocks: u64) {
+        self.set_u32(0x1c, blocks as u32);
+        self.set_u16(0x74, (blocks >> 32) as u16);
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.u32_at(0x20)
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        self.set_u32(0x20, flags);
+    }
+
+    pub fn i_block(&self) -> &[u8] {
+        &self.0[I_BLOCK..I_BLOCK + I_BLOCK_SIZE]
+    }
+
+    pub fn i_block_mut(&mut self) -> &mut [u8] {
+        &mut self.0[I_BLOCK..I_BLOCK + I_BLOCK_SIZE]
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.u32_at(0x64)
+    }
+
+    pub fn set_generation(&mut self, generation: u32) {
+        self.set_u32(0x64, generation);
+    }
+
+    /// The block of extended attributes, 0 for none.
+    pub fn file_acl(&self) -> u64 {
+        self.u32_at(0x68) as u64 | (self.u16_at(0x76) as u64) << 32
+    }
+
+    pub fn set_file_acl(&mut self, block: u64) {
+        self.set_u32(0x68, block as u32);
+        self.set_u16(0x76, (block >> 32) as u16);
+    }
+
+    pub fn set_dtime(&mut self, secs: u32) {
+        self.set_u32(0x14, secs);
+    }
+
+    /// A timestamp whose seconds are at `offset`, and if the inode has it,
+    /// whose nanoseconds and high bits of the seconds are at `extra`.
+    fn time(&self, offset: usize, extra: usize) -> TimeSpec {
+        let mut sec = self.u32_at(offset) as i32 as i64;
+        let mut nsec = 0;
+        if self.has_field(extra, 4) {
+            let extra = self.u32_at(extra);
+            sec += ((extra & 0x3) as i64) << 32;
+            nsec = (extra >> 2) as u64;
+        }
+        TimeSpec {
+            sec: sec.max(0) as u64,
+            nsec,
+        }
+    }
+
+    fn set_time(&mut self, offset: usize, extra: usize, time: TimeSpec) {
+        let sec = time.sec as i64;
+        self.set_u32(offset, sec as u32);
+        if self.has_field(extra, 4) {
+            let epoch = ((sec - sec as i32 as i64) >> 32) as u32 & 0x3;
+            self.set_u32(extra, (time.nsec as u32) << 2 | epoch);
+        }
+    }
+
+    pub fn atime(&self) -> TimeSpec {
+        self.time(0x8, 0x8c)
+    }
+
+    pub fn ctime(&self) -> TimeSpec {
+        self.time(0xc, 0x84)
+    }
+
+    pub fn mtime(&self) -> TimeSpec {
+        self.time(0x10, 0x88)
+    }
+
+    pub fn set_atime(&mut self, time: TimeSpec) {
+        self.set_time(0x8, 0x8c, time);
+    }
+
+    pub fn set_ctime(&mut self, time: TimeSpec) {
+        self.set_time(0xc, 0x84, time);
+    }
+
+    pub fn set_mtime(&mut self, time: TimeSpec) {
+        self.set_time(0x10, 0x88, time);
+    }
+
+    pub fn set_crtime(&mut self, time: TimeSpec) {
+        if self.has_field(0x90, 4) {
+            self.set_time(0x90, 0x94, time);
+        }
+    }
+
+    /// The seed of the checksums of this inode and of the blocks it owns.
+    pub fn csum_seed(&self, fs_seed: u32, ino: u32) -> u32 {
+        let seed = crc32c(fs_seed, &ino.to_le_bytes());
+        crc32c(seed, &self.generation().to_le_bytes())
+    }
+
+    /// Store the checksum of the inode, computed with its checksum fields
+    /// zeroed.
+    pub fn update_checksum(&mut self, fs_seed: u32, ino: u32) {
+        let has_hi = self.has_field(CHECKSUM_HI, 2);
+        self.set_u16(CHECKSUM_LO, 0);
+        if has_hi {
+            self.set_u16(CHECKSUM_HI, 0);
+        }
+        let checksum = crc32c(self.csum_seed(fs_seed, ino), &self.0);
+        self.set_u16(CHECKSUM_LO, checksum as u16);
+        if has_hi {
+            self.set_u16(CHECKSUM_HI, (checksum >> 16) as u16);
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! Mapping file blocks to blocks of the volume: extent trees, and the block
+//! maps of files from ext2 and ext3, which are only read.
+
+use alloc::{vec, vec::Vec};
+
+use crate::fs::{FsError, FsResult};
+
+use super::{
+    crc::crc32c,
+    disk::{DiskInode, EXTENTS_FL, I_BLOCK_SIZE},
+    Ext4Fs,
+};
+
+const EXTENT_MAGIC: u16 = 0xf30a;
+const HEADER_SIZE: usize = 12;
+const ENTRY_SIZE: usize = 12;
+/// Longest initialized extent; longer lengths mark unwritten extents.
+const MAX_LEN: u32 = 32768;
+/// Levels of the tree, beyond which it is corrupted.
+const MAX_DEPTH: u16 = 5;
+/// Pointers in `i_block` of a block-mapped file: 12 direct, then single,
+/// double and triple indirect.
+const DIRECT_BLOCKS: usize = 12;
+
+/// Where a file block is.
+pub enum Mapping {
+    /// At `start`, followed by `len - 1` more blocks of the same extent.
+    /// Unwritten blocks are allocated but read as zeroes.
+    Mapped { start: u64, len: u32, unwritten: bool },
+    /// In a hole, which goes on for `len` blocks.
+    Hole { len: u32 },
+}
+
+/// An entry of a leaf: `len` blocks from file block `logical` on.
+#[derive(Clone, Copy)]
+struct Extent {
+    logical: u32,
+    len: u32,
+    start: u64,
+    unwritten: bool,
+}
+
+impl Extent {
+    fn parse(raw: &[u8]) -> Self {
+        let len = u16::from_le_bytes([raw[4], raw[5]]) as u32;
+        Self {
+            logical: u32::from_le_bytes(raw[0..4].try_into().unwrap()),
+            len: if len > MAX_LEN { len - MAX_LEN } else { len },
+            start: (u16::from_le_bytes([raw[6], raw[7]]) as u64) << 32 | u32::from_le_bytes(raw[8..12].try_into().unwrap()) as u64,
+            unwritten: len > MAX_LEN,
+        }
+    }
+
+    fn store(&self, raw: &mut [u8]) {
+        let len = if self.unwritten { self.len + MAX_LEN } else { self.len };
+        raw[0..4].copy_from_slice(&self.logical.to_le_bytes());
+        raw[4..6].copy_from_slice(&(len as u16).to_le_bytes());
+        raw[6..8].copy_from_slice(&((self.start >> 32) as u16).to_le_bytes());
+        raw[8..12].copy_from_slice(&(self.start as u32).to_le_bytes());
+    }
+}
+
+/// An entry of an interior node: the subtree at `child` maps the file
+/// blocks from `logical` on.
+#[derive(Clone, Copy)]
+struct Index {
+    logical: u32,
+    child: u64,
+}
+
+impl Index {
+    fn parse(raw: &[u8]) -> Self {
+        Self {
+            logical: u32::from_le_bytes(raw[0..4].try_into().unwrap()),
+            child: u32::from_le_bytes(raw[4..8].try_into().unwrap()) as u64 | (u16::from_le_bytes([raw[8], raw[9]]) as u64) << 32,
+        }
+    }
+
+    fn store(&self, raw: &mut [u8]) {
+        raw[0..4].copy_from_slice(&self.logical.to_le_bytes());
+        raw[4..8].copy_from_slice(&(self.child as u32).to_le_bytes());
+        raw[8..10].copy_from_slice(&((self.child >> 32) as u16).to_le_bytes());
+        raw[10..12].fill(0);
+    }
+}
+
+/// A node of the tree, in `i_block` or a block of its own.
+struct Node {
+    depth: u16,
+    max: usize,
+    entries: Vec<[u8; ENTRY_SIZE]>,
+}
+
+impl Node {
+    fn parse(raw: &[u8]) -> FsResult<Self> {
+        let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+        let count = u16_at(2) as usize;
+        let max = u16_at(4) as usize;
+        let depth = u16_at(6);
+        if u16_at(0) != EXTENT_MAGIC || count > max || HEADER_SIZE + max * ENTRY_SIZE > raw.len() || depth > MAX_DEPTH {
+            return Err(FsError::Corrupted);
+        }
+        let entries = raw[HEADER_SIZE..HEADER_SIZE + count * ENTRY_SIZE]
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| entry.try_into().unwrap())
+            .collect();
+        Ok(Self { depth, max, entries })
+    }
+
+    /// An empty node filling `size` bytes.
+    fn empty(depth: u16, size: usize) -> Self {
+        Self {
+            depth,
+            max: (size - HEADER_SIZE) / ENTRY_SIZE,
+            entries: Vec::new(),
+        }
+    }
+
+    fn store(&self, raw: &mut [u8]) {
+        raw[0..2].copy_from_slice(&EXTENT_MAGIC.to_le_bytes());
+        raw[2..4].copy_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        raw[4..6].copy_from_slice(&(self.max as u16).to_le_bytes());
+        raw[6..8].copy_from_slice(&self.depth.to_le_bytes());
+        raw[8..12].fill(0);
+        for (slot, entry) in raw[HEADER_SIZE..].chunks_exact_mut(ENTRY_SIZE).zip(self.entries.iter()) {
+            slot.copy_from_slice(entry);
+        }
+    }
+
+    fn extent(&self, i: usize) -> Extent {
+        Extent::parse(&self.entries[i])
+    }
+
+    fn index(&self, i: usize) -> Index {
+        Index::parse(&self.entries[i])
+    }
+
+    /// The entry whose range holds file block `block`: the last one starting
+    /// at or before it.
+    fn find(&self, block: u32) -> Option<usize> {
+        let after = self
+            .entries
+            .partition_point(|entry| u32::from_le_bytes(entry[0..4].try_into().unwrap()) <= block);
+        after.checked_sub(1)
+    }
+
+    /// The first file block mapped by entry `i`.
+    fn first(&self, i: usize) -> u32 {
+        u32::from_le_bytes(self.entries[i][0..4].try_into().unwrap())
+    }
+}
+
+/// Write an empty extent tree to `i_block`.
+pub fn init(inode: &mut DiskInode) {
+    Node::empty(0, I_BLOCK_SIZE).store(inode.i_block_mut());
+    inode.set_flags(inode.flags() | EXTENTS_FL);
+}
+
+impl Ext4Fs {
+    fn read_node(&self, block: u64) -> FsResult<Node> {
+        let mut raw = vec![0; self.layout.block_size];
+        self.read_block(block, &mut raw)?;
+        Node::parse(&raw)
+    }
+
+    /// Write `node` to `block`, with the checksum after its last slot.
+    fn write_node(&self, block: u64, node: &Node, seed: u32) -> FsResult<()> {
+        let mut raw = vec![0; self.layout.block_size];
+        node.store(&mut raw);
+        let tail = HEADER_SIZE + node.max * ENTRY_SIZE;
+        if self.layout.has_metadata_csum() && tail + 4 <= raw.len() {
+            let checksum = crc32c(seed, &raw[..tail]);
+            raw[tail..tail + 4].copy_from_slice(&checksum.to_le_bytes());
+        }
+        self.write_block(block, &raw)
+    }
+
+    /// Where file block `block` of `inode` is.
+    pub(super) fn map_block(&self, inode: &DiskInode, block: u32) -> FsResult<Mapping> {
+        if inode.flags() & EXTENTS_FL == 0 {
+            return self.map_indirect(inode, block);
+        }
+        let mut node = Node::parse(inode.i_block())?;
+        // The next mapped block, as far as the nodes walked tell.
+        let mut next = u32::MAX;
+        loop {
+            let found = node.find(block);
+            let following = found.map_or(0, |i| i + 1);
+            if following < node.entries.len() {
+                next = next.min(node.first(following));
+            }
+            if node.depth == 0 {
+                if let Some(extent) = found.map(|i| node.extent(i)) {
+                    let offset = block - extent.logical;
+                    if offset < extent.len {
+                        return Ok(Mapping::Mapped {
+                            start: extent.start + offset as u64,
+                            len: extent.len - offset,
+                            unwritten: extent.unwritten,
+                        });
+                    }
+                }
+                return Ok(Mapping::Hole { len: next - block });
+            }
+            let Some(i) = found else {
+                return Ok(Mapping::Hole { len: next - block });
+            };
+            let depth = node.depth;
+            node = self.read_node(node.index(i).child)?;
+            if node.depth + 1 != depth {
+                return Err(FsError::Corrupted);
+            }
+        }
+    }
+
+    /// Where file block `block` of a block-mapped file is.
+    fn map_indirect(&self, inode: &DiskInode, block: u32) -> FsResult<Mapping> {
+        let per_block = (self.layout.block_size / 4) as u64;
+        let pointer = |raw: &[u8], i: usize| u32::from_le_bytes(raw[i * 4..i * 4 + 4].try_into().unwrap()) as u64;
+        let mut index = block as u64;
+        let (mut target, mut levels) = if index < DIRECT_BLOCKS as u64 {
+            (pointer(inode.i_block(), index as usize), 0)
+        } else {
+            index -= DIRECT_BLOCKS as u64;
+            let mut levels = 1;
+            let mut span = per_block;
+            while index >= span {
+                index -= span;
+                levels += 1;
+                span *= per_block;
+                if levels > 3 {
+                    return Err(FsError::InvalidInput);
+                }
+            }
+            (pointer(inode.i_block(), DIRECT_BLOCKS + levels - 1), levels)
+        };
+        let mut raw = vec![0; self.layout.block_size];
+        while levels > 0 && target != 0 {
+            levels -= 1;
+            self.read_block(target, &mut raw)?;
+            target = pointer(&raw, (index / per_block.pow(levels as u32) % per_block) as usize);
+        }
+        Ok(match target {
+            0 => Mapping::Hole { len: 1 },
+            start => Mapping::Mapped {
+                start,
+                len: 1,
+                unwritten: false,
+            },
+        })
+    }
+
+    /// Allocate a block for a node of the tree of `inode`.
+    fn alloc_node(&self, inode: &mut DiskInode, goal: u64) -> FsResult<u64> {
+        let (block, _) = self.alloc_blocks(goal, 1)?;
+        inode.set_blocks(inode.blocks() + (self.layout.block_size / 512) as u64);
+        Ok(block)
+    }
+
+    /// Map the `len` file blocks from `logical` on, which must be a hole, to
+    /// the blocks from `start` on.
+    pub(super) fn insert_extent(&self, inode: &mut DiskInode, seed: u32, logical: u32, len: u32, start: u64) -> FsResult<()> {
+        if inode.flags() & EXTENTS_FL == 0 {
+            return Err(FsError::Unsupported);
+        }
+        let extent = Extent {
+            logical,
+            len,
+            start,
+            unwritten: false,
+        };
+        let mut root = Node::parse(inode.i_block())?;
+        let split = self.insert_into(inode, seed, &mut root, extent)?;
+        let mut root = match split {
+            // The root is full; move its entries down to a new node, which
+            // splits further below as needed.
+            Some(sibling) => {
+                let mut child = Node::empty(root.depth, self.layout.block_size);
+                child.entries = core::mem::take(&mut root.entries);
+                let first = child.first(0);
+                let block = self.alloc_node(inode, start)?;
+                self.write_node(block, &child, seed)?;
+                let mut new_root = Node::empty(root.depth + 1, I_BLOCK_SIZE);
+                for index in [Index { logical: first, child: block }, sibling] {
+                    let mut raw = [0; ENTRY_SIZE];
+                    index.store(&mut raw);
+                    new_root.entries.push(raw);
+                }
+                new_root
+            }
+            None => root,
+        };
+        root.max = (I_BLOCK_SIZE - HEADER_SIZE) / ENTRY_SIZE;
+        root.store(inode.i_block_mut());
+        Ok(())
+    }
+
+    /// Insert `extent` in the subtree of `node`. If `node` had to be split,
+    /// return the index of the new node holding its upper half, which the
+    /// caller stores.
+    fn insert_into(&self, inode: &mut DiskInode, seed: u32, node: &mut Node, extent: Extent) -> FsResult<Option<Index>> {
+        let found = node.find(extent.logical);
+        let mut raw = [0; ENTRY_SIZE];
+        if node.depth == 0 {
+            // Grow the extent before, if the new one continues it.
+            if let Some(i) = found {
+                let mut before = node.extent(i);
+                if !before.unwritten
+                    && before.logical + before.len == extent.logical
+                    && before.start + before.len as u64 == extent.start
+                    && before.len + extent.len <= MAX_LEN
+                {
+                    before.len += extent.len;
+                    before.store(&mut node.entries[i]);
+                    return Ok(None);
+                }
+            }
+            extent.store(&mut raw);
+        } else {
+            let i = found.unwrap_or(0);
+            let mut index = node.index(i);
+            let mut child = self.read_node(index.child)?;
+            let split = self.insert_into(inode, seed, &mut child, extent)?;
+            self.write_node(index.child, &child, seed)?;
+            if extent.logical < index.logical {
+                index.logical = extent.logical;
+                index.store(&mut node.entries[i]);
+            }
+            let Some(sibling) = split else {
+                return Ok(None);
+            };
+            sibling.store(&mut raw);
+        }
+        let logical = u32::from_le_bytes(raw[0..4].try_into().unwrap());
+        let at = node.entries.partition_point(|entry| u32::from_le_bytes(entry[0..4].try_into().unwrap()) < logical);
+        node.entries.insert(at, raw);
+        if node.entries.len() <= node.max {
+            return Ok(None);
+        }
+        // Keep the lower half here and move the upper half to a new node.
+        let mut sibling = Node::empty(node.depth, self.layout.block_size);
+        sibling.entries = node.entries.split_off(node.entries.len() / 2);
+        let block = self.alloc_node(inode, extent.start)?;
+        self.write_node(block, &sibling, seed)?;
+        Ok(Some(Index { logical: sibling.first(0), child: block }))
+    }
+
+    /// Unmap the file blocks of `inode` from `from` on, freeing them and the
+    /// nodes left empty.
+    pub(super) fn truncate_extents(&self, inode: &mut DiskInode, seed: u32, from: u32) -> FsResult<()> {
+        if inode.flags() & EXTENTS_FL == 0 {
+            return Err(FsError::Unsupported);
+        }
+        let mut root = Node::parse(inode.i_block())?;
+        self.truncate_node(inode, seed, &mut root, from)?;
+        if root.entries.is_empty() {
+            root.depth = 0;
+        }
+        root.store(inode.i_block_mut());
+        Ok(())
+    }
+
+    fn truncate_node(&self, inode: &mut DiskInode, seed: u32, node: &mut Node, from: u32) -> FsResult<()> {
+        let sectors = (self.layout.block_size / 512) as u64;
+        let mut i = node.entries.len();
+        while i > 0 {
+            i -= 1;
+            if node.depth == 0 {
+                let mut extent = node.extent(i);
+                if extent.logical + extent.len <= from {
+                    break;
+                }
+                let keep = from.saturating_sub(extent.logical);
+                self.free_blocks(extent.start + keep as u64, (extent.len - keep) as u64)?;
+                inode.set_blocks(inode.blocks().saturating_sub((extent.len - keep) as u64 * sectors));
+                if keep == 0 {
+                    node.entries.remove(i);
+                } else {
+                    extent.len = keep;
+                    extent.store(&mut node.entries[i]);
+                }
+            } else {
+                let index = node.index(i);
+                let mut child = self.read_node(index.child)?;
+                self.truncate_node(inode, seed, &mut child, from)?;
+                if child.entries.is_empty() {
+                    self.free_blocks(index.child, 1)?;
+                    inode.set_blocks(inode.blocks().saturating_sub(sectors));
+                    node.entries.remove(i);
+                } else {
+                    self.write_node(index.child, &child, seed)?;
+                }
+                if index.logical < from {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Zero the unwritten extent holding file block `block` of `inode` and
+    /// mark it as written.
+    pub(super) fn mark_written(&self, inode: &mut DiskInode, seed: u32, block: u32) -> FsResult<()> {
+        let mut root = Node::parse(inode.i_block())?;
+        self.mark_in_node(seed, &mut root, block)?;
+        root.store(inode.i_block_mut());
+        Ok(())
+    }
+
+    fn mark_in_node(&self, seed: u32, node: &mut Node, block: u32) -> FsResult<()> {
+        let i = node.find(block).ok_or(FsError::Corrupted)?;
+        if node.depth == 0 {
+            let mut extent = node.extent(i);
+            let zeroes = vec![0; self.layout.block_size];
+            for block in extent.start..extent.start + extent.len as u64 {
+                self.write_block(block, &zeroes)?;
+            }
+            extent.unwritten = false;
+            extent.store(&mut node.entries[i]);
+            return Ok(());
+        }
+        let child_block = node.index(i).child;
+        let mut child = self.read_node(child_block)?;
+        self.mark_in_node(seed, &mut child, block)?;
+        self.write_node(child_block, &child, seed)
+    }
+
+    /// Free the blocks of a block-mapped file, with its indirect blocks.
+    pub(super) fn free_indirect(&self, inode: &mut DiskInode) -> FsResult<()> {
+        let pointers: Vec<u64> = inode
+            .i_block()
+            .chunks_exact(4)
+            .map(|raw| u32::from_le_bytes(raw.try_into().unwrap()) as u64)
+            .collect();
+        for (i, &block) in pointers.iter().enumerate() {
+            self.free_mapped(block, (i + 1).saturating_sub(DIRECT_BLOCKS))?;
+        }
+        inode.i_block_mut().fill(0);
+        inode.set_blocks(0);
+        Ok(())
+    }
+
+    /// Free `block`, and if it is an indirect block `levels` above the
+    /// data, what it points to.
+    fn free_mapped(&self, block: u64, levels: usize) -> FsResult<()> {
+        if block == 0 {
+            return Ok(());
+        }
+        if levels > 0 {
+            let mut raw = vec![0; self.layout.block_size];
+            self.read_block(block, &mut raw)?;
+            for pointer in raw.chunks_exact(4) {
+                self.free_mapped(u32::from_le_bytes(pointer.try_into().unwrap()) as u64, levels - 1)?;
+            }
+        }
+        self.free_blocks(block, 1)
+    }
+}
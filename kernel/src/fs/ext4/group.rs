@@ -0,0 +1,433 @@
+//! Block groups: their descriptors, and the bitmaps blocks and inodes are
+//! allocated from.
+
+use alloc::{vec, vec::Vec};
+
+use crate::fs::{FsError, FsResult};
+
+use super::{
+    crc::{crc16, crc32c},
+    Ext4Fs, RO_COMPAT_GDT_CSUM, RO_COMPAT_SPARSE_SUPER,
+};
+
+/// The inode bitmap and table of the group were never written.
+const BG_INODE_UNINIT: u16 = 0x1;
+/// The block bitmap of the group was never written, and only the group's
+/// metadata is in use.
+const BG_BLOCK_UNINIT: u16 = 0x2;
+
+/// Offset of the descriptor checksum.
+const DESC_CHECKSUM: usize = 0x1e;
+/// Descriptors at least this long hold the high halves of the bitmap
+/// checksums.
+const BLOCK_BITMAP_CSUM_HI_END: usize = 0x3a;
+const INODE_BITMAP_CSUM_HI_END: usize = 0x3c;
+/// `sparse_super2` keeps backups only in the groups the superblock names.
+const COMPAT_SPARSE_SUPER2: u32 = 0x200;
+
+/// A group descriptor, as stored.
+pub struct GroupDesc {
+    raw: [u8; 64],
+    size: usize,
+}
+
+impl GroupDesc {
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.raw[offset], self.raw[offset + 1]])
+    }
+
+    fn set_u16(&mut self, offset: usize, value: u16) {
+        self.raw[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.raw[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// A 64-bit field split in two halves, the high one only in 64-byte
+    /// descriptors.
+    fn split64(&self, lo: usize, hi: usize) -> u64 {
+        let high = if self.size >= hi + 4 { self.u32_at(hi) as u64 } else { 0 };
+        self.u32_at(lo) as u64 | high << 32
+    }
+
+    /// A 32-bit count split in two 16-bit halves.
+    fn split32(&self, lo: usize, hi: usize) -> u32 {
+        let high = if self.size >= hi + 2 { self.u16_at(hi) as u32 } else { 0 };
+        self.u16_at(lo) as u32 | high << 16
+    }
+
+    fn set_split32(&mut self, lo: usize, hi: usize, value: u32) {
+        self.set_u16(lo, value as u16);
+        if self.size >= hi + 2 {
+            self.set_u16(hi, (value >> 16) as u16);
+        }
+    }
+
+    pub fn block_bitmap(&self) -> u64 {
+        self.split64(0x0, 0x20)
+    }
+
+    pub fn inode_bitmap(&self) -> u64 {
+        self.split64(0x4, 0x24)
+    }
+
+    pub fn inode_table(&self) -> u64 {
+        self.split64(0x8, 0x28)
+    }
+
+    fn free_blocks(&self) -> u32 {
+        self.split32(0xc, 0x2c)
+    }
+
+    fn set_free_blocks(&mut self, count: u32) {
+        self.set_split32(0xc, 0x2c, count);
+    }
+
+    fn free_inodes(&self) -> u32 {
+        self.split32(0xe, 0x2e)
+    }
+
+    fn set_free_inodes(&mut self, count: u32) {
+        self.set_split32(0xe, 0x2e, count);
+    }
+
+    fn used_dirs(&self) -> u32 {
+        self.split32(0x10, 0x30)
+    }
+
+    fn set_used_dirs(&mut self, count: u32) {
+        self.set_split32(0x10, 0x30, count);
+    }
+
+    fn flags(&self) -> u16 {
+        self.u16_at(0x12)
+    }
+
+    fn clear_flags(&mut self, flags: u16) {
+        self.set_u16(0x12, self.flags() & !flags);
+    }
+
+    /// Inodes at the end of the table never used.
+    fn itable_unused(&self) -> u32 {
+        self.split32(0x1c, 0x32)
+    }
+
+    fn set_itable_unused(&mut self, count: u32) {
+        self.set_split32(0x1c, 0x32, count);
+    }
+}
+
+fn test_bit(bitmap: &[u8], bit: usize) -> bool {
+    bitmap[bit / 8] & (1 << (bit % 8)) != 0
+}
+
+fn set_bit(bitmap: &mut [u8], bit: usize, value: bool) {
+    if value {
+        bitmap[bit / 8] |= 1 << (bit % 8);
+    } else {
+        bitmap[bit / 8] &= !(1 << (bit % 8));
+    }
+}
+
+/// Whether `n` is a power of `base`.
+fn is_power_of(mut n: u32, base: u32) -> bool {
+    while n > 1 && n % base == 0 {
+        n /= base;
+    }
+    n == 1
+}
+
+impl Ext4Fs {
+    fn desc_pos(&self, group: u32) -> u64 {
+        self.block_pos(self.layout.gdt_start()) + group as u64 * self.layout.desc_size as u64
+    }
+
+    pub(super) fn group_desc(&self, group: u32) -> FsResult<GroupDesc> {
+        if group >= self.layout.group_count {
+            return Err(FsError::Corrupted);
+        }
+        let size = self.layout.desc_size;
+        let mut desc = GroupDesc { raw: [0; 64], size };
+        self.cache.read_at(self.desc_pos(group), &mut desc.raw[..size])?;
+        Ok(desc)
+    }
+
+    fn write_group_desc(&self, group: u32, desc: &mut GroupDesc) -> FsResult<()> {
+        let size = desc.size;
+        let le_group = group.to_le_bytes();
+        let checksum = if self.layout.has_metadata_csum() {
+            let mut crc = crc32c(self.layout.csum_seed, &le_group);
+            crc = crc32c(crc, &desc.raw[..DESC_CHECKSUM]);
+            crc = crc32c(crc, &[0, 0]);
+            crc = crc32c(crc, &desc.raw[DESC_CHECKSUM + 2..size]);
+            crc as u16
+        } else if self.layout.ro_compat & RO_COMPAT_GDT_CSUM != 0 {
+            let mut crc = crc16(!0, &self.layout.uuid);
+            crc = crc16(crc, &le_group);
+            crc = crc16(crc, &desc.raw[..DESC_CHECKSUM]);
+            crc16(crc, &desc.raw[DESC_CHECKSUM + 2..size])
+        } else {
+            0
+        };
+        desc.set_u16(DESC_CHECKSUM, checksum);
+        self.cache.write_at(self.desc_pos(group), &desc.raw[..size])
+    }
+
+    fn group_start(&self, group: u32) -> u64 {
+        self.layout.first_data_block + group as u64 * self.layout.blocks_per_group as u64
+    }
+
+    /// Blocks in `group`, which is fewer than usual for the last one.
+    fn blocks_in_group(&self, group: u32) -> usize {
+        let left = self.layout.blocks_count - self.group_start(group);
+        left.min(self.layout.blocks_per_group as u64) as usize
+    }
+
+    /// Whether `group` starts with a copy of the superblock and descriptors.
+    fn has_super(&self, group: u32) -> bool {
+        if group == 0 {
+            return true;
+        }
+        if self.layout.compat & COMPAT_SPARSE_SUPER2 != 0 {
+            return self.layout.backup_groups.contains(&group);
+        }
+        self.layout.ro_compat & RO_COMPAT_SPARSE_SUPER == 0
+            || group == 1
+            || is_power_of(group, 3)
+            || is_power_of(group, 5)
+            || is_power_of(group, 7)
+    }
+
+    fn inode_table_blocks(&self) -> u64 {
+        (self.layout.inodes_per_group as u64 * self.layout.inode_size as u64).div_ceil(self.layout.block_size as u64)
+    }
+
+    /// The block bitmap of `group`, made up if it was never written: only
+    /// the metadata placed in the group is in use then.
+    fn block_bitmap(&self, group: u32, desc: &GroupDesc) -> FsResult<Vec<u8>> {
+        let mut bitmap = vec![0; self.layout.block_size];
+        if desc.flags() & BG_BLOCK_UNINIT == 0 {
+            self.read_block(desc.block_bitmap(), &mut bitmap)?;
+            return Ok(bitmap);
+        }
+        let start = self.group_start(group);
+        let count = self.blocks_in_group(group);
+        let mut mark = |block: u64| {
+            if (start..start + count as u64).contains(&block) {
+                set_bit(&mut bitmap, (block - start) as usize, true);
+            }
+        };
+        if self.has_super(group) {
+            for block in 0..1 + self.layout.gdt_blocks() + self.layout.reserved_gdt_blocks {
+                mark(start + block);
+            }
+        }
+        for other in 0..self.layout.group_count {
+            let other = self.group_desc(other)?;
+            mark(other.block_bitmap());
+            mark(other.inode_bitmap());
+            let table = other.inode_table();
+            for block in table..table + self.inode_table_blocks() {
+                mark(block);
+            }
+        }
+        for bit in count..self.layout.block_size * 8 {
+            set_bit(&mut bitmap, bit, true);
+        }
+        Ok(bitmap)
+    }
+
+    fn write_block_bitmap(&self, desc: &mut GroupDesc, bitmap: &[u8]) -> FsResult<()> {
+        if self.layout.has_metadata_csum() {
+            let checksum = crc32c(self.layout.csum_seed, &bitmap[..self.layout.blocks_per_group as usize / 8]);
+            desc.set_u16(0x18, checksum as u16);
+            if desc.size >= BLOCK_BITMAP_CSUM_HI_END {
+                desc.set_u16(0x38, (checksum >> 16) as u16);
+            }
+        }
+        desc.clear_flags(BG_BLOCK_UNINIT);
+        self.write_block(desc.block_bitmap(), bitmap)
+    }
+
+    /// The inode bitmap of `group`, empty if it was never written.
+    fn inode_bitmap(&self, desc: &GroupDesc) -> FsResult<Vec<u8>> {
+        let mut bitmap = vec![0; self.layout.block_size];
+        if desc.flags() & BG_INODE_UNINIT == 0 {
+            self.read_block(desc.inode_bitmap(), &mut bitmap)?;
+        } else {
+            for bit in self.layout.inodes_per_group as usize..self.layout.block_size * 8 {
+                set_bit(&mut bitmap, bit, true);
+            }
+        }
+        Ok(bitmap)
+    }
+
+    fn write_inode_bitmap(&self, desc: &mut GroupDesc, bitmap: &[u8]) -> FsResult<()> {
+        if self.layout.has_metadata_csum() {
+            let checksum = crc32c(self.layout.csum_seed, &bitmap[..self.layout.inodes_per_group as usize / 8]);
+            desc.set_u16(0x1a, checksum as u16);
+            if desc.size >= INODE_BITMAP_CSUM_HI_END {
+                desc.set_u16(0x3a, (checksum >> 16) as u16);
+            }
+        }
+        desc.clear_flags(BG_INODE_UNINIT);
+        self.write_block(desc.inode_bitmap(), bitmap)
+    }
+
+    /// Allocate up to `count` contiguous blocks, as near after `goal` as
+    /// possible. Returns the first block and how many were allocated.
+    pub(super) fn alloc_blocks(&self, goal: u64, count: u32) -> FsResult<(u64, u32)> {
+        self.check_writable()?;
+        let _guard = self.alloc_lock.lock();
+        let layout = &self.layout;
+        let goal = if (layout.first_data_block..layout.blocks_count).contains(&goal) {
+            goal
+        } else {
+            layout.first_data_block
+        };
+        let goal_group = ((goal - layout.first_data_block) / layout.blocks_per_group as u64) as u32;
+        // The goal's group comes again last, for the blocks before the goal.
+        for i in 0..=layout.group_count {
+            let group = (goal_group + i) % layout.group_count;
+            let mut desc = self.group_desc(group)?;
+            if desc.free_blocks() == 0 {
+                continue;
+            }
+            let mut bitmap = self.block_bitmap(group, &desc)?;
+            let from = if i == 0 { (goal - self.group_start(group)) as usize } else { 0 };
+            let limit = self.blocks_in_group(group);
+            let Some(first) = (from..limit).find(|&bit| !test_bit(&bitmap, bit)) else {
+                continue;
+            };
+            let mut len = 0;
+            while len < count as usize && first + len < limit && !test_bit(&bitmap, first + len) {
+                set_bit(&mut bitmap, first + len, true);
+                len += 1;
+            }
+            desc.set_free_blocks(desc.free_blocks().saturating_sub(len as u32));
+            self.write_block_bitmap(&mut desc, &bitmap)?;
+            self.write_group_desc(group, &mut desc)?;
+            self.add_free_counts(-(len as i64), 0);
+            return Ok((self.group_start(group) + first as u64, len as u32));
+        }
+        Err(FsError::NoSpace)
+    }
+
+    /// Free `count` blocks from `start` on.
+    pub(super) fn free_blocks(&self, start: u64, count: u64) -> FsResult<()> {
+        let layout = &self.layout;
+        if start < layout.first_data_block || start + count > layout.blocks_count {
+            return Err(FsError::Corrupted);
+        }
+        let _guard = self.alloc_lock.lock();
+        let mut block = start;
+        while block < start + count {
+            let group = ((block - layout.first_data_block) / layout.blocks_per_group as u64) as u32;
+            let group_start = self.group_start(group);
+            let end = (start + count).min(group_start + layout.blocks_per_group as u64);
+            let mut desc = self.group_desc(group)?;
+            let mut bitmap = self.block_bitmap(group, &desc)?;
+            let mut freed = 0;
+            for bit in (block - group_start) as usize..(end - group_start) as usize {
+                if test_bit(&bitmap, bit) {
+                    set_bit(&mut bitmap, bit, false);
+                    freed += 1;
+                }
+            }
+            desc.set_free_blocks(desc.free_blocks() + freed);
+            self.write_block_bitmap(&mut desc, &bitmap)?;
+            self.write_group_desc(group, &mut desc)?;
+            self.add_free_counts(freed as i64, 0);
+            block = end;
+        }
+        Ok(())
+    }
+
+    /// Allocate an inode near the directory `parent`, or for a directory in
+    /// the group with the most free inodes, to spread directories out.
+    pub(super) fn alloc_inode(&self, parent: u32, dir: bool) -> FsResult<u32> {
+        self.check_writable()?;
+        let _guard = self.alloc_lock.lock();
+        let layout = &self.layout;
+        let inodes_per_group = layout.inodes_per_group;
+        let mut start = (parent - 1) / inodes_per_group;
+        if dir {
+            let mut most = 0;
+            for group in 0..layout.group_count {
+                let free = self.group_desc(group)?.free_inodes();
+                if free > most {
+                    most = free;
+                    start = group;
+                }
+            }
+        }
+        for i in 0..layout.group_count {
+            let group = (start + i) % layout.group_count;
+            let mut desc = self.group_desc(group)?;
+            if desc.free_inodes() == 0 {
+                continue;
+            }
+            let mut bitmap = self.inode_bitmap(&desc)?;
+            let Some(bit) = (0..inodes_per_group as usize).find(|&bit| !test_bit(&bitmap, bit)) else {
+                continue;
+            };
+            set_bit(&mut bitmap, bit, true);
+            desc.set_free_inodes(desc.free_inodes() - 1);
+            if dir {
+                desc.set_used_dirs(desc.used_dirs() + 1);
+            }
+            let unused = inodes_per_group - bit as u32 - 1;
+            if unused < desc.itable_unused() {
+                desc.set_itable_unused(unused);
+            }
+            self.write_inode_bitmap(&mut desc, &bitmap)?;
+            self.write_group_desc(group, &mut desc)?;
+            self.add_free_counts(0, -1);
+            return Ok(group * inodes_per_group + bit as u32 + 1);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    pub(super) fn free_inode(&self, ino: u32, dir: bool) -> FsResult<()> {
+        let _guard = self.alloc_lock.lock();
+        let group = (ino - 1) / self.layout.inodes_per_group;
+        let bit = ((ino - 1) % self.layout.inodes_per_group) as usize;
+        let mut desc = self.group_desc(group)?;
+        let mut bitmap = self.inode_bitmap(&desc)?;
+        if !test_bit(&bitmap, bit) {
+            return Err(FsError::Corrupted);
+        }
+        set_bit(&mut bitmap, bit, false);
+        desc.set_free_inodes(desc.free_inodes() + 1);
+        if dir {
+            desc.set_used_dirs(desc.used_dirs().saturating_sub(1));
+        }
+        self.write_inode_bitmap(&mut desc, &bitmap)?;
+        self.write_group_desc(group, &mut desc)?;
+        self.add_free_counts(0, 1);
+        Ok(())
+    }
+
+    /// Make the free counts of the superblock those of the groups, which
+    /// the journal may have left ahead of it.
+    pub(super) fn recount_free(&self) -> FsResult<()> {
+        let (mut blocks, mut inodes) = (0, 0);
+        for group in 0..self.layout.group_count {
+            let desc = self.group_desc(group)?;
+            blocks += desc.free_blocks() as i64;
+            inodes += desc.free_inodes() as i64;
+        }
+        let (free_blocks, free_inodes) = self.free_counts();
+        self.add_free_counts(blocks - free_blocks as i64, inodes - free_inodes as i64);
+        Ok(())
+    }
+
+    /// Where the inode `ino` is stored.
+    pub(super) fn inode_pos(&self, ino: u32) -> FsResult<u64> {
+        let group = (ino - 1) / self.layout.inodes_per_group;
+        let index = ((ino - 1) % self.layout.inodes_per_group) as u64;
+        let table = self.group_desc(group)?.inode_table();
+        Ok(self.block_pos(table) + index * self.layout.inode_size as u64)
+    }
+}
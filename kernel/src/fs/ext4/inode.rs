@@ -0,0 +1,897 @@
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::{any::Any, sync::atomic::Ordering};
+use log::warn;
+
+use crate::{
+    fs::{DirEntry, FsError, FsResult, Inode, InodeType, Metadata, TimeSpec},
+    sched::SleepMutex,
+};
+
+use super::{
+    crc::crc32c,
+    dir::{self, DxRoot, Record, HASH_UNSIGNED, NAME_MAX},
+    disk::{DiskInode, EXTENTS_FL, HUGE_FILE_FL, INDEX_FL, I_BLOCK_SIZE},
+    extent::{self, Mapping},
+    Ext4Fs, COMPAT_DIR_INDEX, INCOMPAT_LARGEDIR, RO_COMPAT_DIR_NLINK,
+};
+
+/// Most links an inode may have. Directories with `dir_nlink` go past it by
+/// counting 1, for unknown.
+const LINK_MAX: u16 = 65000;
+/// Most blocks allocated at once, the longest extent.
+const MAX_ALLOC: u32 = 32768;
+/// Magic of blocks of extended attributes, and where their reference count
+/// and checksum are.
+const XATTR_MAGIC: u32 = 0xea02_0000;
+const XATTR_REFCOUNT: usize = 0x4;
+const XATTR_CHECKSUM: usize = 0x10;
+
+impl Ext4Fs {
+    /// The inode `ino` as stored, with its checksum checked.
+    pub(super) fn read_inode(&self, ino: u32) -> FsResult<DiskInode> {
+        let mut raw = vec![0; self.layout.inode_size];
+        self.cache.read_at(self.inode_pos(ino)?, &mut raw)?;
+        let inode = DiskInode::new(raw);
+        if self.layout.has_metadata_csum() {
+            let mut expected = DiskInode::new(inode.raw().to_vec());
+            expected.update_checksum(self.layout.csum_seed, ino);
+            if expected.raw() != inode.raw() {
+                warn!("ext4: checksum mismatch of inode {}", ino);
+                return Err(FsError::Corrupted);
+            }
+        }
+        Ok(inode)
+    }
+
+    fn write_inode(&self, ino: u32, inode: &mut DiskInode) -> FsResult<()> {
+        if self.layout.has_metadata_csum() {
+            inode.update_checksum(self.layout.csum_seed, ino);
+        }
+        self.cache.write_at(self.inode_pos(ino)?, inode.raw())
+    }
+}
+
+pub struct Ext4Inode {
+    fs: Arc<Ext4Fs>,
+    ino: u32,
+    kind: InodeType,
+    /// The inode as stored, written back on every change.
+    inner: SleepMutex<DiskInode>,
+}
+
+impl Ext4Inode {
+    pub(super) fn load(fs: &Arc<Ext4Fs>, ino: u32) -> FsResult<Arc<Self>> {
+        let disk = fs.read_inode(ino)?;
+        let kind = disk.kind().ok_or(FsError::Corrupted)?;
+        Ok(Arc::new(Self {
+            fs: fs.clone(),
+            ino,
+            kind,
+            inner: SleepMutex::new(disk),
+        }))
+    }
+
+    /// `inode` as an inode of the same volume.
+    fn same_fs<'a>(&self, inode: &'a Arc<dyn Inode>) -> FsResult<&'a Ext4Inode> {
+        inode
+            .as_any()
+            .downcast_ref::<Ext4Inode>()
+            .filter(|inode| Arc::ptr_eq(&inode.fs, &self.fs))
+            .ok_or(FsError::CrossDevice)
+    }
+
+    fn check_dir(&self) -> FsResult<()> {
+        match self.kind {
+            InodeType::Dir => Ok(()),
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    fn check_file(&self) -> FsResult<()> {
+        match self.kind {
+            InodeType::Dir => Err(FsError::IsDir),
+            _ => Ok(()),
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        self.fs.layout.block_size
+    }
+
+    /// The seed of the checksums of the blocks of this inode.
+    fn seed(&self, disk: &DiskInode) -> u32 {
+        disk.csum_seed(self.fs.layout.csum_seed, self.ino)
+    }
+
+    fn write_inode(&self, disk: &mut DiskInode) -> FsResult<()> {
+        self.fs.write_inode(self.ino, disk)
+    }
+
+    /// Record a change of the data.
+    fn touch(&self, disk: &mut DiskInode) -> FsResult<()> {
+        let now = TimeSpec::now();
+        disk.set_mtime(now);
+        disk.set_ctime(now);
+        self.write_inode(disk)
+    }
+
+    /// Record a change of the inode alone, such as its link count.
+    fn change(&self, disk: &mut DiskInode) -> FsResult<()> {
+        disk.set_ctime(TimeSpec::now());
+        self.write_inode(disk)
+    }
+
+    /// Write `buf` at `offset`, or at the end of the file if `None`,
+    /// returning where it went and how many bytes were written.
+    fn write(&self, offset: Option<usize>, buf: &[u8]) -> FsResult<(usize, usize)> {
+        self.check_file()?;
+        self.fs.check_writable()?;
+        let mut disk = self.inner.lock();
+        let offset = offset.unwrap_or(disk.size() as usize);
+        if buf.is_empty() {
+            return Ok((offset, 0));
+        }
+        // File blocks are numbered in 32 bits.
+        let end = offset
+            .checked_add(buf.len())
+            .filter(|&end| (end / self.block_size()) as u64 <= u32::MAX as u64)
+            .ok_or(FsError::NoSpace)?;
+        let written = self.write_data(&mut disk, offset, buf);
+        if end as u64 > disk.size() && written.is_ok() {
+            disk.set_size(end as u64);
+        }
+        self.touch(&mut disk)?;
+        written.map(|_| (offset, buf.len()))
+    }
+
+    /// Whether `i_block` maps data blocks, rather than holding the target
+    /// of a fast symbolic link or a device number.
+    fn has_blocks(&self, disk: &DiskInode) -> bool {
+        match self.kind {
+            InodeType::File | InodeType::Dir => true,
+            InodeType::Symlink => disk.size() >= I_BLOCK_SIZE as u64,
+            _ => false,
+        }
+    }
+
+    fn read_data(&self, disk: &DiskInode, offset: usize, buf: &mut [u8]) -> FsResult<()> {
+        let block_size = self.block_size();
+        let mut done = 0;
+        while done < buf.len() {
+            let at = offset + done;
+            let within = at % block_size;
+            let (start, run) = match self.fs.map_block(disk, (at / block_size) as u32)? {
+                Mapping::Mapped {
+                    start,
+                    len,
+                    unwritten: false,
+                } => (Some(start), len),
+                Mapping::Mapped { len, .. } | Mapping::Hole { len } => (None, len),
+            };
+            let chunk = (run as usize * block_size - within).min(buf.len() - done);
+            match start {
+                Some(start) => self.fs.cache.read_at(self.fs.block_pos(start) + within as u64, &mut buf[done..done + chunk])?,
+                None => buf[done..done + chunk].fill(0),
+            }
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    fn write_data(&self, disk: &mut DiskInode, offset: usize, buf: &[u8]) -> FsResult<()> {
+        let block_size = self.block_size();
+        let mut done = 0;
+        while done < buf.len() {
+            let at = offset + done;
+            let within = at % block_size;
+            let wanted = (within + buf.len() - done).div_ceil(block_size) as u32;
+            let (start, run) = self.map_for_write(disk, (at / block_size) as u32, wanted)?;
+            let chunk = (run as usize * block_size - within).min(buf.len() - done);
+            self.fs.cache.write_at(self.fs.block_pos(start) + within as u64, &buf[done..done + chunk])?;
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    /// Where file block `block` is, allocating it and up to `wanted - 1`
+    /// blocks after it in a hole. Returns the first block and how many are
+    /// contiguous from there.
+    fn map_for_write(&self, disk: &mut DiskInode, block: u32, wanted: u32) -> FsResult<(u64, u32)> {
+        let seed = self.seed(disk);
+        let len = match self.fs.map_block(disk, block)? {
+            Mapping::Mapped {
+                start,
+                len,
+                unwritten: false,
+            } => return Ok((start, len)),
+            Mapping::Mapped { start, len, .. } => {
+                self.fs.mark_written(disk, seed, block)?;
+                return Ok((start, len));
+            }
+            Mapping::Hole { len } => len,
+        };
+        let (start, count) = self.fs.alloc_blocks(self.goal(disk, block)?, wanted.min(len).min(MAX_ALLOC))?;
+        let block_size = self.block_size();
+        let mapped = self
+            .fs
+            .cache
+            .zero(self.fs.block_pos(start), count as usize * block_size)
+            .and_then(|_| self.fs.insert_extent(disk, seed, block, count, start));
+        if let Err(err) = mapped {
+            self.fs.free_blocks(start, count as u64)?;
+            return Err(err);
+        }
+        disk.set_blocks(disk.blocks() + (count as usize * block_size / 512) as u64);
+        Ok((start, count))
+    }
+
+    /// Where to allocate file block `block`: after the block before it, or
+    /// else in the group of the inode.
+    fn goal(&self, disk: &DiskInode, block: u32) -> FsResult<u64> {
+        if let Some(before) = block.checked_sub(1) {
+            if let Mapping::Mapped { start, .. } = self.fs.map_block(disk, before)? {
+                return Ok(start + 1);
+            }
+        }
+        let layout = &self.fs.layout;
+        let group = (self.ino - 1) / layout.inodes_per_group;
+        Ok(layout.first_data_block + group as u64 * layout.blocks_per_group as u64)
+    }
+
+    /// Free the data past the first `len` bytes, zeroing the rest of the
+    /// block `len` ends in.
+    fn release(&self, disk: &mut DiskInode, len: usize) -> FsResult<()> {
+        let block_size = self.block_size();
+        let keep = len.div_ceil(block_size) as u32;
+        let extents = disk.flags() & EXTENTS_FL != 0;
+        // Block maps are only freed whole.
+        if !extents && keep > 0 {
+            return Err(FsError::Unsupported);
+        }
+        if len % block_size != 0 {
+            if let Mapping::Mapped {
+                start,
+                unwritten: false,
+                ..
+            } = self.fs.map_block(disk, keep - 1)?
+            {
+                let within = len % block_size;
+                self.fs.cache.zero(self.fs.block_pos(start) + within as u64, block_size - within)?;
+            }
+        }
+        match extents {
+            true => {
+                let seed = self.seed(disk);
+                self.fs.truncate_extents(disk, seed, keep)
+            }
+            false => self.fs.free_indirect(disk),
+        }
+    }
+
+    /// Drop the block of extended attributes, shared with other inodes.
+    fn release_xattrs(&self, disk: &mut DiskInode) -> FsResult<()> {
+        let block = disk.file_acl();
+        if block == 0 {
+            return Ok(());
+        }
+        let mut raw = vec![0; self.block_size()];
+        self.fs.read_block(block, &mut raw)?;
+        let u32_at = |raw: &[u8], offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+        if u32_at(&raw, 0) == XATTR_MAGIC {
+            let refs = u32_at(&raw, XATTR_REFCOUNT);
+            if refs <= 1 {
+                self.fs.free_blocks(block, 1)?;
+            } else {
+                raw[XATTR_REFCOUNT..XATTR_REFCOUNT + 4].copy_from_slice(&(refs - 1).to_le_bytes());
+                if self.fs.layout.has_metadata_csum() {
+                    raw[XATTR_CHECKSUM..XATTR_CHECKSUM + 4].fill(0);
+                    let checksum = crc32c(crc32c(self.fs.layout.csum_seed, &block.to_le_bytes()), &raw);
+                    raw[XATTR_CHECKSUM..XATTR_CHECKSUM + 4].copy_from_slice(&checksum.to_le_bytes());
+                }
+                self.fs.write_block(block, &raw)?;
+            }
+        }
+        disk.set_file_acl(0);
+        disk.set_blocks(disk.blocks().saturating_sub((self.block_size() / 512) as u64));
+        Ok(())
+    }
+
+    /// Free everything of the inode, which has no links left.
+    fn free(&self, disk: &mut DiskInode) -> FsResult<()> {
+        if self.has_blocks(disk) {
+            self.release(disk, 0)?;
+        }
+        self.release_xattrs(disk)?;
+        disk.set_size(0);
+        disk.set_dtime(TimeSpec::now().sec as u32);
+        self.write_inode(disk)?;
+        self.fs.free_inode(self.ino, self.kind == InodeType::Dir)
+    }
+
+    fn add_link(&self, disk: &mut DiskInode) -> FsResult<()> {
+        let links = disk.links();
+        if links + 1 >= LINK_MAX || (self.kind == InodeType::Dir && links == 1) {
+            if self.kind != InodeType::Dir || self.fs.layout.ro_compat & RO_COMPAT_DIR_NLINK == 0 {
+                return Err(FsError::TooManyLinks);
+            }
+            disk.set_links(1);
+        } else {
+            disk.set_links(links + 1);
+        }
+        Ok(())
+    }
+
+    fn remove_link(&self, disk: &mut DiskInode) {
+        // Directories keep their own entry, and one counting 1 has an unknown
+        // number of subdirectories.
+        let links = disk.links();
+        if self.kind != InodeType::Dir || links > 2 {
+            disk.set_links(links.saturating_sub(1));
+        }
+    }
+
+    fn dir_blocks(&self, disk: &DiskInode) -> u32 {
+        (disk.size() / self.block_size() as u64) as u32
+    }
+
+    /// Block `block` of the directory, with its checksum checked.
+    fn read_dir_block(&self, disk: &DiskInode, block: u32) -> FsResult<Vec<u8>> {
+        let mut data = vec![0; self.block_size()];
+        self.read_data(disk, block as usize * self.block_size(), &mut data)?;
+        if self.fs.layout.has_metadata_csum() {
+            let mut expected = data.clone();
+            dir::update_checksum(&mut expected, self.seed(disk), Self::dx_entries(disk, block, &data));
+            if expected != data {
+                warn!("ext4: checksum mismatch of block {} of directory {}", block, self.ino);
+                return Err(FsError::Corrupted);
+            }
+        }
+        Ok(data)
+    }
+
+    /// Write `data` back to block `block` of the directory, which must be
+    /// allocated, with its checksum.
+    fn write_dir_block(&self, disk: &DiskInode, block: u32, data: &mut [u8]) -> FsResult<()> {
+        if self.fs.layout.has_metadata_csum() {
+            let dx_entries = Self::dx_entries(disk, block, data);
+            dir::update_checksum(data, self.seed(disk), dx_entries);
+        }
+        match self.fs.map_block(disk, block)? {
+            Mapping::Mapped { start, .. } => self.fs.write_block(start, data),
+            Mapping::Hole { .. } => Err(FsError::Corrupted),
+        }
+    }
+
+    /// Where the index entries of block `block` of the directory start, if
+    /// it is a node of the index.
+    fn dx_entries(disk: &DiskInode, block: u32, data: &[u8]) -> Option<usize> {
+        match block {
+            0 if disk.flags() & INDEX_FL != 0 => DxRoot::parse(data, u8::MAX).map(|root| root.entries),
+            _ => dir::dx_node_entries(data),
+        }
+    }
+
+    /// The root of the directory's index, if it has one this driver can use,
+    /// with the hash version its names hash with.
+    fn dx_root(&self, disk: &DiskInode) -> FsResult<Option<(Vec<u8>, DxRoot, u8)>> {
+        let layout = &self.fs.layout;
+        if disk.flags() & INDEX_FL == 0 || layout.compat & COMPAT_DIR_INDEX == 0 {
+            return Ok(None);
+        }
+        let block = self.read_dir_block(disk, 0)?;
+        let max_levels = if layout.incompat & INCOMPAT_LARGEDIR != 0 { 3 } else { 2 };
+        let Some(root) = DxRoot::parse(&block, max_levels) else {
+            return Ok(None);
+        };
+        let mut version = root.hash_version;
+        if layout.unsigned_hash && version < HASH_UNSIGNED {
+            version += HASH_UNSIGNED;
+        }
+        Ok(Some((block, root, version)))
+    }
+
+    /// The leaf blocks of the directory's index that may hold `name`, or
+    /// `None` if all blocks must be searched.
+    fn dx_leaves(&self, disk: &DiskInode, name: &str) -> FsResult<Option<Vec<u32>>> {
+        let Some((mut node, root, version)) = self.dx_root(disk)? else {
+            return Ok(None);
+        };
+        let Some(hash) = dir::hash(name.as_bytes(), version, &self.fs.layout.hash_seed) else {
+            return Ok(None);
+        };
+        let mut entries = root.entries;
+        for level in 0..=root.levels {
+            let (children, at_end) = dir::dx_lookup(&node, entries, hash)?;
+            if level == root.levels {
+                // Names of this hash may go on in the next node.
+                return Ok((!at_end || level == 0).then_some(children));
+            }
+            if children.len() > 1 {
+                return Ok(None);
+            }
+            node = self.read_dir_block(disk, children[0])?;
+            entries = dir::dx_node_entries(&node).ok_or(FsError::Corrupted)?;
+        }
+        unreachable!()
+    }
+
+    /// The entry `name`, with the block it is in and the block's data.
+    fn find_entry(&self, disk: &DiskInode, name: &str) -> FsResult<Option<(u32, Vec<u8>, Record)>> {
+        let blocks = match self.dx_leaves(disk, name)? {
+            Some(leaves) => leaves,
+            None => (0..self.dir_blocks(disk)).collect(),
+        };
+        for block in blocks {
+            let data = self.read_dir_block(disk, block)?;
+            if let Some(record) = dir::find(&data, name)? {
+                return Ok(Some((block, data, record)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Add the entry `name` for the inode `ino` of type `kind`, growing the
+    /// directory as needed.
+    fn add_entry(&self, disk: &mut DiskInode, name: &str, ino: u32, kind: InodeType) -> FsResult<()> {
+        let file_type = dir::file_type(kind);
+        if disk.flags() & INDEX_FL != 0 {
+            if let Some(leaves) = self.dx_leaves(disk, name)? {
+                let mut data = self.read_dir_block(disk, leaves[0])?;
+                if dir::insert(&mut data, ino, name, file_type)? {
+                    return self.write_dir_block(disk, leaves[0], &mut data);
+                }
+            }
+            // The index is not kept up to date as leaves split.
+            self.drop_index(disk)?;
+        }
+        let blocks = self.dir_blocks(disk);
+        for block in 0..blocks {
+            let mut data = self.read_dir_block(disk, block)?;
+            if dir::insert(&mut data, ino, name, file_type)? {
+                return self.write_dir_block(disk, block, &mut data);
+            }
+        }
+        self.map_for_write(disk, blocks, 1)?;
+        let mut data = vec![0; self.block_size()];
+        dir::init_block(&mut data, self.fs.layout.has_metadata_csum());
+        dir::insert(&mut data, ino, name, file_type)?;
+        disk.set_size(disk.size() + self.block_size() as u64);
+        self.write_dir_block(disk, blocks, &mut data)
+    }
+
+    /// Turn the directory's index back into plain blocks of entries.
+    fn drop_index(&self, disk: &mut DiskInode) -> FsResult<()> {
+        let tail = self.fs.layout.has_metadata_csum();
+        let mut root_block = self.read_dir_block(disk, 0)?;
+        if let Some(root) = DxRoot::parse(&root_block, u8::MAX) {
+            let mut nodes = Vec::new();
+            let mut level = dir::dx_children(&root_block, root.entries);
+            for _ in 0..root.levels {
+                let mut below = Vec::new();
+                for &block in &level {
+                    let node = self.read_dir_block(disk, block)?;
+                    if let Some(entries) = dir::dx_node_entries(&node) {
+                        below.extend(dir::dx_children(&node, entries));
+                    }
+                }
+                nodes.append(&mut level);
+                level = below;
+            }
+            for block in nodes {
+                let mut data = vec![0; self.block_size()];
+                dir::init_block(&mut data, tail);
+                self.write_dir_block(disk, block, &mut data)?;
+            }
+        }
+        disk.set_flags(disk.flags() & !INDEX_FL);
+        dir::drop_dx_root(&mut root_block, tail);
+        self.write_dir_block(disk, 0, &mut root_block)
+    }
+
+    fn remove_entry(&self, disk: &DiskInode, block: u32, data: &mut [u8], record: &Record) -> FsResult<()> {
+        dir::remove(data, record)?;
+        self.write_dir_block(disk, block, data)
+    }
+
+    fn is_empty_dir(&self) -> FsResult<bool> {
+        let disk = self.inner.lock();
+        for block in 0..self.dir_blocks(&disk) {
+            if !dir::is_empty(&self.read_dir_block(&disk, block)?)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Allocate an inode of `kind` near this directory, with the data a new
+    /// directory starts with. It is freed when dropped unless linked.
+    fn new_child(&self, kind: InodeType, mode: u32) -> FsResult<Arc<Ext4Inode>> {
+        let fs = &self.fs;
+        let is_dir = kind == InodeType::Dir;
+        let ino = fs.alloc_inode(self.ino, is_dir)?;
+        let mut disk = DiskInode::zeroed(fs.layout.inode_size, fs.layout.extra_isize);
+        disk.set_mode(kind.mode_bits() as u16 | (mode & 0o7777) as u16);
+        disk.set_links(if is_dir { 2 } else { 1 });
+        let now = TimeSpec::now();
+        disk.set_atime(now);
+        disk.set_ctime(now);
+        disk.set_mtime(now);
+        disk.set_crtime(now);
+        disk.set_generation(fs.next_generation.fetch_add(1, Ordering::Relaxed));
+        if matches!(kind, InodeType::File | InodeType::Dir) {
+            extent::init(&mut disk);
+        }
+        let child = Arc::new(Self {
+            fs: fs.clone(),
+            ino,
+            kind,
+            inner: SleepMutex::new(disk),
+        });
+        fs.inodes.lock().insert(ino, Arc::downgrade(&child));
+        let mut disk = child.inner.lock();
+        let initialized = match kind {
+            InodeType::Dir => child.map_for_write(&mut disk, 0, 1).and_then(|_| {
+                let mut data = vec![0; child.block_size()];
+                dir::init_first_block(&mut data, ino, self.ino, fs.layout.has_metadata_csum());
+                disk.set_size(child.block_size() as u64);
+                child.write_dir_block(&disk, 0, &mut data)
+            }),
+            _ => Ok(()),
+        };
+        if let Err(err) = initialized.and_then(|_| child.write_inode(&mut disk)) {
+            disk.set_links(0);
+            drop(disk);
+            return Err(err);
+        }
+        drop(disk);
+        Ok(child)
+    }
+
+    /// Link `child`, just made by [`Self::new_child`], as `name`, or free it.
+    fn attach(&self, disk: &mut DiskInode, name: &str, child: &Ext4Inode) -> FsResult<()> {
+        let linked = self.add_entry(disk, name, child.ino, child.kind).and_then(|_| {
+            if child.kind == InodeType::Dir {
+                self.add_link(disk)?;
+            }
+            self.touch(disk)
+        });
+        if linked.is_err() {
+            child.inner.lock().set_links(0);
+        }
+        linked
+    }
+}
+
+fn check_name(name: &str) -> FsResult<()> {
+    match name.len() {
+        0 => Err(FsError::InvalidInput),
+        len if len > NAME_MAX => Err(FsError::NameTooLong),
+        _ => Ok(()),
+    }
+}
+
+impl Inode for Ext4Inode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let disk = self.inner.lock();
+        let mut blocks = disk.blocks();
+        if disk.flags() & HUGE_FILE_FL != 0 {
+            blocks *= (self.block_size() / 512) as u64;
+        }
+        // Old device numbers are in the first word, new ones in the second.
+        let rdev = match self.kind {
+            InodeType::CharDevice | InodeType::BlockDevice => {
+                let word = |i: usize| u32::from_le_bytes(disk.i_block()[i * 4..i * 4 + 4].try_into().unwrap());
+                if word(0) != 0 { word(0) } else { word(1) }
+            }
+            _ => 0,
+        };
+        Ok(Metadata {
+            dev: self.fs.dev,
+            ino: self.ino as u64,
+            kind: self.kind,
+            mode: (disk.mode() & 0o7777) as u32,
+            nlink: disk.links() as u32,
+            uid: disk.uid(),
+            gid: disk.gid(),
+            size: disk.size(),
+            blksize: self.block_size() as u32,
+            blocks,
+            rdev: rdev as u64,
+            atime: disk.atime(),
+            mtime: disk.mtime(),
+            ctime: disk.ctime(),
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        self.check_file()?;
+        let disk = self.inner.lock();
+        let size = disk.size() as usize;
+        let start = offset.min(size);
+        let len = buf.len().min(size - start);
+        self.read_data(&disk, start, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> FsResult<usize> {
+        self.write(Some(offset), buf).map(|(_, written)| written)
+    }
+
+    fn append(&self, buf: &[u8]) -> FsResult<(usize, usize)> {
+        self.write(None, buf)
+    }
+
+    fn truncate(&self, len: usize) -> FsResult<()> {
+        self.check_file()?;
+        self.fs.check_writable()?;
+        if (len / self.block_size()) as u64 > u32::MAX as u64 {
+            return Err(FsError::NoSpace);
+        }
+        let mut disk = self.inner.lock();
+        if (len as u64) < disk.size() {
+            self.release(&mut disk, len)?;
+        }
+        disk.set_size(len as u64);
+        self.touch(&mut disk)
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        self.fs.cache.flush()
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        self.check_dir()?;
+        let _guard = self.fs.dir_lock.lock();
+        let disk = self.inner.lock();
+        let (_, _, record) = self.find_entry(&disk, name)?.ok_or(FsError::NotFound)?;
+        drop(disk);
+        Ok(self.fs.inode(record.inode)?)
+    }
+
+    fn create(&self, name: &str, kind: InodeType, mode: u32) -> FsResult<Arc<dyn Inode>> {
+        self.check_dir()?;
+        self.fs.check_writable()?;
+        check_name(name)?;
+        let _guard = self.fs.dir_lock.lock();
+        let mut disk = self.inner.lock();
+        if self.find_entry(&disk, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let child = self.new_child(kind, mode)?;
+        self.attach(&mut disk, name, &child)?;
+        Ok(child)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> FsResult<Arc<dyn Inode>> {
+        self.check_dir()?;
+        self.fs.check_writable()?;
+        check_name(name)?;
+        if target.is_empty() || target.len() >= self.block_size() {
+            return Err(FsError::NameTooLong);
+        }
+        let _guard = self.fs.dir_lock.lock();
+        let mut disk = self.inner.lock();
+        if self.find_entry(&disk, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let child = self.new_child(InodeType::Symlink, 0o777)?;
+        let mut child_disk = child.inner.lock();
+        // Short targets are kept in the inode, as fast symbolic links.
+        let stored = if target.len() < I_BLOCK_SIZE {
+            child_disk.i_block_mut()[..target.len()].copy_from_slice(target.as_bytes());
+            Ok(())
+        } else {
+            extent::init(&mut child_disk);
+            child.write_data(&mut child_disk, 0, target.as_bytes())
+        };
+        child_disk.set_size(target.len() as u64);
+        if let Err(err) = stored.and_then(|_| child.write_inode(&mut child_disk)) {
+            child_disk.set_links(0);
+            return Err(err);
+        }
+        drop(child_disk);
+        self.attach(&mut disk, name, &child)?;
+        Ok(child)
+    }
+
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> FsResult<()> {
+        self.check_dir()?;
+        self.fs.check_writable()?;
+        check_name(name)?;
+        let target = self.same_fs(target)?;
+        if target.kind == InodeType::Dir {
+            return Err(FsError::PermissionDenied);
+        }
+        let _guard = self.fs.dir_lock.lock();
+        let mut disk = self.inner.lock();
+        if self.find_entry(&disk, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let mut target_disk = target.inner.lock();
+        target.add_link(&mut target_disk)?;
+        if let Err(err) = self.add_entry(&mut disk, name, target.ino, target.kind) {
+            target.remove_link(&mut target_disk);
+            return Err(err);
+        }
+        target.change(&mut target_disk)?;
+        drop(target_disk);
+        self.touch(&mut disk)
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        self.check_dir()?;
+        self.fs.check_writable()?;
+        let _guard = self.fs.dir_lock.lock();
+        let mut disk = self.inner.lock();
+        let (block, mut data, record) = self.find_entry(&disk, name)?.ok_or(FsError::NotFound)?;
+        let child = self.fs.inode(record.inode)?;
+        if child.kind == InodeType::Dir {
+            return Err(FsError::IsDir);
+        }
+        self.remove_entry(&disk, block, &mut data, &record)?;
+        self.touch(&mut disk)?;
+        drop(disk);
+        let mut child_disk = child.inner.lock();
+        child.remove_link(&mut child_disk);
+        child.change(&mut child_disk)
+    }
+
+    fn rmdir(&self, name: &str) -> FsResult<()> {
+        self.check_dir()?;
+        self.fs.check_writable()?;
+        let _guard = self.fs.dir_lock.lock();
+        let mut disk = self.inner.lock();
+        let (block, mut data, record) = self.find_entry(&disk, name)?.ok_or(FsError::NotFound)?;
+        let child = self.fs.inode(record.inode)?;
+        if child.kind != InodeType::Dir {
+            return Err(FsError::NotDir);
+        }
+        if !child.is_empty_dir()? {
+            return Err(FsError::NotEmpty);
+        }
+        self.remove_entry(&disk, block, &mut data, &record)?;
+        self.remove_link(&mut disk);
+        self.touch(&mut disk)?;
+        drop(disk);
+        let mut child_disk = child.inner.lock();
+        child_disk.set_links(0);
+        child.change(&mut child_disk)
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> FsResult<()> {
+        self.check_dir()?;
+        self.fs.check_writable()?;
+        check_name(new_name)?;
+        let new_dir = self.same_fs(new_dir)?;
+        new_dir.check_dir()?;
+        let same_dir = core::ptr::eq(self, new_dir);
+        let _guard = self.fs.dir_lock.lock();
+
+        let disk = self.inner.lock();
+        let (_, _, record) = self.find_entry(&disk, old_name)?.ok_or(FsError::NotFound)?;
+        drop(disk);
+        let moved = self.fs.inode(record.inode)?;
+        let moved_dir = moved.kind == InodeType::Dir;
+
+        // Point the target at the moved inode, or add it.
+        let mut new_disk = new_dir.inner.lock();
+        match new_dir.find_entry(&new_disk, new_name)? {
+            Some((_, _, target)) if target.inode == moved.ino => return Ok(()),
+            Some((block, mut data, target)) => {
+                let replaced = self.fs.inode(target.inode)?;
+                match (moved_dir, replaced.kind == InodeType::Dir) {
+                    (true, true) if !replaced.is_empty_dir()? => return Err(FsError::NotEmpty),
+                    (true, false) => return Err(FsError::NotDir),
+                    (false, true) => return Err(FsError::IsDir),
+                    _ => {}
+                }
+                dir::set_inode(&mut data, &target, moved.ino, dir::file_type(moved.kind));
+                new_dir.write_dir_block(&new_disk, block, &mut data)?;
+                let mut replaced_disk = replaced.inner.lock();
+                if replaced.kind == InodeType::Dir {
+                    replaced_disk.set_links(0);
+                    new_dir.remove_link(&mut new_disk);
+                } else {
+                    replaced.remove_link(&mut replaced_disk);
+                }
+                replaced.change(&mut replaced_disk)?;
+            }
+            None => new_dir.add_entry(&mut new_disk, new_name, moved.ino, moved.kind)?,
+        }
+        if moved_dir && !same_dir {
+            new_dir.add_link(&mut new_disk)?;
+        }
+        new_dir.touch(&mut new_disk)?;
+        drop(new_disk);
+
+        let mut disk = self.inner.lock();
+        let (block, mut data, record) = self.find_entry(&disk, old_name)?.ok_or(FsError::Corrupted)?;
+        self.remove_entry(&disk, block, &mut data, &record)?;
+        if moved_dir && !same_dir {
+            self.remove_link(&mut disk);
+        }
+        self.touch(&mut disk)?;
+        drop(disk);
+
+        let mut moved_disk = moved.inner.lock();
+        if moved_dir && !same_dir {
+            let mut data = moved.read_dir_block(&moved_disk, 0)?;
+            let dot_dot = dir::find(&data, "..")?.ok_or(FsError::Corrupted)?;
+            dir::set_inode(&mut data, &dot_dot, new_dir.ino, dir::file_type(InodeType::Dir));
+            moved.write_dir_block(&moved_disk, 0, &mut data)?;
+        }
+        moved.change(&mut moved_disk)
+    }
+
+    fn read_dir(&self, pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        self.check_dir()?;
+        // Positions are offsets in the directory.
+        let block_size = self.block_size();
+        let _guard = self.fs.dir_lock.lock();
+        let disk = self.inner.lock();
+        let mut block = pos / block_size;
+        let mut within = pos % block_size;
+        while block < self.dir_blocks(&disk) as usize {
+            let data = self.read_dir_block(&disk, block as u32)?;
+            for record in dir::records(&data)? {
+                if record.offset < within || record.inode == 0 || record.name_len == 0 {
+                    continue;
+                }
+                let kind = match dir::kind(record.file_type) {
+                    Some(kind) => kind,
+                    None => self.fs.inode(record.inode)?.kind,
+                };
+                let entry = DirEntry {
+                    ino: record.inode as u64,
+                    kind,
+                    name: String::from_utf8_lossy(record.name(&data)).into_owned(),
+                };
+                return Ok(Some((entry, block * block_size + record.offset + record.rec_len)));
+            }
+            block += 1;
+            within = 0;
+        }
+        Ok(None)
+    }
+
+    fn read_link(&self) -> FsResult<String> {
+        if self.kind != InodeType::Symlink {
+            return Err(FsError::InvalidInput);
+        }
+        let disk = self.inner.lock();
+        let len = disk.size() as usize;
+        if len > self.block_size() {
+            return Err(FsError::Corrupted);
+        }
+        let target = match self.has_blocks(&disk) {
+            true => {
+                let mut target = vec![0; len];
+                self.read_data(&disk, 0, &mut target)?;
+                target
+            }
+            false => disk.i_block()[..len].to_vec(),
+        };
+        String::from_utf8(target).map_err(|_| FsError::InvalidInput)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Drop for Ext4Inode {
+    fn drop(&mut self) {
+        {
+            let mut inodes = self.fs.inodes.lock();
+            if inodes.get(&self.ino).is_some_and(|inode| inode.strong_count() == 0) {
+                inodes.remove(&self.ino);
+            }
+        }
+        let mut disk = self.inner.lock();
+        if disk.links() == 0 && !self.fs.read_only {
+            if let Err(err) = self.free(&mut disk) {
+                warn!("ext4: failed to free inode {}: {}", self.ino, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Replaying the journal (jbd2) left by a volume that was not cleanly
+//! unmounted. Nothing is written to the journal.
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use log::{info, warn};
+
+use crate::fs::{FsError, FsResult};
+
+use super::{crc::crc32c, extent::Mapping, Ext4Fs};
+
+const MAGIC: u32 = 0xc03b_3998;
+
+const DESCRIPTOR_BLOCK: u32 = 1;
+const COMMIT_BLOCK: u32 = 2;
+const SUPERBLOCK_V1: u32 = 3;
+const SUPERBLOCK_V2: u32 = 4;
+const REVOKE_BLOCK: u32 = 5;
+
+/// Length of the header every journal block but data starts with.
+const HEADER_SIZE: usize = 12;
+/// Offset of the checksum of the journal superblock.
+const SB_CHECKSUM: usize = 0xfc;
+const SB_SIZE: usize = 1024;
+
+const INCOMPAT_REVOKE: u32 = 0x1;
+const INCOMPAT_64BIT: u32 = 0x2;
+const INCOMPAT_ASYNC_COMMIT: u32 = 0x4;
+const INCOMPAT_CSUM_V2: u32 = 0x8;
+const INCOMPAT_CSUM_V3: u32 = 0x10;
+const INCOMPAT_SUPPORTED: u32 =
+    INCOMPAT_REVOKE | INCOMPAT_64BIT | INCOMPAT_ASYNC_COMMIT | INCOMPAT_CSUM_V2 | INCOMPAT_CSUM_V3;
+
+/// The first word of the data block was the magic, and was zeroed so that
+/// it would not be taken for a journal block.
+const TAG_ESCAPE: u32 = 0x1;
+/// No UUID follows the tag.
+const TAG_SAME_UUID: u32 = 0x2;
+const TAG_LAST: u32 = 0x8;
+const UUID_SIZE: usize = 16;
+/// Length of the checksum ending descriptor and revoke blocks.
+const BLOCK_TAIL_SIZE: usize = 4;
+
+fn be32(raw: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(raw[offset..offset + 4].try_into().unwrap())
+}
+
+/// The journal's own superblock, and how its blocks map to the volume.
+struct Journal<'a> {
+    fs: &'a Ext4Fs,
+    /// Blocks of the volume holding the journal, in order.
+    blocks: Vec<u64>,
+    first: u32,
+    incompat: u32,
+}
+
+impl Journal<'_> {
+    fn block_size(&self) -> usize {
+        self.fs.layout.block_size
+    }
+
+    fn read(&self, block: u32, buf: &mut [u8]) -> FsResult<()> {
+        let block = *self.blocks.get(block as usize).ok_or(FsError::Corrupted)?;
+        self.fs.read_block(block, buf)
+    }
+
+    /// The block after `block`, wrapping around the end of the log.
+    fn next(&self, block: u32) -> u32 {
+        match block + 1 {
+            next if next as usize >= self.blocks.len() => self.first,
+            next => next,
+        }
+    }
+
+    fn has(&self, feature: u32) -> bool {
+        self.incompat & feature != 0
+    }
+
+    fn tag_size(&self) -> usize {
+        if self.has(INCOMPAT_CSUM_V3) {
+            return 16;
+        }
+        let size = if self.has(INCOMPAT_CSUM_V2) { 14 } else { 12 };
+        if self.has(INCOMPAT_64BIT) {
+            size
+        } else {
+            size - 4
+        }
+    }
+
+    /// The blocks of the volume the tags of a descriptor block are for, and
+    /// whether each was escaped.
+    fn tags(&self, raw: &[u8]) -> Vec<(u64, bool)> {
+        let tag_size = self.tag_size();
+        let mut end = raw.len();
+        if self.has(INCOMPAT_CSUM_V2 | INCOMPAT_CSUM_V3) {
+            end -= BLOCK_TAIL_SIZE;
+        }
+        let mut tags = Vec::new();
+        let mut offset = HEADER_SIZE;
+        while offset + tag_size <= end {
+            let tag = &raw[offset..offset + tag_size];
+            // Flags are a word of their own with `csum_v3`, and the low half
+            // of one otherwise.
+            let (flags, high) = match self.has(INCOMPAT_CSUM_V3) {
+                true => (be32(tag, 4), be32(tag, 8)),
+                false => (u16::from_be_bytes([tag[6], tag[7]]) as u32, if tag_size >= 12 { be32(tag, 8) } else { 0 }),
+            };
+            let high = if self.has(INCOMPAT_64BIT) { high as u64 } else { 0 };
+            tags.push((be32(tag, 0) as u64 | high << 32, flags & TAG_ESCAPE != 0));
+            offset += tag_size;
+            if flags & TAG_SAME_UUID == 0 {
+                offset += UUID_SIZE;
+            }
+            if flags & TAG_LAST != 0 {
+                break;
+            }
+        }
+        tags
+    }
+
+    /// The blocks of the volume a revoke block revokes.
+    fn revoked(&self, raw: &[u8]) -> Vec<u64> {
+        let used = (be32(raw, HEADER_SIZE) as usize).min(raw.len());
+        let size = if self.has(INCOMPAT_64BIT) { 8 } else { 4 };
+        raw[HEADER_SIZE + 4..used.max(HEADER_SIZE + 4)]
+            .chunks_exact(size)
+            .map(|entry| match size {
+                8 => u64::from_be_bytes(entry.try_into().unwrap()),
+                _ => be32(entry, 0) as u64,
+            })
+            .collect()
+    }
+
+    /// Walk the committed transactions of the log from `start`, numbered
+    /// from `sequence`, calling `f` with each descriptor or revoke block,
+    /// its transaction, and the block it is at. Returns the number of the
+    /// first transaction not committed.
+    fn walk(&self, start: u32, mut sequence: u32, mut f: impl FnMut(&[u8], u32, u32) -> FsResult<()>) -> FsResult<u32> {
+        let mut raw = vec![0; self.block_size()];
+        // Blocks of the transaction, kept until its commit block is seen.
+        let mut pending: Vec<(Vec<u8>, u32)> = Vec::new();
+        let mut block = start;
+        for _ in 0..self.blocks.len() {
+            self.read(block, &mut raw)?;
+            if be32(&raw, 0) != MAGIC || be32(&raw, 8) != sequence {
+                break;
+            }
+            match be32(&raw, 4) {
+                DESCRIPTOR_BLOCK => {
+                    let count = self.tags(&raw).len();
+                    pending.push((raw.clone(), block));
+                    for _ in 0..=count {
+                        block = self.next(block);
+                    }
+                }
+                REVOKE_BLOCK => {
+                    pending.push((raw.clone(), block));
+                    block = self.next(block);
+                }
+                COMMIT_BLOCK => {
+                    for (raw, at) in pending.drain(..) {
+                        f(&raw, sequence, at)?;
+                    }
+                    sequence = sequence.wrapping_add(1);
+                    block = self.next(block);
+                }
+                _ => break,
+            }
+        }
+        Ok(sequence)
+    }
+}
+
+/// Replay the journal of `fs` onto the volume and mark it empty.
+pub fn replay(fs: &Ext4Fs) -> FsResult<()> {
+    let journal_inode = fs.read_inode(fs.layout.journal_ino)?;
+    let block_size = fs.layout.block_size;
+    let journal_blocks = (journal_inode.size() / block_size as u64) as u32;
+    let mut blocks = Vec::with_capacity(journal_blocks as usize);
+    while (blocks.len() as u32) < journal_blocks {
+        match fs.map_block(&journal_inode, blocks.len() as u32)? {
+            Mapping::Mapped { start, len, .. } => {
+                let len = len.min(journal_blocks - blocks.len() as u32);
+                blocks.extend((0..len as u64).map(|i| start + i));
+            }
+            Mapping::Hole { .. } => return Err(FsError::Corrupted),
+        }
+    }
+    let Some(&sb_block) = blocks.first() else {
+        return Err(FsError::Corrupted);
+    };
+    let mut sb = vec![0; block_size];
+    fs.read_block(sb_block, &mut sb)?;
+    let kind = be32(&sb, 4);
+    if be32(&sb, 0) != MAGIC || !(kind == SUPERBLOCK_V1 || kind == SUPERBLOCK_V2) || be32(&sb, 0xc) as usize != block_size {
+        warn!("ext4: bad journal superblock");
+        return Err(FsError::Corrupted);
+    }
+    let incompat = if kind == SUPERBLOCK_V2 { be32(&sb, 0x28) } else { 0 };
+    if incompat & !INCOMPAT_SUPPORTED != 0 {
+        warn!("ext4: unsupported journal features {:#x}", incompat & !INCOMPAT_SUPPORTED);
+        return Err(FsError::Unsupported);
+    }
+    let max_len = (be32(&sb, 0x10) as usize).min(blocks.len());
+    blocks.truncate(max_len);
+    let journal = Journal {
+        fs,
+        blocks,
+        first: be32(&sb, 0x14),
+        incompat,
+    };
+    let start = be32(&sb, 0x1c);
+    if start == 0 {
+        return Ok(());
+    }
+    if journal.first == 0 || journal.first as usize >= max_len || start as usize >= max_len {
+        return Err(FsError::Corrupted);
+    }
+    let sequence = be32(&sb, 0x18);
+
+    // Blocks revoked, with the last transaction revoking each: copies
+    // logged up to that transaction are stale.
+    let mut revoked = BTreeMap::new();
+    let end = journal.walk(start, sequence, |raw, transaction, _| {
+        if be32(raw, 4) == REVOKE_BLOCK {
+            for block in journal.revoked(raw) {
+                revoked.insert(block, transaction);
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut data = vec![0; block_size];
+    let mut replayed = 0;
+    journal.walk(start, sequence, |raw, transaction, at| {
+        if be32(raw, 4) != DESCRIPTOR_BLOCK {
+            return Ok(());
+        }
+        let mut at = at;
+        for (target, escaped) in journal.tags(raw) {
+            at = journal.next(at);
+            if revoked.get(&target).is_some_and(|&revoking| revoking.wrapping_sub(transaction) as i32 >= 0) {
+                continue;
+            }
+            journal.read(at, &mut data)?;
+            if escaped {
+                data[..4].copy_from_slice(&MAGIC.to_be_bytes());
+            }
+            if target >= fs.layout.blocks_count {
+                return Err(FsError::Corrupted);
+            }
+            fs.write_block(target, &data)?;
+            replayed += 1;
+        }
+        Ok(())
+    })?;
+    info!(
+        "ext4: replayed {} blocks from transactions {} to {}",
+        replayed,
+        sequence,
+        end.wrapping_sub(1)
+    );
+
+    // The log is empty now; the next transaction goes on from where it
+    // stopped.
+    sb[0x18..0x1c].copy_from_slice(&end.to_be_bytes());
+    sb[0x1c..0x20].fill(0);
+    if kind == SUPERBLOCK_V2 && incompat & (INCOMPAT_CSUM_V2 | INCOMPAT_CSUM_V3) != 0 {
+        sb[SB_CHECKSUM..SB_CHECKSUM + 4].fill(0);
+        let checksum = crc32c(!0, &sb[..SB_SIZE]);
+        sb[SB_CHECKSUM..SB_CHECKSUM + 4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    fs.write_block(sb_block, &sb)
+}
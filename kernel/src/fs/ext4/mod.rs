@@ -0,0 +1,352 @@
+//! ext4, with extent trees, hashed directory lookups and metadata checksums.
+//!
+//! Changes are written in place; the journal is only replayed at mount, so
+//! a volume left needing recovery by another system can be used.
+//! Volumes with features this driver does not know how to keep consistent
+//! are mounted read-only, as are those without extents, whose block maps it
+//! only reads. Those it cannot read are refused.
+
+mod crc;
+mod dir;
+mod disk;
+mod extent;
+mod group;
+mod inode;
+mod journal;
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+};
+use core::sync::atomic::AtomicU32;
+use log::warn;
+
+use crate::{
+    drivers::block::{BlockCache, BlockDevice},
+    sched::SleepMutex,
+};
+
+use self::{crc::crc32c, inode::Ext4Inode};
+use super::{mount, FsError, FsResult, Inode, SuperBlock};
+
+/// Where the superblock is, in bytes, whatever the block size.
+const SUPERBLOCK_POS: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xef53;
+/// Offset of the superblock checksum.
+const SB_CHECKSUM: usize = 0x3fc;
+
+const ROOT_INO: u32 = 2;
+
+const COMPAT_HAS_JOURNAL: u32 = 0x4;
+const COMPAT_DIR_INDEX: u32 = 0x20;
+
+const INCOMPAT_FILETYPE: u32 = 0x2;
+const INCOMPAT_RECOVER: u32 = 0x4;
+const INCOMPAT_EXTENTS: u32 = 0x40;
+const INCOMPAT_64BIT: u32 = 0x80;
+const INCOMPAT_FLEX_BG: u32 = 0x200;
+const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+const INCOMPAT_LARGEDIR: u32 = 0x4000;
+/// What this driver can read.
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE
+    | INCOMPAT_RECOVER
+    | INCOMPAT_EXTENTS
+    | INCOMPAT_64BIT
+    | INCOMPAT_FLEX_BG
+    | INCOMPAT_CSUM_SEED
+    | INCOMPAT_LARGEDIR;
+
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+const RO_COMPAT_HUGE_FILE: u32 = 0x8;
+const RO_COMPAT_GDT_CSUM: u32 = 0x10;
+const RO_COMPAT_DIR_NLINK: u32 = 0x20;
+const RO_COMPAT_EXTRA_ISIZE: u32 = 0x40;
+const RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+/// What this driver can write without breaking.
+const RO_COMPAT_SUPPORTED: u32 = RO_COMPAT_SPARSE_SUPER
+    | RO_COMPAT_LARGE_FILE
+    | RO_COMPAT_HUGE_FILE
+    | RO_COMPAT_GDT_CSUM
+    | RO_COMPAT_DIR_NLINK
+    | RO_COMPAT_EXTRA_ISIZE
+    | RO_COMPAT_METADATA_CSUM;
+
+/// `s_flags` bit telling directory hashes treat names as unsigned bytes.
+const FLAGS_UNSIGNED_HASH: u32 = 0x2;
+
+/// The parameters of a volume, from its superblock.
+struct Layout {
+    block_size: usize,
+    blocks_count: u64,
+    first_data_block: u64,
+    blocks_per_group: u32,
+    inodes_per_group: u32,
+    inodes_count: u32,
+    inode_size: usize,
+    group_count: u32,
+    desc_size: usize,
+    reserved_gdt_blocks: u64,
+    compat: u32,
+    incompat: u32,
+    ro_compat: u32,
+    uuid: [u8; 16],
+    /// Groups holding superblock backups with `sparse_super2`.
+    backup_groups: [u32; 2],
+    /// What checksums of metadata start from.
+    csum_seed: u32,
+    hash_seed: [u32; 4],
+    unsigned_hash: bool,
+    /// Bytes of new inodes past the first 128.
+    extra_isize: u16,
+    journal_ino: u32,
+}
+
+impl Layout {
+    fn parse(sb: &[u8; SUPERBLOCK_SIZE]) -> FsResult<Self> {
+        let u16_at = |offset: usize| u16::from_le_bytes([sb[offset], sb[offset + 1]]);
+        let u32_at = |offset: usize| u32::from_le_bytes(sb[offset..offset + 4].try_into().unwrap());
+        if u16_at(0x38) != MAGIC {
+            return Err(FsError::InvalidInput);
+        }
+        let incompat = u32_at(0x60);
+        if incompat & !INCOMPAT_SUPPORTED != 0 {
+            warn!("ext4: unsupported incompatible features {:#x}", incompat & !INCOMPAT_SUPPORTED);
+            return Err(FsError::Unsupported);
+        }
+        let log_block_size = u32_at(0x18);
+        if log_block_size > 6 {
+            return Err(FsError::Corrupted);
+        }
+        let block_size = 1024usize << log_block_size;
+        let is_64bit = incompat & INCOMPAT_64BIT != 0;
+        let blocks_count = u32_at(0x4) as u64 | if is_64bit { (u32_at(0x150) as u64) << 32 } else { 0 };
+        let first_data_block = u32_at(0x14) as u64;
+        let blocks_per_group = u32_at(0x20);
+        let inodes_per_group = u32_at(0x28);
+        // Revision 0 has fixed 128-byte inodes.
+        let inode_size = if u32_at(0x4c) == 0 { 128 } else { u16_at(0x58) as usize };
+        let desc_size = if is_64bit { u16_at(0xfe) as usize } else { 32 };
+        let valid = blocks_per_group > 0
+            && blocks_per_group as usize <= block_size * 8
+            && inodes_per_group > 0
+            && inodes_per_group as usize <= block_size * 8
+            && inode_size.is_power_of_two()
+            && (128..=block_size).contains(&inode_size)
+            && (32..=64).contains(&desc_size)
+            && desc_size.is_power_of_two()
+            && blocks_count > first_data_block;
+        if !valid {
+            return Err(FsError::Corrupted);
+        }
+        let group_count = (blocks_count - first_data_block).div_ceil(blocks_per_group as u64) as u32;
+        let ro_compat = u32_at(0x64);
+        let csum_seed = if incompat & INCOMPAT_CSUM_SEED != 0 {
+            u32_at(0x270)
+        } else {
+            crc32c(!0, &sb[0x68..0x78])
+        };
+        let extra_isize = match inode_size {
+            128 => 0,
+            _ => u16_at(0x15e).max(32),
+        };
+        Ok(Self {
+            block_size,
+            blocks_count,
+            first_data_block,
+            blocks_per_group,
+            inodes_per_group,
+            inodes_count: u32_at(0x0),
+            inode_size,
+            group_count,
+            desc_size,
+            reserved_gdt_blocks: u16_at(0xce) as u64,
+            compat: u32_at(0x5c),
+            incompat,
+            ro_compat,
+            uuid: sb[0x68..0x78].try_into().unwrap(),
+            backup_groups: [u32_at(0x24c), u32_at(0x250)],
+            csum_seed,
+            hash_seed: core::array::from_fn(|i| u32_at(0xec + i * 4)),
+            unsigned_hash: u32_at(0x160) & FLAGS_UNSIGNED_HASH != 0,
+            extra_isize,
+            journal_ino: u32_at(0xe0),
+        })
+    }
+
+    fn has_metadata_csum(&self) -> bool {
+        self.ro_compat & RO_COMPAT_METADATA_CSUM != 0
+    }
+
+    /// The first block after the superblock, where group descriptors start.
+    fn gdt_start(&self) -> u64 {
+        self.first_data_block + 1
+    }
+
+    /// Blocks of group descriptors.
+    fn gdt_blocks(&self) -> u64 {
+        (self.group_count as u64 * self.desc_size as u64).div_ceil(self.block_size as u64)
+    }
+}
+
+/// What the inodes of one volume share.
+pub struct Ext4Fs {
+    cache: BlockCache,
+    layout: Layout,
+    dev: u64,
+    read_only: bool,
+    /// The superblock as stored, whose free counts change as files do.
+    superblock: SleepMutex<[u8; SUPERBLOCK_SIZE]>,
+    /// Held while bitmaps and group descriptors change.
+    alloc_lock: SleepMutex<()>,
+    /// Inodes in use, so a file has one inode however it is looked up.
+    inodes: SleepMutex<BTreeMap<u32, Weak<Ext4Inode>>>,
+    /// Held while a directory is read or changed.
+    dir_lock: SleepMutex<()>,
+    /// Generation numbers of new inodes.
+    next_generation: AtomicU32,
+}
+
+impl Ext4Fs {
+    /// Byte where `block` starts.
+    fn block_pos(&self, block: u64) -> u64 {
+        block * self.layout.block_size as u64
+    }
+
+    fn read_block(&self, block: u64, buf: &mut [u8]) -> FsResult<()> {
+        self.cache.read_at(self.block_pos(block), buf)
+    }
+
+    fn write_block(&self, block: u64, buf: &[u8]) -> FsResult<()> {
+        self.cache.write_at(self.block_pos(block), buf)
+    }
+
+    fn check_writable(&self) -> FsResult<()> {
+        match self.read_only {
+            true => Err(FsError::ReadOnly),
+            false => Ok(()),
+        }
+    }
+
+    /// The inode numbered `ino`.
+    fn inode(self: &Arc<Self>, ino: u32) -> FsResult<Arc<Ext4Inode>> {
+        if ino == 0 || ino > self.layout.inodes_count {
+            return Err(FsError::Corrupted);
+        }
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let inode = Ext4Inode::load(self, ino)?;
+        inodes.insert(ino, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    /// The free blocks and inodes, as the superblock counts them.
+    fn free_counts(&self) -> (u64, u32) {
+        let sb = self.superblock.lock();
+        let u32_at = |offset: usize| u32::from_le_bytes(sb[offset..offset + 4].try_into().unwrap());
+        let mut blocks = u32_at(0xc) as u64;
+        if self.layout.incompat & INCOMPAT_64BIT != 0 {
+            blocks |= (u32_at(0x158) as u64) << 32;
+        }
+        (blocks, u32_at(0x10))
+    }
+
+    /// Add `blocks` and `inodes` to the free counts of the superblock.
+    fn add_free_counts(&self, blocks: i64, inodes: i64) {
+        let (free_blocks, free_inodes) = self.free_counts();
+        let free_blocks = free_blocks.saturating_add_signed(blocks);
+        let free_inodes = (free_inodes as i64 + inodes).max(0) as u32;
+        let mut sb = self.superblock.lock();
+        sb[0xc..0x10].copy_from_slice(&(free_blocks as u32).to_le_bytes());
+        if self.layout.incompat & INCOMPAT_64BIT != 0 {
+            sb[0x158..0x15c].copy_from_slice(&((free_blocks >> 32) as u32).to_le_bytes());
+        }
+        sb[0x10..0x14].copy_from_slice(&free_inodes.to_le_bytes());
+    }
+
+    fn write_superblock(&self) -> FsResult<()> {
+        let mut sb = self.superblock.lock();
+        if self.layout.has_metadata_csum() {
+            let checksum = crc32c(!0, &sb[..SB_CHECKSUM]);
+            sb[SB_CHECKSUM..].copy_from_slice(&checksum.to_le_bytes());
+        }
+        self.cache.write_at(SUPERBLOCK_POS, &sb[..])
+    }
+}
+
+pub struct Ext4 {
+    fs: Arc<Ext4Fs>,
+    root: Arc<Ext4Inode>,
+}
+
+impl Ext4 {
+    /// Mount the volume on `device`, replaying its journal if needed.
+    pub fn new(device: Arc<dyn BlockDevice>) -> FsResult<Arc<Self>> {
+        let cache = BlockCache::new(device);
+        let mut superblock = [0; SUPERBLOCK_SIZE];
+        cache.read_at(SUPERBLOCK_POS, &mut superblock)?;
+        let layout = Layout::parse(&superblock)?;
+        if layout.has_metadata_csum() && crc32c(!0, &superblock[..SB_CHECKSUM]).to_le_bytes()[..] != superblock[SB_CHECKSUM..] {
+            warn!("ext4: superblock checksum mismatch");
+            return Err(FsError::Corrupted);
+        }
+        let unknown_ro = layout.ro_compat & !RO_COMPAT_SUPPORTED;
+        if unknown_ro != 0 {
+            warn!("ext4: mounting read-only for features {:#x}", unknown_ro);
+        }
+        let no_extents = layout.incompat & INCOMPAT_EXTENTS == 0;
+        if no_extents {
+            warn!("ext4: mounting read-only without extents");
+        }
+        let fs = Arc::new(Ext4Fs {
+            cache,
+            read_only: unknown_ro != 0 || no_extents,
+            layout,
+            dev: mount::new_dev(),
+            superblock: SleepMutex::new(superblock),
+            alloc_lock: SleepMutex::new(()),
+            inodes: SleepMutex::new(BTreeMap::new()),
+            dir_lock: SleepMutex::new(()),
+            next_generation: AtomicU32::new(crate::timer::get_ticks() as u32),
+        });
+        if fs.layout.incompat & INCOMPAT_RECOVER != 0 {
+            if fs.layout.compat & COMPAT_HAS_JOURNAL == 0 || unknown_ro != 0 {
+                return Err(FsError::Unsupported);
+            }
+            journal::replay(&fs)?;
+            // The journal may have held the superblock too.
+            let mut sb = fs.superblock.lock();
+            fs.cache.read_at(SUPERBLOCK_POS, &mut sb[..])?;
+            let incompat = u32::from_le_bytes(sb[0x60..0x64].try_into().unwrap()) & !INCOMPAT_RECOVER;
+            sb[0x60..0x64].copy_from_slice(&incompat.to_le_bytes());
+            drop(sb);
+            fs.recount_free()?;
+            fs.write_superblock()?;
+            fs.cache.flush()?;
+        }
+        let root = fs.inode(ROOT_INO)?;
+        Ok(Arc::new(Self { fs, root }))
+    }
+
+    /// Mount a volume for `mount`, which must name a device.
+    pub fn mount(device: Option<Arc<dyn BlockDevice>>) -> FsResult<Arc<dyn SuperBlock>> {
+        let device = device.ok_or(FsError::InvalidInput)?;
+        Ok(Self::new(device)?)
+    }
+}
+
+impl SuperBlock for Ext4 {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        if !self.fs.read_only {
+            self.fs.write_superblock()?;
+        }
+        self.fs.cache.flush()
+    }
+}
@@ -0,0 +1,408 @@
+//! Directory entries: 8.3 short entries, and the long file name entries
+//! that precede them.
+
+use alloc::{string::String, vec::Vec};
+
+use crate::fs::{FsError, FsResult};
+
+use super::time;
+
+pub const ENTRY_SIZE: usize = 32;
+
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// Attributes marking a long file name entry.
+const ATTR_LFN: u8 = 0x0f;
+
+/// First name byte of a free entry.
+pub const DELETED: u8 = 0xe5;
+/// First name byte of the entry after the last one in use.
+const END: u8 = 0x00;
+/// Stands for a first name byte of 0xe5, which means deleted.
+const KANJI_E5: u8 = 0x05;
+/// Order flag of the long name entry stored first, holding the name's end.
+const LAST_LFN: u8 = 0x40;
+
+/// Case flags of Windows NT for short names that are all lowercase.
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+/// UTF-16 units of the name in one long name entry.
+const LFN_CHARS: usize = 13;
+/// Byte offsets of those units in the entry.
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// Longest long name, in UTF-16 units.
+const LFN_MAX: usize = 255;
+
+/// Characters short names may contain besides letters and digits.
+const SHORT_SPECIAL: &[u8] = b"$%'-_@~`!(){}^#&";
+
+/// A short directory entry, as stored.
+#[derive(Clone, Copy)]
+pub struct ShortEntry(pub [u8; ENTRY_SIZE]);
+
+impl ShortEntry {
+    pub fn new(name: [u8; 11], case: u8, attr: u8, first_cluster: u32, now: u64) -> Self {
+        let mut entry = Self([0; ENTRY_SIZE]);
+        entry.set_name(name, case);
+        entry.0[11] = attr;
+        entry.set_first_cluster(first_cluster);
+        entry.set_ctime(now);
+        entry.set_mtime(now);
+        entry.set_atime(now);
+        entry
+    }
+
+    pub fn name(&self) -> [u8; 11] {
+        self.0[..11].try_into().unwrap()
+    }
+
+    pub fn set_name(&mut self, name: [u8; 11], case: u8) {
+        self.0[..11].copy_from_slice(&name);
+        self.0[12] = case;
+    }
+
+    pub fn attr(&self) -> u8 {
+        self.0[11]
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr() & ATTR_DIRECTORY != 0
+    }
+
+    pub fn first_cluster(&self) -> u32 {
+        (u16::from_le_bytes([self.0[20], self.0[21]]) as u32) << 16 | u16::from_le_bytes([self.0[26], self.0[27]]) as u32
+    }
+
+    pub fn set_first_cluster(&mut self, cluster: u32) {
+        self.0[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        self.0[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    }
+
+    pub fn size(&self) -> u32 {
+        u32::from_le_bytes(self.0[28..32].try_into().unwrap())
+    }
+
+    pub fn set_size(&mut self, size: u32) {
+        self.0[28..32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.0[offset], self.0[offset + 1]])
+    }
+
+    pub fn ctime(&self) -> u64 {
+        time::to_unix(self.u16_at(16), self.u16_at(14))
+    }
+
+    pub fn mtime(&self) -> u64 {
+        time::to_unix(self.u16_at(24), self.u16_at(22))
+    }
+
+    pub fn atime(&self) -> u64 {
+        time::to_unix(self.u16_at(18), 0)
+    }
+
+    pub fn set_ctime(&mut self, secs: u64) {
+        let (date, time) = time::from_unix(secs);
+        self.0[13] = 0;
+        self.0[14..16].copy_from_slice(&time.to_le_bytes());
+        self.0[16..18].copy_from_slice(&date.to_le_bytes());
+    }
+
+    pub fn set_mtime(&mut self, secs: u64) {
+        let (date, time) = time::from_unix(secs);
+        self.0[22..24].copy_from_slice(&time.to_le_bytes());
+        self.0[24..26].copy_from_slice(&date.to_le_bytes());
+    }
+
+    pub fn set_atime(&mut self, secs: u64) {
+        self.0[18..20].copy_from_slice(&time::from_unix(secs).0.to_le_bytes());
+    }
+
+    /// The name made from the 8.3 name alone.
+    fn display_name(&self) -> String {
+        let mut raw = self.name();
+        if raw[0] == KANJI_E5 {
+            raw[0] = DELETED;
+        }
+        let case = self.0[12];
+        let part = |bytes: &[u8], lower: bool| -> String {
+            let trimmed = bytes.iter().rposition(|&byte| byte != b' ').map_or(&bytes[..0], |end| &bytes[..=end]);
+            trimmed
+                .iter()
+                .map(|&byte| if lower { byte.to_ascii_lowercase() } else { byte } as char)
+                .collect()
+        };
+        let mut name = part(&raw[..8], case & NT_LOWER_BASE != 0);
+        let ext = part(&raw[8..], case & NT_LOWER_EXT != 0);
+        if !ext.is_empty() {
+            name.push('.');
+            name.push_str(&ext);
+        }
+        name
+    }
+}
+
+/// A file in a directory, with the entries naming it.
+pub struct DirItem {
+    pub name: String,
+    pub entry: ShortEntry,
+    /// Offset of the short entry in the directory.
+    pub offset: usize,
+    /// Offset of the first entry of the item, its first long name entry if
+    /// it has a long name.
+    pub start: usize,
+}
+
+/// Long name entries collected while walking a directory.
+struct LongName {
+    checksum: u8,
+    /// Order number expected next, counting down to 1.
+    next: u8,
+    units: Vec<u16>,
+    start: usize,
+}
+
+/// The first item at or after offset `from` of the directory `data`, skipping
+/// free entries and volume labels.
+pub fn next_item(data: &[u8], from: usize) -> Option<DirItem> {
+    let mut long: Option<LongName> = None;
+    let mut offset = from.next_multiple_of(ENTRY_SIZE);
+    while offset + ENTRY_SIZE <= data.len() {
+        let raw: [u8; ENTRY_SIZE] = data[offset..offset + ENTRY_SIZE].try_into().unwrap();
+        let entry_offset = offset;
+        offset += ENTRY_SIZE;
+        match raw[0] {
+            END => return None,
+            DELETED => {
+                long = None;
+                continue;
+            }
+            _ => {}
+        }
+        if raw[11] & 0x3f == ATTR_LFN {
+            let order = raw[0] & 0x1f;
+            if raw[0] & LAST_LFN != 0 {
+                let mut units = Vec::with_capacity(order as usize * LFN_CHARS);
+                units.resize(order as usize * LFN_CHARS, 0xffff);
+                long = Some(LongName {
+                    checksum: raw[13],
+                    next: order,
+                    units,
+                    start: entry_offset,
+                });
+            }
+            match long.as_mut() {
+                Some(name) if name.next == order && name.checksum == raw[13] && order > 0 => {
+                    let base = (order as usize - 1) * LFN_CHARS;
+                    for (i, &at) in LFN_OFFSETS.iter().enumerate() {
+                        name.units[base + i] = u16::from_le_bytes([raw[at], raw[at + 1]]);
+                    }
+                    name.next -= 1;
+                }
+                _ => long = None,
+            }
+            continue;
+        }
+        let entry = ShortEntry(raw);
+        if entry.attr() & ATTR_VOLUME_ID != 0 {
+            long = None;
+            continue;
+        }
+        let long_name = long
+            .take()
+            .filter(|name| name.next == 0 && name.checksum == checksum(&entry.name()))
+            .and_then(|name| {
+                let end = name.units.iter().position(|&unit| unit == 0 || unit == 0xffff);
+                let decoded = String::from_utf16(&name.units[..end.unwrap_or(name.units.len())]).ok()?;
+                Some((decoded, name.start))
+            });
+        let (name, start) = long_name.unwrap_or_else(|| (entry.display_name(), entry_offset));
+        return Some(DirItem {
+            name,
+            entry,
+            offset: entry_offset,
+            start,
+        });
+    }
+    None
+}
+
+/// Every item of the directory `data`.
+pub fn items(data: &[u8]) -> Vec<DirItem> {
+    let mut items = Vec::new();
+    let mut from = 0;
+    while let Some(item) = next_item(data, from) {
+        from = item.offset + ENTRY_SIZE;
+        items.push(item);
+    }
+    items
+}
+
+/// The item named `name`, which FAT compares ignoring ASCII case.
+pub fn find<'a>(items: &'a [DirItem], name: &str) -> Option<&'a DirItem> {
+    items.iter().find(|item| item.name.eq_ignore_ascii_case(name))
+}
+
+/// The checksum of a short name that its long name entries carry.
+fn checksum(name: &[u8; 11]) -> u8 {
+    name.iter().fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Whether `name` can be stored at all.
+pub fn check_name(name: &str) -> FsResult<()> {
+    if name.encode_utf16().count() > LFN_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    let invalid = |c: char| c < ' ' || "\"*/:<>?\\|".contains(c);
+    if name.is_empty() || name.chars().any(invalid) || name.ends_with(['.', ' ']) {
+        return Err(FsError::InvalidInput);
+    }
+    Ok(())
+}
+
+/// The 8.3 name and case flags storing `name` exactly, if there is one.
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) => (base, ext),
+        None => (name, ""),
+    };
+    let valid = |part: &str, max: usize| {
+        part.len() <= max && part.bytes().all(|byte| byte.is_ascii_alphanumeric() || SHORT_SPECIAL.contains(&byte))
+    };
+    if base.is_empty() || !valid(base, 8) || !valid(ext, 3) {
+        return None;
+    }
+    // A part must be all of one case to be stored with the case flags.
+    let case_of = |part: &str, flag: u8| {
+        if part.bytes().any(|byte| byte.is_ascii_lowercase()) {
+            (!part.bytes().any(|byte| byte.is_ascii_uppercase())).then_some(flag)
+        } else {
+            Some(0)
+        }
+    };
+    let case = case_of(base, NT_LOWER_BASE)? | case_of(ext, NT_LOWER_EXT)?;
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(&base.as_bytes().to_ascii_uppercase());
+    short[8..8 + ext.len()].copy_from_slice(&ext.as_bytes().to_ascii_uppercase());
+    if short[0] == DELETED {
+        short[0] = KANJI_E5;
+    }
+    Some((short, case))
+}
+
+/// A generated 8.3 name for `name`, of the form `BASE~N.EXT`, that `taken`
+/// does not reject.
+fn generated_short_name(name: &str, taken: impl Fn(&[u8; 11]) -> bool) -> FsResult<[u8; 11]> {
+    let clean = |part: &str| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| match c {
+                c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase() as u8,
+                c if c.is_ascii() && SHORT_SPECIAL.contains(&(c as u8)) => c as u8,
+                _ => b'_',
+            })
+            .collect()
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rsplit_once('.') {
+        Some((base, ext)) => (clean(base), clean(ext)),
+        None => (clean(trimmed), Vec::new()),
+    };
+    let mut short = [b' '; 11];
+    for (slot, &byte) in short[8..].iter_mut().zip(ext.iter()) {
+        *slot = byte;
+    }
+    for n in 1..1_000_000usize {
+        let mut tail = [0u8; 8];
+        let mut digits = 0;
+        let mut value = n;
+        while value > 0 {
+            tail[7 - digits] = b'0' + (value % 10) as u8;
+            value /= 10;
+            digits += 1;
+        }
+        let keep = base.len().min(7 - digits);
+        let mut candidate = short;
+        candidate[..8].fill(b' ');
+        candidate[..keep].copy_from_slice(&base[..keep]);
+        candidate[keep] = b'~';
+        candidate[keep + 1..keep + 1 + digits].copy_from_slice(&tail[8 - digits..]);
+        if candidate[0] == DELETED {
+            candidate[0] = KANJI_E5;
+        }
+        if !taken(&candidate) {
+            return Ok(candidate);
+        }
+    }
+    Err(FsError::NoSpace)
+}
+
+/// The entries storing `entry` under `name` in a directory of `items`, in
+/// on-disk order: long name entries, if needed, then the short entry.
+pub fn entries_for(name: &str, mut entry: ShortEntry, items: &[DirItem]) -> FsResult<Vec<[u8; ENTRY_SIZE]>> {
+    check_name(name)?;
+    let mut entries = Vec::new();
+    if let Some((short, case)) = exact_short_name(name) {
+        entry.set_name(short, case);
+        entries.push(entry.0);
+        return Ok(entries);
+    }
+    let short = generated_short_name(name, |candidate| items.iter().any(|item| item.entry.name() == *candidate))?;
+    entry.set_name(short, 0);
+    let sum = checksum(&short);
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LFN_CHARS);
+    // The name is terminated if it does not fill its last entry, and padded
+    // after that.
+    if units.len() < count * LFN_CHARS {
+        units.push(0);
+    }
+    units.resize(count * LFN_CHARS, 0xffff);
+    for order in (1..=count).rev() {
+        let mut raw = [0u8; ENTRY_SIZE];
+        raw[0] = order as u8 | if order == count { LAST_LFN } else { 0 };
+        raw[11] = ATTR_LFN;
+        raw[13] = sum;
+        for (i, &at) in LFN_OFFSETS.iter().enumerate() {
+            raw[at..at + 2].copy_from_slice(&units[(order - 1) * LFN_CHARS + i].to_le_bytes());
+        }
+        entries.push(raw);
+    }
+    entries.push(entry.0);
+    Ok(entries)
+}
+
+/// Offset where `count` consecutive entries are free in the directory
+/// `data`. If there is no such place, the offset where they would start
+/// once the directory is grown.
+pub fn free_slots(data: &[u8], count: usize) -> usize {
+    let mut run_start = 0;
+    let mut run = 0;
+    let mut offset = 0;
+    while offset + ENTRY_SIZE <= data.len() {
+        match data[offset] {
+            // Everything from here on is free.
+            END => return if run > 0 { run_start } else { offset },
+            DELETED => {
+                if run == 0 {
+                    run_start = offset;
+                }
+                run += 1;
+                if run == count {
+                    return run_start;
+                }
+            }
+            _ => run = 0,
+        }
+        offset += ENTRY_SIZE;
+    }
+    if run > 0 {
+        run_start
+    } else {
+        data.len()
+    }
+}
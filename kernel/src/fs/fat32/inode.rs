@@ -0,0 +1,552 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{any::Any, ops::Range};
+
+use crate::{
+    fs::{DirEntry, FsError, FsResult, Inode, InodeType, Metadata, TimeSpec},
+    sched::SleepMutex,
+};
+
+use super::{
+    dir::{self, DirItem, ShortEntry, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_READ_ONLY, DELETED, ENTRY_SIZE},
+    FatFs,
+};
+
+/// Inode number of the root directory, which has no entry.
+const ROOT_INO: u64 = 1;
+/// Directories are limited to 65536 entries.
+const DIR_MAX: usize = 65536 * ENTRY_SIZE;
+
+pub struct FatInode {
+    fs: Arc<FatFs>,
+    ino: u64,
+    kind: InodeType,
+    inner: SleepMutex<FatInodeInner>,
+}
+
+struct FatInodeInner {
+    /// Where the short entry is on the device, `None` for the root and for
+    /// unlinked files.
+    pos: Option<u64>,
+    /// The short entry, kept in step with the one on the device.
+    entry: ShortEntry,
+    /// The clusters of the data, in order.
+    chain: Vec<u32>,
+    /// Free the clusters when the inode goes away.
+    unlinked: bool,
+}
+
+impl FatInode {
+    pub(super) fn new(fs: &Arc<FatFs>, pos: Option<u64>, entry: ShortEntry) -> FsResult<Arc<Self>> {
+        let chain = fs.chain(entry.first_cluster())?;
+        Ok(Arc::new(Self {
+            fs: fs.clone(),
+            ino: pos.map_or(ROOT_INO, |pos| pos / ENTRY_SIZE as u64),
+            kind: if entry.is_dir() { InodeType::Dir } else { InodeType::File },
+            inner: SleepMutex::new(FatInodeInner {
+                pos,
+                entry,
+                chain,
+                unlinked: false,
+            }),
+        }))
+    }
+
+    /// `inode` as an inode of the same volume.
+    fn same_fs<'a>(&self, inode: &'a Arc<dyn Inode>) -> FsResult<&'a FatInode> {
+        inode
+            .as_any()
+            .downcast_ref::<FatInode>()
+            .filter(|inode| Arc::ptr_eq(&inode.fs, &self.fs))
+            .ok_or(FsError::CrossDevice)
+    }
+
+    fn check_dir(&self) -> FsResult<()> {
+        match self.kind {
+            InodeType::Dir => Ok(()),
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    fn check_file(&self) -> FsResult<()> {
+        match self.kind {
+            InodeType::Dir => Err(FsError::IsDir),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run `f` on the pieces of the data from `offset` to `offset + len`
+    /// that are contiguous on the device, with where each piece is and its
+    /// range relative to `offset`. The clusters must be allocated.
+    fn for_each_piece(
+        &self,
+        inner: &FatInodeInner,
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(u64, Range<usize>) -> FsResult<()>,
+    ) -> FsResult<()> {
+        let cluster_size = self.fs.geometry.cluster_size;
+        let mut done = 0;
+        while done < len {
+            let at = offset + done;
+            let within = at % cluster_size;
+            let chunk = (cluster_size - within).min(len - done);
+            f(self.fs.cluster_pos(inner.chain[at / cluster_size]) + within as u64, done..done + chunk)?;
+            done += chunk;
+        }
+        Ok(())
+    }
+
+    fn read_data(&self, inner: &FatInodeInner, offset: usize, buf: &mut [u8]) -> FsResult<()> {
+        self.for_each_piece(inner, offset, buf.len(), |pos, range| self.fs.cache.read_at(pos, &mut buf[range]))
+    }
+
+    fn write_data(&self, inner: &FatInodeInner, offset: usize, buf: &[u8]) -> FsResult<()> {
+        self.for_each_piece(inner, offset, buf.len(), |pos, range| self.fs.cache.write_at(pos, &buf[range]))
+    }
+
+    fn zero_data(&self, inner: &FatInodeInner, range: Range<usize>) -> FsResult<()> {
+        self.for_each_piece(inner, range.start, range.len(), |pos, range| self.fs.cache.zero(pos, range.len()))
+    }
+
+    /// Allocate clusters until the data can hold `len` bytes.
+    fn reserve(&self, inner: &mut FatInodeInner, len: usize) -> FsResult<()> {
+        while inner.chain.len() * self.fs.geometry.cluster_size < len {
+            let cluster = self.fs.alloc_cluster(inner.chain.last().copied())?;
+            if inner.chain.is_empty() {
+                inner.entry.set_first_cluster(cluster);
+            }
+            inner.chain.push(cluster);
+        }
+        Ok(())
+    }
+
+    /// Free the clusters after the first `keep`.
+    fn release(&self, inner: &mut FatInodeInner, keep: usize) -> FsResult<()> {
+        if keep >= inner.chain.len() {
+            return Ok(());
+        }
+        if keep == 0 {
+            self.fs.free_chain(inner.chain[0])?;
+            inner.entry.set_first_cluster(0);
+        } else {
+            self.fs.truncate_chain(inner.chain[keep - 1])?;
+        }
+        inner.chain.truncate(keep);
+        Ok(())
+    }
+
+    /// Write the short entry back to the device.
+    fn write_entry(&self, inner: &FatInodeInner) -> FsResult<()> {
+        match inner.pos {
+            Some(pos) => self.fs.cache.write_at(pos, &inner.entry.0),
+            None => Ok(()),
+        }
+    }
+
+    /// Record a change of the data.
+    fn touch(&self, inner: &mut FatInodeInner) -> FsResult<()> {
+        inner.entry.set_mtime(TimeSpec::now().sec);
+        self.write_entry(inner)
+    }
+
+    /// Write `buf` at `offset`, or at the end of the file if `None`,
+    /// returning where it went and how many bytes were written.
+    fn write(&self, offset: Option<usize>, buf: &[u8]) -> FsResult<(usize, usize)> {
+        self.check_file()?;
+        let mut inner = self.inner.lock();
+        let size = inner.entry.size() as usize;
+        let offset = offset.unwrap_or(size);
+        if buf.is_empty() {
+            return Ok((offset, 0));
+        }
+        // Sizes are 32-bit.
+        let end = offset
+            .checked_add(buf.len())
+            .filter(|&end| end <= u32::MAX as usize)
+            .ok_or(FsError::NoSpace)?;
+        self.reserve(&mut inner, end)?;
+        if offset > size {
+            self.zero_data(&inner, size..offset)?;
+        }
+        self.write_data(&inner, offset, buf)?;
+        if end > size {
+            inner.entry.set_size(end as u32);
+        }
+        self.touch(&mut inner)?;
+        Ok((offset, buf.len()))
+    }
+
+    /// All of the directory's entries.
+    fn dir_data(&self, inner: &FatInodeInner) -> FsResult<Vec<u8>> {
+        let mut data = vec![0; inner.chain.len() * self.fs.geometry.cluster_size];
+        self.read_data(inner, 0, &mut data)?;
+        Ok(data)
+    }
+
+    /// Where the directory entry at `offset` is on the device.
+    fn entry_pos(&self, inner: &FatInodeInner, offset: usize) -> u64 {
+        let cluster_size = self.fs.geometry.cluster_size;
+        self.fs.cluster_pos(inner.chain[offset / cluster_size]) + (offset % cluster_size) as u64
+    }
+
+    /// The cluster `..` entries refer to this directory by.
+    fn dir_cluster(&self, inner: &FatInodeInner) -> u32 {
+        match self.ino {
+            ROOT_INO => 0,
+            _ => inner.entry.first_cluster(),
+        }
+    }
+
+    /// The inode of `item`, an item of this directory.
+    fn child(&self, inner: &FatInodeInner, item: &DirItem) -> FsResult<Arc<FatInode>> {
+        self.fs.inode(self.entry_pos(inner, item.offset), item.entry)
+    }
+
+    /// Store `entries` in free entries of the directory `data`, growing it
+    /// as needed, and return where the last one, the short entry, went.
+    fn insert(&self, inner: &mut FatInodeInner, data: &[u8], entries: &[[u8; ENTRY_SIZE]]) -> FsResult<u64> {
+        let offset = dir::free_slots(data, entries.len());
+        let end = offset + entries.len() * ENTRY_SIZE;
+        if end > DIR_MAX {
+            return Err(FsError::NoSpace);
+        }
+        self.reserve(inner, end)?;
+        self.write_data(inner, offset, entries.as_flattened())?;
+        Ok(self.entry_pos(inner, end - ENTRY_SIZE))
+    }
+
+    /// Free the entries of `item`.
+    fn remove(&self, inner: &FatInodeInner, item: &DirItem) -> FsResult<()> {
+        for offset in (item.start..=item.offset).step_by(ENTRY_SIZE) {
+            self.fs.cache.write_at(self.entry_pos(inner, offset), &[DELETED])?;
+        }
+        Ok(())
+    }
+
+    /// Forget the entry of this file, which was removed from its directory.
+    fn detach(&self) {
+        let mut inner = self.inner.lock();
+        if let Some(pos) = inner.pos.take() {
+            self.fs.inodes.lock().remove(&pos);
+        }
+        inner.unlinked = true;
+    }
+
+    fn is_empty_dir(&self) -> FsResult<bool> {
+        let data = self.dir_data(&self.inner.lock())?;
+        Ok(dir::items(&data).iter().all(|item| item.name == "." || item.name == ".."))
+    }
+
+    /// Fill the new directory at `cluster` with its `.` and `..` entries.
+    fn init_dir(&self, inner: &FatInodeInner, cluster: u32, now: u64) -> FsResult<()> {
+        let dot = ShortEntry::new(*b".          ", 0, ATTR_DIRECTORY, cluster, now);
+        let dot_dot = ShortEntry::new(*b"..         ", 0, ATTR_DIRECTORY, self.dir_cluster(inner), now);
+        let pos = self.fs.cluster_pos(cluster);
+        self.fs.cache.write_at(pos, &dot.0)?;
+        self.fs.cache.write_at(pos + ENTRY_SIZE as u64, &dot_dot.0)
+    }
+}
+
+impl Inode for FatInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let inner = self.inner.lock();
+        let cluster_size = self.fs.geometry.cluster_size;
+        let allocated = (inner.chain.len() * cluster_size) as u64;
+        let (size, nlink) = match self.kind {
+            InodeType::Dir => (allocated, 2),
+            _ => (inner.entry.size() as u64, 1),
+        };
+        let time = |sec| TimeSpec { sec, nsec: 0 };
+        Ok(Metadata {
+            dev: self.fs.dev,
+            ino: self.ino,
+            kind: self.kind,
+            mode: if inner.entry.attr() & ATTR_READ_ONLY != 0 { 0o555 } else { 0o755 },
+            nlink,
+            uid: 0,
+            gid: 0,
+            size,
+            blksize: cluster_size as u32,
+            blocks: allocated / 512,
+            rdev: 0,
+            atime: time(inner.entry.atime()),
+            mtime: time(inner.entry.mtime()),
+            ctime: time(inner.entry.ctime()),
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        self.check_file()?;
+        let inner = self.inner.lock();
+        let size = inner.entry.size() as usize;
+        let start = offset.min(size);
+        let len = buf.len().min(size - start);
+        self.read_data(&inner, start, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> FsResult<usize> {
+        self.write(Some(offset), buf).map(|(_, written)| written)
+    }
+
+    fn append(&self, buf: &[u8]) -> FsResult<(usize, usize)> {
+        self.write(None, buf)
+    }
+
+    fn truncate(&self, len: usize) -> FsResult<()> {
+        self.check_file()?;
+        if len > u32::MAX as usize {
+            return Err(FsError::NoSpace);
+        }
+        let mut inner = self.inner.lock();
+        let size = inner.entry.size() as usize;
+        if len > size {
+            self.reserve(&mut inner, len)?;
+            self.zero_data(&inner, size..len)?;
+        } else {
+            self.release(&mut inner, len.div_ceil(self.fs.geometry.cluster_size))?;
+        }
+        inner.entry.set_size(len as u32);
+        self.touch(&mut inner)
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        self.fs.cache.flush()
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        self.check_dir()?;
+        let _guard = self.fs.dir_lock.lock();
+        let inner = self.inner.lock();
+        let data = self.dir_data(&inner)?;
+        let items = dir::items(&data);
+        let item = dir::find(&items, name).ok_or(FsError::NotFound)?;
+        Ok(self.child(&inner, item)?)
+    }
+
+    fn create(&self, name: &str, kind: InodeType, mode: u32) -> FsResult<Arc<dyn Inode>> {
+        self.check_dir()?;
+        let mut attr = match kind {
+            InodeType::File => ATTR_ARCHIVE,
+            InodeType::Dir => ATTR_DIRECTORY,
+            _ => return Err(FsError::Unsupported),
+        };
+        if mode & 0o222 == 0 {
+            attr |= ATTR_READ_ONLY;
+        }
+        let _guard = self.fs.dir_lock.lock();
+        let mut inner = self.inner.lock();
+        let data = self.dir_data(&inner)?;
+        let items = dir::items(&data);
+        if dir::find(&items, name).is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let now = TimeSpec::now().sec;
+        let first_cluster = match kind {
+            InodeType::Dir => {
+                let cluster = self.fs.alloc_cluster(None)?;
+                self.init_dir(&inner, cluster, now)?;
+                cluster
+            }
+            _ => 0,
+        };
+        let entry = ShortEntry::new([b' '; 11], 0, attr, first_cluster, now);
+        let inserted = dir::entries_for(name, entry, &items).and_then(|entries| {
+            let pos = self.insert(&mut inner, &data, &entries)?;
+            Ok((pos, ShortEntry(*entries.last().unwrap())))
+        });
+        let (pos, entry) = match inserted {
+            Ok(inserted) => inserted,
+            Err(err) => {
+                if first_cluster != 0 {
+                    self.fs.free_chain(first_cluster)?;
+                }
+                return Err(err);
+            }
+        };
+        self.touch(&mut inner)?;
+        drop(inner);
+        Ok(self.fs.inode(pos, entry)?)
+    }
+
+    /// FAT has no symbolic links.
+    fn symlink(&self, _name: &str, _target: &str) -> FsResult<Arc<dyn Inode>> {
+        self.check_dir()?;
+        Err(FsError::PermissionDenied)
+    }
+
+    /// Nor hard links.
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> FsResult<()> {
+        self.check_dir()?;
+        Err(FsError::PermissionDenied)
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        self.check_dir()?;
+        let _guard = self.fs.dir_lock.lock();
+        let mut inner = self.inner.lock();
+        let data = self.dir_data(&inner)?;
+        let items = dir::items(&data);
+        let item = dir::find(&items, name).ok_or(FsError::NotFound)?;
+        if item.entry.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        let child = self.child(&inner, item)?;
+        self.remove(&inner, item)?;
+        self.touch(&mut inner)?;
+        drop(inner);
+        child.detach();
+        Ok(())
+    }
+
+    fn rmdir(&self, name: &str) -> FsResult<()> {
+        self.check_dir()?;
+        let _guard = self.fs.dir_lock.lock();
+        let mut inner = self.inner.lock();
+        let data = self.dir_data(&inner)?;
+        let items = dir::items(&data);
+        let item = dir::find(&items, name).ok_or(FsError::NotFound)?;
+        if !item.entry.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        let child = self.child(&inner, item)?;
+        if !child.is_empty_dir()? {
+            return Err(FsError::NotEmpty);
+        }
+        self.remove(&inner, item)?;
+        self.touch(&mut inner)?;
+        drop(inner);
+        child.detach();
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> FsResult<()> {
+        self.check_dir()?;
+        let new_dir = self.same_fs(new_dir)?;
+        new_dir.check_dir()?;
+        let same_dir = core::ptr::eq(self, new_dir);
+        let _guard = self.fs.dir_lock.lock();
+
+        let inner = self.inner.lock();
+        let data = self.dir_data(&inner)?;
+        let items = dir::items(&data);
+        let item = dir::find(&items, old_name).ok_or(FsError::NotFound)?;
+        let old_pos = self.entry_pos(&inner, item.offset);
+        let moved = self.child(&inner, item)?;
+        drop(inner);
+
+        // Replace the target, unless it is the entry being renamed, which
+        // only changes the case of its name.
+        let mut new_inner = new_dir.inner.lock();
+        let new_data = new_dir.dir_data(&new_inner)?;
+        let new_items = dir::items(&new_data);
+        if let Some(target) = dir::find(&new_items, new_name) {
+            let target_pos = new_dir.entry_pos(&new_inner, target.offset);
+            if target_pos == old_pos {
+                if target.name == new_name {
+                    return Ok(());
+                }
+            } else {
+                let replaced = new_dir.child(&new_inner, target)?;
+                match (moved.kind == InodeType::Dir, replaced.kind == InodeType::Dir) {
+                    (true, true) if !replaced.is_empty_dir()? => return Err(FsError::NotEmpty),
+                    (true, false) => return Err(FsError::NotDir),
+                    (false, true) => return Err(FsError::IsDir),
+                    _ => {}
+                }
+                new_dir.remove(&new_inner, target)?;
+                replaced.detach();
+            }
+        }
+        let new_data = new_dir.dir_data(&new_inner)?;
+        let new_items = dir::items(&new_data);
+        let entries = dir::entries_for(new_name, moved.inner.lock().entry, &new_items)?;
+        let new_pos = new_dir.insert(&mut new_inner, &new_data, &entries)?;
+        new_dir.touch(&mut new_inner)?;
+        drop(new_inner);
+
+        // Entries are never moved, so the old ones are where they were.
+        let mut inner = self.inner.lock();
+        let data = self.dir_data(&inner)?;
+        let items = dir::items(&data);
+        let item = items
+            .iter()
+            .find(|item| self.entry_pos(&inner, item.offset) == old_pos)
+            .ok_or(FsError::Corrupted)?;
+        self.remove(&inner, item)?;
+        self.touch(&mut inner)?;
+        drop(inner);
+
+        let new_entry = ShortEntry(*entries.last().unwrap());
+        let mut moved_inner = moved.inner.lock();
+        moved_inner.entry.set_name(new_entry.name(), new_entry.0[12]);
+        moved_inner.pos = Some(new_pos);
+        let first_cluster = moved_inner.entry.first_cluster();
+        drop(moved_inner);
+        {
+            let mut inodes = self.fs.inodes.lock();
+            inodes.remove(&old_pos);
+            inodes.insert(new_pos, Arc::downgrade(&moved));
+        }
+        if moved.kind == InodeType::Dir && !same_dir {
+            let pos = self.fs.cluster_pos(first_cluster) + ENTRY_SIZE as u64;
+            let mut dot_dot = ShortEntry([0; ENTRY_SIZE]);
+            self.fs.cache.read_at(pos, &mut dot_dot.0)?;
+            dot_dot.set_first_cluster(new_dir.dir_cluster(&new_dir.inner.lock()));
+            self.fs.cache.write_at(pos, &dot_dot.0)?;
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        self.check_dir()?;
+        // Positions 0 and 1 are `.` and `..`, which the root has no entries
+        // for, and the others are offsets in the directory plus 2.
+        if pos < 2 {
+            let entry = DirEntry {
+                ino: self.ino,
+                kind: InodeType::Dir,
+                name: if pos == 0 { ".".into() } else { "..".into() },
+            };
+            return Ok(Some((entry, pos + 1)));
+        }
+        let _guard = self.fs.dir_lock.lock();
+        let inner = self.inner.lock();
+        let data = self.dir_data(&inner)?;
+        let mut from = pos - 2;
+        while let Some(item) = dir::next_item(&data, from) {
+            from = item.offset + ENTRY_SIZE;
+            if item.name == "." || item.name == ".." {
+                continue;
+            }
+            let entry = DirEntry {
+                ino: self.entry_pos(&inner, item.offset) / ENTRY_SIZE as u64,
+                kind: if item.entry.is_dir() { InodeType::Dir } else { InodeType::File },
+                name: item.name,
+            };
+            return Ok(Some((entry, from + 2)));
+        }
+        Ok(None)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Drop for FatInode {
+    fn drop(&mut self) {
+        let inner = self.inner.get_mut();
+        if let Some(pos) = inner.pos {
+            let mut inodes = self.fs.inodes.lock();
+            if inodes.get(&pos).is_some_and(|inode| inode.strong_count() == 0) {
+                inodes.remove(&pos);
+            }
+        }
+        if inner.unlinked {
+            if let Some(&first) = inner.chain.first() {
+                let _ = self.fs.free_chain(first);
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! FAT32, with long file names.
+//!
+//! Files are named by the position of their short directory entry on the
+//! device, which is also what their inode number is made of. An open file
+//! keeps its inode, and the clusters of an unlinked file are freed when the
+//! last one goes away.
+
+mod dir;
+mod inode;
+mod table;
+mod time;
+
+use alloc::{collections::BTreeMap, sync::{Arc, Weak}};
+use log::warn;
+
+use crate::{
+    drivers::block::{BlockCache, BlockDevice},
+    sched::SleepMutex,
+};
+
+use self::{dir::ShortEntry, inode::FatInode, table::AllocState};
+use super::{mount, FsError, FsResult, Inode, SuperBlock};
+
+/// Signatures of the FSInfo sector, at offsets 0, 484 and 508.
+const FSINFO_LEAD: u32 = 0x4161_5252;
+const FSINFO_STRUCT: u32 = 0x6141_7272;
+const FSINFO_TRAIL: u32 = 0xaa55_0000;
+/// What FSInfo holds for counts it does not know.
+const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+
+/// The layout of a volume, from its boot sector.
+struct Geometry {
+    bytes_per_sector: u64,
+    num_fats: u32,
+    /// Bytes of one copy of the FAT.
+    fat_size: u64,
+    reserved: u64,
+    /// The FAT read, and the only one written unless mirroring.
+    active_fat: u32,
+    mirror: bool,
+    root_cluster: u32,
+    /// Sector of FSInfo, if the volume has one.
+    fs_info: Option<u64>,
+    cluster_size: usize,
+    /// Byte where cluster 2 starts.
+    data_start: u64,
+    cluster_count: u32,
+}
+
+impl Geometry {
+    fn parse(boot: &[u8; 512]) -> FsResult<Self> {
+        let u16_at = |offset: usize| u16::from_le_bytes([boot[offset], boot[offset + 1]]) as u64;
+        let u32_at = |offset: usize| u32::from_le_bytes(boot[offset..offset + 4].try_into().unwrap()) as u64;
+        let bytes_per_sector = u16_at(11);
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved = u16_at(14);
+        let num_fats = boot[16] as u32;
+        let total_sectors = u32_at(32);
+        let fat_sectors = u32_at(36);
+        let ext_flags = u16_at(40);
+        let valid = boot[510..512] == [0x55, 0xaa]
+            && bytes_per_sector.is_power_of_two()
+            && (512..=4096).contains(&bytes_per_sector)
+            && sectors_per_cluster.is_power_of_two()
+            && reserved > 0
+            && num_fats > 0
+            // Only FAT12 and FAT16 have a fixed root directory and a
+            // 16-bit FAT size.
+            && u16_at(17) == 0
+            && u16_at(22) == 0
+            && fat_sectors > 0;
+        if !valid {
+            return Err(FsError::InvalidInput);
+        }
+        let data_sector = reserved + num_fats as u64 * fat_sectors;
+        let clusters = total_sectors.checked_sub(data_sector).ok_or(FsError::Corrupted)? / sectors_per_cluster;
+        let fat_entries = fat_sectors * bytes_per_sector / 4 - 2;
+        let mirror = ext_flags & 0x80 == 0;
+        let fs_info = match u16_at(48) {
+            0 | 0xffff => None,
+            sector => Some(sector),
+        };
+        let geometry = Self {
+            bytes_per_sector,
+            num_fats,
+            fat_size: fat_sectors * bytes_per_sector,
+            reserved,
+            active_fat: if mirror { 0 } else { (ext_flags & 0xf) as u32 },
+            mirror,
+            root_cluster: u32_at(44) as u32,
+            fs_info,
+            cluster_size: (sectors_per_cluster * bytes_per_sector) as usize,
+            data_start: data_sector * bytes_per_sector,
+            cluster_count: clusters.min(fat_entries) as u32,
+        };
+        if geometry.active_fat >= num_fats || !(2..geometry.cluster_count + 2).contains(&geometry.root_cluster) {
+            return Err(FsError::Corrupted);
+        }
+        Ok(geometry)
+    }
+
+    /// Byte where copy `fat` of the FAT starts.
+    fn fat_start(&self, fat: u32) -> u64 {
+        self.reserved * self.bytes_per_sector + fat as u64 * self.fat_size
+    }
+}
+
+/// What the inodes of one volume share.
+pub struct FatFs {
+    cache: BlockCache,
+    geometry: Geometry,
+    dev: u64,
+    alloc: SleepMutex<AllocState>,
+    /// Inodes in use, by the position of their short entry, so a file has
+    /// one inode however it is looked up.
+    inodes: SleepMutex<BTreeMap<u64, Weak<FatInode>>>,
+    /// Held while a directory is read or changed, which keeps entries from
+    /// moving under lookups.
+    dir_lock: SleepMutex<()>,
+}
+
+impl FatFs {
+    /// Byte where `cluster` starts.
+    fn cluster_pos(&self, cluster: u32) -> u64 {
+        self.geometry.data_start + (cluster - 2) as u64 * self.geometry.cluster_size as u64
+    }
+
+    /// The inode of the file whose short entry `entry` is at `pos`.
+    fn inode(self: &Arc<Self>, pos: u64, entry: ShortEntry) -> FsResult<Arc<FatInode>> {
+        let mut inodes = self.inodes.lock();
+        if let Some(inode) = inodes.get(&pos).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let inode = FatInode::new(self, Some(pos), entry)?;
+        inodes.insert(pos, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    fn write_fs_info(&self) -> FsResult<()> {
+        let Some(sector) = self.geometry.fs_info else {
+            return Ok(());
+        };
+        let pos = sector * self.geometry.bytes_per_sector;
+        let alloc = self.alloc.lock();
+        self.cache.write_u32(pos + 488, alloc.free_count)?;
+        self.cache.write_u32(pos + 492, alloc.next_free)
+    }
+}
+
+pub struct Fat32 {
+    fs: Arc<FatFs>,
+    root: Arc<FatInode>,
+}
+
+impl Fat32 {
+    /// Mount the volume on `device`.
+    pub fn new(device: Arc<dyn BlockDevice>) -> FsResult<Arc<Self>> {
+        let cache = BlockCache::new(device);
+        let mut boot = [0; 512];
+        cache.read_at(0, &mut boot)?;
+        let geometry = Geometry::parse(&boot)?;
+        let fs_info = match geometry.fs_info {
+            Some(sector) => {
+                let pos = sector * geometry.bytes_per_sector;
+                let valid = cache.read_u32(pos)? == FSINFO_LEAD
+                    && cache.read_u32(pos + 484)? == FSINFO_STRUCT
+                    && cache.read_u32(pos + 508)? == FSINFO_TRAIL;
+                if valid {
+                    Some((cache.read_u32(pos + 488)?, cache.read_u32(pos + 492)?))
+                } else {
+                    warn!("FAT32: FSInfo sector is invalid, ignoring it");
+                    None
+                }
+            }
+            None => None,
+        };
+        let geometry = Geometry {
+            fs_info: fs_info.and(geometry.fs_info),
+            ..geometry
+        };
+        let root_cluster = geometry.root_cluster;
+        let fs = Arc::new(FatFs {
+            cache,
+            geometry,
+            dev: mount::new_dev(),
+            alloc: SleepMutex::new(AllocState {
+                free_count: 0,
+                next_free: 2,
+            }),
+            inodes: SleepMutex::new(BTreeMap::new()),
+            dir_lock: SleepMutex::new(()),
+        });
+        let (free_count, next_free) = fs_info.unwrap_or((FSINFO_UNKNOWN, FSINFO_UNKNOWN));
+        let free_count = if free_count <= fs.geometry.cluster_count {
+            free_count
+        } else {
+            fs.count_free()?
+        };
+        *fs.alloc.lock() = AllocState { free_count, next_free };
+        let mut entry = ShortEntry([0; dir::ENTRY_SIZE]);
+        entry.0[11] = dir::ATTR_DIRECTORY;
+        entry.set_first_cluster(root_cluster);
+        let root = FatInode::new(&fs, None, entry)?;
+        Ok(Arc::new(Self { fs, root }))
+    }
+
+    /// Mount a volume for `mount`, which must name a device.
+    pub fn mount(device: Option<Arc<dyn BlockDevice>>) -> FsResult<Arc<dyn SuperBlock>> {
+        let device = device.ok_or(FsError::InvalidInput)?;
+        Ok(Self::new(device)?)
+    }
+}
+
+impl SuperBlock for Fat32 {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        self.fs.write_fs_info()?;
+        self.fs.cache.flush()
+    }
+}
@@ -0,0 +1,141 @@
+//! The file allocation table, which chains the clusters of each file.
+
+use alloc::vec::Vec;
+
+use crate::fs::{FsError, FsResult};
+
+use super::FatFs;
+
+/// Entries only use their low 28 bits.
+const ENTRY_MASK: u32 = 0x0fff_ffff;
+const FREE: u32 = 0;
+/// Entries from this one on end a chain.
+const END_OF_CHAIN: u32 = 0x0fff_fff8;
+/// What ends the chains this driver writes.
+const END_MARK: u32 = 0x0fff_ffff;
+
+/// Allocation hints, as the FSInfo sector stores them.
+pub struct AllocState {
+    pub free_count: u32,
+    /// Where to start looking for a free cluster.
+    pub next_free: u32,
+}
+
+impl FatFs {
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        (2..self.geometry.cluster_count + 2).contains(&cluster)
+    }
+
+    fn entry(&self, cluster: u32) -> FsResult<u32> {
+        let pos = self.geometry.fat_start(self.geometry.active_fat) + cluster as u64 * 4;
+        Ok(self.cache.read_u32(pos)? & ENTRY_MASK)
+    }
+
+    /// Set the entry of `cluster`, in every copy of the table unless
+    /// mirroring is off.
+    fn set_entry(&self, cluster: u32, value: u32) -> FsResult<()> {
+        for fat in 0..self.geometry.num_fats {
+            if !self.geometry.mirror && fat != self.geometry.active_fat {
+                continue;
+            }
+            let pos = self.geometry.fat_start(fat) + cluster as u64 * 4;
+            let old = self.cache.read_u32(pos)?;
+            self.cache.write_u32(pos, (old & !ENTRY_MASK) | value)?;
+        }
+        Ok(())
+    }
+
+    /// The cluster after `cluster` in its chain.
+    pub(super) fn next_cluster(&self, cluster: u32) -> FsResult<Option<u32>> {
+        match self.entry(cluster)? {
+            next if next >= END_OF_CHAIN => Ok(None),
+            next if self.is_valid_cluster(next) => Ok(Some(next)),
+            _ => Err(FsError::Corrupted),
+        }
+    }
+
+    /// The clusters of the chain starting at `first`, none for 0.
+    pub(super) fn chain(&self, first: u32) -> FsResult<Vec<u32>> {
+        let mut chain = Vec::new();
+        if first == 0 {
+            return Ok(chain);
+        }
+        if !self.is_valid_cluster(first) {
+            return Err(FsError::Corrupted);
+        }
+        let mut cluster = Some(first);
+        while let Some(current) = cluster {
+            // A longer chain must contain a loop.
+            if chain.len() >= self.geometry.cluster_count as usize {
+                return Err(FsError::Corrupted);
+            }
+            chain.push(current);
+            cluster = self.next_cluster(current)?;
+        }
+        Ok(chain)
+    }
+
+    /// Allocate a zeroed cluster and append it to the chain ending at
+    /// `last`, if any.
+    pub(super) fn alloc_cluster(&self, last: Option<u32>) -> FsResult<u32> {
+        let mut alloc = self.alloc.lock();
+        if alloc.free_count == 0 {
+            return Err(FsError::NoSpace);
+        }
+        let count = self.geometry.cluster_count;
+        let start = if self.is_valid_cluster(alloc.next_free) { alloc.next_free } else { 2 };
+        let mut cluster = start;
+        while self.entry(cluster)? != FREE {
+            cluster = if cluster + 1 < count + 2 { cluster + 1 } else { 2 };
+            if cluster == start {
+                alloc.free_count = 0;
+                return Err(FsError::NoSpace);
+            }
+        }
+        self.set_entry(cluster, END_MARK)?;
+        if let Some(last) = last {
+            self.set_entry(last, cluster)?;
+        }
+        alloc.free_count = alloc.free_count.saturating_sub(1);
+        alloc.next_free = cluster + 1;
+        drop(alloc);
+        self.cache
+            .zero(self.cluster_pos(cluster), self.geometry.cluster_size)?;
+        Ok(cluster)
+    }
+
+    /// Free the chain starting at `first`.
+    pub(super) fn free_chain(&self, first: u32) -> FsResult<()> {
+        let chain = self.chain(first)?;
+        let mut alloc = self.alloc.lock();
+        for &cluster in chain.iter() {
+            self.set_entry(cluster, FREE)?;
+            alloc.free_count += 1;
+        }
+        if let Some(&lowest) = chain.iter().min() {
+            alloc.next_free = alloc.next_free.min(lowest);
+        }
+        Ok(())
+    }
+
+    /// Cut the chain after `last`, freeing the clusters that followed it.
+    pub(super) fn truncate_chain(&self, last: u32) -> FsResult<()> {
+        let next = self.next_cluster(last)?;
+        self.set_entry(last, END_MARK)?;
+        match next {
+            Some(next) => self.free_chain(next),
+            None => Ok(()),
+        }
+    }
+
+    /// Count the free clusters, when FSInfo does not know.
+    pub(super) fn count_free(&self) -> FsResult<u32> {
+        let mut free = 0;
+        for cluster in 2..self.geometry.cluster_count + 2 {
+            if self.entry(cluster)? == FREE {
+                free += 1;
+            }
+        }
+        Ok(free)
+    }
+}
@@ -0,0 +1,150 @@
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+};
+use spin::Mutex;
+
+use crate::{
+    drivers::chardev::Tty,
+    mm::memory_set::{BackingFile, SharedMemory},
+};
+
+use super::{Dentry, DirEntry, FsError, FsResult, Inode, Metadata, OpenFlags, SeekFrom};
+
+/// An open file, shared by every descriptor referring to it.
+pub trait File: Send + Sync {
+    fn read(&self, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(FsError::BadDescriptor)
+    }
+
+    fn write(&self, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::BadDescriptor)
+    }
+
+    /// Read at `offset` without moving the file position. Only files that
+    /// can be mapped support it.
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Write at `offset` without moving the file position.
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Move the file position, returning the new one.
+    fn seek(&self, _pos: SeekFrom) -> FsResult<usize> {
+        Err(FsError::NotSeekable)
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        Err(FsError::Unsupported)
+    }
+
+    fn truncate(&self, _len: usize) -> FsResult<()> {
+        Err(FsError::InvalidInput)
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    /// Pass the directory entries from the file position on to `f`, moving
+    /// the position past each one `f` accepts. Stops at the first entry `f`
+    /// rejects.
+    fn read_dir(&self, _f: &mut dyn FnMut(&DirEntry, usize) -> bool) -> FsResult<()> {
+        Err(FsError::NotDir)
+    }
+
+    /// Where the file was opened, for files opened by path.
+    fn dentry(&self) -> Option<Arc<Dentry>> {
+        None
+    }
+
+    /// The flags the file was opened with, minus those only used by `open`.
+    fn status_flags(&self) -> OpenFlags {
+        OpenFlags::RDWR
+    }
+
+    /// Change the flags in [`OpenFlags::SETFL_MASK`].
+    fn set_status_flags(&self, _flags: OpenFlags) {}
+
+    /// The terminal the file is open on, if it is one.
+    fn tty(&self) -> Option<Arc<Tty>> {
+        None
+    }
+}
+
+/// An open file as the backing of a memory mapping.
+pub struct FileBacking(pub Arc<dyn File>);
+
+impl BackingFile for FileBacking {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        read_fully(offset, buf, |offset, buf| self.0.read_at(offset, buf))
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        write_fully(offset, buf, |offset, buf| self.0.write_at(offset, buf))
+    }
+}
+
+/// An inode as the backing of the memory its shared mappings share. The
+/// access mode of each mapping is checked when it is made.
+struct InodeBacking(Arc<dyn Inode>);
+
+impl BackingFile for InodeBacking {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        read_fully(offset, buf, |offset, buf| self.0.read_at(offset, buf))
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        write_fully(offset, buf, |offset, buf| self.0.write_at(offset, buf))
+    }
+}
+
+fn read_fully(offset: usize, buf: &mut [u8], read: impl Fn(usize, &mut [u8]) -> FsResult<usize>) -> usize {
+    let mut done = 0;
+    while done < buf.len() {
+        match read(offset + done, &mut buf[done..]) {
+            Ok(0) | Err(_) => break,
+            Ok(read) => done += read,
+        }
+    }
+    done
+}
+
+fn write_fully(offset: usize, buf: &[u8], write: impl Fn(usize, &[u8]) -> FsResult<usize>) -> usize {
+    let mut done = 0;
+    while done < buf.len() {
+        match write(offset + done, &buf[done..]) {
+            Ok(0) | Err(_) => break,
+            Ok(written) => done += written,
+        }
+    }
+    done
+}
+
+/// The memory of the shared mappings of each file, by device and inode
+/// number.
+static SHARED_MAPPINGS: Mutex<BTreeMap<(u64, u64), Weak<SharedMemory>>> = Mutex::new(BTreeMap::new());
+
+/// The memory behind a `MAP_SHARED` mapping of `file`. Every shared mapping
+/// of a file opened by path gets the same one, so they all see the same
+/// pages. Reads and writes of the file do not go through these pages; they
+/// only meet the file when they are read in and written back.
+pub fn shared_memory(file: Arc<dyn File>) -> FsResult<Arc<SharedMemory>> {
+    let Some(dentry) = file.dentry() else {
+        return Ok(Arc::new(SharedMemory::with_file(Arc::new(FileBacking(file)))));
+    };
+    let inode = dentry.inode().clone();
+    let metadata = inode.metadata()?;
+    let key = (metadata.dev, metadata.ino);
+    let mut mappings = SHARED_MAPPINGS.lock();
+    if let Some(memory) = mappings.get(&key).and_then(Weak::upgrade) {
+        return Ok(memory);
+    }
+    mappings.retain(|_, memory| memory.strong_count() > 0);
+    let memory = Arc::new(SharedMemory::with_file(Arc::new(InodeBacking(inode))));
+    mappings.insert(key, Arc::downgrade(&memory));
+    Ok(memory)
+}
@@ -0,0 +1,53 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// Flags of `open`, with the values of the generic Linux ABI.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 0o1;
+        const RDWR = 0o2;
+        const CREAT = 0o100;
+        const EXCL = 0o200;
+        const NOCTTY = 0o400;
+        const TRUNC = 0o1000;
+        const APPEND = 0o2000;
+        const NONBLOCK = 0o4000;
+        const DSYNC = 0o10000;
+        const DIRECT = 0o40000;
+        const LARGEFILE = 0o100000;
+        const DIRECTORY = 0o200000;
+        const NOFOLLOW = 0o400000;
+        const NOATIME = 0o1000000;
+        const CLOEXEC = 0o2000000;
+        const SYNC = 0o4010000;
+        const PATH = 0o10000000;
+    }
+}
+
+impl OpenFlags {
+    /// Bits of the access mode.
+    const ACCMODE: u32 = 0o3;
+
+    /// Flags `fcntl(F_SETFL)` can change.
+    pub const SETFL_MASK: OpenFlags = OpenFlags::APPEND
+        .union(OpenFlags::NONBLOCK)
+        .union(OpenFlags::DIRECT)
+        .union(OpenFlags::NOATIME);
+
+    pub fn readable(self) -> bool {
+        !self.contains(OpenFlags::PATH) && self.bits() & Self::ACCMODE != OpenFlags::WRONLY.bits()
+    }
+
+    pub fn writable(self) -> bool {
+        !self.contains(OpenFlags::PATH) && self.bits() & Self::ACCMODE != OpenFlags::RDONLY.bits()
+    }
+}
+
+/// Where `lseek` counts from.
+#[derive(Clone, Copy, Debug)]
+pub enum SeekFrom {
+    Start(usize),
+    Current(isize),
+    End(isize),
+}
@@ -0,0 +1,202 @@
+use alloc::{string::String, sync::Arc};
+use core::any::Any;
+
+use crate::timer;
+
+use super::{File, FsError, FsResult, OpenFlags};
+
+/// The kind of file an inode is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InodeType {
+    File,
+    Dir,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+}
+
+impl InodeType {
+    /// The file type bits of `st_mode`.
+    pub fn mode_bits(self) -> u32 {
+        match self {
+            InodeType::Fifo => 0o010000,
+            InodeType::CharDevice => 0o020000,
+            InodeType::Dir => 0o040000,
+            InodeType::BlockDevice => 0o060000,
+            InodeType::File => 0o100000,
+            InodeType::Symlink => 0o120000,
+            InodeType::Socket => 0o140000,
+        }
+    }
+
+    /// The `d_type` of directory entries.
+    pub fn dirent_type(self) -> u8 {
+        match self {
+            InodeType::Fifo => 1,
+            InodeType::CharDevice => 2,
+            InodeType::Dir => 4,
+            InodeType::BlockDevice => 6,
+            InodeType::File => 8,
+            InodeType::Symlink => 10,
+            InodeType::Socket => 12,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimeSpec {
+    pub sec: u64,
+    pub nsec: u64,
+}
+
+impl TimeSpec {
+    /// The current time. There is no real-time clock, so time starts at boot.
+    pub fn now() -> Self {
+        let ns = timer::get_time_ns() as u64;
+        Self {
+            sec: ns / 1_000_000_000,
+            nsec: ns % 1_000_000_000,
+        }
+    }
+}
+
+/// What `stat` reports about an inode.
+#[derive(Clone, Copy, Debug)]
+pub struct Metadata {
+    pub dev: u64,
+    pub ino: u64,
+    pub kind: InodeType,
+    /// Permission bits, without the file type.
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub blksize: u32,
+    /// Space used, in 512-byte units.
+    pub blocks: u64,
+    /// Device number of device files.
+    pub rdev: u64,
+    pub atime: TimeSpec,
+    pub mtime: TimeSpec,
+    pub ctime: TimeSpec,
+}
+
+/// An entry of a directory.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    pub ino: u64,
+    pub kind: InodeType,
+    pub name: String,
+}
+
+/// A file of a mounted file system.
+///
+/// Operations a kind of file does not support keep their defaults, which
+/// fail. Directory operations take names of single path components, never
+/// `.` or `..`.
+pub trait Inode: Send + Sync {
+    fn metadata(&self) -> FsResult<Metadata>;
+
+    /// Read file data at `offset`, returning how many bytes were read.
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> FsResult<usize> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Write file data at `offset`, growing the file as needed.
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Write file data at the end of the file, returning the offset it went
+    /// to and how many bytes were written.
+    ///
+    /// File systems override this to find the end and write under one lock,
+    /// so that concurrent appends do not overwrite each other.
+    fn append(&self, buf: &[u8]) -> FsResult<(usize, usize)> {
+        let offset = self.metadata()?.size as usize;
+        Ok((offset, self.write_at(offset, buf)?))
+    }
+
+    /// Set the size of the file, filling what grows with zeroes.
+    fn truncate(&self, _len: usize) -> FsResult<()> {
+        Err(FsError::Unsupported)
+    }
+
+    /// Write cached data and metadata of the file back.
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+
+    /// Open the file. Files whose data is not simply read and written at
+    /// offsets, such as devices, return their own open file. The others
+    /// return `None` and are opened by the VFS.
+    fn open(&self, _flags: OpenFlags) -> FsResult<Option<Arc<dyn File>>> {
+        Ok(None)
+    }
+
+    /// The entry `name` of the directory.
+    fn lookup(&self, _name: &str) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotDir)
+    }
+
+    /// Create `name` in the directory, as an empty file or directory.
+    fn create(&self, _name: &str, _kind: InodeType, _mode: u32) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotDir)
+    }
+
+    /// Create a symbolic link `name` to `target` in the directory.
+    fn symlink(&self, _name: &str, _target: &str) -> FsResult<Arc<dyn Inode>> {
+        Err(FsError::NotDir)
+    }
+
+    /// Add `name` to the directory as another link to `target`.
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> FsResult<()> {
+        Err(FsError::NotDir)
+    }
+
+    /// Remove the entry `name` of the directory, which must not be a
+    /// directory.
+    fn unlink(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::NotDir)
+    }
+
+    /// Remove the empty directory `name` from the directory.
+    fn rmdir(&self, _name: &str) -> FsResult<()> {
+        Err(FsError::NotDir)
+    }
+
+    /// Move the entry `old_name` of the directory to `new_name` in
+    /// `new_dir`, replacing what is there.
+    fn rename(&self, _old_name: &str, _new_dir: &Arc<dyn Inode>, _new_name: &str) -> FsResult<()> {
+        Err(FsError::NotDir)
+    }
+
+    /// The directory entry at position `pos`, and the position of the next
+    /// one. Positions are opaque to the caller, reading starts at 0.
+    fn read_dir(&self, _pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        Err(FsError::NotDir)
+    }
+
+    /// The target of the symbolic link.
+    fn read_link(&self) -> FsResult<String> {
+        Err(FsError::InvalidInput)
+    }
+
+    /// For file systems to find their own inodes behind `dyn Inode`, when
+    /// linking or renaming.
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// A mounted file system.
+pub trait SuperBlock: Send + Sync {
+    /// The root directory.
+    fn root(&self) -> Arc<dyn Inode>;
+
+    /// Write everything cached back to the device.
+    fn sync(&self) -> FsResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use crate::sched::SleepMutex;
+
+use super::{Dentry, DirEntry, File, FsError, FsResult, InodeType, Metadata, OpenFlags, SeekFrom};
+
+/// A file of a file system opened by path, with its own position.
+pub struct InodeFile {
+    dentry: Arc<Dentry>,
+    flags: Mutex<OpenFlags>,
+    /// Bytes into the data of a file, an opaque position in a directory.
+    /// Held while transferring, so that transfers through the same open
+    /// file do not interleave.
+    pos: SleepMutex<usize>,
+}
+
+impl InodeFile {
+    pub fn new(dentry: Arc<Dentry>, flags: OpenFlags) -> Self {
+        Self {
+            dentry,
+            flags: Mutex::new(flags - OpenFlags::CREAT - OpenFlags::EXCL - OpenFlags::TRUNC - OpenFlags::CLOEXEC),
+            pos: SleepMutex::new(0),
+        }
+    }
+
+    fn flags(&self) -> OpenFlags {
+        *self.flags.lock()
+    }
+}
+
+impl File for InodeFile {
+    fn read(&self, buf: &mut [u8]) -> FsResult<usize> {
+        if !self.flags().readable() {
+            return Err(FsError::BadDescriptor);
+        }
+        if self.dentry.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        let mut pos = self.pos.lock();
+        let read = self.dentry.inode().read_at(*pos, buf)?;
+        *pos += read;
+        Ok(read)
+    }
+
+    fn write(&self, buf: &[u8]) -> FsResult<usize> {
+        let flags = self.flags();
+        if !flags.writable() {
+            return Err(FsError::BadDescriptor);
+        }
+        let inode = self.dentry.inode();
+        let mut pos = self.pos.lock();
+        let (offset, written) = if flags.contains(OpenFlags::APPEND) {
+            inode.append(buf)?
+        } else {
+            (*pos, inode.write_at(*pos, buf)?)
+        };
+        *pos = offset + written;
+        Ok(written)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        if !self.flags().readable() {
+            return Err(FsError::BadDescriptor);
+        }
+        if self.dentry.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        self.dentry.inode().read_at(offset, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> FsResult<usize> {
+        if !self.flags().writable() {
+            return Err(FsError::BadDescriptor);
+        }
+        self.dentry.inode().write_at(offset, buf)
+    }
+
+    fn seek(&self, from: SeekFrom) -> FsResult<usize> {
+        if self.flags().contains(OpenFlags::PATH) {
+            return Err(FsError::BadDescriptor);
+        }
+        let mut pos = self.pos.lock();
+        let new = match from {
+            SeekFrom::Start(offset) => Some(offset),
+            // Directory positions only make sense as given by `read_dir`.
+            SeekFrom::Current(_) | SeekFrom::End(_) if self.dentry.is_dir() => None,
+            SeekFrom::Current(delta) => pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => (self.dentry.inode().metadata()?.size as usize).checked_add_signed(delta),
+        };
+        *pos = new.ok_or(FsError::InvalidInput)?;
+        Ok(*pos)
+    }
+
+    fn metadata(&self) -> FsResult<Metadata> {
+        self.dentry.inode().metadata()
+    }
+
+    fn truncate(&self, len: usize) -> FsResult<()> {
+        if !self.flags().writable() {
+            return Err(FsError::InvalidInput);
+        }
+        match self.dentry.kind() {
+            InodeType::File => self.dentry.inode().truncate(len),
+            InodeType::Dir => Err(FsError::IsDir),
+            _ => Err(FsError::InvalidInput),
+        }
+    }
+
+    fn sync(&self) -> FsResult<()> {
+        self.dentry.inode().sync()
+    }
+
+    fn read_dir(&self, f: &mut dyn FnMut(&DirEntry, usize) -> bool) -> FsResult<()> {
+        if self.flags().contains(OpenFlags::PATH) {
+            return Err(FsError::BadDescriptor);
+        }
+        let inode = self.dentry.inode();
+        let mut pos = self.pos.lock();
+        while let Some((entry, next)) = inode.read_dir(*pos)? {
+            if !f(&entry, next) {
+                break;
+            }
+            *pos = next;
+        }
+        Ok(())
+    }
+
+    fn dentry(&self) -> Option<Arc<Dentry>> {
+        Some(self.dentry.clone())
+    }
+
+    fn status_flags(&self) -> OpenFlags {
+        self.flags()
+    }
+
+    fn set_status_flags(&self, flags: OpenFlags) {
+        let mut current = self.flags.lock();
+        *current = (*current - OpenFlags::SETFL_MASK) | (flags & OpenFlags::SETFL_MASK);
+    }
+}
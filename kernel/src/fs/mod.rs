@@ -0,0 +1,77 @@
+//! Files as seen by processes.
+//!
+//! File systems implement [`SuperBlock`] and [`Inode`]. Their files are
+//! named by [`Dentry`]s, which cache lookups and link mounted file systems
+//! into one tree, walked by [`PathWalker`]. An open file is a [`File`],
+//! usually an [`InodeFile`] keeping the position and flags of an inode
+//! opened by path.
+
+mod context;
+mod dentry;
+pub mod devfs;
+mod error;
+pub mod ext4;
+pub mod fat32;
+mod file;
+mod flags;
+mod inode;
+mod inode_file;
+pub mod mount;
+mod path;
+pub mod procfs;
+pub mod tmpfs;
+
+use alloc::{format, string::String, sync::Arc};
+use log::{info, warn};
+
+use crate::drivers::block;
+
+pub use self::{
+    context::FsContext,
+    dentry::Dentry,
+    error::{FsError, FsResult},
+    file::{shared_memory, File, FileBacking},
+    flags::{OpenFlags, SeekFrom},
+    inode::{DirEntry, Inode, InodeType, Metadata, SuperBlock, TimeSpec},
+    inode_file::InodeFile,
+    path::PathWalker,
+};
+
+/// Register the file system types and mount the root, with `/dev`, `/proc`
+/// and `/tmp` on it. The root is the file system on the first disk, or an
+/// empty in-memory one for disks to be mounted over later.
+pub fn init() {
+    mount::register_fs_type("tmpfs", |_| Ok(tmpfs::TmpFs::new()));
+    mount::register_fs_type("devtmpfs", |_| Ok(devfs::DevFs::new()));
+    mount::register_fs_type("proc", |_| Ok(procfs::ProcFs::new()));
+    mount::register_fs_type("vfat", fat32::Fat32::mount);
+    mount::register_fs_type("ext4", ext4::Ext4::mount);
+    let (superblock, source, fs_type) =
+        root_disk().unwrap_or_else(|| (tmpfs::TmpFs::new(), String::from("rootfs"), "tmpfs"));
+    mount::mount_root(superblock, &source, fs_type).expect("Failed to mount the root file system");
+    info!("Root file system mounted from {}.", source);
+
+    let pseudo: [(&str, Arc<dyn SuperBlock>, &'static str); 3] = [
+        ("dev", devfs::DevFs::new(), "devtmpfs"),
+        ("proc", procfs::ProcFs::new(), "proc"),
+        ("tmp", tmpfs::TmpFs::with_root_mode(0o1777), "tmpfs"),
+    ];
+    let root = mount::root();
+    for (name, superblock, fs_type) in pseudo {
+        let point = root.lookup(name).or_else(|_| root.create(name, InodeType::Dir, 0o755));
+        if let Err(e) = point.and_then(|point| mount::mount(superblock, fs_type, fs_type, &point)) {
+            warn!("Failed to mount {} on /{}: {}", fs_type, name, e);
+        }
+    }
+}
+
+/// The file system on the first disk, as the first type that can mount it.
+fn root_disk() -> Option<(Arc<dyn SuperBlock>, String, &'static str)> {
+    let name = block::names().into_iter().next()?;
+    let device = block::get(&name)?;
+    ["ext4", "vfat"].into_iter().find_map(|fs_type| {
+        let (fs_type, factory) = mount::fs_type(fs_type)?;
+        let superblock = factory(Some(device.clone())).ok()?;
+        Some((superblock, format!("/dev/{}", name), fs_type))
+    })
+}
@@ -0,0 +1,139 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::{Mutex, Once};
+
+use crate::drivers::block::BlockDevice;
+
+use super::{Dentry, FsError, FsResult, SuperBlock};
+
+/// Creates a file system, from the device it is mounted from if it needs one.
+pub type FsFactory = fn(Option<Arc<dyn BlockDevice>>) -> FsResult<Arc<dyn SuperBlock>>;
+
+/// A file system mounted somewhere in the tree.
+pub struct Mount {
+    /// What was mounted, such as a device path, as `/proc/mounts` shows it.
+    pub source: String,
+    pub fs_type: &'static str,
+    /// Where it is mounted, relative to the first root.
+    pub path: String,
+    pub superblock: Arc<dyn SuperBlock>,
+    pub root: Arc<Dentry>,
+    /// The directory it covers, `None` for the first root.
+    pub point: Option<Arc<Dentry>>,
+}
+
+/// Mounts in the order they were made.
+static MOUNTS: Mutex<Vec<Arc<Mount>>> = Mutex::new(Vec::new());
+
+/// The root of the first file system, where every lookup starts.
+static ROOT: Once<Arc<Dentry>> = Once::new();
+
+/// File system types by name, as `mount` takes them.
+static FS_TYPES: Mutex<BTreeMap<&'static str, FsFactory>> = Mutex::new(BTreeMap::new());
+
+/// Device numbers for `stat`, one per file system mounted.
+static NEXT_DEV: AtomicU64 = AtomicU64::new(1);
+
+/// A device number for a new file system.
+pub fn new_dev() -> u64 {
+    NEXT_DEV.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Make the file system type `name` mountable.
+pub fn register_fs_type(name: &'static str, factory: FsFactory) {
+    FS_TYPES.lock().insert(name, factory);
+}
+
+/// The file system type `name`, with its name as registered.
+pub fn fs_type(name: &str) -> Option<(&'static str, FsFactory)> {
+    FS_TYPES.lock().get_key_value(name).map(|(&name, &factory)| (name, factory))
+}
+
+/// The root directory of the system, before following what is mounted on it.
+pub fn root() -> Arc<Dentry> {
+    ROOT.get().expect("No root file system mounted").clone()
+}
+
+/// Make `superblock` the first root file system.
+pub fn mount_root(superblock: Arc<dyn SuperBlock>, source: &str, fs_type: &'static str) -> FsResult<()> {
+    let root = Dentry::new_root(superblock.root(), None)?;
+    ROOT.call_once(|| root.clone());
+    MOUNTS.lock().push(Arc::new(Mount {
+        source: source.to_string(),
+        fs_type,
+        path: "/".to_string(),
+        superblock,
+        root,
+        point: None,
+    }));
+    Ok(())
+}
+
+/// Mount `superblock` on the directory `point`, over whatever is mounted
+/// there already.
+pub fn mount(superblock: Arc<dyn SuperBlock>, source: &str, fs_type: &'static str, point: &Arc<Dentry>) -> FsResult<()> {
+    let point = point.follow_mounts();
+    if !point.is_dir() {
+        return Err(FsError::NotDir);
+    }
+    let root = Dentry::new_root(superblock.root(), Some(&point))?;
+    point.set_mounted(Some(root.clone()));
+    MOUNTS.lock().push(Arc::new(Mount {
+        source: source.to_string(),
+        fs_type,
+        path: point.path(&self::root()),
+        superblock,
+        root,
+        point: Some(point),
+    }));
+    Ok(())
+}
+
+/// Unmount the file system whose root is `root`. Fails if another file
+/// system is mounted on top of or inside it.
+pub fn umount(root: &Arc<Dentry>) -> FsResult<()> {
+    let mut mounts = MOUNTS.lock();
+    let index = mounts
+        .iter()
+        .position(|mount| Arc::ptr_eq(&mount.root, root))
+        .ok_or(FsError::InvalidInput)?;
+    let Some(point) = mounts[index].point.clone() else {
+        return Err(FsError::Busy);
+    };
+    let busy = root.mounted().is_some()
+        || mounts.iter().any(|mount| {
+            mount.point.as_ref().is_some_and(|point| {
+                let mut dentry = point.clone();
+                while let Some(parent) = dentry.parent() {
+                    dentry = parent;
+                }
+                Arc::ptr_eq(&dentry, root)
+            })
+        });
+    if busy {
+        return Err(FsError::Busy);
+    }
+    let mount = mounts.remove(index);
+    drop(mounts);
+    mount.superblock.sync()?;
+    point.set_mounted(None);
+    root.forget_children();
+    Ok(())
+}
+
+/// Every mounted file system, the first root first.
+pub fn mounts() -> Vec<Arc<Mount>> {
+    MOUNTS.lock().clone()
+}
+
+/// Write every mounted file system back to its device.
+pub fn sync_all() {
+    for mount in mounts() {
+        let _ = mount.superblock.sync();
+    }
+}
@@ -0,0 +1,142 @@
+use alloc::{string::String, sync::Arc};
+
+use super::{Dentry, FsError, FsResult, InodeType};
+
+/// Most symbolic links a single lookup follows, as in Linux.
+pub const MAX_SYMLINKS: usize = 40;
+
+/// Longest name of a single path component.
+pub const NAME_MAX: usize = 255;
+
+/// Resolves paths to dentries, for one lookup.
+///
+/// Absolute paths and symbolic links to them start at `root`, which `..`
+/// never leaves. Mounts are crossed in both directions.
+pub struct PathWalker {
+    root: Arc<Dentry>,
+    links: usize,
+}
+
+impl PathWalker {
+    pub fn new(root: &Arc<Dentry>) -> Self {
+        Self {
+            root: root.follow_mounts(),
+            links: 0,
+        }
+    }
+
+    /// Look `path` up relative to the directory `base`. A symbolic link in
+    /// the last component is followed if `follow`, or if the path ends with
+    /// a slash.
+    pub fn walk(&mut self, base: &Arc<Dentry>, path: &str, follow: bool) -> FsResult<Arc<Dentry>> {
+        let (dir, name) = self.walk_parent(base, path)?;
+        let must_be_dir = path.ends_with('/');
+        let dentry = self.step(&dir, name, follow || must_be_dir)?;
+        if must_be_dir && !dentry.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        Ok(dentry)
+    }
+
+    /// Look up the directory holding the last component of `path`, and
+    /// return it with that component. A path naming the root returns `.`.
+    pub fn walk_parent<'a>(&mut self, base: &Arc<Dentry>, path: &'a str) -> FsResult<(Arc<Dentry>, &'a str)> {
+        if path.is_empty() {
+            return Err(FsError::NotFound);
+        }
+        let mut dir = if path.starts_with('/') {
+            self.root.clone()
+        } else {
+            base.follow_mounts()
+        };
+        let mut components = path.split('/').filter(|name| !name.is_empty()).peekable();
+        while let Some(name) = components.next() {
+            if components.peek().is_none() {
+                if !dir.is_dir() {
+                    return Err(FsError::NotDir);
+                }
+                return Ok((dir, name));
+            }
+            dir = self.step(&dir, name, true)?;
+        }
+        Ok((dir, "."))
+    }
+
+    /// Like [`walk_parent`](Self::walk_parent), but if `follow`, go on to
+    /// where a symbolic link in the last component points, so that a
+    /// dangling link names the file to create.
+    pub fn walk_create(&mut self, base: &Arc<Dentry>, path: &str, follow: bool) -> FsResult<(Arc<Dentry>, String)> {
+        let (mut dir, name) = self.walk_parent(base, path)?;
+        let mut name = String::from(name);
+        if follow {
+            loop {
+                match self.step(&dir, &name, false) {
+                    Ok(link) if link.kind() == InodeType::Symlink => {
+                        let target = self.read_link(&link)?;
+                        if target.ends_with('/') {
+                            return Err(FsError::IsDir);
+                        }
+                        let (parent, last) = self.walk_parent(&dir, &target)?;
+                        name = String::from(last);
+                        dir = parent;
+                    }
+                    _ => break,
+                }
+            }
+        }
+        Ok((dir, name))
+    }
+
+    /// Go from the directory `dir` to its entry `name`.
+    pub fn step(&mut self, dir: &Arc<Dentry>, name: &str, follow: bool) -> FsResult<Arc<Dentry>> {
+        if !dir.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        match name {
+            "." => Ok(dir.clone()),
+            ".." => Ok(self.parent_of(dir)),
+            _ if name.len() > NAME_MAX => Err(FsError::NameTooLong),
+            _ => {
+                let dentry = dir.lookup(name)?.follow_mounts();
+                if follow && dentry.kind() == InodeType::Symlink {
+                    self.follow_link(dir, &dentry)
+                } else {
+                    Ok(dentry)
+                }
+            }
+        }
+    }
+
+    /// Resolve the symbolic link `link` found in `dir`.
+    fn follow_link(&mut self, dir: &Arc<Dentry>, link: &Arc<Dentry>) -> FsResult<Arc<Dentry>> {
+        let target = self.read_link(link)?;
+        self.walk(dir, &target, true)
+    }
+
+    /// Read the target of the symbolic link `link`, counting it against
+    /// [`MAX_SYMLINKS`].
+    fn read_link(&mut self, link: &Arc<Dentry>) -> FsResult<String> {
+        self.links += 1;
+        if self.links > MAX_SYMLINKS {
+            return Err(FsError::SymlinkLoop);
+        }
+        link.inode().read_link()
+    }
+
+    fn parent_of(&self, dir: &Arc<Dentry>) -> Arc<Dentry> {
+        let mut dentry = dir.clone();
+        while !Arc::ptr_eq(&dentry, &self.root) {
+            if let Some(parent) = dentry.parent() {
+                dentry = parent;
+                break;
+            }
+            // The root of a mounted file system, continue from the
+            // directory it covers.
+            match dentry.covers() {
+                Some(covered) => dentry = covered,
+                None => break,
+            }
+        }
+        dentry.follow_mounts()
+    }
+}
@@ -0,0 +1,354 @@
+//! Kernel state as files, mounted at `/proc`. Files are generated when
+//! read, from what the kernel tracks anyway, and report a size of 0.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{any::Any, fmt::Write};
+
+use crate::{
+    mm::{consts::FRAME_SIZE, frame, heap},
+    task::{self, Process, TaskStatus},
+    timer::{self, consts::INTERRUPT_PER_SEC},
+};
+
+use super::{mount, DirEntry, FsError, FsResult, Inode, InodeType, Metadata, SuperBlock, TimeSpec};
+
+/// Clock ticks per second as user space counts them, `sysconf(_SC_CLK_TCK)`.
+const USER_HZ: usize = 100;
+
+/// Entries of the root besides the directories of processes.
+const ROOT_FILES: [(&str, Node); 4] = [
+    ("meminfo", Node::MemInfo),
+    ("mounts", Node::Mounts),
+    ("self", Node::SelfLink),
+    ("uptime", Node::Uptime),
+];
+
+/// Entries of the directory of a process.
+const PROCESS_FILES: [(&str, fn(usize) -> Node); 2] = [("exe", Node::Exe), ("stat", Node::Stat)];
+
+pub struct ProcFs {
+    root: Arc<ProcInode>,
+}
+
+impl ProcFs {
+    pub fn new() -> Arc<Self> {
+        let fs = Arc::new(ProcFsShared {
+            dev: mount::new_dev(),
+            mounted: TimeSpec::now(),
+        });
+        Arc::new(Self {
+            root: Arc::new(ProcInode { fs, node: Node::Root }),
+        })
+    }
+}
+
+impl SuperBlock for ProcFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// What the inodes of one mount share.
+struct ProcFsShared {
+    dev: u64,
+    /// Every file claims to have been changed at mount time.
+    mounted: TimeSpec,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Node {
+    Root,
+    MemInfo,
+    Mounts,
+    /// Links to the directory of the process reading it.
+    SelfLink,
+    Uptime,
+    /// The directory of the process with this pid.
+    Process(usize),
+    Exe(usize),
+    Stat(usize),
+}
+
+impl Node {
+    fn ino(self) -> u64 {
+        let process = |pid: usize, index: u64| (pid as u64 + 1) << 4 | index;
+        match self {
+            Node::Root => 1,
+            Node::MemInfo => 2,
+            Node::Mounts => 3,
+            Node::SelfLink => 4,
+            Node::Uptime => 5,
+            Node::Process(pid) => process(pid, 0),
+            Node::Exe(pid) => process(pid, 1),
+            Node::Stat(pid) => process(pid, 2),
+        }
+    }
+
+    fn kind(self) -> InodeType {
+        match self {
+            Node::Root | Node::Process(_) => InodeType::Dir,
+            Node::SelfLink | Node::Exe(_) => InodeType::Symlink,
+            Node::MemInfo | Node::Mounts | Node::Uptime | Node::Stat(_) => InodeType::File,
+        }
+    }
+
+    /// The process the node is about, which must not have been reaped.
+    fn process(self) -> FsResult<Option<Arc<Process>>> {
+        match self {
+            Node::Process(pid) | Node::Exe(pid) | Node::Stat(pid) => {
+                task::find_process(pid).map(Some).ok_or(FsError::NotFound)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn entry(self, name: &str) -> DirEntry {
+        DirEntry {
+            ino: self.ino(),
+            kind: self.kind(),
+            name: name.to_string(),
+        }
+    }
+}
+
+struct ProcInode {
+    fs: Arc<ProcFsShared>,
+    node: Node,
+}
+
+impl ProcInode {
+    fn child(&self, node: Node) -> Arc<dyn Inode> {
+        Arc::new(ProcInode {
+            fs: self.fs.clone(),
+            node,
+        })
+    }
+
+    /// The contents of a file.
+    fn generate(&self) -> FsResult<String> {
+        match self.node {
+            Node::MemInfo => Ok(meminfo()),
+            Node::Mounts => Ok(mounts()),
+            Node::Uptime => {
+                let ticks = timer::get_ticks();
+                let (sec, centis) = (ticks / INTERRUPT_PER_SEC, ticks % INTERRUPT_PER_SEC * 100 / INTERRUPT_PER_SEC);
+                Ok(format!("{}.{:02} 0.00\n", sec, centis))
+            }
+            Node::Stat(pid) => Ok(stat(&task::find_process(pid).ok_or(FsError::NotFound)?)),
+            _ => Err(FsError::IsDir),
+        }
+    }
+}
+
+impl Inode for ProcInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        self.node.process()?;
+        let (mode, nlink) = match self.node.kind() {
+            InodeType::Dir => (0o555, 2),
+            InodeType::Symlink => (0o777, 1),
+            _ => (0o444, 1),
+        };
+        Ok(Metadata {
+            dev: self.fs.dev,
+            ino: self.node.ino(),
+            kind: self.node.kind(),
+            mode,
+            nlink,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            blksize: 4096,
+            blocks: 0,
+            rdev: 0,
+            atime: self.fs.mounted,
+            mtime: self.fs.mounted,
+            ctime: self.fs.mounted,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let data = self.generate()?;
+        let data = data.as_bytes();
+        let start = offset.min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        let node = match self.node {
+            Node::Root => match ROOT_FILES.iter().find(|(file, _)| *file == name) {
+                Some(&(_, node)) => node,
+                None => {
+                    let pid = name.parse().map_err(|_| FsError::NotFound)?;
+                    task::find_process(pid).ok_or(FsError::NotFound)?;
+                    Node::Process(pid)
+                }
+            },
+            Node::Process(pid) => {
+                self.node.process()?;
+                let &(_, node) = PROCESS_FILES.iter().find(|(file, _)| *file == name).ok_or(FsError::NotFound)?;
+                node(pid)
+            }
+            _ => return Err(FsError::NotDir),
+        };
+        Ok(self.child(node))
+    }
+
+    fn create(&self, _name: &str, _kind: InodeType, _mode: u32) -> FsResult<Arc<dyn Inode>> {
+        match self.node.kind() {
+            InodeType::Dir => Err(FsError::PermissionDenied),
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    fn read_dir(&self, pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        let entry = match (self.node, pos) {
+            (Node::Root | Node::Process(_), 0) => self.node.entry("."),
+            (Node::Root | Node::Process(_), 1) => Node::Root.entry(".."),
+            (Node::Root, _) if pos - 2 < ROOT_FILES.len() => {
+                let (name, node) = ROOT_FILES[pos - 2];
+                node.entry(name)
+            }
+            (Node::Root, _) => {
+                let processes = task::all_processes();
+                let Some(process) = processes.get(pos - 2 - ROOT_FILES.len()) else {
+                    return Ok(None);
+                };
+                Node::Process(process.pid()).entry(&process.pid().to_string())
+            }
+            (Node::Process(pid), _) => {
+                self.node.process()?;
+                let Some(&(name, node)) = PROCESS_FILES.get(pos - 2) else {
+                    return Ok(None);
+                };
+                node(pid).entry(name)
+            }
+            _ => return Err(FsError::NotDir),
+        };
+        Ok(Some((entry, pos + 1)))
+    }
+
+    fn read_link(&self) -> FsResult<String> {
+        match self.node {
+            Node::SelfLink => Ok(task::current().process().pid().to_string()),
+            Node::Exe(_) => {
+                let process = self.node.process()?.unwrap();
+                let exe = process.inner().exe.clone().ok_or(FsError::NotFound)?;
+                // As seen from the root of the process reading the link.
+                let fs = task::current().process().inner().fs.clone();
+                let root = fs.lock().root.clone();
+                Ok(exe.path(&root))
+            }
+            _ => Err(FsError::InvalidInput),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// `/proc/meminfo`. Memory is what the frame allocator manages; the kernel
+/// heap is reported as slab.
+fn meminfo() -> String {
+    let (frames, allocated) = frame::usage();
+    let (_, heap_used) = heap::usage();
+    let kib = |frames: usize| frames * FRAME_SIZE / 1024;
+    let mut info = String::new();
+    let mut line = |name: &str, kib: usize| {
+        let _ = writeln!(info, "{:<16}{:>8} kB", format!("{}:", name), kib);
+    };
+    line("MemTotal", kib(frames));
+    line("MemFree", kib(frames - allocated));
+    line("MemAvailable", kib(frames - allocated));
+    line("Buffers", 0);
+    line("Cached", 0);
+    line("SwapTotal", 0);
+    line("SwapFree", 0);
+    line("Slab", heap_used / 1024);
+    line("SUnreclaim", heap_used / 1024);
+    info
+}
+
+/// `/proc/mounts`, whose fields escape white space and backslashes in
+/// octal.
+fn mounts() -> String {
+    let escape = |field: &str| {
+        field.chars().fold(String::new(), |mut escaped, c| {
+            match c {
+                ' ' | '\t' | '\n' | '\\' => {
+                    let _ = write!(escaped, "\\{:03o}", c as u32);
+                }
+                c => escaped.push(c),
+            }
+            escaped
+        })
+    };
+    let mut list = String::new();
+    for mount in mount::mounts() {
+        let _ = writeln!(
+            list,
+            "{} {} {} rw 0 0",
+            escape(&mount.source),
+            escape(&mount.path),
+            mount.fs_type
+        );
+    }
+    list
+}
+
+/// `/proc/<pid>/stat`. CPU time is not accounted, so those fields are 0.
+fn stat(process: &Arc<Process>) -> String {
+    let threads = process.threads();
+    let (comm, pgid, sid, tty, zombie) = {
+        let inner = process.inner();
+        let comm: String = match &inner.exe {
+            Some(exe) => exe.name().chars().take(15).collect(),
+            None => String::new(),
+        };
+        (comm, inner.pgid, inner.sid, inner.tty.clone(), inner.exit_status.is_some())
+    };
+    // The controlling terminal and its foreground group.
+    let (tty_nr, tpgid) = match tty.filter(|tty| tty.controls(sid)) {
+        Some(tty) => (tty.rdev() as isize, tty.foreground() as isize),
+        None => (0, -1),
+    };
+    let state = if zombie {
+        'Z'
+    } else if threads
+        .iter()
+        .any(|thread| matches!(thread.inner().status, TaskStatus::Running | TaskStatus::Ready))
+    {
+        'R'
+    } else {
+        'S'
+    };
+    let mut fields: Vec<String> = Vec::new();
+    fields.push(process.pid().to_string());
+    fields.push(format!("({})", comm));
+    fields.push(state.to_string());
+    // ppid, pgrp, session, tty_nr, tpgid, flags, minflt, cminflt, majflt,
+    // cmajflt, utime, stime, cutime, cstime, priority, nice.
+    let numbers = [process.ppid() as isize, pgid as isize, sid as isize, tty_nr, tpgid, 0, 0, 0, 0, 0, 0, 0, 0, 0, 20, 0];
+    fields.extend(numbers.iter().map(isize::to_string));
+    fields.push(threads.len().to_string());
+    // itrealvalue.
+    fields.push("0".to_string());
+    fields.push((process.start_ticks() * USER_HZ / INTERRUPT_PER_SEC).to_string());
+    // vsize, rss and the rest, which nothing tracks, up to the 52 fields
+    // Linux has.
+    fields.resize(52, "0".to_string());
+    let mut stat = fields.join(" ");
+    stat.push('\n');
+    stat
+}
@@ -0,0 +1,358 @@
+//! A file system keeping everything in memory, which serves as the first
+//! root until a disk is mounted over it.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use spin::Mutex;
+
+use super::{mount, DirEntry, FsError, FsResult, Inode, InodeType, Metadata, SuperBlock, TimeSpec};
+
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Arc<Self> {
+        Self::with_root_mode(0o755)
+    }
+
+    /// An empty file system whose root has permissions `mode`.
+    pub fn with_root_mode(mode: u32) -> Arc<Self> {
+        let fs = Arc::new(TmpFsShared {
+            dev: mount::new_dev(),
+            next_ino: AtomicU64::new(1),
+        });
+        let root = TmpInode::new(&fs, InodeType::Dir, mode, Content::Dir(BTreeMap::new()));
+        root.inner.lock().nlink = 2;
+        Arc::new(Self { root })
+    }
+}
+
+impl SuperBlock for TmpFs {
+    fn root(&self) -> Arc<dyn Inode> {
+        self.root.clone()
+    }
+}
+
+/// What the inodes of one file system share.
+struct TmpFsShared {
+    dev: u64,
+    next_ino: AtomicU64,
+}
+
+enum Content {
+    File(Vec<u8>),
+    Dir(BTreeMap<String, Arc<TmpInode>>),
+    Symlink(String),
+}
+
+struct TmpInode {
+    fs: Arc<TmpFsShared>,
+    ino: u64,
+    kind: InodeType,
+    /// The directory holding this one, for `..`. Unused by other files.
+    parent: Mutex<Weak<TmpInode>>,
+    this: Weak<TmpInode>,
+    inner: Mutex<TmpInodeInner>,
+}
+
+struct TmpInodeInner {
+    mode: u32,
+    nlink: u32,
+    atime: TimeSpec,
+    mtime: TimeSpec,
+    ctime: TimeSpec,
+    content: Content,
+}
+
+impl TmpInode {
+    fn new(fs: &Arc<TmpFsShared>, kind: InodeType, mode: u32, content: Content) -> Arc<Self> {
+        let now = TimeSpec::now();
+        Arc::new_cyclic(|this| Self {
+            fs: fs.clone(),
+            ino: fs.next_ino.fetch_add(1, Ordering::Relaxed),
+            kind,
+            parent: Mutex::new(Weak::new()),
+            this: this.clone(),
+            inner: Mutex::new(TmpInodeInner {
+                mode,
+                nlink: 1,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                content,
+            }),
+        })
+    }
+
+    /// `inode` as an inode of the same file system.
+    fn same_fs<'a>(&self, inode: &'a Arc<dyn Inode>) -> FsResult<&'a TmpInode> {
+        inode
+            .as_any()
+            .downcast_ref::<TmpInode>()
+            .filter(|inode| Arc::ptr_eq(&inode.fs, &self.fs))
+            .ok_or(FsError::CrossDevice)
+    }
+
+    /// Run `f` on the entries of this directory.
+    fn with_entries<T>(&self, f: impl FnOnce(&mut BTreeMap<String, Arc<TmpInode>>) -> FsResult<T>) -> FsResult<T> {
+        match &mut self.inner.lock().content {
+            Content::Dir(entries) => f(entries),
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    /// Record a change of the contents.
+    fn touch(&self) {
+        let mut inner = self.inner.lock();
+        let now = TimeSpec::now();
+        inner.mtime = now;
+        inner.ctime = now;
+    }
+
+    /// Add `inode` as `name`, which must not exist yet.
+    fn add_entry(&self, name: &str, inode: Arc<TmpInode>) -> FsResult<()> {
+        self.with_entries(|entries| {
+            if entries.contains_key(name) {
+                return Err(FsError::AlreadyExists);
+            }
+            entries.insert(name.to_string(), inode.clone());
+            Ok(())
+        })?;
+        self.touch();
+        if inode.kind == InodeType::Dir {
+            *inode.parent.lock() = self.this.clone();
+            self.inner.lock().nlink += 1;
+        }
+        Ok(())
+    }
+
+    /// Write `buf` at `offset`, or at the end of the file if `None`,
+    /// returning where it went and how many bytes were written.
+    fn write(&self, offset: Option<usize>, buf: &[u8]) -> FsResult<(usize, usize)> {
+        let mut inner = self.inner.lock();
+        let Content::File(data) = &mut inner.content else {
+            return Err(FsError::IsDir);
+        };
+        let offset = offset.unwrap_or(data.len());
+        let end = offset.checked_add(buf.len()).ok_or(FsError::InvalidInput)?;
+        if end > data.len() {
+            data.try_reserve(end - data.len()).map_err(|_| FsError::NoSpace)?;
+            data.resize(end, 0);
+        }
+        data[offset..end].copy_from_slice(buf);
+        let now = TimeSpec::now();
+        inner.mtime = now;
+        inner.ctime = now;
+        Ok((offset, buf.len()))
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        matches!(&self.inner.lock().content, Content::Dir(entries) if entries.is_empty())
+    }
+}
+
+impl Inode for TmpInode {
+    fn metadata(&self) -> FsResult<Metadata> {
+        let inner = self.inner.lock();
+        let size = match &inner.content {
+            Content::File(data) => data.len(),
+            Content::Dir(entries) => entries.len() * 32,
+            Content::Symlink(target) => target.len(),
+        } as u64;
+        Ok(Metadata {
+            dev: self.fs.dev,
+            ino: self.ino,
+            kind: self.kind,
+            mode: inner.mode,
+            nlink: inner.nlink,
+            uid: 0,
+            gid: 0,
+            size,
+            blksize: 4096,
+            blocks: size.div_ceil(512),
+            rdev: 0,
+            atime: inner.atime,
+            mtime: inner.mtime,
+            ctime: inner.ctime,
+        })
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> FsResult<usize> {
+        let mut inner = self.inner.lock();
+        let Content::File(data) = &inner.content else {
+            return Err(FsError::IsDir);
+        };
+        let start = offset.min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        inner.atime = TimeSpec::now();
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> FsResult<usize> {
+        self.write(Some(offset), buf).map(|(_, written)| written)
+    }
+
+    fn append(&self, buf: &[u8]) -> FsResult<(usize, usize)> {
+        self.write(None, buf)
+    }
+
+    fn truncate(&self, len: usize) -> FsResult<()> {
+        let mut inner = self.inner.lock();
+        let Content::File(data) = &mut inner.content else {
+            return Err(FsError::IsDir);
+        };
+        if len > data.len() {
+            data.try_reserve(len - data.len()).map_err(|_| FsError::NoSpace)?;
+        }
+        data.resize(len, 0);
+        data.shrink_to_fit();
+        let now = TimeSpec::now();
+        inner.mtime = now;
+        inner.ctime = now;
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> FsResult<Arc<dyn Inode>> {
+        self.with_entries(|entries| {
+            entries
+                .get(name)
+                .map(|inode| inode.clone() as Arc<dyn Inode>)
+                .ok_or(FsError::NotFound)
+        })
+    }
+
+    fn create(&self, name: &str, kind: InodeType, mode: u32) -> FsResult<Arc<dyn Inode>> {
+        let content = match kind {
+            InodeType::File => Content::File(Vec::new()),
+            InodeType::Dir => Content::Dir(BTreeMap::new()),
+            _ => return Err(FsError::Unsupported),
+        };
+        let inode = TmpInode::new(&self.fs, kind, mode, content);
+        if kind == InodeType::Dir {
+            inode.inner.lock().nlink = 2;
+        }
+        self.add_entry(name, inode.clone())?;
+        Ok(inode)
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> FsResult<Arc<dyn Inode>> {
+        let inode = TmpInode::new(&self.fs, InodeType::Symlink, 0o777, Content::Symlink(target.to_string()));
+        self.add_entry(name, inode.clone())?;
+        Ok(inode)
+    }
+
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> FsResult<()> {
+        let target = self.same_fs(target)?;
+        let target = target.this.upgrade().ok_or(FsError::NotFound)?;
+        self.add_entry(name, target.clone())?;
+        let mut inner = target.inner.lock();
+        inner.nlink += 1;
+        inner.ctime = TimeSpec::now();
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> FsResult<()> {
+        let inode = self.with_entries(|entries| match entries.get(name) {
+            Some(inode) if inode.kind == InodeType::Dir => Err(FsError::IsDir),
+            Some(_) => Ok(entries.remove(name).unwrap()),
+            None => Err(FsError::NotFound),
+        })?;
+        self.touch();
+        let mut inner = inode.inner.lock();
+        inner.nlink -= 1;
+        inner.ctime = TimeSpec::now();
+        Ok(())
+    }
+
+    fn rmdir(&self, name: &str) -> FsResult<()> {
+        self.with_entries(|entries| match entries.get(name) {
+            Some(inode) if inode.kind != InodeType::Dir => Err(FsError::NotDir),
+            Some(inode) if !inode.is_empty_dir() => Err(FsError::NotEmpty),
+            Some(_) => {
+                let inode = entries.remove(name).unwrap();
+                inode.inner.lock().nlink = 0;
+                Ok(())
+            }
+            None => Err(FsError::NotFound),
+        })?;
+        self.touch();
+        self.inner.lock().nlink -= 1;
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> FsResult<()> {
+        let new_dir = self.same_fs(new_dir)?;
+        let inode = self.lookup(old_name)?;
+        let inode = self.same_fs(&inode)?.this.upgrade().ok_or(FsError::NotFound)?;
+        // Check the replaced entry before anything changes.
+        if let Ok(replaced) = new_dir.lookup(new_name) {
+            let replaced = self.same_fs(&replaced)?;
+            if core::ptr::eq(replaced, Arc::as_ptr(&inode)) {
+                return Ok(());
+            }
+            match (inode.kind == InodeType::Dir, replaced.kind == InodeType::Dir) {
+                (true, true) if !replaced.is_empty_dir() => return Err(FsError::NotEmpty),
+                (true, true) => new_dir.rmdir(new_name)?,
+                (false, false) => new_dir.unlink(new_name)?,
+                (true, false) => return Err(FsError::NotDir),
+                (false, true) => return Err(FsError::IsDir),
+            }
+        }
+        self.with_entries(|entries| entries.remove(old_name).ok_or(FsError::NotFound))?;
+        self.touch();
+        if inode.kind == InodeType::Dir {
+            self.inner.lock().nlink -= 1;
+        }
+        new_dir.add_entry(new_name, inode.clone())?;
+        inode.inner.lock().ctime = TimeSpec::now();
+        Ok(())
+    }
+
+    fn read_dir(&self, pos: usize) -> FsResult<Option<(DirEntry, usize)>> {
+        let dot = |name: &str, inode: &TmpInode| DirEntry {
+            ino: inode.ino,
+            kind: InodeType::Dir,
+            name: name.to_string(),
+        };
+        match pos {
+            0 => return Ok(Some((dot(".", self), 1))),
+            1 => {
+                let parent = self.parent.lock().upgrade();
+                let entry = dot("..", parent.as_deref().unwrap_or(self));
+                return Ok(Some((entry, 2)));
+            }
+            _ => {}
+        }
+        self.with_entries(|entries| {
+            Ok(entries.iter().nth(pos - 2).map(|(name, inode)| {
+                let entry = DirEntry {
+                    ino: inode.ino,
+                    kind: inode.kind,
+                    name: name.clone(),
+                };
+                (entry, pos + 1)
+            }))
+        })
+    }
+
+    fn read_link(&self) -> FsResult<String> {
+        match &self.inner.lock().content {
+            Content::Symlink(target) => Ok(target.clone()),
+            _ => Err(FsError::InvalidInput),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
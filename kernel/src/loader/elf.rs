@@ -0,0 +1,102 @@
+//! The parts of the ELF64 format needed to load executables.
+
+use alloc::vec::Vec;
+use core::mem::{size_of, MaybeUninit};
+
+use crate::mm::memory_set::BackingFile;
+
+use super::{LoadError, LoadResult};
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_RISCV: u16 = 243;
+
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_INTERP: u32 = 3;
+pub const PT_PHDR: u32 = 6;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+/// More program headers than this are refused.
+const MAX_PHNUM: usize = 128;
+
+/// `Elf64_Ehdr`.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct ElfHeader {
+    pub ident: [u8; 16],
+    pub elf_type: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+/// `Elf64_Phdr`.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+/// Read exactly `buf.len()` bytes at `offset`.
+pub fn read_exact(file: &dyn BackingFile, offset: usize, buf: &mut [u8]) -> LoadResult<()> {
+    if file.read_at(offset, buf) != buf.len() {
+        return Err(LoadError::Truncated);
+    }
+    Ok(())
+}
+
+fn read_struct<T: Copy>(file: &dyn BackingFile, offset: usize) -> LoadResult<T> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes = unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    read_exact(file, offset, bytes)?;
+    Ok(unsafe { value.assume_init() })
+}
+
+impl ElfHeader {
+    /// Read and check the header of a RISC-V ELF64 executable.
+    pub fn read(file: &dyn BackingFile) -> LoadResult<Self> {
+        let header: Self = read_struct(file, 0).map_err(|_| LoadError::NotElf)?;
+        if header.ident[..4] != ELF_MAGIC {
+            return Err(LoadError::NotElf);
+        }
+        if header.ident[4] != ELFCLASS64
+            || header.ident[5] != ELFDATA2LSB
+            || header.machine != EM_RISCV
+            || !matches!(header.elf_type, ET_EXEC | ET_DYN)
+            || header.phentsize as usize != size_of::<ProgramHeader>()
+            || header.phnum as usize > MAX_PHNUM
+        {
+            return Err(LoadError::Unsupported);
+        }
+        Ok(header)
+    }
+
+    pub fn program_headers(&self, file: &dyn BackingFile) -> LoadResult<Vec<ProgramHeader>> {
+        (0..self.phnum as usize)
+            .map(|i| read_struct(file, self.phoff as usize + i * size_of::<ProgramHeader>()))
+            .collect()
+    }
+}
@@ -0,0 +1,40 @@
+use core::fmt::{self, Display};
+
+use crate::mm::MmError;
+
+/// Errors of loading a program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// The file is not an ELF file.
+    NotElf,
+    /// The ELF file is not a RISC-V executable we can run.
+    Unsupported,
+    /// A header or segment lies beyond the end of the file.
+    Truncated,
+    /// The interpreter the program asks for cannot be opened.
+    NoInterpreter,
+    /// The arguments and environment do not fit on the stack.
+    ArgumentsTooLong,
+    Memory(MmError),
+}
+
+pub type LoadResult<T> = Result<T, LoadError>;
+
+impl From<MmError> for LoadError {
+    fn from(e: MmError) -> Self {
+        LoadError::Memory(e)
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::NotElf => f.write_str("not an ELF file"),
+            LoadError::Unsupported => f.write_str("unsupported ELF file"),
+            LoadError::Truncated => f.write_str("truncated ELF file"),
+            LoadError::NoInterpreter => f.write_str("interpreter not found"),
+            LoadError::ArgumentsTooLong => f.write_str("argument list too long"),
+            LoadError::Memory(e) => write!(f, "{}", e),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Loading ELF programs into an address space.
+//!
+//! Static, position-independent and dynamically linked RISC-V executables
+//! are supported, the latter through the interpreter named by `PT_INTERP`,
+//! which is mapped at a base of its own. Segments are mapped as private
+//! anonymous memory filled from the file, so the rest of the last file page
+//! and everything up to `p_memsz` reads as zero.
+
+mod elf;
+mod error;
+mod stack;
+
+use alloc::{string::String, sync::Arc, vec};
+
+use crate::{
+    config::{USER_PIE_BASE, USER_SPACE_END},
+    mm::{
+        addr::VirtAddr,
+        consts::PAGE_SIZE,
+        memory_set::{Backing, BackingFile, MapPerm, MemorySet, VmArea},
+        MmError,
+    },
+    timer::consts::INTERRUPT_PER_SEC,
+};
+
+use self::{
+    elf::{read_exact, ElfHeader, ProgramHeader, ET_DYN, PF_R, PF_W, PF_X, PT_INTERP, PT_LOAD, PT_PHDR},
+    stack::*,
+};
+
+pub use self::error::{LoadError, LoadResult};
+
+/// `AT_HWCAP` bits of the extensions we run user code with: IMAFDC.
+const HWCAP_RV64IMAFDC: usize =
+    hwcap(b'I') | hwcap(b'M') | hwcap(b'A') | hwcap(b'F') | hwcap(b'D') | hwcap(b'C');
+
+/// The `AT_HWCAP` bit of the single-letter extension `letter`.
+const fn hwcap(letter: u8) -> usize {
+    1 << (letter - b'A')
+}
+
+/// Longest interpreter path we accept, with its terminator.
+const PATH_MAX: usize = 4096;
+
+/// Where a loaded program starts.
+#[derive(Clone, Copy, Debug)]
+pub struct Image {
+    /// Entry of the interpreter if there is one, else of the program.
+    pub entry: usize,
+    /// Initial stack pointer, pointing at argc.
+    pub sp: usize,
+    /// End of the highest segment, where the heap starts.
+    pub brk: usize,
+}
+
+/// Load the executable `file` into the empty `memory_set` and build its
+/// stack from `args` and `envs`.
+///
+/// A dynamically linked program gets its interpreter, found through `open`,
+/// mapped as well, and starts there.
+pub fn load(
+    memory_set: &mut MemorySet,
+    file: &dyn BackingFile,
+    args: &[String],
+    envs: &[String],
+    open: &dyn Fn(&str) -> Option<Arc<dyn BackingFile>>,
+) -> LoadResult<Image> {
+    let header = ElfHeader::read(file)?;
+    let phdrs = header.program_headers(file)?;
+    let (lowest, _) = load_span(&phdrs)?;
+    let bias = if header.elf_type == ET_DYN {
+        USER_PIE_BASE.checked_sub(lowest).ok_or(LoadError::Unsupported)?
+    } else {
+        0
+    };
+    let brk = map_image(memory_set, file, &phdrs, bias)?;
+
+    // Where the program headers are in memory, for the C library to find
+    // its TLS and dynamic sections.
+    let loads = || phdrs.iter().filter(|ph| ph.p_type == PT_LOAD);
+    let phdr = phdrs
+        .iter()
+        .find(|ph| ph.p_type == PT_PHDR)
+        .map(|ph| ph.vaddr)
+        .or_else(|| {
+            loads()
+                .find(|ph| (ph.offset..ph.offset + ph.filesz).contains(&header.phoff))
+                .map(|ph| ph.vaddr + header.phoff - ph.offset)
+        })
+        .map_or(0, |vaddr| vaddr as usize + bias);
+    let entry = header.entry as usize + bias;
+
+    let (interp_base, start) = match phdrs.iter().find(|ph| ph.p_type == PT_INTERP) {
+        Some(ph) => {
+            let path = interpreter_path(file, ph)?;
+            let interp = open(&path).ok_or(LoadError::NoInterpreter)?;
+            load_interpreter(memory_set, interp.as_ref())?
+        }
+        None => (0, entry),
+    };
+
+    let aux = [
+        (AT_PHDR, phdr),
+        (AT_PHENT, header.phentsize as usize),
+        (AT_PHNUM, header.phnum as usize),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, interp_base),
+        (AT_FLAGS, 0),
+        (AT_ENTRY, entry),
+        (AT_HWCAP, HWCAP_RV64IMAFDC),
+        (AT_CLKTCK, INTERRUPT_PER_SEC),
+        (AT_SECURE, 0),
+    ];
+    let sp = stack::build(memory_set, args, envs, &aux)?;
+    Ok(Image { entry: start, sp, brk })
+}
+
+/// The page-aligned range the `PT_LOAD` segments cover, before relocation.
+fn load_span(phdrs: &[ProgramHeader]) -> LoadResult<(usize, usize)> {
+    let loads = || phdrs.iter().filter(|ph| ph.p_type == PT_LOAD);
+    let start = loads().map(|ph| ph.vaddr as usize).min().ok_or(LoadError::Unsupported)?;
+    let end = loads()
+        .map(|ph| (ph.vaddr as usize).saturating_add(ph.memsz as usize))
+        .max()
+        .ok_or(LoadError::Unsupported)?;
+    Ok((VirtAddr(start).floor().0, VirtAddr(end).ceil().0))
+}
+
+/// Map every `PT_LOAD` segment of `file` moved up by `bias`. Returns the end
+/// of the highest one.
+fn map_image(memory_set: &mut MemorySet, file: &dyn BackingFile, phdrs: &[ProgramHeader], bias: usize) -> LoadResult<usize> {
+    let mut end = 0;
+    for ph in phdrs.iter().filter(|ph| ph.p_type == PT_LOAD) {
+        end = end.max(map_segment(memory_set, file, ph, bias)?);
+    }
+    Ok(end)
+}
+
+/// The path in the `PT_INTERP` segment `ph`.
+fn interpreter_path(file: &dyn BackingFile, ph: &ProgramHeader) -> LoadResult<String> {
+    if ph.filesz == 0 || ph.filesz as usize > PATH_MAX {
+        return Err(LoadError::Unsupported);
+    }
+    let mut path = vec![0u8; ph.filesz as usize];
+    read_exact(file, ph.offset as usize, &mut path)?;
+    if path.pop() != Some(0) {
+        return Err(LoadError::Unsupported);
+    }
+    String::from_utf8(path).map_err(|_| LoadError::Unsupported)
+}
+
+/// Map the dynamic linker `interp` wherever there is room for it. Returns
+/// its base and entry point.
+fn load_interpreter(memory_set: &mut MemorySet, interp: &dyn BackingFile) -> LoadResult<(usize, usize)> {
+    let header = ElfHeader::read(interp)?;
+    if header.elf_type != ET_DYN {
+        return Err(LoadError::Unsupported);
+    }
+    let phdrs = header.program_headers(interp)?;
+    let (start, end) = load_span(&phdrs)?;
+    let base = memory_set
+        .find_free_range(end - start, None)
+        .ok_or(LoadError::Memory(MmError::OutOfMemory))?
+        .0;
+    let bias = base.checked_sub(start).ok_or(LoadError::Unsupported)?;
+    map_image(memory_set, interp, &phdrs, bias)?;
+    Ok((base, header.entry as usize + bias))
+}
+
+/// Map one `PT_LOAD` segment and copy its file contents in. Returns the end
+/// of the segment.
+fn map_segment(memory_set: &mut MemorySet, file: &dyn BackingFile, ph: &ProgramHeader, bias: usize) -> LoadResult<usize> {
+    let (filesz, memsz) = (ph.filesz as usize, ph.memsz as usize);
+    if filesz > memsz {
+        return Err(LoadError::Unsupported);
+    }
+    let start = (ph.vaddr as usize).checked_add(bias).ok_or(LoadError::Unsupported)?;
+    let end = start.checked_add(memsz).ok_or(LoadError::Unsupported)?;
+    if end > USER_SPACE_END {
+        return Err(LoadError::Unsupported);
+    }
+
+    let mut perm = MapPerm::U;
+    if ph.flags & PF_R != 0 {
+        perm |= MapPerm::R;
+    }
+    if ph.flags & PF_W != 0 {
+        perm |= MapPerm::W;
+    }
+    if ph.flags & PF_X != 0 {
+        perm |= MapPerm::X;
+    }
+    // The first page may already be mapped by the segment before, when the
+    // two share it. It then gets the permissions of both.
+    let mut area_start = VirtAddr(start).floor();
+    let area_end = VirtAddr(end).ceil();
+    while area_start < area_end && !memory_set.is_free(area_start, area_start + PAGE_SIZE) {
+        if let Some(shared) = memory_set.find_area(area_start).map(|area| area.perm()) {
+            memory_set.protect(area_start, PAGE_SIZE, shared | perm)?;
+        }
+        area_start += PAGE_SIZE;
+    }
+    if area_start < area_end {
+        memory_set.insert(VmArea::new(area_start, area_end, perm, Backing::Anonymous, true))?;
+    }
+
+    let mut buf = vec![0u8; PAGE_SIZE];
+    let mut done = 0;
+    while done < filesz {
+        let len = PAGE_SIZE.min(filesz - done);
+        read_exact(file, ph.offset as usize + done, &mut buf[..len])?;
+        memory_set.fill(VirtAddr(start + done), &buf[..len])?;
+        done += len;
+    }
+    Ok(end)
+}
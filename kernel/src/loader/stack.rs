@@ -0,0 +1,100 @@
+//! The initial stack of a program: argc, argv, envp and the auxiliary vector
+//! below the strings they point to.
+
+use alloc::{string::String, vec::Vec};
+use core::mem::size_of;
+
+use crate::{
+    config::{USER_STACK_SIZE, USER_STACK_TOP},
+    mm::{
+        addr::VirtAddr,
+        memory_set::{Backing, MapPerm, MemorySet, VmArea},
+    },
+    timer,
+};
+
+use super::{LoadError, LoadResult};
+
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_BASE: usize = 7;
+pub const AT_FLAGS: usize = 8;
+pub const AT_ENTRY: usize = 9;
+pub const AT_HWCAP: usize = 16;
+pub const AT_CLKTCK: usize = 17;
+pub const AT_SECURE: usize = 23;
+pub const AT_RANDOM: usize = 25;
+
+/// Arguments and environment may take at most this much of the stack.
+const MAX_ARG_SIZE: usize = USER_STACK_SIZE / 4;
+
+/// Bytes for `AT_RANDOM`, which C libraries seed stack protectors from.
+fn random_bytes() -> [u8; 16] {
+    // splitmix64 over the boot time, good enough until there is an entropy pool.
+    let mut state = timer::get_time_ns() as u64;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&next().to_le_bytes());
+    bytes[8..].copy_from_slice(&next().to_le_bytes());
+    bytes
+}
+
+/// Map the stack and lay out `args`, `envs` and `aux` on it, `AT_RANDOM`
+/// and `AT_NULL` being added here. Returns the initial stack pointer.
+pub fn build(memory_set: &mut MemorySet, args: &[String], envs: &[String], aux: &[(usize, usize)]) -> LoadResult<usize> {
+    let strings_size: usize = args.iter().chain(envs).map(|s| s.len() + 1).sum();
+    let words = 1 + args.len() + 1 + envs.len() + 1 + (aux.len() + 2) * 2;
+    if strings_size + words * size_of::<usize>() > MAX_ARG_SIZE {
+        return Err(LoadError::ArgumentsTooLong);
+    }
+    memory_set.insert(VmArea::new(
+        VirtAddr(USER_STACK_TOP - USER_STACK_SIZE),
+        VirtAddr(USER_STACK_TOP),
+        MapPerm::R | MapPerm::W | MapPerm::U,
+        Backing::Anonymous,
+        true,
+    ))?;
+
+    let mut sp = USER_STACK_TOP;
+    let mut push_str = |memory_set: &mut MemorySet, s: &str| -> LoadResult<usize> {
+        sp -= s.len() + 1;
+        memory_set.fill(VirtAddr(sp), s.as_bytes())?;
+        memory_set.fill(VirtAddr(sp + s.len()), &[0])?;
+        Ok(sp)
+    };
+    let argv = args
+        .iter()
+        .map(|arg| push_str(memory_set, arg))
+        .collect::<LoadResult<Vec<_>>>()?;
+    let envp = envs
+        .iter()
+        .map(|env| push_str(memory_set, env))
+        .collect::<LoadResult<Vec<_>>>()?;
+    let random = sp - 16;
+    memory_set.fill(VirtAddr(random), &random_bytes())?;
+
+    let mut table = Vec::with_capacity(words);
+    table.push(args.len());
+    table.extend(argv);
+    table.push(0);
+    table.extend(envp);
+    table.push(0);
+    for &(key, value) in aux.iter().chain(&[(AT_RANDOM, random), (AT_NULL, 0)]) {
+        table.push(key);
+        table.push(value);
+    }
+    // The ABI wants `sp` 16-byte aligned, pointing at argc.
+    let sp = (random - table.len() * size_of::<usize>()) & !0xf;
+    let bytes = unsafe { core::slice::from_raw_parts(table.as_ptr() as *const u8, table.len() * size_of::<usize>()) };
+    memory_set.fill(VirtAddr(sp), bytes)?;
+    Ok(sp)
+}
@@ -20,29 +20,55 @@ mod board;
 mod boot;
 mod config;
 mod console;
+mod cpu;
+mod drivers;
+mod dtb;
+mod fs;
+mod loader;
 mod logging;
 mod macros;
 mod mm;
 mod panic;
+mod sched;
+mod syscall;
+mod task;
 mod timer;
 mod trap;
 
 #[no_mangle]
-extern "C" fn kernel_init(hart_id: usize, _dtb_pa: usize) -> ! {
+extern "C" fn kernel_init(hart_id: usize, dtb_pa: usize) -> ! {
     clear_bss();
+    cpu::init(hart_id);
+    cpu::set_boot_hart(hart_id);
     logging::init();
     display_banner();
+    dtb::init(dtb_pa);
     info!(
         "GeneralOS-Rust-RiscV srarted in hart {} with {} harts in total.",
         hart_id,
-        arch::get_hart_count()
+        dtb::machine().hart_count()
     );
     mm::init();
+    drivers::init();
+    fs::init();
+    task::spawn_init();
     trap::init();
     timer::init();
-    loop {
-        arch::wfi();
-    }
+    cpu::set_online();
+    cpu::smp::start_secondary_harts();
+    sched::run()
+}
+
+#[no_mangle]
+extern "C" fn secondary_init(hart_id: usize) -> ! {
+    cpu::init(hart_id);
+    mm::init_hart();
+    drivers::init_hart();
+    trap::init();
+    timer::init_hart();
+    info!("Hart {} started.", hart_id);
+    cpu::set_online();
+    sched::run()
 }
 
 fn display_banner() {
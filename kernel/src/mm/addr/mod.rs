@@ -3,23 +3,73 @@
 mod phys;
 mod virt;
 
-use log::warn;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 pub use phys::*;
 pub use virt::*;
 
-use crate::config::{KERNEL_VIRTUAL_MEMORY_END, KERNEL_VIRTUAL_MEMORY_START, PHYSICAL_MEMORY_END, PHYSICAL_MEMORY_START};
+use crate::config::{BOOT_DIRECT_MAP_END, DIRECT_MAP_MAX_PA, PHYSICAL_MEMORY_START, PHYS_VIRT_OFFSET};
+
+/// Start of the physical memory reachable through the direct map.
+///
+/// The boot page table maps all of RAM from its start. The kernel page table
+/// leaves out the firmware below the kernel image.
+static DIRECT_MAP_START: AtomicUsize = AtomicUsize::new(PHYSICAL_MEMORY_START);
+
+/// End of the physical memory reachable through the direct map.
+///
+/// Until the size of physical memory is known this is what the boot page
+/// table maps.
+static DIRECT_MAP_END: AtomicUsize = AtomicUsize::new(BOOT_DIRECT_MAP_END);
+
+/// Set the end of the direct map from the memory size found at runtime.
+///
+/// Returns the end actually in effect, which is capped at `DIRECT_MAP_MAX_PA`.
+pub fn set_direct_map_end(end: PhysAddr) -> PhysAddr {
+    let end = end.0.min(DIRECT_MAP_MAX_PA);
+    DIRECT_MAP_END.store(end, Ordering::Relaxed);
+    PhysAddr(end)
+}
+
+pub fn direct_map_end() -> PhysAddr {
+    PhysAddr(DIRECT_MAP_END.load(Ordering::Relaxed))
+}
+
+/// Set the start of the direct map, once the page table mapping it is in use.
+pub fn set_direct_map_start(start: PhysAddr) {
+    DIRECT_MAP_START.store(start.0, Ordering::Relaxed);
+}
+
+pub fn direct_map_start() -> PhysAddr {
+    PhysAddr(DIRECT_MAP_START.load(Ordering::Relaxed))
+}
 
+pub fn is_direct_mapped(pa: PhysAddr) -> bool {
+    (direct_map_start().0..direct_map_end().0).contains(&pa.0)
+}
+
+/// The kernel virtual address of `pa` in the direct map.
+///
+/// Panics if `pa` is outside the direct map.
 pub fn pa2kva(pa: PhysAddr) -> VirtAddr {
-    if !(PHYSICAL_MEMORY_START..PHYSICAL_MEMORY_END).contains(&pa.0) {
-        warn!("Address not in physical memory range");
+    if !is_direct_mapped(pa) {
+        panic!(
+            "{} is outside the direct map (0x{:x} - 0x{:x})",
+            pa,
+            direct_map_start().0,
+            direct_map_end().0
+        );
     }
-    VirtAddr(pa.0 - PHYSICAL_MEMORY_START + KERNEL_VIRTUAL_MEMORY_START)
+    VirtAddr(pa.0 + PHYS_VIRT_OFFSET)
 }
 
+/// The physical address behind the direct-mapped kernel address `va`.
+///
+/// Panics if `va` is outside the direct map.
 pub fn kva2pa(va: VirtAddr) -> PhysAddr {
-    if !(KERNEL_VIRTUAL_MEMORY_START..KERNEL_VIRTUAL_MEMORY_END).contains(&va.0) {
-        warn!("Address not in kernel virtual memory range");
+    let pa = PhysAddr(va.0.wrapping_sub(PHYS_VIRT_OFFSET));
+    if va.0 < PHYS_VIRT_OFFSET || !is_direct_mapped(pa) {
+        panic!("{} is outside the direct map", va);
     }
-    PhysAddr(va.0 - KERNEL_VIRTUAL_MEMORY_START + PHYSICAL_MEMORY_START)
+    pa
 }
-
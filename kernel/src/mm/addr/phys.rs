@@ -3,7 +3,7 @@ use core::{fmt::{self, Display}, ops::{Add, AddAssign, Sub, SubAssign}};
 
 use crate::{mask, mm::consts::{PAGE_SIZE, PAGE_SIZE_BITS, PA_WIDTH}, round_up};
 
-use super::pa2kva;
+use super::{pa2kva, VirtAddr};
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PhysAddr(pub usize);
@@ -46,7 +46,7 @@ impl PhysAddr {
     }
 
     pub fn floor(self) -> Self {
-        Self(self.0 & mask!(PAGE_SIZE))
+        Self(self.0 & !mask!(PAGE_SIZE_BITS))
     }
 
     pub fn offset(self) -> usize {
@@ -61,12 +61,18 @@ impl PhysAddr {
         PhysPageNum(self.0 >> PAGE_SIZE_BITS)
     }
     
-    pub fn as_ptr<T>(self) -> *mut T {
-        self.0 as *mut T
+    /// Pointer to this address through the direct map.
+    pub fn as_ptr<T>(self) -> *const T {
+        pa2kva(self).as_ptr()
     }
 
+    /// Mutable pointer to this address through the direct map.
     pub fn as_mut_ptr<T>(self) -> *mut T {
-        self.0 as *mut T
+        pa2kva(self).as_mut_ptr()
+    }
+
+    pub fn kva(self) -> VirtAddr {
+        pa2kva(self)
     }
 
     pub unsafe fn as_slice(&self, len: usize) -> &[u8] {
@@ -119,6 +125,17 @@ impl SubAssign<usize> for PhysPageNum {
     }
 }
 
+impl PhysPageNum {
+    pub fn addr(self) -> PhysAddr {
+        self.into()
+    }
+
+    /// Kernel address of the frame in the direct map.
+    pub fn kva(self) -> VirtAddr {
+        pa2kva(self.into())
+    }
+}
+
 impl Display for PhysPageNum {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "PPN(0x{:x})", self.0)
@@ -45,7 +45,7 @@ impl VirtAddr {
     }
 
     pub fn floor(self) -> Self {
-        Self(self.0 & mask!(PAGE_SIZE))
+        Self(self.0 & !mask!(PAGE_SIZE_BITS))
     }
 
     pub fn offset(self) -> usize {
@@ -60,8 +60,8 @@ impl VirtAddr {
         VirtPageNum(self.0 >> PAGE_SIZE_BITS)
     }
     
-    pub fn as_ptr<T>(self) -> *mut T {
-        self.0 as *mut T
+    pub fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
     }
 
     pub fn as_mut_ptr<T>(self) -> *mut T {
@@ -120,8 +120,10 @@ impl const From<VirtAddr> for usize {
     }
 }
 impl const From<usize> for VirtAddr {
+    /// Sign-extends bit `VA_WIDTH - 1`, as Sv39 requires.
     fn from(v: usize) -> Self {
-        Self(v & mask!(VA_WIDTH))
+        let shift = usize::BITS as usize - VA_WIDTH;
+        Self((((v << shift) as isize) >> shift) as usize)
     }
 }
 
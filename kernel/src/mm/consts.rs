@@ -5,6 +5,9 @@ use crate::mask;
 pub const PAGE_SIZE_BITS: usize = 12;
 pub const PAGE_SIZE: usize = 1 << PAGE_SIZE_BITS;
 
+pub const MEGA_PAGE_SIZE_BITS: usize = 21;
+pub const MEGA_PAGE_SIZE: usize = 1 << MEGA_PAGE_SIZE_BITS;
+
 pub const HUGE_PAGE_SIZE_BITS: usize = 30;
 pub const HUGE_PAGE_SIZE: usize = 1 << HUGE_PAGE_SIZE_BITS;
 
@@ -0,0 +1,31 @@
+use core::fmt::{self, Display};
+
+/// Errors of memory management operations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MmError {
+    /// No free frame was left for a page or a page table.
+    OutOfMemory,
+    /// The virtual address is already mapped.
+    AlreadyMapped,
+    /// The virtual address is not mapped.
+    NotMapped,
+    /// An address or size is not aligned to the page size in use.
+    Misaligned,
+    /// The access is not allowed by the permissions of the mapping.
+    PermissionDenied,
+}
+
+pub type MmResult<T> = Result<T, MmError>;
+
+impl Display for MmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            MmError::OutOfMemory => "out of memory",
+            MmError::AlreadyMapped => "already mapped",
+            MmError::NotMapped => "not mapped",
+            MmError::Misaligned => "misaligned address or size",
+            MmError::PermissionDenied => "permission denied",
+        };
+        f.write_str(msg)
+    }
+}
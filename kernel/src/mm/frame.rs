@@ -1,9 +1,10 @@
 #![allow(dead_code)] // TODO
 
 use alloc::vec::Vec;
+use core::{mem::size_of, sync::atomic::{AtomicU32, Ordering}};
 use lazy_static::lazy_static;
-use log::{info, warn};
-use spin::Mutex;
+use log::{debug, info, warn};
+use spin::{Mutex, Once};
 
 use crate::{prev_pow_of_2, print, println};
 
@@ -16,6 +17,21 @@ lazy_static! {
         Mutex::new(FrameAllocator::new());
 }
 
+/// References to the frames of physical memory beyond the first, indexed by
+/// PPN from the start of memory. A frame with none has a single owner.
+static FRAME_REFS: Once<FrameRefs> = Once::new();
+
+struct FrameRefs {
+    base: PhysPageNum,
+    extra: &'static [AtomicU32],
+}
+
+impl FrameRefs {
+    fn of(&self, frame: PhysPageNum) -> &AtomicU32 {
+        &self.extra[frame.0 - self.base.0]
+    }
+}
+
 pub struct FrameAllocator<const ORDER: usize> {
     free_list: [Vec<PhysPageNum>; ORDER],
     total: usize,
@@ -32,10 +48,12 @@ impl<const ORDER: usize> FrameAllocator<ORDER> {
         }
     }
 
-    pub fn init(&mut self, start: PhysAddr, end: PhysAddr) {
-        assert!(start.0 < end.0);
+    pub fn add_range(&mut self, start: PhysAddr, end: PhysAddr) {
         let start = start.ceil_page();
         let end = end.floor_page();
+        if start >= end {
+            return;
+        }
         let mut current = start;
         while current < end {
             let lowbit = 1 << current.0.trailing_zeros();
@@ -44,7 +62,7 @@ impl<const ORDER: usize> FrameAllocator<ORDER> {
             self.free_list[order].push(current);
             current += size;
         }
-        self.total = end.0 - start.0;
+        self.total += end.0 - start.0;
     }
 
     pub fn alloc(&mut self, size: usize, align: usize) -> Option<PhysPageNum> {
@@ -123,22 +141,32 @@ impl<const ORDER: usize> FrameAllocator<ORDER> {
 }
 
 unsafe fn clear_frame(frame: PhysPageNum, size: usize) {
-    let ptr = PhysAddr::from(frame).as_mut_ptr::<u8>();
-    ptr.write_bytes(0, FRAME_SIZE * size);
+    PhysAddr::from(frame).as_mut_slice(FRAME_SIZE * size).fill(0);
 }
 
 pub fn debug_print() {
     FRAME_ALLOCATOR.lock().debug_print();
 }
 
-pub fn init(start: PhysAddr, end: PhysAddr) {
-    FRAME_ALLOCATOR.lock().init(start, end);
+/// Hand the physical range `[start, end)` over to the frame allocator.
+pub fn add_range(start: PhysAddr, end: PhysAddr) {
+    debug!("Adding frames: {} - {}", start, end);
+    FRAME_ALLOCATOR.lock().add_range(start, end);
+}
+
+pub fn print_summary() {
     info!(
         "Initialized frame allocator with {} frames in total.",
         FRAME_ALLOCATOR.lock().total
     );
 }
 
+/// Frames managed in total and frames allocated.
+pub fn usage() -> (usize, usize) {
+    let allocator = FRAME_ALLOCATOR.lock();
+    (allocator.total, allocator.allocated)
+}
+
 pub fn alloc_frames(size: usize, align: usize) -> Option<PhysPageNum> {
     let frame = FRAME_ALLOCATOR
         .lock()
@@ -163,4 +191,41 @@ pub fn alloc() -> Option<PhysPageNum> {
 
 pub fn dealloc(frame: PhysPageNum) {
     dealloc_frames(frame, 1);
-}
\ No newline at end of file
+}
+
+/// Set up reference counting for the frames of `[start, end)`, which must
+/// cover all of physical memory. The counts take frames of their own, so
+/// this comes after the frames are added.
+pub fn init_refs(start: PhysAddr, end: PhysAddr) {
+    let base = start.floor_page();
+    let frames = end.ceil_page().0 - base.0;
+    let size = (frames * size_of::<AtomicU32>()).div_ceil(FRAME_SIZE).next_power_of_two();
+    let table = alloc_frames(size, 1).expect("no frames left for the reference counts");
+    // Zeroed by the allocation, which is no extra reference.
+    let extra = unsafe { core::slice::from_raw_parts(table.addr().as_ptr::<AtomicU32>(), frames) };
+    FRAME_REFS.call_once(|| FrameRefs { base, extra });
+}
+
+fn refs() -> &'static FrameRefs {
+    FRAME_REFS.get().expect("frame reference counts used before init_refs")
+}
+
+/// Take another reference to `frame`.
+pub fn add_ref(frame: PhysPageNum) {
+    refs().of(frame).fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn ref_count(frame: PhysPageNum) -> usize {
+    refs().of(frame).load(Ordering::Acquire) as usize + 1
+}
+
+/// Drop a reference to the `size` frames starting at `frame`, freeing them
+/// with the last one.
+pub fn release(frame: PhysPageNum, size: usize) {
+    let dropped = refs()
+        .of(frame)
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |extra| extra.checked_sub(1));
+    if dropped.is_err() {
+        dealloc_frames(frame, size);
+    }
+}
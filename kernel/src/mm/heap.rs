@@ -28,6 +28,12 @@ pub fn init() {
     );
 }
 
+/// Bytes of kernel heap in total and in use.
+pub fn usage() -> (usize, usize) {
+    let heap = ALLOCATOR.lock();
+    (heap.total(), heap.allocated())
+}
+
 #[alloc_error_handler]
 fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
     panic!("Heap allocation error: {:?}", layout)
@@ -0,0 +1,101 @@
+//! The kernel address space.
+//!
+//! Built once by the boot hart to replace the boot page table. Kernel sections
+//! are mapped with their own permissions and the rest of physical memory is
+//! mapped into the direct map, so [`pa2kva`] works for every frame. Every
+//! hart runs on this table, and user address spaces copy its root entries.
+
+use log::info;
+use spin::{Mutex, MutexGuard, Once};
+
+use crate::{arch, config::PHYS_VIRT_OFFSET};
+
+use super::{
+    addr::{direct_map_end, kva2pa, pa2kva, set_direct_map_start, PhysAddr, VirtAddr},
+    consts::PAGE_SIZE,
+    layout::{
+        __boot_start, __bss_end, __data_end, __data_start, __kernel_end, __rodata_end,
+        __rodata_start, __text_end,
+    },
+    paging::{pagetable::PageTable, pte::PteFlags},
+    MmResult,
+};
+
+static KERNEL_PAGE_TABLE: Once<Mutex<PageTable>> = Once::new();
+
+/// Build the kernel page table and switch the current hart to it.
+pub fn init() {
+    let mut page_table = PageTable::new().expect("No memory for the kernel page table");
+
+    let global = PteFlags::G | PteFlags::A;
+    let sections = [
+        ("text", __boot_start as usize, __text_end as usize, PteFlags::R | PteFlags::X),
+        ("rodata", __rodata_start as usize, __rodata_end as usize, PteFlags::R),
+        ("data", __data_start as usize, __data_end as usize, PteFlags::R | PteFlags::W | PteFlags::D),
+        ("bss", __data_end as usize, __bss_end as usize, PteFlags::R | PteFlags::W | PteFlags::D),
+        ("eh_frame", __bss_end as usize, __kernel_end as usize, PteFlags::R),
+    ];
+    for (name, start, end, perm) in sections {
+        let start = VirtAddr(start).floor();
+        let end = VirtAddr(end).ceil();
+        info!("Mapping .{}: {} - {}", name, start, end);
+        page_table
+            .map_region(start, kva2pa(start), end.0 - start.0, perm | global)
+            .unwrap_or_else(|e| panic!("Failed to map .{}: {}", name, e));
+    }
+
+    let start = kva2pa(VirtAddr(__kernel_end as usize));
+    let end = direct_map_end();
+    info!("Mapping physical memory: {} - {}", start, end);
+    page_table
+        .map_region(
+            pa2kva(start),
+            start,
+            end.0 - start.0,
+            PteFlags::R | PteFlags::W | PteFlags::D | global,
+        )
+        .unwrap_or_else(|e| panic!("Failed to map physical memory: {}", e));
+
+    let page_table = KERNEL_PAGE_TABLE.call_once(|| Mutex::new(page_table));
+    arch::switch_page_table(page_table.lock().root_pa().0);
+    // The firmware below the kernel image is no longer mapped.
+    set_direct_map_start(kva2pa(VirtAddr(__boot_start as usize).floor()));
+    info!("Switched to the kernel page table.");
+}
+
+/// Map the device registers at `[pa, pa + size)`, which lie below RAM, at
+/// the same offset as the direct map. Pages mapped already, such as those
+/// of another device sharing the page, are kept.
+///
+/// Devices are mapped while booting, before user address spaces copy the
+/// kernel's root entries and before other harts start.
+pub fn map_mmio(pa: PhysAddr, size: usize) -> MmResult<VirtAddr> {
+    let va = VirtAddr(pa.0 + PHYS_VIRT_OFFSET);
+    let mut page_table = kernel_page_table();
+    let mut page = pa.floor();
+    while page.0 < pa.0 + size {
+        let page_va = VirtAddr(page.0 + PHYS_VIRT_OFFSET);
+        if page_table.query(page_va).is_err() {
+            page_table.map_page(page_va, page, PteFlags::R | PteFlags::W | PteFlags::A | PteFlags::D | PteFlags::G)?;
+            arch::flush_tlb(page_va.0);
+        }
+        page += PAGE_SIZE;
+    }
+    Ok(va)
+}
+
+/// Switch the current hart to the kernel page table.
+pub fn activate() {
+    arch::switch_page_table(root_pa().0);
+}
+
+pub fn root_pa() -> PhysAddr {
+    kernel_page_table().root_pa()
+}
+
+pub fn kernel_page_table() -> MutexGuard<'static, PageTable> {
+    KERNEL_PAGE_TABLE
+        .get()
+        .expect("Kernel page table not initialized")
+        .lock()
+}
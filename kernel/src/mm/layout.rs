@@ -1,6 +1,7 @@
 use log::info;
 
 extern "C" {
+    pub fn __boot_start();
     pub fn __kernel_start();
     pub fn __kernel_end();
     pub fn __text_start();
@@ -13,9 +14,6 @@ extern "C" {
     pub fn __bss_end();
 }
 
-pub const K_SEG_PHY_MEM_BEG: usize = 0xffff_fff0_0000_0000;
-pub const K_SEG_PHY_MEM_END: usize = 0xffff_ffff_8000_0000;
-
 pub fn print_memory_layout() {
     let kernel_start = __kernel_start as usize;
     let kernel_end   = __kernel_end as usize ;
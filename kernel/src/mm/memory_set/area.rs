@@ -0,0 +1,369 @@
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use bitflags::bitflags;
+use log::warn;
+
+use crate::{
+    mm::{
+        addr::{PhysPageNum, VirtAddr},
+        consts::PAGE_SIZE,
+        frame,
+        paging::{pagetable::PageTable, pte::PteFlags},
+        MmError, MmResult,
+    },
+    sched::SleepMutex,
+};
+
+bitflags! {
+    /// Access permissions of an area, as seen by user space.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct MapPerm: u8 {
+        const R = 1 << 0;
+        const W = 1 << 1;
+        const X = 1 << 2;
+        const U = 1 << 3;
+    }
+}
+
+impl From<MapPerm> for PteFlags {
+    fn from(perm: MapPerm) -> Self {
+        let mut flags = PteFlags::A | PteFlags::D;
+        if perm.contains(MapPerm::R) {
+            flags |= PteFlags::R;
+        }
+        // W without R is reserved in Sv39.
+        if perm.contains(MapPerm::W) {
+            flags |= PteFlags::R | PteFlags::W;
+        }
+        if perm.contains(MapPerm::X) {
+            flags |= PteFlags::X;
+        }
+        if perm.contains(MapPerm::U) {
+            flags |= PteFlags::U;
+        }
+        flags
+    }
+}
+
+/// A file an area can be mapped from.
+pub trait BackingFile: Send + Sync {
+    /// Read into `buf` from `offset`, returning how many bytes were read.
+    /// Whatever is not read stays zero.
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+
+    /// Write `buf` at `offset`, returning how many bytes were written.
+    /// Files that cannot be written through a mapping keep the default.
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> usize {
+        0
+    }
+}
+
+/// A page of a [`SharedMemory`].
+struct SharedPage {
+    frame: PhysPageNum,
+    /// Bytes of the page backed by the file, the rest lies past its end
+    /// and is never written back.
+    file_len: usize,
+    /// Written through a mapping since it was read in.
+    dirty: bool,
+}
+
+/// Frames shared by every area mapping the same memory object.
+///
+/// Frames are allocated on first use and freed when the last area goes away.
+/// An object mirroring a file reads its pages in from the file, and writes
+/// the dirty ones back on [`sync`](Self::sync) and when it goes away.
+#[derive(Default)]
+pub struct SharedMemory {
+    pages: SleepMutex<BTreeMap<usize, SharedPage>>,
+    file: Option<Arc<dyn BackingFile>>,
+}
+
+impl SharedMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Memory mirroring `file`, page `n` of the object being page `n` of
+    /// the file.
+    pub fn with_file(file: Arc<dyn BackingFile>) -> Self {
+        Self {
+            pages: SleepMutex::new(BTreeMap::new()),
+            file: Some(file),
+        }
+    }
+
+    /// The frame holding page `index` of the object.
+    fn frame(&self, index: usize) -> MmResult<PhysPageNum> {
+        let mut pages = self.pages.lock();
+        if let Some(page) = pages.get(&index) {
+            return Ok(page.frame);
+        }
+        let frame = frame::alloc().ok_or(MmError::OutOfMemory)?;
+        let file_len = match &self.file {
+            Some(file) => file.read_at(index * PAGE_SIZE, unsafe { frame.addr().as_mut_page_slice() }),
+            None => 0,
+        };
+        pages.insert(index, SharedPage { frame, file_len, dirty: false });
+        Ok(frame)
+    }
+
+    /// Note that page `index` is about to be written through a mapping.
+    ///
+    /// The page stays dirty from then on, as the mappings keep it writable.
+    fn mark_dirty(&self, index: usize) {
+        if let Some(page) = self.pages.lock().get_mut(&index) {
+            page.dirty = true;
+        }
+    }
+
+    /// Write the dirty pages of `[start, end)` back to the file, if there is one.
+    pub fn sync(&self, start: usize, end: usize) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        for (index, page) in self.pages.lock().range(start..end).filter(|(_, page)| page.dirty) {
+            let pa = page.frame.addr();
+            let data = unsafe { pa.as_slice(page.file_len) };
+            if file.write_at(index * PAGE_SIZE, data) != data.len() {
+                warn!("Failed to write back page {} of a shared mapping", index);
+            }
+        }
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        self.sync(0, usize::MAX);
+        for page in self.pages.get_mut().values() {
+            frame::dealloc(page.frame);
+        }
+    }
+}
+
+/// What the pages of an area hold.
+#[derive(Clone)]
+pub enum Backing {
+    /// Private zero-filled memory.
+    Anonymous,
+    /// Private copy of a file, starting at byte `offset`.
+    File {
+        file: Arc<dyn BackingFile>,
+        offset: usize,
+    },
+    /// Memory shared with other areas, starting at byte `offset` of the object.
+    /// `writable` is false for a file opened read-only, whose mapping must
+    /// never become writable.
+    Shared {
+        memory: Arc<SharedMemory>,
+        offset: usize,
+        writable: bool,
+    },
+}
+
+impl Backing {
+    /// The same backing, `bytes` further in.
+    fn advance(&self, bytes: usize) -> Self {
+        match self {
+            Backing::Anonymous => Backing::Anonymous,
+            Backing::File { file, offset } => Backing::File {
+                file: file.clone(),
+                offset: offset + bytes,
+            },
+            Backing::Shared { memory, offset, writable } => Backing::Shared {
+                memory: memory.clone(),
+                offset: offset + bytes,
+                writable: *writable,
+            },
+        }
+    }
+
+    /// Whether mapped frames belong to the area and are freed with it.
+    fn owns_frames(&self) -> bool {
+        matches!(self, Backing::Anonymous | Backing::File { .. })
+    }
+}
+
+/// A range of virtual memory with uniform permissions and backing.
+#[derive(Clone)]
+pub struct VmArea {
+    start: VirtAddr,
+    end: VirtAddr,
+    perm: MapPerm,
+    backing: Backing,
+    /// Pages are mapped on first access instead of when the area is inserted.
+    lazy: bool,
+}
+
+impl VmArea {
+    pub fn new(start: VirtAddr, end: VirtAddr, perm: MapPerm, backing: Backing, lazy: bool) -> Self {
+        debug_assert!(start < end);
+        Self {
+            start,
+            end,
+            perm,
+            backing,
+            lazy,
+        }
+    }
+
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    pub fn end(&self) -> VirtAddr {
+        self.end
+    }
+
+    pub fn size(&self) -> usize {
+        self.end.0 - self.start.0
+    }
+
+    pub fn perm(&self) -> MapPerm {
+        self.perm
+    }
+
+    /// Move the end of the area to `end`. Pages of a lazy area past the old
+    /// end are mapped on first access, otherwise the caller maps them.
+    pub fn set_end(&mut self, end: VirtAddr) {
+        debug_assert!(self.start < end && end.offset() == 0);
+        self.end = end;
+    }
+
+    /// The same area at `start`, for moving its pages there.
+    pub fn moved_to(&self, start: VirtAddr) -> VmArea {
+        VmArea {
+            start,
+            end: start + self.size(),
+            ..self.clone()
+        }
+    }
+
+    pub fn set_perm(&mut self, perm: MapPerm) {
+        self.perm = perm;
+    }
+
+    /// Whether writes to the pages are tracked, so that only pages written
+    /// to go back to the file. Such pages are mapped read-only until the
+    /// first write fault.
+    pub fn tracks_writes(&self) -> bool {
+        matches!(&self.backing, Backing::Shared { memory, .. } if memory.file.is_some())
+    }
+
+    /// The flags the pages of the area are mapped with.
+    pub fn pte_flags(&self) -> PteFlags {
+        if self.tracks_writes() {
+            (self.perm - MapPerm::W).into()
+        } else {
+            self.perm.into()
+        }
+    }
+
+    /// Whether the area may be given write permission.
+    pub fn may_write(&self) -> bool {
+        !matches!(self.backing, Backing::Shared { writable: false, .. })
+    }
+
+    pub fn backing(&self) -> &Backing {
+        &self.backing
+    }
+
+    pub fn is_lazy(&self) -> bool {
+        self.lazy
+    }
+
+    pub fn contains(&self, va: VirtAddr) -> bool {
+        self.start <= va && va < self.end
+    }
+
+    fn is_aligned(&self) -> bool {
+        self.start.offset() == 0 && self.end.offset() == 0
+    }
+
+    /// Split the area at `va`. `self` keeps `[start, va)` and the rest is returned.
+    pub fn split_off(&mut self, va: VirtAddr) -> VmArea {
+        debug_assert!(self.start < va && va < self.end && va.offset() == 0);
+        let tail = VmArea {
+            start: va,
+            end: self.end,
+            perm: self.perm,
+            backing: self.backing.advance(va.0 - self.start.0),
+            lazy: self.lazy,
+        };
+        self.end = va;
+        tail
+    }
+
+    /// Map the page containing `va`.
+    pub fn populate(&self, page_table: &mut PageTable, va: VirtAddr) -> MmResult<()> {
+        debug_assert!(self.contains(va));
+        let va = va.floor();
+        let offset = va.0 - self.start.0;
+        let pa = match &self.backing {
+            Backing::Anonymous => frame::alloc().ok_or(MmError::OutOfMemory)?.addr(),
+            Backing::File { file, offset: file_offset } => {
+                let pa = frame::alloc().ok_or(MmError::OutOfMemory)?.addr();
+                file.read_at(file_offset + offset, unsafe { pa.as_mut_page_slice() });
+                pa
+            }
+            Backing::Shared { memory, offset: base, .. } => memory.frame((base + offset) / PAGE_SIZE)?.addr(),
+        };
+        page_table.map_page(va, pa, self.pte_flags()).inspect_err(|_| {
+            if self.backing.owns_frames() {
+                frame::dealloc(pa.into());
+            }
+        })
+    }
+
+    /// Make the page at `va` of an area that [tracks writes](Self::tracks_writes)
+    /// writable on its first write, marking it dirty.
+    pub fn mark_written(&self, page_table: &mut PageTable, va: VirtAddr) -> MmResult<()> {
+        debug_assert!(self.contains(va) && self.perm.contains(MapPerm::W));
+        let va = va.floor();
+        if let Backing::Shared { memory, offset, .. } = &self.backing {
+            memory.mark_dirty((offset + (va.0 - self.start.0)) / PAGE_SIZE);
+        }
+        page_table.protect_region(va, PAGE_SIZE, self.perm.into())
+    }
+
+    /// Map every page of the area. On failure nothing stays mapped.
+    pub fn map(&self, page_table: &mut PageTable) -> MmResult<()> {
+        if !self.is_aligned() {
+            return Err(MmError::Misaligned);
+        }
+        let mut va = self.start;
+        while va < self.end {
+            if let Err(e) = self.populate(page_table, va) {
+                self.unmap_range(page_table, self.start, va)?;
+                return Err(e);
+            }
+            va += PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Unmap the whole area. Pages that were never populated are skipped.
+    pub fn unmap(&self, page_table: &mut PageTable) -> MmResult<()> {
+        self.unmap_range(page_table, self.start, self.end)
+    }
+
+    /// Unmap the pages of `[start, end)`, which must lie inside the area.
+    pub fn unmap_range(&self, page_table: &mut PageTable, start: VirtAddr, end: VirtAddr) -> MmResult<()> {
+        page_table.unmap_region(start, end.0 - start.0, self.backing.owns_frames())
+    }
+
+    /// Unmap the pages of `[start, end)` like [`Self::unmap_range`], adding
+    /// the frames the area owns to `unmapped` for the caller to release.
+    pub fn unmap_range_deferred(
+        &self,
+        page_table: &mut PageTable,
+        start: VirtAddr,
+        end: VirtAddr,
+        unmapped: &mut Vec<(PhysPageNum, usize)>,
+    ) -> MmResult<()> {
+        if self.backing.owns_frames() {
+            page_table.unmap_region_deferred(start, end.0 - start.0, unmapped)
+        } else {
+            page_table.unmap_region(start, end.0 - start.0, false)
+        }
+    }
+}
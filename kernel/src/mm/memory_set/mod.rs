@@ -0,0 +1,571 @@
+//! User address spaces.
+//!
+//! A [`MemorySet`] owns the page table of a process and the areas mapped in
+//! its user half, ordered by start address. Areas never overlap.
+
+mod area;
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use log::warn;
+
+use crate::{
+    arch,
+    config::{USER_MMAP_BASE, USER_SPACE_END},
+    cpu,
+    sched::{SleepMutex, SleepMutexGuard},
+};
+
+pub use self::area::{Backing, BackingFile, MapPerm, SharedMemory, VmArea};
+
+use super::{
+    addr::{PhysAddr, PhysPageNum, VirtAddr},
+    consts::PAGE_SIZE,
+    frame,
+    paging::{pagetable::PageTable, pte::PageTableEntry},
+    MmError, MmResult,
+};
+
+/// A memory set shared by the threads of a process, with the `satp` of its
+/// page table, which never changes, at hand without taking the lock.
+///
+/// The lock sleeps, as faulting pages in and writing them back may wait for
+/// their file.
+pub struct AddressSpace {
+    satp: usize,
+    memory_set: SleepMutex<MemorySet>,
+}
+
+impl AddressSpace {
+    pub fn new(memory_set: MemorySet) -> Self {
+        Self {
+            satp: memory_set.satp(),
+            memory_set: SleepMutex::new(memory_set),
+        }
+    }
+
+    pub fn lock(&self) -> SleepMutexGuard<'_, MemorySet> {
+        self.memory_set.lock()
+    }
+
+    pub fn satp(&self) -> usize {
+        self.satp
+    }
+}
+
+pub struct MemorySet {
+    page_table: PageTable,
+    areas: BTreeMap<VirtAddr, VmArea>,
+    /// Where the heap starts, right after the loaded image.
+    brk_start: VirtAddr,
+    /// The current end of the heap, as `brk` reports it.
+    brk: VirtAddr,
+}
+
+impl MemorySet {
+    /// An empty address space, with only the kernel mapped.
+    pub fn new() -> MmResult<Self> {
+        Ok(Self {
+            page_table: PageTable::new_user()?,
+            areas: BTreeMap::new(),
+            brk_start: VirtAddr(0),
+            brk: VirtAddr(0),
+        })
+    }
+
+    pub fn root_pa(&self) -> PhysAddr {
+        self.page_table.root_pa()
+    }
+
+    /// The `satp` value selecting this address space.
+    pub fn satp(&self) -> usize {
+        arch::satp_of(self.root_pa().0)
+    }
+
+    /// The area containing `va`.
+    pub fn find_area(&self, va: VirtAddr) -> Option<&VmArea> {
+        area_at(&self.areas, va)
+    }
+
+    /// Whether `[start, end)` overlaps no area.
+    pub fn is_free(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        self.areas
+            .range(..end)
+            .next_back()
+            .is_none_or(|(_, area)| area.end() <= start)
+    }
+
+    /// Add `area`, mapping it right away unless it is lazy.
+    pub fn insert(&mut self, area: VmArea) -> MmResult<()> {
+        if area.start().offset() != 0 || area.end().offset() != 0 {
+            return Err(MmError::Misaligned);
+        }
+        if area.end().0 > USER_SPACE_END || !self.is_free(area.start(), area.end()) {
+            return Err(MmError::AlreadyMapped);
+        }
+        if !area.is_lazy() {
+            area.map(&mut self.page_table)?;
+        }
+        self.areas.insert(area.start(), area);
+        Ok(())
+    }
+
+    /// Unmap `[start, start + len)`. Areas partially inside the range are
+    /// split and keep their outside parts.
+    pub fn remove(&mut self, start: VirtAddr, len: usize) -> MmResult<()> {
+        if start.offset() != 0 {
+            return Err(MmError::Misaligned);
+        }
+        let end = (start + len).ceil();
+        let mut unmapped = Vec::new();
+        let mut result = Ok(());
+        while let Some(key) = self
+            .areas
+            .range(..end)
+            .next_back()
+            .filter(|(_, area)| area.end() > start)
+            .map(|(key, _)| *key)
+        {
+            let mut area = self.areas.remove(&key).unwrap();
+            if area.end() > end {
+                let tail = area.split_off(end);
+                self.areas.insert(tail.start(), tail);
+            }
+            if area.start() < start {
+                let middle = area.split_off(start);
+                self.areas.insert(area.start(), area);
+                area = middle;
+            }
+            result = area.unmap_range_deferred(&mut self.page_table, area.start(), area.end(), &mut unmapped);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.flush_others(start, end.0 - start.0);
+        release(unmapped);
+        result
+    }
+
+    /// Change the permissions of `[start, start + len)` to `perm`, splitting
+    /// areas partially inside it. Fails without changing anything if part of
+    /// the range is not mapped, or may not be made writable.
+    pub fn protect(&mut self, start: VirtAddr, len: usize, perm: MapPerm) -> MmResult<()> {
+        if start.offset() != 0 {
+            return Err(MmError::Misaligned);
+        }
+        let end = (start + len).ceil();
+        if !self.is_covered(start, end) {
+            return Err(MmError::NotMapped);
+        }
+        if perm.contains(MapPerm::W)
+            && self
+                .areas
+                .range(..end)
+                .map(|(_, area)| area)
+                .any(|area| area.end() > start && !area.may_write())
+        {
+            return Err(MmError::PermissionDenied);
+        }
+        self.split_at(start);
+        self.split_at(end);
+        for area in self.areas.range_mut(start..end).map(|(_, area)| area) {
+            area.set_perm(perm);
+            self.page_table.protect_region(area.start(), area.size(), area.pte_flags())?;
+        }
+        self.flush_others(start, end.0 - start.0);
+        Ok(())
+    }
+
+    /// Whether every page of `[start, end)` lies in some area.
+    fn is_covered(&self, start: VirtAddr, end: VirtAddr) -> bool {
+        let mut covered = start;
+        for area in self.areas.range(..end).map(|(_, area)| area) {
+            if area.end() <= covered {
+                continue;
+            }
+            if area.start() > covered {
+                return false;
+            }
+            covered = area.end();
+        }
+        covered >= end
+    }
+
+    /// Split the area containing `va`, if any, so that one starts at `va`.
+    fn split_at(&mut self, va: VirtAddr) {
+        let Some(area) = self
+            .areas
+            .range_mut(..va)
+            .next_back()
+            .map(|(_, area)| area)
+            .filter(|area| area.contains(va))
+        else {
+            return;
+        };
+        let tail = area.split_off(va);
+        self.areas.insert(va, tail);
+    }
+
+    /// Grow the area starting at `start` so that it ends at `end`. Fails if
+    /// the space it grows into is not free.
+    pub fn grow_area(&mut self, start: VirtAddr, end: VirtAddr) -> MmResult<()> {
+        if end.offset() != 0 {
+            return Err(MmError::Misaligned);
+        }
+        let area = self.areas.get(&start).ok_or(MmError::NotMapped)?;
+        let old_end = area.end();
+        if end <= old_end {
+            return Ok(());
+        }
+        if end.0 > USER_SPACE_END || !self.is_free(old_end, end) {
+            return Err(MmError::AlreadyMapped);
+        }
+        let area = self.areas.get_mut(&start).unwrap();
+        area.set_end(end);
+        if area.is_lazy() {
+            return Ok(());
+        }
+        let mut va = old_end;
+        while va < end {
+            if let Err(e) = area.populate(&mut self.page_table, va) {
+                area.unmap_range(&mut self.page_table, old_end, va)?;
+                area.set_end(old_end);
+                return Err(e);
+            }
+            va += PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Move the pages of `[start, start + len)` to `to`, which must be free.
+    /// The range must be exactly one area after splitting, as `mremap`
+    /// requires.
+    pub fn move_range(&mut self, start: VirtAddr, len: usize, to: VirtAddr) -> MmResult<()> {
+        if start.offset() != 0 || to.offset() != 0 {
+            return Err(MmError::Misaligned);
+        }
+        let end = (start + len).ceil();
+        let size = end.0 - start.0;
+        if to.0 + size > USER_SPACE_END || !self.is_free(to, to + size) {
+            return Err(MmError::AlreadyMapped);
+        }
+        match area_at(&self.areas, start) {
+            Some(area) if area.end() >= end => {}
+            _ => return Err(MmError::NotMapped),
+        }
+        self.split_at(start);
+        self.split_at(end);
+        let area = self.areas.remove(&start).unwrap();
+        // Every page leaves the old range, and the other harts forget it,
+        // before any appears in the new one.
+        let mut pages = Vec::new();
+        let mut offset = 0;
+        while offset < size {
+            if let Ok((entry, _)) = self.page_table.query_entry(start + offset) {
+                let pa = self.page_table.unmap_page(start + offset, false)?;
+                pages.push((offset, pa, entry.flags()));
+            }
+            offset += PAGE_SIZE;
+        }
+        self.flush_others(start, size);
+        for (offset, pa, flags) in pages {
+            self.page_table.map_page(to + offset, pa, flags)?;
+        }
+        self.areas.insert(to, area.moved_to(to));
+        Ok(())
+    }
+
+    /// Drop the pages of `[start, start + len)`, as `MADV_DONTNEED` does.
+    ///
+    /// The areas stay. Touching the pages again maps zeroes, or the contents
+    /// of the file or shared memory behind them.
+    pub fn discard(&mut self, start: VirtAddr, len: usize) -> MmResult<()> {
+        if start.offset() != 0 {
+            return Err(MmError::Misaligned);
+        }
+        let end = (start + len).ceil();
+        if !self.is_covered(start, end) {
+            return Err(MmError::NotMapped);
+        }
+        let mut unmapped = Vec::new();
+        let mut result = Ok(());
+        for area in self
+            .areas
+            .range(..end)
+            .map(|(_, area)| area)
+            .filter(|area| area.end() > start)
+        {
+            let (first, last) = (area.start().max(start), area.end().min(end));
+            result = area.unmap_range_deferred(&mut self.page_table, first, last, &mut unmapped);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.flush_others(start, end.0 - start.0);
+        release(unmapped);
+        result
+    }
+
+    /// Write the shared file mappings of `[start, start + len)` back to their
+    /// files.
+    pub fn sync(&self, start: VirtAddr, len: usize) -> MmResult<()> {
+        if start.offset() != 0 {
+            return Err(MmError::Misaligned);
+        }
+        let end = (start + len).ceil();
+        if !self.is_covered(start, end) {
+            return Err(MmError::NotMapped);
+        }
+        for area in self
+            .areas
+            .range(..end)
+            .map(|(_, area)| area)
+            .filter(|area| area.end() > start)
+        {
+            if let Backing::Shared { memory, offset, .. } = area.backing() {
+                let first = offset + (area.start().max(start).0 - area.start().0);
+                let last = offset + (area.end().min(end).0 - area.start().0);
+                memory.sync(first / PAGE_SIZE, last / PAGE_SIZE);
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the heap at `end`, the end of the loaded image.
+    pub fn init_brk(&mut self, end: VirtAddr) {
+        self.brk_start = end;
+        self.brk = end;
+    }
+
+    pub fn brk(&self) -> VirtAddr {
+        self.brk
+    }
+
+    /// Move the end of the heap to `brk`. The heap never shrinks below its
+    /// start, and pages are mapped on first touch.
+    pub fn set_brk(&mut self, brk: VirtAddr) -> MmResult<()> {
+        if brk < self.brk_start {
+            return Err(MmError::Misaligned);
+        }
+        let heap = self.brk_start.ceil();
+        let (old_end, new_end) = (self.brk.ceil(), brk.ceil());
+        if new_end > old_end {
+            if old_end == heap {
+                let area = VmArea::new(heap, new_end, MapPerm::R | MapPerm::W | MapPerm::U, Backing::Anonymous, true);
+                self.insert(area)?;
+            } else {
+                self.grow_area(heap, new_end)?;
+            }
+        } else if new_end < old_end {
+            self.remove(new_end, old_end.0 - new_end.0)?;
+        }
+        self.brk = brk;
+        Ok(())
+    }
+
+    /// Flush `[start, start + size)` from the TLBs of other harts running
+    /// this address space, after mappings there lost permissions or went
+    /// away. Harts switching to it later flush on the way to user mode.
+    fn flush_others(&self, start: VirtAddr, size: usize) {
+        arch::flush_tlb_remote(cpu::harts_on_satp(self.satp()), start.0, size);
+    }
+
+    /// Find `len` bytes of unmapped user space, at `hint` if it is free,
+    /// else anywhere above [`USER_MMAP_BASE`].
+    pub fn find_free_range(&self, len: usize, hint: Option<VirtAddr>) -> Option<VirtAddr> {
+        let len = (VirtAddr(0) + len).ceil().0;
+        if len == 0 {
+            return None;
+        }
+        if let Some(hint) = hint.map(VirtAddr::floor) {
+            if hint.0 != 0 && hint.0 + len <= USER_SPACE_END && self.is_free(hint, hint + len) {
+                return Some(hint);
+            }
+        }
+        let mut candidate = VirtAddr(USER_MMAP_BASE);
+        for area in self.areas.values() {
+            if area.end() <= candidate {
+                continue;
+            }
+            if area.start().0 >= candidate.0 + len {
+                break;
+            }
+            candidate = area.end();
+        }
+        (candidate.0 + len <= USER_SPACE_END).then_some(candidate)
+    }
+
+    /// Duplicate this address space for a child process.
+    ///
+    /// Private pages are shared copy-on-write, shared areas map the same
+    /// memory in both.
+    pub fn fork(&mut self) -> MmResult<Self> {
+        let mut child = Self::new()?;
+        // Take the areas first, so a failure below unmaps what was shared so far.
+        child.areas = self.areas.clone();
+        let areas = &self.areas;
+        let cow = |va| {
+            area_at(areas, va)
+                .is_none_or(|area| !matches!(area.backing(), Backing::Shared { .. }))
+        };
+        self.page_table
+            .copy_table_and_mark_self_cow(&mut child.page_table, cow)?;
+        // Pages that just became copy-on-write must not stay writable
+        // through other harts' TLBs.
+        self.flush_others(VirtAddr(0), USER_SPACE_END);
+        child.brk_start = self.brk_start;
+        child.brk = self.brk;
+        Ok(child)
+    }
+
+    /// Resolve a page fault at `va` caused by an `access`.
+    ///
+    /// Pages of lazy areas are populated on first touch, copy-on-write
+    /// pages get a frame of their own on the first write and pages of shared
+    /// file mappings are marked dirty on it. Fails if no area
+    /// allows the access.
+    pub fn handle_page_fault(&mut self, va: VirtAddr, access: Access) -> MmResult<()> {
+        // Borrow only the areas, the page table is modified below.
+        let area = area_at(&self.areas, va).ok_or(MmError::NotMapped)?;
+        if !area.perm().contains(access.required_perm()) {
+            return Err(MmError::PermissionDenied);
+        }
+        match self.page_table.query_entry(va) {
+            Err(MmError::NotMapped) => area.populate(&mut self.page_table, va),
+            Err(e) => Err(e),
+            Ok((entry, _)) if access == Access::Write && entry.is_shared() => {
+                self.page_table.unshare_page(va.floor())?;
+                // Other threads must not keep reading the frame left behind.
+                self.flush_others(va.floor(), PAGE_SIZE);
+                Ok(())
+            }
+            Ok((entry, _)) if access == Access::Write && !entry.is_writable() && area.tracks_writes() => {
+                area.mark_written(&mut self.page_table, va)
+            }
+            Ok(_) => {
+                // Already resolved by another hart, or a stale TLB entry.
+                arch::flush_tlb(va.0);
+                Ok(())
+            }
+        }
+    }
+
+    /// The physical address of user address `va`, faulting the page in first
+    /// if its mapping does not allow `access` yet.
+    pub fn translate(&mut self, va: VirtAddr, access: Access) -> MmResult<PhysAddr> {
+        match self.page_table.query_entry(va) {
+            Ok((entry, _)) if entry.is_user() && access.allowed_by(&entry) => {}
+            _ => self.handle_page_fault(va, access)?,
+        }
+        self.page_table.query(va)
+    }
+
+    /// Copy `data` to `va` regardless of the permissions of the areas there,
+    /// to set up an address space before it runs.
+    pub fn fill(&mut self, va: VirtAddr, data: &[u8]) -> MmResult<()> {
+        let mut done = 0;
+        while done < data.len() {
+            let page = (va + done).floor();
+            match self.page_table.query_entry(page) {
+                Ok((entry, _)) if entry.is_shared() => self.page_table.unshare_page(page)?,
+                Ok(_) => {}
+                Err(MmError::NotMapped) => {
+                    let area = area_at(&self.areas, page).ok_or(MmError::NotMapped)?;
+                    area.populate(&mut self.page_table, page)?;
+                }
+                Err(e) => return Err(e),
+            }
+            let pa = self.page_table.query(va + done)?;
+            let len = (PAGE_SIZE - pa.offset()).min(data.len() - done);
+            unsafe { pa.as_mut_slice(len) }.copy_from_slice(&data[done..done + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Copy user memory at `va` into `buf`.
+    pub fn read_bytes(&mut self, va: VirtAddr, buf: &mut [u8]) -> MmResult<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pa = self.translate(va + done, Access::Read)?;
+            let len = (PAGE_SIZE - pa.offset()).min(buf.len() - done);
+            buf[done..done + len].copy_from_slice(unsafe { pa.as_slice(len) });
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Copy `buf` into user memory at `va`.
+    pub fn write_bytes(&mut self, va: VirtAddr, buf: &[u8]) -> MmResult<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let pa = self.translate(va + done, Access::Write)?;
+            let len = (PAGE_SIZE - pa.offset()).min(buf.len() - done);
+            unsafe { pa.as_mut_slice(len) }.copy_from_slice(&buf[done..done + len]);
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+/// Release frames unmapped with [`VmArea::unmap_range_deferred`], once the
+/// other harts flushed them from their TLBs.
+fn release(unmapped: Vec<(PhysPageNum, usize)>) {
+    for (ppn, frames) in unmapped {
+        frame::release(ppn, frames);
+    }
+}
+
+/// The area of `areas` containing `va`.
+fn area_at(areas: &BTreeMap<VirtAddr, VmArea>, va: VirtAddr) -> Option<&VmArea> {
+    areas
+        .range(..=va)
+        .next_back()
+        .map(|(_, area)| area)
+        .filter(|area| area.contains(va))
+}
+
+impl Drop for MemorySet {
+    fn drop(&mut self) {
+        for area in self.areas.values() {
+            if let Err(e) = area.unmap(&mut self.page_table) {
+                warn!("Failed to unmap {} - {}: {}", area.start(), area.end(), e);
+            }
+        }
+    }
+}
+
+/// The kind of access that caused a page fault.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    fn required_perm(self) -> MapPerm {
+        match self {
+            Access::Read => MapPerm::R,
+            Access::Write => MapPerm::W,
+            Access::Execute => MapPerm::X,
+        }
+    }
+
+    fn allowed_by(self, entry: &PageTableEntry) -> bool {
+        match self {
+            Access::Read => entry.is_readable(),
+            Access::Write => entry.is_writable(),
+            Access::Execute => entry.is_executable(),
+        }
+    }
+}
+
+/// The user address space of the current hart.
+pub fn current() -> Option<Arc<AddressSpace>> {
+    cpu::current().memory_set()
+}
+
+/// Resolve a page fault at `va` in the current address space.
+pub fn handle_page_fault(va: VirtAddr, access: Access) -> MmResult<()> {
+    current().ok_or(MmError::NotMapped)?.lock().handle_page_fault(va, access)
+}
@@ -1,17 +1,23 @@
 use core::ptr::addr_of;
 
 use alloc::vec::Vec;
-use log::{debug, info};
+use fdt::MemRegion;
+use log::{debug, warn};
 
-use crate::config::{PHYSICAL_MEMORY_END, PHYSICAL_MEMORY_START};
+use crate::{config::PHYSICAL_MEMORY_START, dtb};
 
 use self::addr::{kva2pa, PhysAddr, VirtAddr};
 
+pub use self::error::{MmError, MmResult};
+
 pub mod addr;
 pub mod consts;
-mod frame;
-mod heap;
+mod error;
+pub mod frame;
+pub mod heap;
+pub mod kernel_space;
 pub mod layout;
+pub mod memory_set;
 mod paging;
 
 extern "C" {
@@ -21,14 +27,60 @@ extern "C" {
 pub fn init() {
     heap::init();
     test_heap();
-    frame::init(
-        kva2pa(VirtAddr(unsafe { addr_of!(__kernel_end) as usize })),
-        PhysAddr(PHYSICAL_MEMORY_END),
-    );
+    init_frame_allocator();
     layout::print_memory_layout();
-    paging::pagetable::map_kernel_phys_seg();
-    info!("Physical memory mapped at 0x{:x}", PHYSICAL_MEMORY_START);
+    kernel_space::init();
+}
+
+/// Give every usable physical frame to the frame allocator.
+///
+/// Usable memory is what the device tree reports, minus its reserved ranges
+/// and everything from the start of RAM up to the end of the kernel image,
+/// which holds the firmware and the kernel itself. Reference counts are
+/// then kept for all of memory.
+fn init_frame_allocator() {
+    let machine = dtb::machine();
+    let kernel_end = kva2pa(VirtAddr(unsafe { addr_of!(__kernel_end) as usize }));
+    let firmware_and_kernel = MemRegion {
+        start: PHYSICAL_MEMORY_START,
+        size: kernel_end.0 - PHYSICAL_MEMORY_START,
+    };
+
+    let memory_end = addr::set_direct_map_end(PhysAddr(machine.memory_end())).0;
+    if memory_end < machine.memory_end() {
+        warn!(
+            "Physical memory above 0x{:x} does not fit in the direct map and is ignored.",
+            memory_end
+        );
+    }
+    for region in machine.memory() {
+        let region = MemRegion {
+            start: region.start,
+            size: region.end().min(memory_end).saturating_sub(region.start),
+        };
+        let mut current = region.start;
+        let reserved = core::iter::once(&firmware_and_kernel).chain(machine.reserved());
+        for r in reserved {
+            if r.end() <= current || r.start >= region.end() {
+                continue;
+            }
+            if r.start > current {
+                frame::add_range(PhysAddr(current), PhysAddr(r.start));
+            }
+            current = current.max(r.end());
+        }
+        if current < region.end() {
+            frame::add_range(PhysAddr(current), PhysAddr(region.end()));
+        }
+    }
+    let memory_start = machine.memory().first().map_or(memory_end, |region| region.start);
+    frame::init_refs(PhysAddr(memory_start), PhysAddr(memory_end));
+    frame::print_summary();
+}
 
+/// Memory setup for secondary harts: switch to the kernel page table.
+pub fn init_hart() {
+    kernel_space::activate();
 }
 
 fn test_heap() {
@@ -3,50 +3,94 @@ use alloc::vec::Vec;
 use log::trace;
 
 use crate::{
-    arch, boot::{self, boot_page_table_pa, boot_pagetable}, config::{PHYSICAL_MEMORY_END, PHYSICAL_MEMORY_START}, mm::{addr::{kva2pa, pa2kva, PhysAddr, VirtAddr, VirtPageNum}, consts::{HUGE_PAGE_SIZE, PAGE_SIZE}, frame, layout::K_SEG_PHY_MEM_BEG}
+    arch, round_down,
+    mm::{
+        addr::{pa2kva, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum},
+        consts::{HUGE_PAGE_SIZE, MEGA_PAGE_SIZE, PAGE_SIZE},
+        frame, kernel_space, MmError, MmResult,
+    },
 };
 use super::pte::{PageTableEntry, PteFlags};
 
 use crate::mm::consts::PAGE_TABLE_ENTRY_COUNT as ENTRY_COUNT;
 
+/// First root entry of the upper half of the address space, owned by the kernel.
+const KERNEL_ROOT_INDEX: usize = ENTRY_COUNT / 2;
+
+/// Number of levels of an Sv39 page table.
+const LEVELS: usize = 3;
+
 impl VirtAddr {
-    fn p4_index(self) -> usize {
-        (self.0 >> (12 + 27)) & (ENTRY_COUNT - 1)
+    /// Index into the table at `level`, where level 0 holds 4 KiB leaves.
+    fn table_index(self, level: usize) -> usize {
+        (self.0 >> (12 + 9 * level)) & (ENTRY_COUNT - 1)
     }
+}
 
-    fn p3_index(self) -> usize {
-        (self.0 >> (12 + 18)) & (ENTRY_COUNT - 1)
+/// Size of a leaf mapping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PageSize {
+    Size4K,
+    Size2M,
+    Size1G,
+}
+
+impl PageSize {
+    pub const fn bytes(self) -> usize {
+        match self {
+            PageSize::Size4K => PAGE_SIZE,
+            PageSize::Size2M => MEGA_PAGE_SIZE,
+            PageSize::Size1G => HUGE_PAGE_SIZE,
+        }
     }
 
-    fn p2_index(self) -> usize {
-        (self.0 >> (12 + 9)) & (ENTRY_COUNT - 1)
+    /// Number of 4 KiB frames covered.
+    pub const fn frames(self) -> usize {
+        self.bytes() / PAGE_SIZE
     }
 
-    fn p1_index(self) -> usize {
-        (self.0 >> 12) & (ENTRY_COUNT - 1)
+    /// Level of the table holding leaves of this size.
+    const fn level(self) -> usize {
+        match self {
+            PageSize::Size4K => 0,
+            PageSize::Size2M => 1,
+            PageSize::Size1G => 2,
+        }
     }
 
-}
+    const fn from_level(level: usize) -> Self {
+        match level {
+            0 => PageSize::Size4K,
+            1 => PageSize::Size2M,
+            _ => PageSize::Size1G,
+        }
+    }
 
-pub fn enable_boot_pagetable() {
-    let boot_pagetable = boot_page_table_pa().0;
-    arch::switch_page_table(boot_pagetable);
-}
+    fn smaller(self) -> Option<Self> {
+        match self {
+            PageSize::Size4K => None,
+            PageSize::Size2M => Some(PageSize::Size4K),
+            PageSize::Size1G => Some(PageSize::Size2M),
+        }
+    }
 
-pub fn unmap_boot_seg() {
-    let boot_pagetable = boot::boot_pagetable();
-    boot_pagetable[0] = 0;
-    boot_pagetable[2] = 0;
+    /// The largest page that can map `va` to `pa` within `remaining` bytes.
+    fn fitting(va: VirtAddr, pa: PhysAddr, remaining: usize) -> Self {
+        [PageSize::Size1G, PageSize::Size2M]
+            .into_iter()
+            .find(|size| {
+                let bytes = size.bytes();
+                va.0 % bytes == 0 && pa.0 % bytes == 0 && remaining >= bytes
+            })
+            .unwrap_or(PageSize::Size4K)
+    }
 }
 
-pub fn map_kernel_phys_seg() {
-    let boot_pagetable = boot::boot_pagetable();
-
-    for i in (0..PHYSICAL_MEMORY_END).step_by(HUGE_PAGE_SIZE) {
-        let pa: usize = i + PHYSICAL_MEMORY_START;
-        let va = VirtAddr::from(i + K_SEG_PHY_MEM_BEG);
-        boot_pagetable[va.p3_index()] = (pa >> 2) | 0xcf;
-    }
+/// Result of walking the table for a virtual address.
+enum Walk<'a> {
+    Leaf(&'a mut PageTableEntry, PageSize),
+    /// Nothing is mapped in the aligned block of this size around the address.
+    Hole(PageSize),
 }
 
 pub struct PageTable {
@@ -55,24 +99,24 @@ pub struct PageTable {
 }
 
 impl PageTable {
-    pub fn new() -> Self {
-        let root_pa = frame::alloc().unwrap().into();
-        Self {
+    pub fn new() -> MmResult<Self> {
+        let root_pa = frame::alloc().ok_or(MmError::OutOfMemory)?.into();
+        Ok(Self {
             root_pa,
             intrm_tables: vec![root_pa]
-        }
+        })
     }
 
-    pub fn new_from_boot_table() -> Self {
-        let root_pa = boot_page_table_pa();
-        let boot_root_pa: PhysAddr = boot::boot_page_table_pa();
-
-        unsafe { root_pa.as_mut_page_slice().copy_from_slice(boot_root_pa.as_page_slice()) }
-
-        Self {
-            root_pa,
-            intrm_tables: vec![root_pa]
-        }
+    /// Create a page table for a user address space.
+    ///
+    /// The kernel half of the root table is copied from the kernel page
+    /// table, so the kernel stays mapped and the lower level tables are shared.
+    pub fn new_user() -> MmResult<Self> {
+        let page_table = Self::new()?;
+        let kernel_root = kernel_space::root_pa();
+        let root = page_table.table_of_mut(page_table.root_pa);
+        root[KERNEL_ROOT_INDEX..].copy_from_slice(&page_table.table_of(kernel_root)[KERNEL_ROOT_INDEX..]);
+        Ok(page_table)
     }
 
     pub fn new_with_pa(pa: PhysAddr) -> Self {
@@ -81,29 +125,50 @@ impl PageTable {
             intrm_tables: vec![pa]
         }
     }
-    
+
     pub const fn root_pa(&self) -> PhysAddr {
         self.root_pa
     }
 
-    pub fn map_page(&mut self, va: VirtAddr, pa: PhysAddr, perm: PteFlags) {
-        let pte = PageTableEntry::new(pa, perm | PteFlags::V);
-        let entry = self.get_entry_mut_or_create(va);
-        *entry = pte;
+    pub fn map_page(&mut self, va: VirtAddr, pa: PhysAddr, perm: PteFlags) -> MmResult<()> {
+        self.map(va, pa, PageSize::Size4K, perm)
+    }
+
+    /// Map a single page of `size`. Both addresses must be aligned to it.
+    ///
+    /// Fails with [`MmError::AlreadyMapped`] if any part of the page is mapped.
+    /// A page `perm` allows no access to is mapped with V clear.
+    pub fn map(&mut self, va: VirtAddr, pa: PhysAddr, size: PageSize, perm: PteFlags) -> MmResult<()> {
+        if va.0 % size.bytes() != 0 || pa.0 % size.bytes() != 0 {
+            return Err(MmError::Misaligned);
+        }
+        let entry = self.get_entry_mut_or_create(va, size)?;
+        if entry.is_present() {
+            return Err(MmError::AlreadyMapped);
+        }
+        *entry = PageTableEntry::new(pa, perm.with_presence());
+        Ok(())
     }
 
-    pub fn unmap_page(&mut self, va: VirtAddr, dealloc: bool) -> PhysAddr {
-        let entry = self.get_entry_mut(va);
+    /// Unmap the page containing `va`, returning the physical address it was mapped to.
+    ///
+    /// A superpage is split first, so only the 4 KiB page at `va` goes away.
+    pub fn unmap_page(&mut self, va: VirtAddr, dealloc: bool) -> MmResult<PhysAddr> {
+        let entry = self.get_leaf_split(va, PageSize::Size4K)?;
         let pa = entry.pa();
         entry.clear();
         if dealloc {
-            frame::dealloc(pa.into());
+            frame::release(pa.into(), 1);
         }
-        pa
+        arch::flush_tlb(va.0);
+        Ok(pa)
     }
 
-
-    pub fn map_region(&mut self, va: VirtAddr, pa: PhysAddr, size: usize, perm: PteFlags) {
+    /// Map `[va, va + size)` to `[pa, pa + size)`, using the largest pages
+    /// alignment allows.
+    ///
+    /// On failure nothing of the range stays mapped.
+    pub fn map_region(&mut self, va: VirtAddr, pa: PhysAddr, size: usize, perm: PteFlags) -> MmResult<()> {
         trace!(
             "map_region: va: {}, pa: {}, size: {:#x}, perm: {:?}",
             va,
@@ -111,70 +176,200 @@ impl PageTable {
             size,
             perm
         );
-        for offset in (0..size).step_by(PAGE_SIZE) {
-            let va = va + offset;
-            let pa = pa + offset;
-            self.map_page(va, pa, perm);
+        if va.offset() != 0 || pa.offset() != 0 || size % PAGE_SIZE != 0 {
+            return Err(MmError::Misaligned);
+        }
+        let mut offset = 0;
+        while offset < size {
+            let page_va = va + offset;
+            let page_pa = pa + offset;
+            let mut page = PageSize::fitting(page_va, page_pa, size - offset);
+            // Do not cover a finer grained table that already exists.
+            while self.has_table_at(page_va, page) {
+                page = page.smaller().unwrap();
+            }
+            if let Err(e) = self.map(page_va, page_pa, page, perm) {
+                self.unmap_region(va, offset, false)?;
+                return Err(e);
+            }
+            offset += page.bytes();
         }
+        Ok(())
     }
 
-    pub fn unmap_region(&mut self, va: VirtAddr, size: usize, dealloc: bool) {
+    /// Unmap `[va, va + size)`. Superpages only partially inside the range
+    /// are split, holes in the range are skipped.
+    pub fn unmap_region(&mut self, va: VirtAddr, size: usize, dealloc: bool) -> MmResult<()> {
+        self.unmap_leaves(va, size, |ppn, frames| {
+            if dealloc {
+                frame::release(ppn, frames);
+            }
+        })
+    }
+
+    /// Unmap `[va, va + size)` like [`Self::unmap_region`], adding the frames
+    /// to `unmapped` instead of releasing them. They may be released once
+    /// no other hart can reach them through its TLB.
+    pub fn unmap_region_deferred(
+        &mut self,
+        va: VirtAddr,
+        size: usize,
+        unmapped: &mut Vec<(PhysPageNum, usize)>,
+    ) -> MmResult<()> {
+        self.unmap_leaves(va, size, |ppn, frames| unmapped.push((ppn, frames)))
+    }
+
+    fn unmap_leaves(
+        &mut self,
+        va: VirtAddr,
+        size: usize,
+        mut unmapped: impl FnMut(PhysPageNum, usize),
+    ) -> MmResult<()> {
         trace!("unmap_region: va: {}, size: {:#x}", va, size);
-        for offset in (0..size).step_by(PAGE_SIZE) {
-            let va = va + offset;
-            let pa = self.unmap_page(va, dealloc);
+        if va.offset() != 0 || size % PAGE_SIZE != 0 {
+            return Err(MmError::Misaligned);
         }
+        let end = va + size;
+        let mut va = va;
+        while va < end {
+            let (entry, page) = match self.walk(va) {
+                Walk::Leaf(entry, page) => (entry, page),
+                Walk::Hole(hole) => {
+                    va = VirtAddr(round_down!(va.0, hole.bytes()) + hole.bytes());
+                    continue;
+                }
+            };
+            if va.0 % page.bytes() != 0 || end.0 - va.0 < page.bytes() {
+                self.split(entry, page)?;
+                continue;
+            }
+            unmapped(entry.ppn(), page.frames());
+            entry.clear();
+            arch::flush_tlb(va.0);
+            va += page.bytes();
+        }
+        Ok(())
     }
-    
-    pub fn get_pte_copied_from_vpn(&mut self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.get_entry_mut_opt(vpn.into()).as_deref().copied()
+
+    /// Change the permissions of `[va, va + size)`, splitting superpages
+    /// that are only partially covered. Holes in the range are skipped.
+    ///
+    /// Copy-on-write pages stay read-only, they become writable when the
+    /// write fault gives them a frame of their own.
+    pub fn protect_region(&mut self, va: VirtAddr, size: usize, perm: PteFlags) -> MmResult<()> {
+        trace!("protect_region: va: {}, size: {:#x}, perm: {:?}", va, size, perm);
+        if va.offset() != 0 || size % PAGE_SIZE != 0 {
+            return Err(MmError::Misaligned);
+        }
+        let end = va + size;
+        let mut va = va;
+        while va < end {
+            let (entry, page) = match self.walk(va) {
+                Walk::Leaf(entry, page) => (entry, page),
+                Walk::Hole(hole) => {
+                    va = VirtAddr(round_down!(va.0, hole.bytes()) + hole.bytes());
+                    continue;
+                }
+            };
+            if va.0 % page.bytes() != 0 || end.0 - va.0 < page.bytes() {
+                self.split(entry, page)?;
+                continue;
+            }
+            if entry.is_shared() {
+                entry.set_perm(perm - PteFlags::W);
+            } else {
+                entry.set_perm(perm);
+            }
+            arch::flush_tlb(va.0);
+            va += page.bytes();
+        }
+        Ok(())
     }
 
-    pub fn query(&self, va: VirtAddr) -> PhysAddr {
-        self.get_entry_mut(va).pa() + va.offset()
+    pub fn get_pte_copied_from_vpn(&mut self, vpn: VirtPageNum) -> Option<PageTableEntry> {
+        self.find_leaf(vpn.into()).map(|(entry, _)| *entry)
     }
 
-        pub fn copy_table_and_mark_self_cow(&mut self, do_with_frame: impl Fn(PhysAddr)) -> Self {
-        let old = self;
-        let mut new = Self::new();
+    /// The physical address `va` is mapped to.
+    pub fn query(&self, va: VirtAddr) -> MmResult<PhysAddr> {
+        let (entry, page) = self.find_leaf(va).ok_or(MmError::NotMapped)?;
+        Ok(entry.pa() + (va.0 & (page.bytes() - 1)))
+    }
 
-        let op1_iter = old.table_of_mut(old.root_pa).iter_mut();
-        let np1_iter = new.table_of_mut(new.root_pa).iter_mut();
+    /// The leaf entry mapping `va` and the size of its page.
+    pub fn query_entry(&self, va: VirtAddr) -> MmResult<(PageTableEntry, PageSize)> {
+        self.find_leaf(va)
+            .map(|(entry, page)| (*entry, page))
+            .ok_or(MmError::NotMapped)
+    }
 
-        for (op1, np1) in Iterator::zip(op1_iter, np1_iter) {
-            if op1.is_leaf() {
-                // Huge Page
-                *np1 = *op1;
+    /// Map every user page of this table into `dst` as well, as `fork` does.
+    ///
+    /// Pages at addresses for which `cow` holds are shared copy-on-write: they
+    /// are mapped as 4 KiB pages, lose write permission in both tables, are
+    /// marked [`PteFlags::COW`] and gain a reference. Both tables simply map
+    /// the same frames for the others.
+    ///
+    /// On failure, what was mapped into `dst` so far stays mapped.
+    pub fn copy_table_and_mark_self_cow(
+        &mut self,
+        dst: &mut PageTable,
+        cow: impl Fn(VirtAddr) -> bool,
+    ) -> MmResult<()> {
+        let end = VirtAddr(KERNEL_ROOT_INDEX * HUGE_PAGE_SIZE);
+        let mut va = VirtAddr(0);
+        while va < end {
+            let (entry, page) = match self.walk(va) {
+                Walk::Leaf(entry, page) => (entry, page),
+                Walk::Hole(hole) => {
+                    va = VirtAddr(round_down!(va.0, hole.bytes()) + hole.bytes());
+                    continue;
+                }
+            };
+            let cow = cow(va);
+            if cow && page != PageSize::Size4K {
+                self.split(entry, page)?;
                 continue;
             }
-            let op2t = old.next_table_mut_opt(&op1);
-            if op2t.is_none() {
-                continue;
+            if cow && !entry.is_shared() {
+                entry.become_shared(false);
+                arch::flush_tlb(va.0);
             }
-            let op2_iter = op2t.unwrap().iter_mut();
-            let np2_iter = new.next_table_mut_or_create(np1).iter_mut();
+            dst.map(va, entry.pa(), page, entry.flags())?;
+            if cow {
+                frame::add_ref(entry.ppn());
+            }
+            va += page.bytes();
+        }
+        Ok(())
+    }
 
-            for (op2, np2) in Iterator::zip(op2_iter, np2_iter) {
-                let op3t = old.next_table_mut_opt(&op2);
-                if op3t.is_none() {
-                    continue;
-                }
-                let op3_iter = op3t.unwrap().iter_mut();
-                let np3_iter = new.next_table_mut_or_create(np2).iter_mut();
-
-                for (op3, np3) in Iterator::zip(op3_iter, np3_iter) {
-                    if op3.is_valid() {
-                        debug_assert!(op3.is_leaf());
-                        if op3.is_user() {
-                            do_with_frame(op3.pa());
-                            op3.set_shared();
-                        }
-                        *np3 = *op3;
-                    }
-                }
+    /// Give the copy-on-write page at `va` a frame of its own and make it
+    /// writable again. The frame is copied only if it is still shared.
+    pub fn unshare_page(&mut self, va: VirtAddr) -> MmResult<()> {
+        let entry = self.get_leaf_split(va, PageSize::Size4K)?;
+        if !entry.is_shared() {
+            // Another hart may have got here first.
+            return if entry.is_writable() {
+                Ok(())
+            } else {
+                Err(MmError::PermissionDenied)
+            };
+        }
+        let old = entry.ppn();
+        if frame::ref_count(old) > 1 {
+            let new = frame::alloc().ok_or(MmError::OutOfMemory)?;
+            unsafe {
+                new.addr()
+                    .as_mut_page_slice()
+                    .copy_from_slice(old.addr().as_page_slice());
             }
+            *entry = PageTableEntry::new(new.into(), entry.flags());
+            frame::release(old, 1);
         }
-        new
+        entry.become_unique(true);
+        arch::flush_tlb(va.0);
+        Ok(())
     }
 }
 
@@ -191,61 +386,111 @@ impl PageTable {
         unsafe { core::slice::from_raw_parts_mut(kernel_vaddr.0 as _, ENTRY_COUNT) }
     }
 
-    fn next_table_mut_opt<'a>(&self, pte: &PageTableEntry) -> Option<&'a mut [PageTableEntry]> {
-        if pte.is_valid() {
-            debug_assert!(pte.is_directory()); // Must be a directory
-            Some(self.table_of_mut(pte.pa()))
-        } else {
-            None
-        }
-    }
-
     fn next_table_mut<'a>(&self, pte: &PageTableEntry) -> &'a mut [PageTableEntry] {
-        debug_assert!(pte.is_valid());
+        debug_assert!(pte.is_directory());
         self.table_of_mut(pte.pa())
     }
 
-    fn next_table_mut_or_create<'a>(&mut self, pte: &mut PageTableEntry) -> &'a mut [PageTableEntry] {
+    fn next_table_mut_or_create<'a>(&mut self, pte: &mut PageTableEntry) -> MmResult<&'a mut [PageTableEntry]> {
         if pte.is_valid() {
-            self.next_table_mut(pte)
+            Ok(self.next_table_mut(pte))
         } else {
-            let frame = frame::alloc().unwrap();
+            let frame = frame::alloc().ok_or(MmError::OutOfMemory)?;
             *pte = PageTableEntry::new(frame.into(), PteFlags::V);
             self.intrm_tables.push(frame.into());
-            self.table_of_mut(frame.into())
+            Ok(self.table_of_mut(frame.into()))
         }
     }
 
-    fn get_entry_mut_opt(&self, va: VirtAddr) -> Option<&mut PageTableEntry> {
-        let p3 = self.table_of_mut(self.root_pa);
-        let p3e = &mut p3[va.p3_index()];
-        let p2 = self.next_table_mut_opt(p3e)?;
-        let p2e = &mut p2[va.p2_index()];
-        let p1 = self.next_table_mut_opt(p2e)?;
-        let p1e = &mut p1[va.p1_index()];
-        if p1e.is_valid() {
-            Some(p1e)
-        } else {
-            None
+    /// Walk down to the leaf mapping `va`, at whatever level it is.
+    ///
+    /// Only valid entries are followed. Pages mapped without access are
+    /// leaves too, so they keep their frames until unmapped.
+    fn walk<'a>(&self, va: VirtAddr) -> Walk<'a> {
+        let mut table = self.table_of_mut(self.root_pa);
+        for level in (0..LEVELS).rev() {
+            let entry = &mut table[va.table_index(level)];
+            if !entry.is_present() {
+                return Walk::Hole(PageSize::from_level(level));
+            }
+            if entry.is_leaf() {
+                return Walk::Leaf(entry, PageSize::from_level(level));
+            }
+            if level == 0 {
+                return Walk::Hole(PageSize::from_level(level));
+            }
+            table = self.next_table_mut(entry);
+        }
+        unreachable!()
+    }
+
+    fn find_leaf<'a>(&self, va: VirtAddr) -> Option<(&'a mut PageTableEntry, PageSize)> {
+        match self.walk(va) {
+            Walk::Leaf(entry, page) => Some((entry, page)),
+            Walk::Hole(_) => None,
+        }
+    }
+
+    /// Whether the slot for a `size` page at `va` holds a lower level table.
+    fn has_table_at(&self, va: VirtAddr, size: PageSize) -> bool {
+        if size == PageSize::Size4K {
+            return false;
         }
+        let mut table = self.table_of(self.root_pa);
+        for level in (size.level()..LEVELS).rev() {
+            let entry = &table[va.table_index(level)];
+            if !entry.is_directory() {
+                return false;
+            }
+            if level == size.level() {
+                return true;
+            }
+            table = self.table_of(entry.pa());
+        }
+        false
     }
 
-    fn get_entry_mut(&self, va: VirtAddr) -> &mut PageTableEntry {
-        let p3 = self.table_of_mut(self.root_pa);
-        let p3e = &mut p3[va.p3_index()];
-        let p2 = self.next_table_mut(p3e);
-        let p2e = &mut p2[va.p2_index()];
-        let p1 = self.next_table_mut(p2e);
-        &mut p1[va.p1_index()]
+    /// Walk to the slot for a `size` page at `va`, creating tables on the way.
+    ///
+    /// Fails if a larger leaf already covers `va`.
+    fn get_entry_mut_or_create<'a>(&mut self, va: VirtAddr, size: PageSize) -> MmResult<&'a mut PageTableEntry> {
+        let mut table = self.table_of_mut(self.root_pa);
+        for level in (size.level() + 1..LEVELS).rev() {
+            let entry = &mut table[va.table_index(level)];
+            if entry.is_leaf() {
+                return Err(MmError::AlreadyMapped);
+            }
+            table = self.next_table_mut_or_create(entry)?;
+        }
+        Ok(&mut table[va.table_index(size.level())])
     }
 
-    fn get_entry_mut_or_create(&mut self, va: VirtAddr) -> &mut PageTableEntry {
-        let p3 = self.table_of_mut(self.root_pa);
-        let p3e = &mut p3[va.p3_index()];
-        let p2 = self.next_table_mut_or_create(p3e);
-        let p2e = &mut p2[va.p2_index()];
-        let p1 = self.next_table_mut_or_create(p2e);
-        &mut p1[va.p1_index()]
+    /// Find the leaf mapping `va`, splitting superpages until it is of `size`.
+    fn get_leaf_split<'a>(&mut self, va: VirtAddr, size: PageSize) -> MmResult<&'a mut PageTableEntry> {
+        loop {
+            let (entry, page) = self.find_leaf(va).ok_or(MmError::NotMapped)?;
+            if page == size {
+                return Ok(entry);
+            }
+            self.split(entry, page)?;
+        }
+    }
+
+    /// Replace the superpage leaf `entry` by a table of next smaller pages
+    /// with the same permissions.
+    fn split(&mut self, entry: &mut PageTableEntry, page: PageSize) -> MmResult<()> {
+        let smaller = page.smaller().expect("cannot split a 4 KiB page");
+        let frame = frame::alloc().ok_or(MmError::OutOfMemory)?;
+        self.intrm_tables.push(frame.into());
+        let table = self.table_of_mut(frame.into());
+        let base = entry.pa();
+        let flags = entry.flags();
+        for (i, pte) in table.iter_mut().enumerate() {
+            *pte = PageTableEntry::new(base + i * smaller.bytes(), flags);
+        }
+        *entry = PageTableEntry::new(frame.into(), PteFlags::V);
+        arch::flush_tlb_all();
+        Ok(())
     }
 }
 
@@ -255,4 +500,4 @@ impl Drop for PageTable {
             frame::dealloc((*table_pa).into());
         }
     }
-}
\ No newline at end of file
+}
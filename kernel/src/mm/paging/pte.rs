@@ -31,6 +31,21 @@ bitflags! {
         const COW = 1 << 8;
         // Reserved for software
         const RSW2 = 1 << 9;
+        // Mapped without access, V is clear so that every access faults
+        const PROT_NONE = 1 << 9;
+    }
+}
+
+impl PteFlags {
+    /// `self` with V set if it allows some access, or with
+    /// [`PROT_NONE`](Self::PROT_NONE) instead if it allows none.
+    pub fn with_presence(self) -> Self {
+        let perm = self - Self::V - Self::PROT_NONE;
+        if perm.intersects(Self::R | Self::W | Self::X) {
+            perm | Self::V
+        } else {
+            perm | Self::PROT_NONE
+        }
     }
 }
 
@@ -70,6 +85,11 @@ impl PageTableEntry {
         self.flags().contains(PteFlags::V)
     }
 
+    /// Valid, or a page mapped without access that still owns its frame.
+    pub fn is_present(&self) -> bool {
+        self.flags().intersects(PteFlags::V | PteFlags::PROT_NONE)
+    }
+
     pub fn is_directory(&self) -> bool {
         let mask = PteFlags::R | PteFlags::W | PteFlags::X | PteFlags::U;
         self.is_valid() && !self.flags().intersects(mask)
@@ -77,7 +97,7 @@ impl PageTableEntry {
 
     pub fn is_leaf(&self) -> bool {
         let mask = PteFlags::R | PteFlags::W | PteFlags::X | PteFlags::U;
-        self.is_valid() && self.flags().intersects(mask)
+        self.is_present() && self.flags().intersects(mask)
     }
 
     pub fn is_readable(&self) -> bool {
@@ -112,6 +132,15 @@ impl PageTableEntry {
         self.flags().contains(PteFlags::RSW2)
     }
 
+    /// Replace the R, W, X, U and G bits, keeping the frame and the other flags.
+    ///
+    /// V is cleared when `perm` allows no access, see [`PteFlags::with_presence`].
+    pub fn set_perm(&mut self, perm: PteFlags) {
+        let mask = PteFlags::R | PteFlags::W | PteFlags::X | PteFlags::U | PteFlags::G;
+        let cleared = mask | PteFlags::V | PteFlags::PROT_NONE;
+        self.bits = (self.bits & !(cleared.bits() as usize)) | (perm & mask).with_presence().bits() as usize;
+    }
+
     pub fn set_writable(&mut self) {
         self.bits |= PteFlags::W.bits() as usize;
     }
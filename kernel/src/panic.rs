@@ -1,4 +1,4 @@
-use crate::{mm::layout::{__text_end, __text_start}, shutdown};
+use crate::{console, mm::layout::{__text_end, __text_start}, shutdown};
 use core::{mem::size_of, panic::PanicInfo};
 use alloc::{format, string::String};
 use log::error;
@@ -16,6 +16,7 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         error!("\x1b[1;31mPanicked: {}{}\x1b[1;0m", info.message().unwrap(), backtrace());
     }
+    console::flush();
     shutdown()
 }
 
@@ -0,0 +1,43 @@
+use core::arch::global_asm;
+
+global_asm!(include_str!("switch.S"));
+
+/// Kernel registers of a task that is switched out. Offsets are hard-coded
+/// in `switch.S`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskContext {
+    ra: usize,
+    sp: usize,
+    s: [usize; 12],
+}
+
+impl TaskContext {
+    pub const fn empty() -> Self {
+        Self {
+            ra: 0,
+            sp: 0,
+            s: [0; 12],
+        }
+    }
+
+    /// A context that starts running `entry` on the stack ending at `sp`.
+    pub fn new(entry: usize, sp: usize) -> Self {
+        Self {
+            ra: entry,
+            sp,
+            s: [0; 12],
+        }
+    }
+}
+
+extern "C" {
+    fn __switch(current: *mut TaskContext, next: *const TaskContext);
+}
+
+/// Save the current kernel registers into `current` and resume `next`.
+///
+/// Returns when something switches back to `current`.
+pub unsafe fn switch(current: *mut TaskContext, next: *const TaskContext) {
+    __switch(current, next);
+}
@@ -0,0 +1,175 @@
+//! Task scheduling.
+//!
+//! Every hart runs [`run`], its idle loop, on its boot stack. The loop picks
+//! tasks from the hart's run queue and switches to them; a task gives the
+//! hart back by switching to the idle loop again, which then puts it back in
+//! a queue if it is still runnable. Tasks are preempted on the way back to
+//! user mode once their time slice is used up.
+
+mod context;
+mod policy;
+mod sleep_mutex;
+mod wait_queue;
+
+use alloc::sync::Arc;
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use riscv::register::sstatus;
+use spin::Mutex;
+
+use crate::{
+    arch,
+    config::MAX_HART_COUNT,
+    cpu, dtb,
+    task::{self, Task, TaskStatus},
+    trap,
+};
+
+pub use self::{
+    context::TaskContext,
+    policy::{RoundRobin, SchedPolicy},
+    sleep_mutex::{SleepMutex, SleepMutexGuard},
+    wait_queue::WaitQueue,
+};
+
+/// The policy every hart schedules with.
+type Policy = RoundRobin;
+
+struct HartSched {
+    run_queue: Mutex<Policy>,
+    /// Where the idle loop is saved while a task runs.
+    idle_context: UnsafeCell<TaskContext>,
+    need_resched: AtomicBool,
+}
+
+unsafe impl Sync for HartSched {}
+
+impl HartSched {
+    const fn new() -> Self {
+        Self {
+            run_queue: Mutex::new(Policy::new()),
+            idle_context: UnsafeCell::new(TaskContext::empty()),
+            need_resched: AtomicBool::new(false),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const HART_SCHED_INIT: HartSched = HartSched::new();
+static HARTS: [HartSched; MAX_HART_COUNT] = [HART_SCHED_INIT; MAX_HART_COUNT];
+
+fn this_hart() -> &'static HartSched {
+    &HARTS[cpu::hart_id()]
+}
+
+/// Make `task` runnable, on the hart with the shortest run queue.
+pub fn add_task(task: Arc<Task>) {
+    let hart = dtb::machine()
+        .harts()
+        .iter()
+        .filter(|&&id| cpu::get(id).is_online())
+        .min_by_key(|&&id| HARTS[id].run_queue.lock().len())
+        .copied()
+        .unwrap_or_else(cpu::hart_id);
+    arch::without_interrupts(|| HARTS[hart].run_queue.lock().push(task));
+}
+
+/// Make a blocked task runnable again.
+pub fn wake(task: &Arc<Task>) {
+    let mut inner = task.inner();
+    if inner.status != TaskStatus::Blocked {
+        return;
+    }
+    inner.status = TaskStatus::Ready;
+    // A task still switching out is queued by its idle loop.
+    if !inner.on_cpu {
+        drop(inner);
+        add_task(task.clone());
+    }
+}
+
+/// The idle loop of the current hart.
+///
+/// Interrupts are only enabled while waiting for work.
+pub fn run() -> ! {
+    unsafe { sstatus::clear_sie() };
+    let hart = this_hart();
+    loop {
+        let next = hart.run_queue.lock().pick_next();
+        let Some(task) = next else {
+            unsafe { sstatus::set_sie() };
+            arch::wfi();
+            unsafe { sstatus::clear_sie() };
+            continue;
+        };
+        {
+            let mut inner = task.inner();
+            inner.status = TaskStatus::Running;
+            inner.on_cpu = true;
+        }
+        cpu::current().set_task(Some(task.clone()));
+        cpu::current().set_memory_set(task.process().memory_set());
+        hart.need_resched.store(false, Ordering::Relaxed);
+        unsafe { context::switch(hart.idle_context.get(), task.sched_context()) };
+
+        // The task gave the hart back and its stack is free now.
+        cpu::current().set_task(None);
+        cpu::current().set_memory_set(None);
+        let mut inner = task.inner();
+        inner.on_cpu = false;
+        if inner.status == TaskStatus::Ready {
+            drop(inner);
+            add_task(task);
+        }
+    }
+}
+
+/// Give the hart back to the idle loop, which treats the current task
+/// according to the status it was left in.
+fn switch_to_idle() {
+    let context = task::current().sched_context();
+    unsafe { context::switch(context, this_hart().idle_context.get()) };
+}
+
+/// Let other tasks run. The current one stays runnable.
+pub fn yield_now() {
+    task::current().inner().status = TaskStatus::Ready;
+    switch_to_idle();
+}
+
+/// Stop running the current task, which must already be marked blocked and
+/// be known to whoever is going to wake it. If it was woken in the meantime,
+/// it is simply queued again.
+fn block_current() {
+    switch_to_idle();
+}
+
+/// Stop running the current task for good.
+pub fn exit_current() -> ! {
+    task::current().inner().status = TaskStatus::Exited;
+    switch_to_idle();
+    unreachable!("exited task was resumed");
+}
+
+/// Account a timer tick to the task running on the current hart.
+pub fn tick() {
+    let hart = this_hart();
+    if cpu::current().task().is_some() && hart.run_queue.lock().tick() {
+        hart.need_resched.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Yield if the time slice of the current task is used up.
+pub fn preempt_if_needed() {
+    if this_hart().need_resched.swap(false, Ordering::Relaxed) {
+        yield_now();
+    }
+}
+
+/// Where new tasks start, on their own kernel stack.
+pub extern "C" fn task_entry() -> ! {
+    let context = task::current().trap_context() as *mut trap::Context;
+    trap::user_return(unsafe { &mut *context })
+}
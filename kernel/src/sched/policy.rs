@@ -0,0 +1,62 @@
+use alloc::{collections::VecDeque, sync::Arc};
+
+use crate::{task::Task, timer::consts::INTERRUPT_PER_SEC};
+
+/// Ticks a task may run before it is preempted, about 20 ms.
+const TIME_SLICE_TICKS: usize = if INTERRUPT_PER_SEC >= 50 { INTERRUPT_PER_SEC / 50 } else { 1 };
+
+/// Decides which ready task of a hart runs next.
+pub trait SchedPolicy {
+    /// Make `task` eligible to run.
+    fn push(&mut self, task: Arc<Task>);
+
+    /// Take the task to run next.
+    fn pick_next(&mut self) -> Option<Arc<Task>>;
+
+    /// Account a timer tick to the running task. Returns whether it should
+    /// give up the hart.
+    fn tick(&mut self) -> bool;
+
+    /// Number of tasks waiting to run.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Tasks run in turn, each for at most one time slice.
+pub struct RoundRobin {
+    queue: VecDeque<Arc<Task>>,
+    slice_left: usize,
+}
+
+impl RoundRobin {
+    pub const fn new() -> Self {
+        Self {
+            queue: VecDeque::new(),
+            slice_left: TIME_SLICE_TICKS,
+        }
+    }
+}
+
+impl SchedPolicy for RoundRobin {
+    fn push(&mut self, task: Arc<Task>) {
+        self.queue.push_back(task);
+    }
+
+    fn pick_next(&mut self) -> Option<Arc<Task>> {
+        self.slice_left = TIME_SLICE_TICKS;
+        self.queue.pop_front()
+    }
+
+    fn tick(&mut self) -> bool {
+        self.slice_left = self.slice_left.saturating_sub(1);
+        // Nobody else to run, keep going.
+        self.slice_left == 0 && !self.queue.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
@@ -0,0 +1,60 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use spin::Mutex;
+
+use crate::task::{self, Task, TaskStatus};
+
+/// Tasks blocked until some event happens.
+pub struct WaitQueue {
+    tasks: Mutex<VecDeque<Arc<Task>>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            tasks: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Block until `condition` holds.
+    ///
+    /// The task is queued before `condition` is checked, so a wakeup between
+    /// the check and blocking is not lost.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        loop {
+            let task = task::current();
+            {
+                let mut tasks = self.tasks.lock();
+                task.inner().status = TaskStatus::Blocked;
+                tasks.push_back(task.clone());
+            }
+            if condition() {
+                // Unless a wakeup took it out already, it is still queued.
+                let mut tasks = self.tasks.lock();
+                tasks.retain(|queued| !Arc::ptr_eq(queued, &task));
+                task.inner().status = TaskStatus::Running;
+                return;
+            }
+            drop(task);
+            super::block_current();
+        }
+    }
+
+    pub fn wake_one(&self) {
+        if let Some(task) = self.tasks.lock().pop_front() {
+            super::wake(&task);
+        }
+    }
+
+    pub fn wake_all(&self) {
+        let tasks = core::mem::take(&mut *self.tasks.lock());
+        for task in tasks.iter() {
+            super::wake(task);
+        }
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
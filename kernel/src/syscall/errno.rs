@@ -0,0 +1,101 @@
+use crate::{
+    fs::FsError,
+    loader::LoadError,
+    mm::MmError,
+};
+
+/// Error numbers of the Linux ABI. System calls return them negated.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(isize)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    ENXIO = 6,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    EBUSY = 16,
+    EEXIST = 17,
+    EXDEV = 18,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    ENFILE = 23,
+    EMFILE = 24,
+    ENOTTY = 25,
+    EFBIG = 27,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EROFS = 30,
+    EMLINK = 31,
+    EPIPE = 32,
+    ERANGE = 34,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+    ELOOP = 40,
+    EOPNOTSUPP = 95,
+    ETIMEDOUT = 110,
+}
+
+impl From<MmError> for Errno {
+    fn from(e: MmError) -> Self {
+        match e {
+            MmError::OutOfMemory => Errno::ENOMEM,
+            MmError::AlreadyMapped => Errno::EEXIST,
+            MmError::NotMapped => Errno::EFAULT,
+            MmError::Misaligned => Errno::EINVAL,
+            MmError::PermissionDenied => Errno::EACCES,
+        }
+    }
+}
+
+impl From<FsError> for Errno {
+    fn from(e: FsError) -> Self {
+        match e {
+            FsError::Unsupported => Errno::EOPNOTSUPP,
+            FsError::BadDescriptor => Errno::EBADF,
+            FsError::InvalidInput => Errno::EINVAL,
+            FsError::TooManyFiles => Errno::EMFILE,
+            FsError::Io | FsError::Corrupted => Errno::EIO,
+            FsError::NotFound => Errno::ENOENT,
+            FsError::AlreadyExists => Errno::EEXIST,
+            FsError::NotDir => Errno::ENOTDIR,
+            FsError::IsDir => Errno::EISDIR,
+            FsError::NotEmpty => Errno::ENOTEMPTY,
+            FsError::SymlinkLoop => Errno::ELOOP,
+            FsError::NameTooLong => Errno::ENAMETOOLONG,
+            FsError::CrossDevice => Errno::EXDEV,
+            FsError::TooManyLinks => Errno::EMLINK,
+            FsError::Busy => Errno::EBUSY,
+            FsError::ReadOnly => Errno::EROFS,
+            FsError::NoSpace => Errno::ENOSPC,
+            FsError::NotSeekable => Errno::ESPIPE,
+            FsError::PermissionDenied => Errno::EACCES,
+            FsError::WouldBlock => Errno::EAGAIN,
+            FsError::Interrupted => Errno::EINTR,
+            FsError::NoDevice => Errno::ENXIO,
+        }
+    }
+}
+
+impl From<LoadError> for Errno {
+    fn from(e: LoadError) -> Self {
+        match e {
+            LoadError::NotElf | LoadError::Unsupported | LoadError::Truncated => Errno::ENOEXEC,
+            LoadError::NoInterpreter => Errno::ENOENT,
+            LoadError::ArgumentsTooLong => Errno::E2BIG,
+            LoadError::Memory(e) => e.into(),
+        }
+    }
+}
@@ -0,0 +1,586 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use crate::{
+    drivers::block,
+    fs::{mount, Dentry, File, FsContext, InodeType, Metadata, OpenFlags, SeekFrom},
+    task,
+};
+
+use super::{
+    uaccess::{copy_from_user, copy_to_user, read_str, read_value, write_value, PATH_MAX},
+    Errno, SysResult,
+};
+
+/// Largest buffer a single transfer goes through; bigger requests are split.
+const IO_CHUNK: usize = 0x10000;
+
+/// Most buffers `readv` and `writev` take.
+const IOV_MAX: usize = 1024;
+
+/// Directory descriptor standing for the working directory.
+const AT_FDCWD: isize = -100;
+const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+const AT_REMOVEDIR: usize = 0x200;
+const AT_SYMLINK_FOLLOW: usize = 0x400;
+const AT_EMPTY_PATH: usize = 0x1000;
+
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+const RENAME_NOREPLACE: usize = 1 << 0;
+
+const F_DUPFD: usize = 0;
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const F_GETFL: usize = 3;
+const F_SETFL: usize = 4;
+const F_DUPFD_CLOEXEC: usize = 1030;
+const FD_CLOEXEC: usize = 1;
+
+/// Execute permission of `faccessat`.
+const X_OK: usize = 1;
+
+/// Longest file system type name `mount` takes.
+const FS_TYPE_MAX: usize = 64;
+
+/// `struct iovec`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+/// `struct stat` of the generic Linux ABI.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct Kstat {
+    st_dev: u64,
+    st_ino: u64,
+    st_mode: u32,
+    st_nlink: u32,
+    st_uid: u32,
+    st_gid: u32,
+    st_rdev: u64,
+    __pad1: u64,
+    st_size: i64,
+    st_blksize: i32,
+    __pad2: i32,
+    st_blocks: i64,
+    st_atime_sec: i64,
+    st_atime_nsec: u64,
+    st_mtime_sec: i64,
+    st_mtime_nsec: u64,
+    st_ctime_sec: i64,
+    st_ctime_nsec: u64,
+    __unused: [u32; 2],
+}
+
+impl From<Metadata> for Kstat {
+    fn from(meta: Metadata) -> Self {
+        Self {
+            st_dev: meta.dev,
+            st_ino: meta.ino,
+            st_mode: meta.kind.mode_bits() | meta.mode,
+            st_nlink: meta.nlink,
+            st_uid: meta.uid,
+            st_gid: meta.gid,
+            st_rdev: meta.rdev,
+            st_size: meta.size as i64,
+            st_blksize: meta.blksize as i32,
+            st_blocks: meta.blocks as i64,
+            st_atime_sec: meta.atime.sec as i64,
+            st_atime_nsec: meta.atime.nsec,
+            st_mtime_sec: meta.mtime.sec as i64,
+            st_mtime_nsec: meta.mtime.nsec,
+            st_ctime_sec: meta.ctime.sec as i64,
+            st_ctime_nsec: meta.ctime.nsec,
+            ..Default::default()
+        }
+    }
+}
+
+/// `struct linux_dirent64` without its name.
+#[repr(C)]
+struct Dirent64 {
+    d_ino: u64,
+    d_off: i64,
+    d_reclen: u16,
+    d_type: u8,
+}
+
+fn fs_context() -> FsContext {
+    let fs = task::current().process().inner().fs.clone();
+    let context = fs.lock().clone();
+    context
+}
+
+/// Open the file at `path` for reading, to execute it.
+pub fn open_path(path: &str) -> SysResult<Arc<dyn File>> {
+    let file = fs_context().open(None, path, OpenFlags::RDONLY, 0)?;
+    match file.metadata()?.kind {
+        InodeType::File => Ok(file),
+        _ => Err(Errno::EACCES),
+    }
+}
+
+/// The open file descriptor `fd` refers to.
+pub fn file(fd: usize) -> SysResult<Arc<dyn File>> {
+    let files = task::current().process().inner().files.clone();
+    let file = files.lock().get(fd)?;
+    Ok(file)
+}
+
+fn install(file: Arc<dyn File>, cloexec: bool) -> SysResult<usize> {
+    let files = task::current().process().inner().files.clone();
+    let fd = files.lock().insert(file, cloexec)?;
+    Ok(fd)
+}
+
+/// The directory relative paths of an `*at` call start from, `None` for
+/// the working directory. Absolute paths ignore `dirfd`.
+fn base_dir(dirfd: usize, path: &str) -> SysResult<Option<Arc<Dentry>>> {
+    if path.starts_with('/') || dirfd as isize == AT_FDCWD {
+        return Ok(None);
+    }
+    let dentry = file(dirfd)?.dentry().ok_or(Errno::ENOTDIR)?;
+    if !dentry.is_dir() {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok(Some(dentry))
+}
+
+/// Look up `path` relative to `dirfd`. With `AT_EMPTY_PATH` an empty path
+/// refers to `dirfd` itself.
+fn lookup_at(context: &FsContext, dirfd: usize, path: &str, flags: usize) -> SysResult<Arc<Dentry>> {
+    if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        if dirfd as isize == AT_FDCWD {
+            return Ok(context.cwd.clone());
+        }
+        return file(dirfd)?.dentry().ok_or(Errno::EBADF);
+    }
+    let base = base_dir(dirfd, path)?;
+    let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+    Ok(context.lookup(base.as_ref(), path, follow)?)
+}
+
+/// Look up the directory holding the last component of `path`, for calls
+/// that create or remove it.
+fn lookup_parent(context: &FsContext, dirfd: usize, path: &str) -> SysResult<(Arc<Dentry>, alloc::string::String)> {
+    let base = base_dir(dirfd, path)?;
+    let (dir, name) = context
+        .walker()
+        .walk_parent(base.as_ref().unwrap_or(&context.cwd), path)?;
+    Ok((dir, name.into()))
+}
+
+/// Read into `[buf, buf + len)` with `read`, which gets the number of bytes
+/// read so far, until it returns less than asked for.
+fn read_into(buf: usize, len: usize, mut read: impl FnMut(usize, &mut [u8]) -> SysResult<usize>) -> SysResult<usize> {
+    let mut chunk = vec![0u8; len.min(IO_CHUNK)];
+    let mut done = 0;
+    while done < len {
+        let want = chunk.len().min(len - done);
+        let read = match read(done, &mut chunk[..want]) {
+            Ok(read) => read,
+            Err(_) if done > 0 => break,
+            Err(e) => return Err(e),
+        };
+        copy_to_user(buf + done, &chunk[..read])?;
+        done += read;
+        if read < want {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+/// Write `[buf, buf + len)` with `write`, which gets the number of bytes
+/// written so far, until it takes less than offered.
+fn write_from(buf: usize, len: usize, mut write: impl FnMut(usize, &[u8]) -> SysResult<usize>) -> SysResult<usize> {
+    let mut chunk = vec![0u8; len.min(IO_CHUNK)];
+    let mut done = 0;
+    while done < len {
+        let want = chunk.len().min(len - done);
+        copy_from_user(buf + done, &mut chunk[..want])?;
+        let written = match write(done, &chunk[..want]) {
+            Ok(written) => written,
+            Err(_) if done > 0 => break,
+            Err(e) => return Err(e),
+        };
+        done += written;
+        if written < want {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+pub fn sys_read(fd: usize, buf: usize, len: usize) -> SysResult<usize> {
+    let file = file(fd)?;
+    read_into(buf, len, |_, chunk| Ok(file.read(chunk)?))
+}
+
+pub fn sys_write(fd: usize, buf: usize, len: usize) -> SysResult<usize> {
+    let file = file(fd)?;
+    write_from(buf, len, |_, chunk| Ok(file.write(chunk)?))
+}
+
+pub fn sys_pread64(fd: usize, buf: usize, len: usize, offset: usize) -> SysResult<usize> {
+    let file = file(fd)?;
+    if (offset as isize) < 0 {
+        return Err(Errno::EINVAL);
+    }
+    read_into(buf, len, |done, chunk| {
+        let offset = offset.checked_add(done).ok_or(Errno::EINVAL)?;
+        Ok(file.read_at(offset, chunk)?)
+    })
+}
+
+pub fn sys_pwrite64(fd: usize, buf: usize, len: usize, offset: usize) -> SysResult<usize> {
+    let file = file(fd)?;
+    if (offset as isize) < 0 {
+        return Err(Errno::EINVAL);
+    }
+    write_from(buf, len, |done, chunk| {
+        let offset = offset.checked_add(done).ok_or(Errno::EFBIG)?;
+        Ok(file.write_at(offset, chunk)?)
+    })
+}
+
+pub fn sys_readv(fd: usize, iov: usize, count: usize) -> SysResult<usize> {
+    if count > IOV_MAX {
+        return Err(Errno::EINVAL);
+    }
+    let file = file(fd)?;
+    let mut done = 0;
+    for i in 0..count {
+        let vec: IoVec = read_value(iov + i * core::mem::size_of::<IoVec>())?;
+        let read = read_into(vec.base, vec.len, |_, chunk| Ok(file.read(chunk)?))?;
+        done += read;
+        if read < vec.len {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+pub fn sys_writev(fd: usize, iov: usize, count: usize) -> SysResult<usize> {
+    if count > IOV_MAX {
+        return Err(Errno::EINVAL);
+    }
+    let file = file(fd)?;
+    let mut done = 0;
+    for i in 0..count {
+        let vec: IoVec = read_value(iov + i * core::mem::size_of::<IoVec>())?;
+        let written = write_from(vec.base, vec.len, |_, chunk| Ok(file.write(chunk)?))?;
+        done += written;
+        if written < vec.len {
+            break;
+        }
+    }
+    Ok(done)
+}
+
+pub fn sys_lseek(fd: usize, offset: usize, whence: usize) -> SysResult<usize> {
+    let pos = match whence {
+        SEEK_SET if (offset as isize) < 0 => return Err(Errno::EINVAL),
+        SEEK_SET => SeekFrom::Start(offset),
+        SEEK_CUR => SeekFrom::Current(offset as isize),
+        SEEK_END => SeekFrom::End(offset as isize),
+        _ => return Err(Errno::EINVAL),
+    };
+    Ok(file(fd)?.seek(pos)?)
+}
+
+pub fn sys_close(fd: usize) -> SysResult<usize> {
+    let files = task::current().process().inner().files.clone();
+    // Releasing the file may write it back, which is not done with the
+    // table locked.
+    let file = files.lock().remove(fd)?;
+    drop(file);
+    Ok(0)
+}
+
+pub fn sys_openat(dirfd: usize, path: usize, flags: usize, mode: usize) -> SysResult<usize> {
+    let path = read_str(path, PATH_MAX)?;
+    let flags = OpenFlags::from_bits_truncate(flags as u32);
+    let context = fs_context();
+    let base = base_dir(dirfd, &path)?;
+    let file = context.open(base.as_ref(), &path, flags, mode as u32)?;
+    install(file, flags.contains(OpenFlags::CLOEXEC))
+}
+
+pub fn sys_dup(fd: usize) -> SysResult<usize> {
+    install(file(fd)?, false)
+}
+
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: usize) -> SysResult<usize> {
+    let cloexec = match OpenFlags::from_bits(flags as u32) {
+        Some(flags) if flags.is_empty() => false,
+        Some(OpenFlags::CLOEXEC) => true,
+        _ => return Err(Errno::EINVAL),
+    };
+    if old_fd == new_fd {
+        return Err(Errno::EINVAL);
+    }
+    let files = task::current().process().inner().files.clone();
+    let mut files = files.lock();
+    let file = files.get(old_fd)?;
+    let replaced = files.insert_at(new_fd, file, cloexec)?;
+    drop(files);
+    drop(replaced);
+    Ok(new_fd)
+}
+
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> SysResult<usize> {
+    let files = task::current().process().inner().files.clone();
+    let mut files = files.lock();
+    let file = files.get(fd)?;
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => Ok(files.insert_from(arg, file, cmd == F_DUPFD_CLOEXEC)?),
+        F_GETFD => Ok(if files.cloexec(fd)? { FD_CLOEXEC } else { 0 }),
+        F_SETFD => {
+            files.set_cloexec(fd, arg & FD_CLOEXEC != 0)?;
+            Ok(0)
+        }
+        F_GETFL => Ok(file.status_flags().bits() as usize),
+        F_SETFL => {
+            file.set_status_flags(OpenFlags::from_bits_truncate(arg as u32));
+            Ok(0)
+        }
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+pub fn sys_getcwd(buf: usize, size: usize) -> SysResult<usize> {
+    let context = fs_context();
+    let mut path = context.cwd.path(&context.root).into_bytes();
+    path.push(0);
+    if path.len() > size {
+        return Err(Errno::ERANGE);
+    }
+    copy_to_user(buf, &path)?;
+    Ok(path.len())
+}
+
+/// Look up a directory for `chdir` or `chroot`.
+fn lookup_dir(path: usize) -> SysResult<Arc<Dentry>> {
+    let path = read_str(path, PATH_MAX)?;
+    let dentry = fs_context().lookup(None, &path, true)?;
+    if !dentry.is_dir() {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok(dentry)
+}
+
+pub fn sys_chdir(path: usize) -> SysResult<usize> {
+    let dentry = lookup_dir(path)?;
+    task::current().process().inner().fs.lock().cwd = dentry;
+    Ok(0)
+}
+
+pub fn sys_fchdir(fd: usize) -> SysResult<usize> {
+    let dentry = file(fd)?.dentry().ok_or(Errno::ENOTDIR)?;
+    if !dentry.is_dir() {
+        return Err(Errno::ENOTDIR);
+    }
+    task::current().process().inner().fs.lock().cwd = dentry;
+    Ok(0)
+}
+
+pub fn sys_chroot(path: usize) -> SysResult<usize> {
+    let dentry = lookup_dir(path)?;
+    task::current().process().inner().fs.lock().root = dentry;
+    Ok(0)
+}
+
+pub fn sys_mkdirat(dirfd: usize, path: usize, mode: usize) -> SysResult<usize> {
+    let path = read_str(path, PATH_MAX)?;
+    let context = fs_context();
+    let (dir, name) = lookup_parent(&context, dirfd, &path)?;
+    if name == "." || name == ".." {
+        return Err(Errno::EEXIST);
+    }
+    dir.create(&name, InodeType::Dir, mode as u32 & 0o7777 & !context.umask)?;
+    Ok(0)
+}
+
+pub fn sys_unlinkat(dirfd: usize, path: usize, flags: usize) -> SysResult<usize> {
+    let path = read_str(path, PATH_MAX)?;
+    let context = fs_context();
+    let (dir, name) = lookup_parent(&context, dirfd, &path)?;
+    let remove_dir = flags & AT_REMOVEDIR != 0;
+    match name.as_str() {
+        "." if remove_dir => return Err(Errno::EINVAL),
+        ".." if remove_dir => return Err(Errno::ENOTEMPTY),
+        "." | ".." => return Err(Errno::EISDIR),
+        _ => {}
+    }
+    dir.unlink(&name, remove_dir)?;
+    Ok(0)
+}
+
+pub fn sys_symlinkat(target: usize, dirfd: usize, path: usize) -> SysResult<usize> {
+    let target = read_str(target, PATH_MAX)?;
+    let path = read_str(path, PATH_MAX)?;
+    if target.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    let context = fs_context();
+    let (dir, name) = lookup_parent(&context, dirfd, &path)?;
+    if name == "." || name == ".." {
+        return Err(Errno::EEXIST);
+    }
+    dir.symlink(&name, &target)?;
+    Ok(0)
+}
+
+pub fn sys_linkat(old_dirfd: usize, old_path: usize, new_dirfd: usize, new_path: usize, flags: usize) -> SysResult<usize> {
+    let old_path = read_str(old_path, PATH_MAX)?;
+    let new_path = read_str(new_path, PATH_MAX)?;
+    let context = fs_context();
+    // Unlike the other calls, links are only followed when asked to.
+    let follow = if flags & AT_SYMLINK_FOLLOW != 0 { 0 } else { AT_SYMLINK_NOFOLLOW };
+    let old = lookup_at(&context, old_dirfd, &old_path, (flags & AT_EMPTY_PATH) | follow)?;
+    let (dir, name) = lookup_parent(&context, new_dirfd, &new_path)?;
+    if name == "." || name == ".." {
+        return Err(Errno::EEXIST);
+    }
+    dir.link(&name, &old)?;
+    Ok(0)
+}
+
+pub fn sys_readlinkat(dirfd: usize, path: usize, buf: usize, size: usize) -> SysResult<usize> {
+    let path = read_str(path, PATH_MAX)?;
+    if size == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let dentry = lookup_at(&fs_context(), dirfd, &path, AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH)?;
+    if dentry.kind() != InodeType::Symlink {
+        return Err(Errno::EINVAL);
+    }
+    let target = dentry.inode().read_link()?;
+    let len = target.len().min(size);
+    copy_to_user(buf, &target.as_bytes()[..len])?;
+    Ok(len)
+}
+
+pub fn sys_renameat2(old_dirfd: usize, old_path: usize, new_dirfd: usize, new_path: usize, flags: usize) -> SysResult<usize> {
+    if flags & !RENAME_NOREPLACE != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let old_path = read_str(old_path, PATH_MAX)?;
+    let new_path = read_str(new_path, PATH_MAX)?;
+    let context = fs_context();
+    let (old_dir, old_name) = lookup_parent(&context, old_dirfd, &old_path)?;
+    let (new_dir, new_name) = lookup_parent(&context, new_dirfd, &new_path)?;
+    if [old_name.as_str(), new_name.as_str()].iter().any(|name| *name == "." || *name == "..") {
+        return Err(Errno::EBUSY);
+    }
+    if flags & RENAME_NOREPLACE != 0 && new_dir.lookup(&new_name).is_ok() {
+        return Err(Errno::EEXIST);
+    }
+    old_dir.rename(&old_name, &new_dir, &new_name)?;
+    Ok(0)
+}
+
+pub fn sys_getdents64(fd: usize, buf: usize, len: usize) -> SysResult<usize> {
+    let file = file(fd)?;
+    let header = core::mem::size_of::<Dirent64>();
+    let mut out = Vec::new();
+    let mut rejected = false;
+    file.read_dir(&mut |entry, next| {
+        // Name and terminator follow the header, records are 8-byte aligned.
+        let reclen = (header + entry.name.len() + 1 + 7) & !7;
+        if out.len() + reclen > len.min(IO_CHUNK) {
+            rejected = true;
+            return false;
+        }
+        let dirent = Dirent64 {
+            d_ino: entry.ino,
+            d_off: next as i64,
+            d_reclen: reclen as u16,
+            d_type: entry.kind.dirent_type(),
+        };
+        let start = out.len();
+        out.resize(start + reclen, 0);
+        out[start..start + 8].copy_from_slice(&dirent.d_ino.to_ne_bytes());
+        out[start + 8..start + 16].copy_from_slice(&dirent.d_off.to_ne_bytes());
+        out[start + 16..start + 18].copy_from_slice(&dirent.d_reclen.to_ne_bytes());
+        out[start + 18] = dirent.d_type;
+        out[start + 19..start + 19 + entry.name.len()].copy_from_slice(entry.name.as_bytes());
+        true
+    })?;
+    if out.is_empty() && rejected {
+        return Err(Errno::EINVAL);
+    }
+    copy_to_user(buf, &out)?;
+    Ok(out.len())
+}
+
+pub fn sys_fstat(fd: usize, statbuf: usize) -> SysResult<usize> {
+    let stat = Kstat::from(file(fd)?.metadata()?);
+    write_value(statbuf, &stat)?;
+    Ok(0)
+}
+
+pub fn sys_newfstatat(dirfd: usize, path: usize, statbuf: usize, flags: usize) -> SysResult<usize> {
+    let path = read_str(path, PATH_MAX)?;
+    let meta = if path.is_empty() && flags & AT_EMPTY_PATH != 0 && dirfd as isize != AT_FDCWD {
+        // Files not opened by path have metadata too.
+        file(dirfd)?.metadata()?
+    } else {
+        lookup_at(&fs_context(), dirfd, &path, flags)?.inode().metadata()?
+    };
+    write_value(statbuf, &Kstat::from(meta))?;
+    Ok(0)
+}
+
+/// Check access to a file. Everything runs as root, which may read and write
+/// anything and execute files with any execute bit set.
+pub fn sys_faccessat(dirfd: usize, path: usize, mode: usize, flags: usize) -> SysResult<usize> {
+    let path = read_str(path, PATH_MAX)?;
+    let dentry = lookup_at(&fs_context(), dirfd, &path, flags & AT_SYMLINK_NOFOLLOW)?;
+    if mode & X_OK != 0 && !dentry.is_dir() && dentry.inode().metadata()?.mode & 0o111 == 0 {
+        return Err(Errno::EACCES);
+    }
+    Ok(0)
+}
+
+pub fn sys_ftruncate(fd: usize, len: usize) -> SysResult<usize> {
+    if (len as isize) < 0 {
+        return Err(Errno::EINVAL);
+    }
+    file(fd)?.truncate(len)?;
+    Ok(0)
+}
+
+pub fn sys_fsync(fd: usize) -> SysResult<usize> {
+    file(fd)?.sync()?;
+    Ok(0)
+}
+
+pub fn sys_mount(source: usize, target: usize, fs_type: usize, _flags: usize, _data: usize) -> SysResult<usize> {
+    let source = read_str(source, PATH_MAX)?;
+    let target = read_str(target, PATH_MAX)?;
+    let fs_type = read_str(fs_type, FS_TYPE_MAX)?;
+    let (fs_type, factory) = mount::fs_type(&fs_type).ok_or(Errno::ENODEV)?;
+    let point = fs_context().lookup(None, &target, true)?;
+    // Block devices are known by name, without device files.
+    let device = block::get(source.strip_prefix("/dev/").unwrap_or(&source));
+    let superblock = factory(device)?;
+    mount::mount(superblock, &source, fs_type, &point)?;
+    Ok(0)
+}
+
+pub fn sys_umount2(target: usize, _flags: usize) -> SysResult<usize> {
+    let target = read_str(target, PATH_MAX)?;
+    let root = fs_context().lookup(None, &target, true)?;
+    if root.covers().is_none() {
+        return Err(Errno::EINVAL);
+    }
+    mount::umount(&root)?;
+    Ok(0)
+}
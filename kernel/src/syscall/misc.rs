@@ -0,0 +1,36 @@
+use super::{uaccess::write_value, SysResult};
+
+const UTS_LEN: usize = 65;
+
+/// `struct utsname`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct UtsName {
+    sysname: [u8; UTS_LEN],
+    nodename: [u8; UTS_LEN],
+    release: [u8; UTS_LEN],
+    version: [u8; UTS_LEN],
+    machine: [u8; UTS_LEN],
+    domainname: [u8; UTS_LEN],
+}
+
+fn uts_field(s: &str) -> [u8; UTS_LEN] {
+    let mut field = [0u8; UTS_LEN];
+    field[..s.len()].copy_from_slice(s.as_bytes());
+    field
+}
+
+/// C libraries check the release against the oldest kernel they support,
+/// so we claim to be a recent Linux.
+pub fn sys_uname(buf: usize) -> SysResult<usize> {
+    let uts = UtsName {
+        sysname: uts_field("Linux"),
+        nodename: uts_field("GeneralOS"),
+        release: uts_field("6.1.0"),
+        version: uts_field("#1 SMP"),
+        machine: uts_field("riscv64"),
+        domainname: uts_field(""),
+    };
+    write_value(buf, &uts)?;
+    Ok(0)
+}
@@ -0,0 +1,272 @@
+use alloc::sync::Arc;
+use bitflags::bitflags;
+
+use crate::{
+    fs::{self, FileBacking},
+    mm::{
+        addr::VirtAddr,
+        consts::PAGE_SIZE,
+        memory_set::{self, AddressSpace, Backing, MapPerm, SharedMemory, VmArea},
+        MmError,
+    },
+    task,
+};
+
+use super::{Errno, SysResult};
+
+bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    struct MmapProt: usize {
+        const READ = 1 << 0;
+        const WRITE = 1 << 1;
+        const EXEC = 1 << 2;
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    struct MmapFlags: usize {
+        const SHARED = 1 << 0;
+        const PRIVATE = 1 << 1;
+        const FIXED = 1 << 4;
+        const ANONYMOUS = 1 << 5;
+        const FIXED_NOREPLACE = 1 << 20;
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    struct MremapFlags: usize {
+        const MAYMOVE = 1 << 0;
+        const FIXED = 1 << 1;
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    struct MsyncFlags: usize {
+        const ASYNC = 1 << 0;
+        const INVALIDATE = 1 << 1;
+        const SYNC = 1 << 2;
+    }
+}
+
+const MADV_NORMAL: usize = 0;
+const MADV_WILLNEED: usize = 3;
+const MADV_DONTNEED: usize = 4;
+const MADV_FREE: usize = 8;
+const MADV_DONTFORK: usize = 10;
+const MADV_DODUMP: usize = 17;
+
+impl From<MmapProt> for MapPerm {
+    fn from(prot: MmapProt) -> Self {
+        let mut perm = MapPerm::U;
+        if prot.contains(MmapProt::READ) {
+            perm |= MapPerm::R;
+        }
+        // Writable pages are readable too, a PTE cannot be write-only.
+        if prot.contains(MmapProt::WRITE) {
+            perm |= MapPerm::R | MapPerm::W;
+        }
+        if prot.contains(MmapProt::EXEC) {
+            perm |= MapPerm::X;
+        }
+        perm
+    }
+}
+
+fn current_memory_set() -> SysResult<Arc<AddressSpace>> {
+    memory_set::current().ok_or(Errno::EFAULT)
+}
+
+/// The error of a call on a range that has to be mapped, which is `ENOMEM`
+/// where part of it is not.
+fn range_error(e: MmError) -> Errno {
+    match e {
+        MmError::NotMapped => Errno::ENOMEM,
+        e => e.into(),
+    }
+}
+
+/// `len` rounded up to whole pages.
+fn page_len(len: usize) -> SysResult<usize> {
+    len.checked_add(PAGE_SIZE - 1).ok_or(Errno::ENOMEM).map(|len| len & !(PAGE_SIZE - 1))
+}
+
+/// Move the end of the heap. Failing leaves it where it was, which is what
+/// the call returns then.
+pub fn sys_brk(addr: usize) -> SysResult<usize> {
+    let memory_set = current_memory_set()?;
+    let mut memory_set = memory_set.lock();
+    if addr != 0 {
+        let _ = memory_set.set_brk(VirtAddr(addr));
+    }
+    Ok(memory_set.brk().0)
+}
+
+pub fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> SysResult<usize> {
+    let prot = MmapProt::from_bits_truncate(prot);
+    let flags = MmapFlags::from_bits_truncate(flags);
+    if len == 0 || offset % PAGE_SIZE != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let len = page_len(len)?;
+    let shared = flags.contains(MmapFlags::SHARED);
+    if shared == flags.contains(MmapFlags::PRIVATE) {
+        return Err(Errno::EINVAL);
+    }
+    let backing = if flags.contains(MmapFlags::ANONYMOUS) {
+        if shared {
+            Backing::Shared {
+                memory: Arc::new(SharedMemory::new()),
+                offset: 0,
+                writable: true,
+            }
+        } else {
+            Backing::Anonymous
+        }
+    } else {
+        let files = task::current().process().inner().files.clone();
+        let file = files.lock().get(fd)?;
+        // Mapping a file reads it, and stores through a shared mapping
+        // write it.
+        let access = file.status_flags();
+        if !access.readable() || (shared && prot.contains(MmapProt::WRITE) && !access.writable()) {
+            return Err(Errno::EACCES);
+        }
+        if shared {
+            Backing::Shared {
+                memory: fs::shared_memory(file)?,
+                offset,
+                writable: access.writable(),
+            }
+        } else {
+            Backing::File {
+                file: Arc::new(FileBacking(file)),
+                offset,
+            }
+        }
+    };
+
+    let memory_set = current_memory_set()?;
+    let mut memory_set = memory_set.lock();
+    let start = if flags.intersects(MmapFlags::FIXED | MmapFlags::FIXED_NOREPLACE) {
+        let start = VirtAddr(addr);
+        if start.offset() != 0 || addr == 0 {
+            return Err(Errno::EINVAL);
+        }
+        if flags.contains(MmapFlags::FIXED_NOREPLACE) {
+            if !memory_set.is_free(start, start + len) {
+                return Err(Errno::EEXIST);
+            }
+        } else {
+            memory_set.remove(start, len)?;
+        }
+        start
+    } else {
+        memory_set
+            .find_free_range(len, Some(VirtAddr(addr)))
+            .ok_or(Errno::ENOMEM)?
+    };
+    memory_set.insert(VmArea::new(start, start + len, prot.into(), backing, true))?;
+    Ok(start.0)
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> SysResult<usize> {
+    if addr % PAGE_SIZE != 0 || len == 0 {
+        return Err(Errno::EINVAL);
+    }
+    current_memory_set()?.lock().remove(VirtAddr(addr), len)?;
+    Ok(0)
+}
+
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SysResult<usize> {
+    if addr % PAGE_SIZE != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let prot = MmapProt::from_bits(prot).ok_or(Errno::EINVAL)?;
+    current_memory_set()?
+        .lock()
+        .protect(VirtAddr(addr), len, prot.into())
+        .map_err(range_error)?;
+    Ok(0)
+}
+
+/// Resize the mapping at `old_addr`, growing it in place if the space after
+/// it is free and moving it elsewhere otherwise, if allowed to.
+pub fn sys_mremap(old_addr: usize, old_len: usize, new_len: usize, flags: usize, new_addr: usize) -> SysResult<usize> {
+    let flags = MremapFlags::from_bits(flags).ok_or(Errno::EINVAL)?;
+    if old_addr % PAGE_SIZE != 0
+        || old_len == 0
+        || new_len == 0
+        || (flags.contains(MremapFlags::FIXED) && !flags.contains(MremapFlags::MAYMOVE))
+    {
+        return Err(Errno::EINVAL);
+    }
+    let (old, old_len, new_len) = (VirtAddr(old_addr), page_len(old_len)?, page_len(new_len)?);
+    let memory_set = current_memory_set()?;
+    let mut memory_set = memory_set.lock();
+    let area = memory_set.find_area(old).ok_or(Errno::EFAULT)?;
+    if area.end() < old + old_len {
+        return Err(Errno::EFAULT);
+    }
+    let area_start = area.start();
+    let in_place = area.end() == old + old_len;
+
+    if flags.contains(MremapFlags::FIXED) {
+        let new = VirtAddr(new_addr);
+        if new.offset() != 0 || (new_addr < old_addr + old_len && old_addr < new_addr + new_len) {
+            return Err(Errno::EINVAL);
+        }
+        memory_set.remove(new, new_len)?;
+        if new_len < old_len {
+            memory_set.remove(old + new_len, old_len - new_len)?;
+        }
+        memory_set
+            .move_range(old, old_len.min(new_len), new)
+            .map_err(|_| Errno::ENOMEM)?;
+        memory_set.grow_area(new, new + new_len).map_err(|_| Errno::ENOMEM)?;
+        return Ok(new_addr);
+    }
+    if new_len <= old_len {
+        memory_set.remove(old + new_len, old_len - new_len)?;
+        return Ok(old_addr);
+    }
+    if in_place && memory_set.grow_area(area_start, old + new_len).is_ok() {
+        return Ok(old_addr);
+    }
+    if !flags.contains(MremapFlags::MAYMOVE) {
+        return Err(Errno::ENOMEM);
+    }
+    let new = memory_set.find_free_range(new_len, None).ok_or(Errno::ENOMEM)?;
+    memory_set.move_range(old, old_len, new).map_err(|_| Errno::ENOMEM)?;
+    memory_set.grow_area(new, new + new_len).map_err(|_| Errno::ENOMEM)?;
+    Ok(new.0)
+}
+
+pub fn sys_madvise(addr: usize, len: usize, advice: usize) -> SysResult<usize> {
+    if addr % PAGE_SIZE != 0 {
+        return Err(Errno::EINVAL);
+    }
+    match advice {
+        MADV_DONTNEED | MADV_FREE => {
+            current_memory_set()?
+                .lock()
+                .discard(VirtAddr(addr), len)
+                .map_err(range_error)?;
+            Ok(0)
+        }
+        // Hints there is nothing to act on for.
+        MADV_NORMAL..=MADV_WILLNEED | MADV_DONTFORK..=MADV_DODUMP => Ok(0),
+        _ => Err(Errno::EINVAL),
+    }
+}
+
+pub fn sys_msync(addr: usize, len: usize, flags: usize) -> SysResult<usize> {
+    let flags = MsyncFlags::from_bits(flags).ok_or(Errno::EINVAL)?;
+    if addr % PAGE_SIZE != 0 || flags.contains(MsyncFlags::ASYNC | MsyncFlags::SYNC) {
+        return Err(Errno::EINVAL);
+    }
+    // Every shared mapping of a file maps the same pages, so there is no
+    // other mapping to invalidate, and asynchronous writeback is done right
+    // away.
+    current_memory_set()?
+        .lock()
+        .sync(VirtAddr(addr), len)
+        .map_err(range_error)?;
+    Ok(0)
+}
@@ -0,0 +1,101 @@
+//! System calls, with the numbers and calling convention of riscv64 Linux.
+//!
+//! The number is passed in `a7` and up to six arguments in `a0`–`a5`. The
+//! result goes back in `a0`, errors as a negated errno.
+
+mod errno;
+mod fs;
+mod misc;
+mod mm;
+mod nr;
+mod process;
+mod signal;
+mod time;
+mod tty;
+mod uaccess;
+
+use log::trace;
+
+pub use self::errno::Errno;
+
+use self::{fs::*, misc::*, mm::*, nr::*, process::*, signal::*, time::*, tty::*};
+
+pub type SysResult<T> = Result<T, Errno>;
+
+/// Run system call `id` for the current task.
+pub fn syscall(id: usize, args: [usize; 6]) -> isize {
+    let result = match id {
+        SYS_CLOSE => sys_close(args[0]),
+        SYS_READ => sys_read(args[0], args[1], args[2]),
+        SYS_WRITE => sys_write(args[0], args[1], args[2]),
+        SYS_READV => sys_readv(args[0], args[1], args[2]),
+        SYS_WRITEV => sys_writev(args[0], args[1], args[2]),
+        SYS_PREAD64 => sys_pread64(args[0], args[1], args[2], args[3]),
+        SYS_PWRITE64 => sys_pwrite64(args[0], args[1], args[2], args[3]),
+        SYS_LSEEK => sys_lseek(args[0], args[1], args[2]),
+        SYS_OPENAT => sys_openat(args[0], args[1], args[2], args[3]),
+        SYS_DUP => sys_dup(args[0]),
+        SYS_DUP3 => sys_dup3(args[0], args[1], args[2]),
+        SYS_FCNTL => sys_fcntl(args[0], args[1], args[2]),
+        SYS_IOCTL => sys_ioctl(args[0], args[1], args[2]),
+        SYS_GETCWD => sys_getcwd(args[0], args[1]),
+        SYS_CHDIR => sys_chdir(args[0]),
+        SYS_FCHDIR => sys_fchdir(args[0]),
+        SYS_CHROOT => sys_chroot(args[0]),
+        SYS_MKDIRAT => sys_mkdirat(args[0], args[1], args[2]),
+        SYS_UNLINKAT => sys_unlinkat(args[0], args[1], args[2]),
+        SYS_SYMLINKAT => sys_symlinkat(args[0], args[1], args[2]),
+        SYS_LINKAT => sys_linkat(args[0], args[1], args[2], args[3], args[4]),
+        SYS_READLINKAT => sys_readlinkat(args[0], args[1], args[2], args[3]),
+        SYS_RENAMEAT2 => sys_renameat2(args[0], args[1], args[2], args[3], args[4]),
+        SYS_GETDENTS64 => sys_getdents64(args[0], args[1], args[2]),
+        SYS_FSTAT => sys_fstat(args[0], args[1]),
+        SYS_NEWFSTATAT => sys_newfstatat(args[0], args[1], args[2], args[3]),
+        SYS_FACCESSAT => sys_faccessat(args[0], args[1], args[2], args[3]),
+        SYS_FTRUNCATE => sys_ftruncate(args[0], args[1]),
+        SYS_FSYNC => sys_fsync(args[0]),
+        SYS_MOUNT => sys_mount(args[0], args[1], args[2], args[3], args[4]),
+        SYS_UMOUNT2 => sys_umount2(args[0], args[1]),
+        SYS_EXIT => sys_exit(args[0]),
+        SYS_EXIT_GROUP => sys_exit_group(args[0]),
+        SYS_SET_TID_ADDRESS => sys_set_tid_address(args[0]),
+        SYS_NANOSLEEP => sys_nanosleep(args[0], args[1]),
+        SYS_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1]),
+        SYS_SCHED_YIELD => sys_sched_yield(),
+        SYS_TIMES => sys_times(args[0]),
+        SYS_UNAME => sys_uname(args[0]),
+        SYS_GETTIMEOFDAY => sys_gettimeofday(args[0]),
+        SYS_SETPGID => sys_setpgid(args[0], args[1]),
+        SYS_GETPGID => sys_getpgid(args[0]),
+        SYS_GETSID => sys_getsid(args[0]),
+        SYS_SETSID => sys_setsid(),
+        SYS_GETPID => sys_getpid(),
+        SYS_GETPPID => sys_getppid(),
+        SYS_GETUID => sys_getuid(),
+        SYS_GETEUID => sys_geteuid(),
+        SYS_GETGID => sys_getgid(),
+        SYS_GETEGID => sys_getegid(),
+        SYS_GETTID => sys_gettid(),
+        SYS_MUNMAP => sys_munmap(args[0], args[1]),
+        SYS_CLONE => sys_clone(args[0], args[1], args[2], args[3], args[4]),
+        SYS_EXECVE => sys_execve(args[0], args[1], args[2]),
+        SYS_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
+        SYS_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
+        SYS_BRK => sys_brk(args[0]),
+        SYS_MREMAP => sys_mremap(args[0], args[1], args[2], args[3], args[4]),
+        SYS_MADVISE => sys_madvise(args[0], args[1], args[2]),
+        SYS_MSYNC => sys_msync(args[0], args[1], args[2]),
+        SYS_WAIT4 => sys_wait4(args[0] as isize, args[1], args[2], args[3]),
+        SYS_KILL => sys_kill(args[0] as isize, args[1]),
+        SYS_RT_SIGACTION => sys_rt_sigaction(args[0], args[1], args[2], args[3]),
+        SYS_RT_SIGPROCMASK => sys_rt_sigprocmask(args[0], args[1], args[2], args[3]),
+        _ => {
+            trace!("Unknown syscall {}, args: {:x?}", id, args);
+            Err(Errno::ENOSYS)
+        }
+    };
+    match result {
+        Ok(ret) => ret as isize,
+        Err(errno) => -(errno as isize),
+    }
+}
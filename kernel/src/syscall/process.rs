@@ -0,0 +1,361 @@
+use alloc::sync::Arc;
+use bitflags::bitflags;
+use log::debug;
+use spin::Mutex;
+
+use crate::{
+    cpu,
+    fs::FileBacking,
+    loader,
+    mm::{
+        addr::VirtAddr,
+        memory_set::{AddressSpace, BackingFile, MemorySet},
+    },
+    sched,
+    task::{
+        self,
+        signal::{NSIG, SIGKILL},
+        Process, Task, WaitResult,
+    },
+    trap::Context,
+};
+
+use super::{
+    fs::open_path,
+    uaccess::{read_str, read_str_array, write_value, PATH_MAX},
+    Errno, SysResult,
+};
+
+bitflags! {
+    #[derive(Clone, Copy, Debug)]
+    struct CloneFlags: usize {
+        const VM = 0x100;
+        const FS = 0x200;
+        const FILES = 0x400;
+        const SIGHAND = 0x800;
+        const VFORK = 0x4000;
+        const PARENT = 0x8000;
+        const THREAD = 0x10000;
+        const SETTLS = 0x80000;
+        const PARENT_SETTID = 0x100000;
+        const CHILD_CLEARTID = 0x200000;
+        const CHILD_SETTID = 0x1000000;
+    }
+}
+
+/// The signal sent to the parent on exit, in the low byte of the flags.
+const CSIGNAL: usize = 0xff;
+
+const WNOHANG: usize = 1;
+const WUNTRACED: usize = 2;
+const WCONTINUED: usize = 8;
+
+/// End the calling thread.
+pub fn sys_exit(code: usize) -> ! {
+    task::exit_current(task::exit_status(code as i32))
+}
+
+/// End every thread of the calling process.
+pub fn sys_exit_group(code: usize) -> ! {
+    task::exit_group(task::exit_status(code as i32))
+}
+
+pub fn sys_sched_yield() -> SysResult<usize> {
+    sched::yield_now();
+    Ok(0)
+}
+
+pub fn sys_getpid() -> SysResult<usize> {
+    Ok(task::current().process().pid())
+}
+
+pub fn sys_getppid() -> SysResult<usize> {
+    Ok(task::current().process().ppid())
+}
+
+/// The process `pid` names for the calls taking 0 for the caller.
+fn process_or_current(pid: usize) -> SysResult<Arc<Process>> {
+    match pid {
+        0 => Ok(task::current().process().clone()),
+        pid => task::find_process(pid).ok_or(Errno::ESRCH),
+    }
+}
+
+/// Move process `pid`, the caller or one of its children, to group `pgid`
+/// of its session. A process makes a new group with its own pid.
+pub fn sys_setpgid(pid: usize, pgid: usize) -> SysResult<usize> {
+    let current = task::current().process().clone();
+    let target = match pid {
+        0 => current.clone(),
+        pid if pid == current.pid() => current.clone(),
+        pid => current
+            .inner()
+            .children
+            .iter()
+            .find(|child| child.pid() == pid)
+            .cloned()
+            .ok_or(Errno::ESRCH)?,
+    };
+    if (pgid as isize) < 0 {
+        return Err(Errno::EINVAL);
+    }
+    let pgid = if pgid == 0 { target.pid() } else { pgid };
+    let sid = current.inner().sid;
+    let (target_sid, target_pgid) = {
+        let inner = target.inner();
+        (inner.sid, inner.pgid)
+    };
+    if target_sid != sid || target_sid == target.pid() {
+        return Err(Errno::EPERM);
+    }
+    if pgid != target.pid() && pgid != target_pgid {
+        let exists = task::all_processes().iter().any(|other| {
+            let inner = other.inner();
+            inner.pgid == pgid && inner.sid == sid
+        });
+        if !exists {
+            return Err(Errno::EPERM);
+        }
+    }
+    target.inner().pgid = pgid;
+    Ok(0)
+}
+
+pub fn sys_getpgid(pid: usize) -> SysResult<usize> {
+    Ok(process_or_current(pid)?.inner().pgid)
+}
+
+pub fn sys_getsid(pid: usize) -> SysResult<usize> {
+    Ok(process_or_current(pid)?.inner().sid)
+}
+
+/// Make the caller the leader of a new session and process group, without
+/// a controlling terminal. A group leader cannot, since its group would be
+/// split across sessions.
+pub fn sys_setsid() -> SysResult<usize> {
+    let process = task::current().process().clone();
+    let pid = process.pid();
+    if task::all_processes().iter().any(|other| other.inner().pgid == pid) {
+        return Err(Errno::EPERM);
+    }
+    let mut inner = process.inner();
+    inner.sid = pid;
+    inner.pgid = pid;
+    inner.tty = None;
+    Ok(pid)
+}
+
+pub fn sys_gettid() -> SysResult<usize> {
+    Ok(task::current().tid())
+}
+
+pub fn sys_getuid() -> SysResult<usize> {
+    Ok(task::current().process().inner().cred.uid as usize)
+}
+
+pub fn sys_geteuid() -> SysResult<usize> {
+    Ok(task::current().process().inner().cred.euid as usize)
+}
+
+pub fn sys_getgid() -> SysResult<usize> {
+    Ok(task::current().process().inner().cred.gid as usize)
+}
+
+pub fn sys_getegid() -> SysResult<usize> {
+    Ok(task::current().process().inner().cred.egid as usize)
+}
+
+pub fn sys_set_tid_address(tidptr: usize) -> SysResult<usize> {
+    let task = task::current();
+    task.inner().clear_child_tid = tidptr;
+    Ok(task.tid())
+}
+
+/// Create a thread or process. A new process sends its parent the signal in
+/// the low byte of `flags` when it exits.
+pub fn sys_clone(flags: usize, stack: usize, ptid: usize, tls: usize, ctid: usize) -> SysResult<usize> {
+    let exit_signal = flags & CSIGNAL;
+    let flags = CloneFlags::from_bits_truncate(flags & !CSIGNAL);
+    if exit_signal > NSIG {
+        return Err(Errno::EINVAL);
+    }
+    if flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::SIGHAND)
+        || flags.contains(CloneFlags::SIGHAND) && !flags.contains(CloneFlags::VM)
+    {
+        return Err(Errno::EINVAL);
+    }
+    let current = task::current();
+    let mut context = *current.trap_context();
+    context.regs[10] = 0;
+    if stack != 0 {
+        context.regs[2] = stack;
+    }
+    if flags.contains(CloneFlags::SETTLS) {
+        context.regs[4] = tls;
+    }
+
+    let tid = Arc::new(task::alloc_pid().ok_or(Errno::EAGAIN)?);
+    let child = if flags.contains(CloneFlags::THREAD) {
+        Task::new(tid, current.process().clone(), context)?
+    } else {
+        let parent = current.process();
+        let (memory_set, files, fs, sig_actions) = {
+            let inner = parent.inner();
+            let files = if flags.contains(CloneFlags::FILES) {
+                inner.files.clone()
+            } else {
+                Arc::new(Mutex::new(inner.files.lock().clone()))
+            };
+            let fs = if flags.contains(CloneFlags::FS) {
+                inner.fs.clone()
+            } else {
+                Arc::new(Mutex::new(inner.fs.lock().clone()))
+            };
+            let sig_actions = if flags.contains(CloneFlags::SIGHAND) {
+                inner.sig_actions.clone()
+            } else {
+                Arc::new(Mutex::new(inner.sig_actions.lock().clone()))
+            };
+            // Only gone if another thread is exiting the process.
+            let memory_set = inner.memory_set.clone().ok_or(Errno::EINTR)?;
+            (memory_set, files, fs, sig_actions)
+        };
+        // Copying the address space may sleep, so not with the process locked.
+        let memory_set = if flags.contains(CloneFlags::VM) {
+            memory_set
+        } else {
+            Arc::new(AddressSpace::new(memory_set.lock().fork()?))
+        };
+        let parent = match flags.contains(CloneFlags::PARENT) {
+            true => parent.parent().ok_or(Errno::EINVAL)?,
+            false => parent.clone(),
+        };
+        let process = Process::new(tid.clone(), Some(&parent), memory_set, files, fs, sig_actions);
+        process.inner().exit_signal = exit_signal;
+        Task::new(tid, process, context)?
+    };
+    child.inner().sig_blocked = current.inner().sig_blocked;
+    let child_tid = child.tid();
+
+    if flags.contains(CloneFlags::PARENT_SETTID) {
+        write_value(ptid, &(child_tid as u32))?;
+    }
+    if flags.contains(CloneFlags::CHILD_SETTID) {
+        child
+            .process()
+            .memory_set()
+            .ok_or(Errno::EINTR)?
+            .lock()
+            .write_bytes(VirtAddr(ctid), &(child_tid as u32).to_ne_bytes())
+            .map_err(|_| Errno::EFAULT)?;
+    }
+    if flags.contains(CloneFlags::CHILD_CLEARTID) {
+        child.inner().clear_child_tid = ctid;
+    }
+    let vfork = flags.contains(CloneFlags::VFORK);
+    if vfork {
+        child.inner().vfork_parent = Some(Arc::downgrade(current.process()));
+    }
+    debug!("Task {} cloned task {} with {:?}", current.tid(), child_tid, flags);
+    sched::add_task(child.clone());
+
+    if vfork {
+        // The child runs on our memory until it lets us go.
+        current
+            .process()
+            .child_exited()
+            .wait_until(|| child.inner().vfork_parent.is_none());
+    }
+    Ok(child_tid)
+}
+
+/// Replace the program of the calling process.
+pub fn sys_execve(path: usize, argv: usize, envp: usize) -> SysResult<usize> {
+    let path = read_str(path, PATH_MAX)?;
+    let mut args = read_str_array(argv)?;
+    let envs = read_str_array(envp)?;
+    if args.is_empty() {
+        args.push(path.clone());
+    }
+    let file = open_path(&path)?;
+    let exe = file.dentry();
+    let open = |path: &str| -> Option<Arc<dyn BackingFile>> {
+        let file = open_path(path).ok()?;
+        Some(Arc::new(FileBacking(file)))
+    };
+    let mut memory_set = MemorySet::new()?;
+    let image = loader::load(&mut memory_set, &FileBacking(file), &args, &envs, &open)?;
+    memory_set.init_brk(VirtAddr(image.brk));
+    debug!("execve {} {:?}", path, args);
+
+    let current = task::current();
+    let process = current.process().clone();
+    for thread in process.threads() {
+        if !Arc::ptr_eq(&thread, &current) {
+            thread.send_signal(SIGKILL);
+            sched::wake(&thread);
+        }
+    }
+    // They may still be running on the address space replaced below. A
+    // thread killed meanwhile by another one goes no further.
+    process
+        .thread_exited()
+        .wait_until(|| process.inner().threads.len() == 1 || task::fatal_signal_pending());
+    if task::fatal_signal_pending() {
+        return Err(Errno::EINTR);
+    }
+    let memory_set = Arc::new(AddressSpace::new(memory_set));
+    // Released once the process is unlocked, as writing back the old
+    // address space and closed files may sleep.
+    let (old, closed) = {
+        let mut inner = process.inner();
+        let old = inner.memory_set.replace(memory_set.clone());
+        inner.exe = exe;
+        let closed = inner.files.lock().close_on_exec();
+        let mut sig_actions = inner.sig_actions.lock().clone();
+        sig_actions.reset_handlers();
+        inner.sig_actions = Arc::new(Mutex::new(sig_actions));
+        (old, closed)
+    };
+    cpu::current().set_memory_set(Some(memory_set));
+    drop((old, closed));
+    current.release_vfork_parent();
+
+    let context = current.trap_context();
+    *context = Context::new_user(image.entry, image.sp);
+    context.kernel_sp = current.kernel_stack_top();
+    Ok(0)
+}
+
+/// Wait for a child to exit, or to stop with `WUNTRACED` or continue with
+/// `WCONTINUED`.
+pub fn sys_wait4(pid: isize, wstatus: usize, options: usize, _rusage: usize) -> SysResult<usize> {
+    let current = task::current();
+    let process = current.process().clone();
+    let pgid = process.inner().pgid;
+    let matches = |child: &Process| match pid {
+        -1 => true,
+        0 => child.inner().pgid == pgid,
+        pid if pid > 0 => child.pid() == pid as usize,
+        pid => child.inner().pgid == pid.unsigned_abs(),
+    };
+    let mut result = WaitResult::Running;
+    let mut interrupted = false;
+    let condition = || {
+        result = process.reap_child(matches, options & WUNTRACED != 0, options & WCONTINUED != 0);
+        interrupted = task::signal_pending();
+        result != WaitResult::Running || options & WNOHANG != 0 || interrupted
+    };
+    process.child_exited().wait_until(condition);
+    match result {
+        WaitResult::NoChild => Err(Errno::ECHILD),
+        WaitResult::Running if interrupted => Err(Errno::EINTR),
+        WaitResult::Running => Ok(0),
+        WaitResult::Exited { pid, status } | WaitResult::Changed { pid, status } => {
+            if wstatus != 0 {
+                write_value(wstatus, &status)?;
+            }
+            Ok(pid)
+        }
+    }
+}
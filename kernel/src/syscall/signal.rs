@@ -0,0 +1,130 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::task::{
+    self,
+    signal::{default_action, DefaultAction, SigAction, SigSet, NSIG, SIGKILL, SIGSTOP, SIG_DFL, SIG_IGN},
+    Process,
+};
+
+use super::{
+    uaccess::{read_value, write_value},
+    Errno, SysResult,
+};
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+/// `sigset_t` of the kernel ABI, one bit per signal.
+const SIGSET_SIZE: usize = 8;
+
+/// The processes `kill` sends to for `pid`: the one with that pid, the
+/// caller's group for 0, every process but init and the caller for -1, and
+/// group `-pid` below that.
+fn kill_targets(pid: isize) -> Vec<Arc<Process>> {
+    let current = task::current().process().clone();
+    if pid > 0 {
+        return task::find_process(pid as usize).into_iter().collect();
+    }
+    let pgid = if pid == 0 { current.inner().pgid } else { pid.unsigned_abs() };
+    task::all_processes()
+        .into_iter()
+        .filter(|process| {
+            let inner = process.inner();
+            let matches = if pid == -1 {
+                process.pid() != 1 && !Arc::ptr_eq(process, &current)
+            } else {
+                inner.pgid == pgid
+            };
+            matches && inner.exit_status.is_none()
+        })
+        .collect()
+}
+
+/// Send `sig` to the processes `pid` names, see [`kill_targets`]. Signal 0
+/// only checks that there is one.
+pub fn sys_kill(pid: isize, sig: usize) -> SysResult<usize> {
+    if sig > NSIG {
+        return Err(Errno::EINVAL);
+    }
+    let targets = kill_targets(pid);
+    if targets.is_empty() {
+        return Err(Errno::ESRCH);
+    }
+    if sig != 0 {
+        for process in targets {
+            process.kill(sig);
+        }
+    }
+    Ok(0)
+}
+
+/// Whether a signal with `action` is discarded rather than delivered.
+fn is_ignored(sig: usize, action: &SigAction) -> bool {
+    match action.handler {
+        SIG_IGN => true,
+        SIG_DFL => default_action(sig) == DefaultAction::Ignore,
+        _ => false,
+    }
+}
+
+/// Read and change the disposition of `sig`. Handlers are recorded, but
+/// signals they would catch take their default action.
+pub fn sys_rt_sigaction(sig: usize, act: usize, oldact: usize, sigsetsize: usize) -> SysResult<usize> {
+    if sigsetsize != SIGSET_SIZE || !(1..=NSIG).contains(&sig) {
+        return Err(Errno::EINVAL);
+    }
+    let new = match act {
+        0 => None,
+        _ if sig == SIGKILL || sig == SIGSTOP => return Err(Errno::EINVAL),
+        act => Some(read_value::<SigAction>(act)?),
+    };
+    let process = task::current().process().clone();
+    let actions = process.inner().sig_actions.clone();
+    let old = actions.lock().get(sig);
+    if let Some(mut new) = new {
+        new.mask.remove(SIGKILL);
+        new.mask.remove(SIGSTOP);
+        actions.lock().set(sig, new);
+        // Signals already pending are discarded once ignored.
+        if is_ignored(sig, &new) {
+            process.inner().sig_pending.remove(sig);
+            for thread in process.threads() {
+                thread.inner().sig_pending.remove(sig);
+            }
+        }
+    }
+    if oldact != 0 {
+        write_value(oldact, &old)?;
+    }
+    Ok(0)
+}
+
+/// Read and change the signals the calling thread blocks. `SIGKILL` and
+/// `SIGSTOP` cannot be blocked and are left out silently.
+pub fn sys_rt_sigprocmask(how: usize, set: usize, oldset: usize, sigsetsize: usize) -> SysResult<usize> {
+    if sigsetsize != SIGSET_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let new = match set {
+        0 => None,
+        set => Some(read_value::<SigSet>(set)?),
+    };
+    let current = task::current();
+    let old = current.inner().sig_blocked;
+    if let Some(set) = new {
+        let mut blocked = match how {
+            SIG_BLOCK => SigSet(old.0 | set.0),
+            SIG_UNBLOCK => SigSet(old.0 & !set.0),
+            SIG_SETMASK => set,
+            _ => return Err(Errno::EINVAL),
+        };
+        blocked.remove(SIGKILL);
+        blocked.remove(SIGSTOP);
+        current.inner().sig_blocked = blocked;
+    }
+    if oldset != 0 {
+        write_value(oldset, &old)?;
+    }
+    Ok(0)
+}
@@ -0,0 +1,97 @@
+use crate::{
+    sched,
+    timer::{
+        self,
+        consts::{NSEC_PER_SEC, USEC_PER_SEC},
+    },
+};
+
+use super::{
+    uaccess::{read_value, write_value},
+    Errno, SysResult,
+};
+
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_BOOTTIME: usize = 7;
+
+/// `struct timespec`.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct TimeSpec {
+    sec: usize,
+    nsec: usize,
+}
+
+impl TimeSpec {
+    fn from_ns(ns: usize) -> Self {
+        Self {
+            sec: ns / NSEC_PER_SEC,
+            nsec: ns % NSEC_PER_SEC,
+        }
+    }
+
+    fn as_ns(&self) -> usize {
+        self.sec.saturating_mul(NSEC_PER_SEC).saturating_add(self.nsec)
+    }
+}
+
+/// `struct timeval`.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct TimeVal {
+    sec: usize,
+    usec: usize,
+}
+
+/// `struct tms`.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct Tms {
+    utime: usize,
+    stime: usize,
+    cutime: usize,
+    cstime: usize,
+}
+
+/// Every clock counts from boot, there is no real-time clock to start from.
+pub fn sys_clock_gettime(clock: usize, tp: usize) -> SysResult<usize> {
+    if !(CLOCK_REALTIME..=CLOCK_BOOTTIME).contains(&clock) {
+        return Err(Errno::EINVAL);
+    }
+    write_value(tp, &TimeSpec::from_ns(timer::get_time_ns()))?;
+    Ok(0)
+}
+
+pub fn sys_gettimeofday(tv: usize) -> SysResult<usize> {
+    let us = timer::get_time_ns() / (NSEC_PER_SEC / USEC_PER_SEC);
+    let time = TimeVal {
+        sec: us / USEC_PER_SEC,
+        usec: us % USEC_PER_SEC,
+    };
+    write_value(tv, &time)?;
+    Ok(0)
+}
+
+/// CPU time is not accounted yet, only the ticks since boot are real.
+pub fn sys_times(buf: usize) -> SysResult<usize> {
+    if buf != 0 {
+        write_value(buf, &Tms::default())?;
+    }
+    Ok(timer::get_ticks())
+}
+
+/// Sleep by yielding until the deadline has passed.
+pub fn sys_nanosleep(req: usize, rem: usize) -> SysResult<usize> {
+    let req: TimeSpec = read_value(req)?;
+    if req.nsec >= NSEC_PER_SEC {
+        return Err(Errno::EINVAL);
+    }
+    let deadline = timer::get_time_ns().saturating_add(req.as_ns());
+    while timer::get_time_ns() < deadline {
+        sched::yield_now();
+    }
+    if rem != 0 {
+        write_value(rem, &TimeSpec::default())?;
+    }
+    Ok(0)
+}
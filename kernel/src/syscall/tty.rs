@@ -0,0 +1,90 @@
+//! `ioctl`, of which the terminal requests are supported.
+
+use alloc::sync::Arc;
+
+use crate::{
+    drivers::chardev::{Termios, Tty, WinSize},
+    task::{self, Process},
+};
+
+use super::{
+    fs::file,
+    uaccess::{read_value, write_value},
+    Errno, SysResult,
+};
+
+const TCGETS: usize = 0x5401;
+const TCSETS: usize = 0x5402;
+const TCSETSW: usize = 0x5403;
+const TCSETSF: usize = 0x5404;
+const TIOCSCTTY: usize = 0x540e;
+const TIOCGPGRP: usize = 0x540f;
+const TIOCSPGRP: usize = 0x5410;
+const TIOCGWINSZ: usize = 0x5413;
+const TIOCSWINSZ: usize = 0x5414;
+const TIOCGSID: usize = 0x5429;
+
+/// Whether `tty` is the controlling terminal of `process`.
+fn controls(tty: &Arc<Tty>, process: &Process) -> bool {
+    let inner = process.inner();
+    inner.tty.as_ref().is_some_and(|ctty| Arc::ptr_eq(ctty, tty)) && tty.controls(inner.sid)
+}
+
+pub fn sys_ioctl(fd: usize, request: usize, arg: usize) -> SysResult<usize> {
+    let tty = file(fd)?.tty().ok_or(Errno::ENOTTY)?;
+    let process = task::current().process().clone();
+    // The request is an `unsigned int`.
+    match request & 0xffff_ffff {
+        TCGETS => write_value(arg, &tty.termios())?,
+        // Output goes to the console as it is written, so there is none
+        // for `TCSETSW` to wait for.
+        request @ (TCSETS | TCSETSW | TCSETSF) => {
+            let termios: Termios = read_value(arg)?;
+            tty.set_termios(termios, request == TCSETSF);
+        }
+        TIOCGWINSZ => write_value(arg, &tty.winsize())?,
+        TIOCSWINSZ => {
+            let winsize: WinSize = read_value(arg)?;
+            tty.set_winsize(winsize);
+        }
+        TIOCSCTTY => {
+            if !tty.attach(&process, arg == 1) {
+                return Err(Errno::EPERM);
+            }
+        }
+        TIOCGPGRP => {
+            if !controls(&tty, &process) {
+                return Err(Errno::ENOTTY);
+            }
+            write_value(arg, &(tty.foreground() as i32))?;
+        }
+        TIOCSPGRP => {
+            if !controls(&tty, &process) {
+                return Err(Errno::ENOTTY);
+            }
+            let pgrp: i32 = read_value(arg)?;
+            if pgrp < 0 {
+                return Err(Errno::EINVAL);
+            }
+            let pgrp = pgrp as usize;
+            let sid = process.inner().sid;
+            // The group must be one of the session.
+            let exists = task::all_processes().iter().any(|other| {
+                let inner = other.inner();
+                inner.pgid == pgrp && inner.sid == sid
+            });
+            if !exists {
+                return Err(Errno::EPERM);
+            }
+            tty.set_foreground(pgrp);
+        }
+        TIOCGSID => {
+            if !controls(&tty, &process) {
+                return Err(Errno::ENOTTY);
+            }
+            write_value(arg, &(process.inner().sid as i32))?;
+        }
+        _ => return Err(Errno::ENOTTY),
+    }
+    Ok(0)
+}
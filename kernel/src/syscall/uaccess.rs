@@ -0,0 +1,108 @@
+//! Access to the memory of the current process.
+//!
+//! The kernel runs on its own page table, so user pointers are never
+//! dereferenced directly. Every access translates through the address space
+//! of the current hart, faulting pages in as the user would have.
+
+use alloc::{string::String, vec::Vec};
+use core::mem::{size_of, MaybeUninit};
+
+use crate::mm::{addr::VirtAddr, consts::PAGE_SIZE, memory_set, MmError};
+
+use super::{Errno, SysResult};
+
+/// Longest path a system call accepts, with its terminator.
+pub const PATH_MAX: usize = 4096;
+
+/// Longest single argument or environment string, `MAX_ARG_STRLEN`, with
+/// its terminator.
+const ARG_MAX_LEN: usize = 32 * PAGE_SIZE;
+
+/// Most strings in an `argv` or `envp` array.
+const ARGS_MAX: usize = 0x10000;
+
+fn fault(e: MmError) -> Errno {
+    match e {
+        MmError::OutOfMemory => Errno::ENOMEM,
+        _ => Errno::EFAULT,
+    }
+}
+
+pub fn copy_from_user(va: usize, buf: &mut [u8]) -> SysResult<()> {
+    memory_set::current()
+        .ok_or(Errno::EFAULT)?
+        .lock()
+        .read_bytes(VirtAddr(va), buf)
+        .map_err(fault)
+}
+
+pub fn copy_to_user(va: usize, buf: &[u8]) -> SysResult<()> {
+    memory_set::current()
+        .ok_or(Errno::EFAULT)?
+        .lock()
+        .write_bytes(VirtAddr(va), buf)
+        .map_err(fault)
+}
+
+/// Read a `T` from user memory. `T` must be valid for any bit pattern.
+pub fn read_value<T: Copy>(va: usize) -> SysResult<T> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes = unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    copy_from_user(va, bytes)?;
+    Ok(unsafe { value.assume_init() })
+}
+
+pub fn write_value<T: Copy>(va: usize, value: &T) -> SysResult<()> {
+    let bytes = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(va, bytes)
+}
+
+/// Read a NUL-terminated string of at most `max` bytes, counting the
+/// terminator as `PATH_MAX` and `MAX_ARG_STRLEN` do.
+pub fn read_str(va: usize, max: usize) -> SysResult<String> {
+    let mut bytes = Vec::new();
+    // Read up to a page boundary at a time, never touching the page after
+    // the terminator.
+    let mut chunk = [0u8; 256];
+    loop {
+        let start = va + bytes.len();
+        let len = chunk.len().min(PAGE_SIZE - start % PAGE_SIZE);
+        copy_from_user(start, &mut chunk[..len])?;
+        match chunk[..len].iter().position(|&byte| byte == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&chunk[..end]);
+                break;
+            }
+            None => bytes.extend_from_slice(&chunk[..len]),
+        }
+        if bytes.len() >= max {
+            return Err(Errno::ENAMETOOLONG);
+        }
+    }
+    if bytes.len() >= max {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
+
+/// Read a NULL-terminated array of string pointers, like `argv`. A null
+/// array is taken as empty.
+pub fn read_str_array(va: usize) -> SysResult<Vec<String>> {
+    let mut strings = Vec::new();
+    if va == 0 {
+        return Ok(strings);
+    }
+    loop {
+        let ptr: usize = read_value(va + strings.len() * size_of::<usize>())?;
+        if ptr == 0 {
+            return Ok(strings);
+        }
+        if strings.len() == ARGS_MAX {
+            return Err(Errno::E2BIG);
+        }
+        strings.push(read_str(ptr, ARG_MAX_LEN).map_err(|e| match e {
+            Errno::ENAMETOOLONG => Errno::E2BIG,
+            e => e,
+        })?);
+    }
+}
@@ -0,0 +1,95 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::fs::{File, FsError, FsResult};
+
+/// Highest number of open descriptors per process, `RLIMIT_NOFILE`.
+pub const MAX_FDS: usize = 1024;
+
+#[derive(Clone)]
+struct FdEntry {
+    file: Arc<dyn File>,
+    /// Closed on `execve`.
+    cloexec: bool,
+}
+
+/// The descriptor table of a process, shared by threads created with
+/// `CLONE_FILES`.
+#[derive(Clone, Default)]
+pub struct FdTable {
+    entries: Vec<Option<FdEntry>>,
+}
+
+impl FdTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, fd: usize) -> FsResult<Arc<dyn File>> {
+        self.entries
+            .get(fd)
+            .and_then(|entry| entry.as_ref())
+            .map(|entry| entry.file.clone())
+            .ok_or(FsError::BadDescriptor)
+    }
+
+    /// Install `file` at the lowest free descriptor not below `min`.
+    pub fn insert_from(&mut self, min: usize, file: Arc<dyn File>, cloexec: bool) -> FsResult<usize> {
+        let fd = (min..MAX_FDS)
+            .find(|&fd| self.entries.get(fd).is_none_or(|entry| entry.is_none()))
+            .ok_or(FsError::TooManyFiles)?;
+        self.insert_at(fd, file, cloexec)?;
+        Ok(fd)
+    }
+
+    pub fn insert(&mut self, file: Arc<dyn File>, cloexec: bool) -> FsResult<usize> {
+        self.insert_from(0, file, cloexec)
+    }
+
+    /// Install `file` at `fd`, returning what was there.
+    pub fn insert_at(&mut self, fd: usize, file: Arc<dyn File>, cloexec: bool) -> FsResult<Option<Arc<dyn File>>> {
+        if fd >= MAX_FDS {
+            return Err(FsError::BadDescriptor);
+        }
+        if fd >= self.entries.len() {
+            self.entries.resize(fd + 1, None);
+        }
+        let old = self.entries[fd].replace(FdEntry { file, cloexec });
+        Ok(old.map(|entry| entry.file))
+    }
+
+    pub fn remove(&mut self, fd: usize) -> FsResult<Arc<dyn File>> {
+        self.entries
+            .get_mut(fd)
+            .and_then(|entry| entry.take())
+            .map(|entry| entry.file)
+            .ok_or(FsError::BadDescriptor)
+    }
+
+    pub fn cloexec(&self, fd: usize) -> FsResult<bool> {
+        self.entries
+            .get(fd)
+            .and_then(|entry| entry.as_ref())
+            .map(|entry| entry.cloexec)
+            .ok_or(FsError::BadDescriptor)
+    }
+
+    pub fn set_cloexec(&mut self, fd: usize, cloexec: bool) -> FsResult<()> {
+        let entry = self
+            .entries
+            .get_mut(fd)
+            .and_then(|entry| entry.as_mut())
+            .ok_or(FsError::BadDescriptor)?;
+        entry.cloexec = cloexec;
+        Ok(())
+    }
+
+    /// Close every descriptor marked close-on-exec, returning their files.
+    pub fn close_on_exec(&mut self) -> Vec<Arc<dyn File>> {
+        self.entries
+            .iter_mut()
+            .filter(|entry| entry.as_ref().is_some_and(|entry| entry.cloexec))
+            .filter_map(|entry| entry.take())
+            .map(|entry| entry.file)
+            .collect()
+    }
+}
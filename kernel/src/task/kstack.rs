@@ -0,0 +1,29 @@
+use crate::{
+    config::TASK_KERNEL_STACK_SIZE,
+    mm::{addr::PhysPageNum, consts::PAGE_SIZE, frame, MmError, MmResult},
+};
+
+const STACK_FRAMES: usize = TASK_KERNEL_STACK_SIZE / PAGE_SIZE;
+
+/// The kernel stack of a task, in the direct map.
+pub struct KernelStack {
+    base: PhysPageNum,
+}
+
+impl KernelStack {
+    pub fn new() -> MmResult<Self> {
+        let base = frame::alloc_frames(STACK_FRAMES, 1).ok_or(MmError::OutOfMemory)?;
+        Ok(Self { base })
+    }
+
+    /// Initial stack pointer, the end of the stack.
+    pub fn top(&self) -> usize {
+        self.base.kva().0 + TASK_KERNEL_STACK_SIZE
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        frame::dealloc_frames(self.base, STACK_FRAMES);
+    }
+}
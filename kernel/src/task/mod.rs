@@ -0,0 +1,269 @@
+//! Processes and threads.
+//!
+//! A [`Process`] is a thread group owning an address space, a descriptor
+//! table, credentials and signal dispositions. A [`Task`] is one thread of
+//! it, with its own kernel stack and saved user registers. Processes form a
+//! tree; an exited process stays a zombie until its parent reaps it.
+
+mod files;
+mod kstack;
+mod pid;
+mod process;
+pub mod signal;
+#[allow(clippy::module_inception)]
+mod task;
+
+use alloc::{string::String, sync::Arc};
+use log::{info, warn};
+use spin::Mutex;
+
+use crate::{
+    cpu,
+    fs::{FileBacking, FsContext, OpenFlags},
+    loader::{self, LoadResult},
+    mm::{
+        addr::VirtAddr,
+        memory_set::{self, AddressSpace, MemorySet},
+    },
+    sched,
+    trap::Context,
+};
+
+use self::signal::{default_action, DefaultAction, SigSet, NSIG, SIGKILL, SIGSTOP, SIG_DFL, SIG_IGN};
+
+use self::{files::FdTable, signal::SigActions};
+
+pub use self::{
+    pid::alloc_pid,
+    process::{all_processes, find_process, signal_group, Process, WaitResult},
+    task::{Task, TaskStatus},
+};
+
+/// Programs tried in turn as the first user process, as Linux does.
+const INIT_PATHS: [&str; 4] = ["/sbin/init", "/etc/init", "/bin/init", "/bin/sh"];
+
+/// The task running on the current hart.
+pub fn current() -> Arc<Task> {
+    try_current().expect("No task running on this hart")
+}
+
+pub fn try_current() -> Option<Arc<Task>> {
+    cpu::current().task()
+}
+
+/// Wait status of a process that exited with `code`.
+pub fn exit_status(code: i32) -> i32 {
+    (code & 0xff) << 8
+}
+
+/// Wait status of a process killed by `sig`.
+pub fn signal_status(sig: usize, core_dumped: bool) -> i32 {
+    sig as i32 | if core_dumped { 0x80 } else { 0 }
+}
+
+/// Wait status of a process stopped by `sig`.
+pub fn stop_status(sig: usize) -> i32 {
+    (sig as i32) << 8 | 0x7f
+}
+
+/// Wait status of a stopped process that `SIGCONT` continued.
+pub const CONTINUED_STATUS: i32 = 0xffff;
+
+/// End the current thread. The process exits with wait status `status` if
+/// this was its last thread.
+pub fn exit_current(status: i32) -> ! {
+    let task = current();
+    let clear_child_tid = {
+        let mut inner = task.inner();
+        inner.exit_code = status;
+        core::mem::take(&mut inner.clear_child_tid)
+    };
+    if clear_child_tid != 0 {
+        // The thread library waits for this to become zero. Nothing to do
+        // if the address went away.
+        if let Some(memory_set) = memory_set::current() {
+            let _ = memory_set.lock().write_bytes(VirtAddr(clear_child_tid), &0u32.to_ne_bytes());
+        }
+    }
+    task.release_vfork_parent();
+    let process = task.process().clone();
+    // The thread is forgotten already if the whole process exited.
+    let last = {
+        let mut inner = process.inner();
+        let count = inner.threads.len();
+        inner
+            .threads
+            .retain(|thread| thread.strong_count() > 0 && thread.as_ptr() != Arc::as_ptr(&task));
+        inner.threads.len() < count && inner.threads.is_empty()
+    };
+    drop(task);
+    if last {
+        info!("Process {} exited with status 0x{:x}", process.pid(), status);
+        process.exit(status);
+    } else {
+        process.thread_exited().wake_all();
+    }
+    drop(process);
+    sched::exit_current()
+}
+
+/// End every thread of the current process, which exits with wait status
+/// `status`.
+pub fn exit_group(status: i32) -> ! {
+    let current = current();
+    let process = current.process().clone();
+    // Exiting forgets the threads, so they are taken first.
+    let threads = process.threads();
+    // The status is set before the other threads are killed, so whichever
+    // thread exits last does not override it.
+    process.exit(status);
+    for thread in threads {
+        if !Arc::ptr_eq(&thread, &current) {
+            thread.send_signal(SIGKILL);
+            sched::wake(&thread);
+        }
+    }
+    drop((current, process));
+    exit_current(status)
+}
+
+/// Whether the current task has a signal pending that is neither blocked
+/// nor ignored, which interrupts blocking system calls.
+pub fn signal_pending() -> bool {
+    let task = current();
+    let process = task.process().clone();
+    let inner = task.inner();
+    let process_inner = process.inner();
+    let actions = process_inner.sig_actions.lock();
+    let pending = SigSet(inner.sig_pending.0 | process_inner.sig_pending.0);
+    (1..=NSIG).any(|sig| {
+        pending.contains(sig)
+            && (!inner.sig_blocked.contains(sig) || sig == SIGKILL || sig == SIGSTOP)
+            && match actions.get(sig).handler {
+                SIG_IGN => false,
+                SIG_DFL => default_action(sig) != DefaultAction::Ignore,
+                _ => true,
+            }
+    })
+}
+
+/// Whether the current task or its process has been sent `SIGKILL`, so that
+/// it will not return to user mode.
+pub fn fatal_signal_pending() -> bool {
+    let task = current();
+    let process = task.process().clone();
+    let pending = task.inner().sig_pending.contains(SIGKILL);
+    pending || process.inner().sig_pending.contains(SIGKILL)
+}
+
+/// Act on the signals pending for the current task, before it returns to
+/// user mode.
+pub fn handle_signals() {
+    loop {
+        let task = current();
+        let process = task.process().clone();
+        if process.inner().stopped {
+            process.continued().wait_until(|| {
+                let stopped = process.inner().stopped;
+                !stopped || fatal_signal_pending()
+            });
+        }
+        let sig = {
+            let mut inner = task.inner();
+            let mut process_inner = process.inner();
+            let own = inner.sig_pending.first_unblocked(inner.sig_blocked);
+            match own {
+                Some(sig) => {
+                    inner.sig_pending.remove(sig);
+                    sig
+                }
+                None => match process_inner.sig_pending.first_unblocked(inner.sig_blocked) {
+                    Some(sig) => {
+                        process_inner.sig_pending.remove(sig);
+                        sig
+                    }
+                    None => return,
+                },
+            }
+        };
+        let action = process.inner().sig_actions.lock().get(sig);
+        match action.handler {
+            SIG_IGN => continue,
+            SIG_DFL => {}
+            _ => warn!("Signal handlers are not supported, signal {} takes its default action", sig),
+        }
+        match default_action(sig) {
+            DefaultAction::Terminate => {
+                drop((task, process));
+                exit_group(signal_status(sig, false))
+            }
+            DefaultAction::CoreDump => {
+                drop((task, process));
+                exit_group(signal_status(sig, true))
+            }
+            DefaultAction::Stop => process.stop(sig),
+            // Sending it continued the process already.
+            DefaultAction::Ignore | DefaultAction::Continue => {}
+        }
+    }
+}
+
+/// Start the first user process, which orphans are handed to, running the
+/// first of [`INIT_PATHS`] there is. Its standard streams are on the
+/// console, which becomes its controlling terminal.
+pub fn spawn_init() {
+    let fs = FsContext::new();
+    let found = INIT_PATHS
+        .iter()
+        .find_map(|&path| Some((path, fs.open(None, path, OpenFlags::RDONLY, 0).ok()?)));
+    let Some((path, file)) = found else {
+        warn!("No init program found, tried {:?}", INIT_PATHS);
+        return;
+    };
+    let exe = file.dentry();
+    let open = |path: &str| -> Option<Arc<dyn memory_set::BackingFile>> {
+        let file = fs.open(None, path, OpenFlags::RDONLY, 0).ok()?;
+        Some(Arc::new(FileBacking(file)))
+    };
+    let load = || -> LoadResult<(MemorySet, loader::Image)> {
+        let mut memory_set = MemorySet::new()?;
+        let image = loader::load(&mut memory_set, &FileBacking(file), &[String::from(path)], &[], &open)?;
+        memory_set.init_brk(VirtAddr(image.brk));
+        Ok((memory_set, image))
+    };
+    let (memory_set, image) = match load() {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            warn!("Failed to load {}: {}", path, e);
+            return;
+        }
+    };
+
+    let mut files = FdTable::new();
+    let console = fs.open(None, "/dev/console", OpenFlags::RDWR | OpenFlags::NOCTTY, 0);
+    match &console {
+        Ok(console) => {
+            for _ in 0..3 {
+                let _ = files.insert(console.clone(), false);
+            }
+        }
+        Err(e) => warn!("Failed to open the console for init: {}", e),
+    }
+    let pid = Arc::new(alloc_pid().expect("No pid left for init"));
+    let process = Process::new(
+        pid.clone(),
+        None,
+        Arc::new(AddressSpace::new(memory_set)),
+        Arc::new(Mutex::new(files)),
+        Arc::new(Mutex::new(fs)),
+        Arc::new(Mutex::new(SigActions::new())),
+    );
+    process.inner().exe = exe;
+    if let Some(tty) = console.ok().and_then(|console| console.tty()) {
+        tty.attach(&process, false);
+    }
+    process.set_init();
+    let task = Task::new(pid, process, Context::new_user(image.entry, image.sp)).expect("Failed to create init");
+    info!("Starting {} as init.", path);
+    sched::add_task(task);
+}
@@ -0,0 +1,58 @@
+use alloc::collections::BTreeSet;
+use spin::Mutex;
+
+use crate::config::PID_MAX;
+
+/// Process and thread ids come from one namespace, as in Linux.
+///
+/// Ids are handed out in increasing order and freed ones are only reused once
+/// the counter reaches [`PID_MAX`], so a stale id rarely names a new task.
+struct PidAllocator {
+    next: usize,
+    recycled: BTreeSet<usize>,
+}
+
+impl PidAllocator {
+    const fn new() -> Self {
+        Self {
+            next: 1,
+            recycled: BTreeSet::new(),
+        }
+    }
+
+    fn alloc(&mut self) -> Option<usize> {
+        if self.next < PID_MAX {
+            self.next += 1;
+            return Some(self.next - 1);
+        }
+        self.recycled.pop_first()
+    }
+
+    fn dealloc(&mut self, pid: usize) {
+        debug_assert!(pid < self.next && !self.recycled.contains(&pid));
+        self.recycled.insert(pid);
+    }
+}
+
+static PID_ALLOCATOR: Mutex<PidAllocator> = Mutex::new(PidAllocator::new());
+
+/// An allocated id, given back when dropped.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PidHandle(usize);
+
+impl PidHandle {
+    pub fn get(&self) -> usize {
+        self.0
+    }
+}
+
+impl Drop for PidHandle {
+    fn drop(&mut self) {
+        PID_ALLOCATOR.lock().dealloc(self.0);
+    }
+}
+
+/// Allocate an id, or `None` if all [`PID_MAX`] ids are in use.
+pub fn alloc_pid() -> Option<PidHandle> {
+    PID_ALLOCATOR.lock().alloc().map(PidHandle)
+}
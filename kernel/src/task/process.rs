@@ -0,0 +1,387 @@
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::{Mutex, MutexGuard, Once};
+
+use crate::{
+    drivers::chardev::Tty,
+    fs::{Dentry, FsContext},
+    mm::memory_set::AddressSpace,
+    sched::{self, WaitQueue},
+    timer,
+};
+
+use super::{
+    files::FdTable,
+    pid::PidHandle,
+    signal::{SigActions, SigSet, SIGCHLD, SIGCONT, SIGKILL, SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU},
+    stop_status,
+    task::Task,
+    CONTINUED_STATUS,
+};
+
+/// Every process that has not been reaped yet, by pid.
+static PROCESSES: Mutex<BTreeMap<usize, Weak<Process>>> = Mutex::new(BTreeMap::new());
+
+/// Orphans are re-parented to the first process.
+static INIT_PROCESS: Once<Arc<Process>> = Once::new();
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    pub euid: u32,
+    pub egid: u32,
+}
+
+/// A thread group, which owns the resources its threads share.
+pub struct Process {
+    pid: Arc<PidHandle>,
+    inner: Mutex<ProcessInner>,
+    /// Woken when a child exits, stops or continues, or when a
+    /// `CLONE_VFORK` child lets its parent continue.
+    child_exited: WaitQueue,
+    /// Woken when the process is continued, or killed while stopped.
+    continued: WaitQueue,
+    /// Woken when one of its threads exits, but not the last one.
+    thread_exited: WaitQueue,
+    /// Timer ticks since boot when the process was created.
+    start_ticks: usize,
+}
+
+pub struct ProcessInner {
+    /// Released on exit, as a zombie keeps nothing but its wait status.
+    pub memory_set: Option<Arc<AddressSpace>>,
+    pub parent: Option<Weak<Process>>,
+    pub children: Vec<Arc<Process>>,
+    pub threads: Vec<Weak<Task>>,
+    pub files: Arc<Mutex<FdTable>>,
+    /// Root and working directory, shared by processes created with
+    /// `CLONE_FS`.
+    pub fs: Arc<Mutex<FsContext>>,
+    /// The program last executed, inherited from the parent until then.
+    pub exe: Option<Arc<Dentry>>,
+    pub cred: Credentials,
+    pub sig_actions: Arc<Mutex<SigActions>>,
+    /// Signals sent to the process rather than to one of its threads.
+    pub sig_pending: SigSet,
+    pub pgid: usize,
+    pub sid: usize,
+    /// The controlling terminal of the session, inherited from the parent.
+    pub tty: Option<Arc<Tty>>,
+    /// Wait status, set once the process has exited. It stays a zombie
+    /// until its parent reaps it.
+    pub exit_status: Option<i32>,
+    /// Whether a stop signal stopped the process, until `SIGCONT`.
+    pub stopped: bool,
+    /// Wait status of the last stop or continue, until the parent is told.
+    pub job_status: Option<i32>,
+    /// Signal the parent gets when the process exits, none if 0.
+    pub exit_signal: usize,
+}
+
+/// What waiting for a child found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitResult {
+    /// No child matches.
+    NoChild,
+    /// Matching children exist but none has exited.
+    Running,
+    /// A child exited and was reaped.
+    Exited { pid: usize, status: i32 },
+    /// A child stopped or continued, with the wait status telling which.
+    Changed { pid: usize, status: i32 },
+}
+
+impl Process {
+    /// Create a process and register it. The pid is shared with its first thread.
+    pub fn new(
+        pid: Arc<PidHandle>,
+        parent: Option<&Arc<Process>>,
+        memory_set: Arc<AddressSpace>,
+        files: Arc<Mutex<FdTable>>,
+        fs: Arc<Mutex<FsContext>>,
+        sig_actions: Arc<Mutex<SigActions>>,
+    ) -> Arc<Self> {
+        let (exe, cred, pgid, sid, tty) = match parent {
+            Some(parent) => {
+                let parent = parent.inner();
+                (parent.exe.clone(), parent.cred, parent.pgid, parent.sid, parent.tty.clone())
+            }
+            None => (None, Credentials::default(), pid.get(), pid.get(), None),
+        };
+        let process = Arc::new(Self {
+            pid,
+            inner: Mutex::new(ProcessInner {
+                memory_set: Some(memory_set),
+                parent: parent.map(Arc::downgrade),
+                children: Vec::new(),
+                threads: Vec::new(),
+                files,
+                fs,
+                exe,
+                cred,
+                sig_actions,
+                sig_pending: SigSet::EMPTY,
+                pgid,
+                sid,
+                tty,
+                exit_status: None,
+                stopped: false,
+                job_status: None,
+                exit_signal: SIGCHLD,
+            }),
+            child_exited: WaitQueue::new(),
+            continued: WaitQueue::new(),
+            thread_exited: WaitQueue::new(),
+            start_ticks: timer::get_ticks(),
+        });
+        if let Some(parent) = parent {
+            parent.inner().children.push(process.clone());
+        }
+        PROCESSES
+            .lock()
+            .insert(process.pid(), Arc::downgrade(&process));
+        process
+    }
+
+    pub fn pid(&self) -> usize {
+        self.pid.get()
+    }
+
+    pub fn start_ticks(&self) -> usize {
+        self.start_ticks
+    }
+
+    pub fn inner(&self) -> MutexGuard<'_, ProcessInner> {
+        self.inner.lock()
+    }
+
+    pub fn parent(&self) -> Option<Arc<Process>> {
+        self.inner().parent.as_ref().and_then(Weak::upgrade)
+    }
+
+    pub fn ppid(&self) -> usize {
+        self.parent().map_or(0, |parent| parent.pid())
+    }
+
+    pub fn memory_set(&self) -> Option<Arc<AddressSpace>> {
+        self.inner().memory_set.clone()
+    }
+
+    pub fn child_exited(&self) -> &WaitQueue {
+        &self.child_exited
+    }
+
+    pub fn thread_exited(&self) -> &WaitQueue {
+        &self.thread_exited
+    }
+
+    pub fn is_zombie(&self) -> bool {
+        self.inner().exit_status.is_some()
+    }
+
+    /// Threads that have not exited yet.
+    pub fn threads(&self) -> Vec<Arc<Task>> {
+        self.inner()
+            .threads
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect()
+    }
+
+    pub fn continued(&self) -> &WaitQueue {
+        &self.continued
+    }
+
+    /// Make `sig` pending for the process, for any of its threads to take.
+    ///
+    /// `SIGCONT` continues a stopped process right away and discards pending
+    /// stop signals, which in turn discard a pending `SIGCONT`.
+    pub fn send_signal(&self, sig: usize) {
+        let continued = {
+            let mut inner = self.inner();
+            match sig {
+                SIGCONT => {
+                    for stop in [SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU] {
+                        inner.sig_pending.remove(stop);
+                    }
+                }
+                SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => inner.sig_pending.remove(SIGCONT),
+                _ => {}
+            }
+            inner.sig_pending.add(sig);
+            let continued = sig == SIGCONT && inner.stopped;
+            if continued {
+                inner.stopped = false;
+                inner.job_status = Some(CONTINUED_STATUS);
+            }
+            continued
+        };
+        if continued {
+            self.notify_parent(SIGCHLD);
+        }
+        if continued || sig == SIGKILL {
+            self.continued.wake_all();
+        }
+    }
+
+    /// Send `sig` like [`Self::send_signal`], waking a thread that does not
+    /// block it to take it.
+    pub fn kill(&self, sig: usize) {
+        self.send_signal(sig);
+        let threads = self.threads();
+        if let Some(thread) = threads.iter().find(|thread| !thread.inner().sig_blocked.contains(sig)) {
+            sched::wake(thread);
+        }
+    }
+
+    /// Stop the process on `sig`. Its threads wait for `SIGCONT` before
+    /// returning to user mode.
+    pub fn stop(&self, sig: usize) {
+        {
+            let mut inner = self.inner();
+            if inner.stopped || inner.exit_status.is_some() {
+                return;
+            }
+            inner.stopped = true;
+            inner.job_status = Some(stop_status(sig));
+        }
+        self.notify_parent(SIGCHLD);
+    }
+
+    /// Send the parent `sig`, unless 0, and wake it if it waits for a child.
+    fn notify_parent(&self, sig: usize) {
+        if let Some(parent) = self.parent() {
+            if sig != 0 {
+                parent.inner().sig_pending.add(sig);
+            }
+            parent.child_exited.wake_all();
+        }
+    }
+
+    /// Make this process the one orphans are given to.
+    pub fn set_init(self: &Arc<Self>) {
+        INIT_PROCESS.call_once(|| self.clone());
+    }
+
+    /// Turn the process into a zombie with wait status `status`.
+    ///
+    /// Its address space and descriptors are released. Children are handed
+    /// to the init process and the parent gets the exit signal. A session
+    /// leader hangs up its controlling terminal.
+    pub fn exit(self: &Arc<Self>, status: i32) {
+        let (children, tty, sid, memory_set, files, exit_signal) = {
+            let mut inner = self.inner();
+            if inner.exit_status.is_some() {
+                return;
+            }
+            inner.exit_status = Some(status);
+            inner.threads.clear();
+            (
+                core::mem::take(&mut inner.children),
+                inner.tty.take(),
+                inner.sid,
+                inner.memory_set.take(),
+                core::mem::replace(&mut inner.files, Arc::new(Mutex::new(FdTable::new()))),
+                inner.exit_signal,
+            )
+        };
+        // Released with the process unlocked, as writing back may sleep. The
+        // hart keeps the address space it runs on until it switches away.
+        drop((memory_set, files));
+        if let Some(tty) = tty.filter(|_| sid == self.pid()) {
+            tty.hangup(sid);
+        }
+        if let Some(init) = INIT_PROCESS.get().filter(|init| !Arc::ptr_eq(init, self)) {
+            let zombies = children.iter().any(|child| child.is_zombie());
+            for child in children.iter() {
+                let mut child = child.inner();
+                child.parent = Some(Arc::downgrade(init));
+                // Init only expects SIGCHLD.
+                child.exit_signal = SIGCHLD;
+            }
+            init.inner().children.extend(children);
+            if zombies {
+                init.child_exited.wake_all();
+            }
+        }
+        self.notify_parent(exit_signal);
+    }
+
+    /// Reap an exited child among those `matches` accepts. Otherwise, report
+    /// a child that stopped if `stopped`, or continued if `continued`, since
+    /// it was last reported.
+    pub fn reap_child(&self, matches: impl Fn(&Process) -> bool, stopped: bool, continued: bool) -> WaitResult {
+        let mut inner = self.inner();
+        if !inner.children.iter().any(|child| matches(child)) {
+            return WaitResult::NoChild;
+        }
+        let zombie = inner
+            .children
+            .iter()
+            .position(|child| matches(child) && child.is_zombie());
+        match zombie {
+            Some(index) => {
+                let child = inner.children.remove(index);
+                // Unlocked before the child goes, as freeing its address
+                // space and files may sleep.
+                drop(inner);
+                let status = child.inner().exit_status.unwrap();
+                PROCESSES.lock().remove(&child.pid());
+                WaitResult::Exited {
+                    pid: child.pid(),
+                    status,
+                }
+            }
+            None => {
+                let reported = |status: i32| if status == CONTINUED_STATUS { continued } else { stopped };
+                for child in inner.children.iter().filter(|child| matches(child)) {
+                    let mut child_inner = child.inner();
+                    if let Some(status) = child_inner.job_status.filter(|&status| reported(status)) {
+                        child_inner.job_status = None;
+                        return WaitResult::Changed {
+                            pid: child.pid(),
+                            status,
+                        };
+                    }
+                }
+                WaitResult::Running
+            }
+        }
+    }
+}
+
+/// The process with `pid`, if it has not been reaped.
+pub fn find_process(pid: usize) -> Option<Arc<Process>> {
+    PROCESSES.lock().get(&pid).and_then(Weak::upgrade)
+}
+
+/// [Kill](Process::kill) every process in process group `pgid` with `sig`,
+/// returning whether there was any.
+pub fn signal_group(pgid: usize, sig: usize) -> bool {
+    let mut found = false;
+    for process in all_processes() {
+        let member = {
+            let inner = process.inner();
+            inner.pgid == pgid && inner.exit_status.is_none()
+        };
+        if !member {
+            continue;
+        }
+        process.kill(sig);
+        found = true;
+    }
+    found
+}
+
+/// Every process that has not been reaped, by increasing pid.
+pub fn all_processes() -> Vec<Arc<Process>> {
+    PROCESSES
+        .lock()
+        .values()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
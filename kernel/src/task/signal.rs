@@ -0,0 +1,131 @@
+//! Signal numbers, sets and dispositions, with Linux values.
+
+/// Number of signals, numbered from 1.
+pub const NSIG: usize = 64;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGWINCH: usize = 28;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// A set of signals, bit `n - 1` standing for signal `n`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct SigSet(pub u64);
+
+impl SigSet {
+    pub const EMPTY: Self = Self(0);
+
+    pub fn add(&mut self, sig: usize) {
+        debug_assert!((1..=NSIG).contains(&sig));
+        self.0 |= 1 << (sig - 1);
+    }
+
+    pub fn remove(&mut self, sig: usize) {
+        self.0 &= !(1 << (sig - 1));
+    }
+
+    pub fn contains(&self, sig: usize) -> bool {
+        self.0 & (1 << (sig - 1)) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// The lowest signal in the set that `blocked` does not contain.
+    pub fn first_unblocked(&self, blocked: SigSet) -> Option<usize> {
+        let mut deliverable = self.0 & !blocked.0;
+        // SIGKILL and SIGSTOP cannot be blocked.
+        deliverable |= self.0 & ((1 << (SIGKILL - 1)) | (1 << (SIGSTOP - 1)));
+        (deliverable != 0).then(|| deliverable.trailing_zeros() as usize + 1)
+    }
+}
+
+/// `struct sigaction` as the riscv64 kernel ABI lays it out.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: SigSet,
+}
+
+/// What happens to a process receiving a signal left at `SIG_DFL`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    /// Terminate and dump core, which we do not do.
+    CoreDump,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+pub fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        SIGCHLD | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV => DefaultAction::CoreDump,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Dispositions of all signals, shared by threads created with `CLONE_SIGHAND`.
+#[derive(Clone)]
+pub struct SigActions {
+    actions: [SigAction; NSIG],
+}
+
+impl SigActions {
+    pub fn new() -> Self {
+        Self {
+            actions: [SigAction::default(); NSIG],
+        }
+    }
+
+    pub fn get(&self, sig: usize) -> SigAction {
+        self.actions[sig - 1]
+    }
+
+    pub fn set(&mut self, sig: usize, action: SigAction) {
+        self.actions[sig - 1] = action;
+    }
+
+    /// Reset caught signals to `SIG_DFL`, as `execve` does.
+    pub fn reset_handlers(&mut self) {
+        for action in self.actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+}
+
+impl Default for SigActions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
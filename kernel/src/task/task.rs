@@ -0,0 +1,120 @@
+use alloc::sync::{Arc, Weak};
+use core::cell::UnsafeCell;
+use spin::{Mutex, MutexGuard};
+
+use crate::{
+    mm::MmResult,
+    sched::{self, TaskContext},
+    trap::Context,
+};
+
+use super::{kstack::KernelStack, pid::PidHandle, process::Process, signal::SigSet};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskStatus {
+    Ready,
+    Running,
+    /// Waiting for something other than a hart to run on.
+    Blocked,
+    Exited,
+}
+
+/// A thread, the unit the scheduler runs.
+pub struct Task {
+    tid: Arc<PidHandle>,
+    process: Arc<Process>,
+    kernel_stack: KernelStack,
+    /// User registers, saved here by `_user_trap`. Only the hart running the
+    /// task touches them.
+    trap_context: UnsafeCell<Context>,
+    /// Kernel registers while the task is switched out.
+    sched_context: UnsafeCell<TaskContext>,
+    inner: Mutex<TaskInner>,
+}
+
+unsafe impl Sync for Task {}
+
+pub struct TaskInner {
+    pub status: TaskStatus,
+    /// Still running on its kernel stack, possibly about to switch out.
+    pub on_cpu: bool,
+    pub exit_code: i32,
+    /// Cleared and woken on exit, set by `CLONE_CHILD_CLEARTID` and
+    /// `set_tid_address`.
+    pub clear_child_tid: usize,
+    pub sig_pending: SigSet,
+    pub sig_blocked: SigSet,
+    /// The parent of a `CLONE_VFORK` child, waiting until this task exits or
+    /// calls `execve`.
+    pub vfork_parent: Option<Weak<Process>>,
+}
+
+impl Task {
+    /// Create a thread of `process` that enters user mode with `context`.
+    pub fn new(tid: Arc<PidHandle>, process: Arc<Process>, context: Context) -> MmResult<Arc<Self>> {
+        let kernel_stack = KernelStack::new()?;
+        let mut context = context;
+        context.kernel_sp = kernel_stack.top();
+        let sched_context = TaskContext::new(sched::task_entry as usize, kernel_stack.top());
+        let task = Arc::new(Self {
+            tid,
+            process,
+            kernel_stack,
+            trap_context: UnsafeCell::new(context),
+            sched_context: UnsafeCell::new(sched_context),
+            inner: Mutex::new(TaskInner {
+                status: TaskStatus::Ready,
+                on_cpu: false,
+                exit_code: 0,
+                clear_child_tid: 0,
+                sig_pending: SigSet::EMPTY,
+                sig_blocked: SigSet::EMPTY,
+                vfork_parent: None,
+            }),
+        });
+        task.process.inner().threads.push(Arc::downgrade(&task));
+        Ok(task)
+    }
+
+    pub fn tid(&self) -> usize {
+        self.tid.get()
+    }
+
+    pub fn process(&self) -> &Arc<Process> {
+        &self.process
+    }
+
+    pub fn kernel_stack_top(&self) -> usize {
+        self.kernel_stack.top()
+    }
+
+    /// The saved user registers.
+    ///
+    /// Must only be called by the hart running the task, or while it is not
+    /// running.
+    #[allow(clippy::mut_from_ref)]
+    pub fn trap_context(&self) -> &mut Context {
+        unsafe { &mut *self.trap_context.get() }
+    }
+
+    /// Make `sig` pending for this thread.
+    pub fn send_signal(&self, sig: usize) {
+        self.inner().sig_pending.add(sig);
+    }
+
+    /// Let the parent that created this task with `CLONE_VFORK` continue.
+    pub fn release_vfork_parent(&self) {
+        let parent = self.inner().vfork_parent.take();
+        if let Some(parent) = parent.and_then(|parent| parent.upgrade()) {
+            parent.child_exited().wake_all();
+        }
+    }
+
+    pub fn sched_context(&self) -> *mut TaskContext {
+        self.sched_context.get()
+    }
+
+    pub fn inner(&self) -> MutexGuard<'_, TaskInner> {
+        self.inner.lock()
+    }
+}
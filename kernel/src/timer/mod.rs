@@ -1,21 +1,37 @@
-mod consts;
+pub mod consts;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use log::info;
 use riscv::register::{sie, sstatus, time};
 use sbi::legacy::sbi_set_timer;
 
-use self::consts::{CLOCK_FREQ, INTERRUPT_PER_SEC};
+use crate::{board, cpu, dtb};
+
+use self::consts::{INTERRUPT_PER_SEC, NSEC_PER_SEC, USEC_PER_SEC};
 
 
 static mut TICKS: usize = 0;
 
+/// Frequency of the `time` CSR, taken from the device tree if it has one.
+static CLOCK_FREQ: AtomicUsize = AtomicUsize::new(board::CLOCK_FREQ);
+
 pub fn init() {
+    if let Some(freq) = dtb::machine().timebase_frequency() {
+        CLOCK_FREQ.store(freq, Ordering::Relaxed);
+    }
+    info!("Timer frequency: {} Hz", clock_freq());
+    init_hart();
+}
+
+/// Start the timer interrupt on the current hart.
+pub fn init_hart() {
     unsafe {
         sie::set_stimer();
         sstatus::set_sie();
     }
     set_next_timeout();
-    info!("timer initialized.");
+    info!("timer initialized on hart {}.", cpu::hart_id());
 }
 
 pub fn set_next_timeout() {
@@ -23,9 +39,14 @@ pub fn set_next_timeout() {
 }
 
 fn get_next_int_time() -> u64 {
-    (time::read() + CLOCK_FREQ / INTERRUPT_PER_SEC) as u64
+    (time::read() + clock_freq() / INTERRUPT_PER_SEC) as u64
 }
 
+pub fn clock_freq() -> usize {
+    CLOCK_FREQ.load(Ordering::Relaxed)
+}
+
+
 pub fn get_ticks() -> usize {
     unsafe { TICKS }
 }
@@ -35,11 +56,21 @@ pub fn get_time_sec() -> usize {
 }
 
 pub fn get_time_usec() -> usize {
-    get_ticks() % INTERRUPT_PER_SEC * (consts::USEC_PER_SEC / INTERRUPT_PER_SEC)
+    get_ticks() % INTERRUPT_PER_SEC * (USEC_PER_SEC / INTERRUPT_PER_SEC)
+}
+
+/// Time since boot in nanoseconds, read from the `time` CSR.
+pub fn get_time_ns() -> usize {
+    (time::read() as u128 * NSEC_PER_SEC as u128 / clock_freq() as u128) as usize
 }
 
 pub fn tick() {
     set_next_timeout();
+    cpu::current().tick();
+    // Wall-clock ticks are only counted by the boot hart.
+    if !cpu::is_boot_hart() {
+        return;
+    }
     unsafe {
         TICKS += 1;
         if TICKS % INTERRUPT_PER_SEC == 0 {
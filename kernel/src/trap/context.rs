@@ -1,5 +1,14 @@
 use core::fmt::{self, Debug};
 
+const SSTATUS_SPIE: usize = 1 << 5;
+const SSTATUS_SPP: usize = 1 << 8;
+const SSTATUS_FS_INITIAL: usize = 1 << 13;
+
+/// Registers saved on a trap.
+///
+/// Traps from S-mode keep it on the kernel stack. Traps from U-mode save into
+/// the task's own `Context`, whose `kernel_*` fields tell `_user_trap` where
+/// the kernel continues. Offsets are hard-coded in `ktrap.S` and `utrap.S`.
 #[repr(C)]
 #[derive(Default, Clone, Copy)]
 pub struct Context {
@@ -11,6 +20,29 @@ pub struct Context {
     pub kernel_stval: usize,
     pub trap_handler: usize,
     pub hartid: usize,
+    /// `tp` of the kernel, pointing at the `Cpu` of the hart.
+    pub kernel_tp: usize,
+    /// Floating point registers, saved only for traps from U-mode.
+    pub fregs: [usize; 32],
+    pub fcsr: usize,
+}
+
+impl Context {
+    /// Context of a user program starting at `entry` with stack pointer `sp`.
+    pub fn new_user(entry: usize, sp: usize) -> Self {
+        let mut ctx = Self {
+            sstatus: SSTATUS_FS_INITIAL,
+            sepc: entry,
+            ..Self::default()
+        };
+        ctx.regs[2] = sp;
+        ctx
+    }
+
+    /// Make `sret` go to U-mode with interrupts enabled.
+    pub fn set_user_mode(&mut self) {
+        self.sstatus = (self.sstatus & !SSTATUS_SPP) | SSTATUS_SPIE;
+    }
 }
 
 impl Debug for Context {
@@ -26,6 +58,7 @@ impl Debug for Context {
         writeln!(f, "kernel_sp: {:#x}", self.kernel_sp)?;
         writeln!(f, "trap_handler: {:#x}", self.trap_handler)?;
         writeln!(f, "hartid: {:#x}", self.hartid)?;
+        writeln!(f, "kernel_tp: {:#x}", self.kernel_tp)?;
         Ok(())
     }
 }
\ No newline at end of file
@@ -2,9 +2,14 @@ use riscv::register::scause::Exception;
 
 use super::context::Context;
 
-pub fn handle_exception(ctx: &mut Context, e: Exception) {
+pub fn handle_exception(ctx: &mut Context, e: Exception, stval: usize) {
     match e {
+        // The kernel runs on its own page table, so it never touches user
+        // pages directly and any page fault here is a kernel bug.
+        Exception::LoadPageFault | Exception::StorePageFault | Exception::InstructionPageFault => {
+            panic!("{:?} at 0x{:x}, sepc: 0x{:x}", e, stval, ctx.sepc)
+        }
         _ => panic!("unhandled exception: {:?}!", e)
     }
 
-}
\ No newline at end of file
+}
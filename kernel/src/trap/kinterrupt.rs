@@ -1,16 +1,18 @@
 use riscv::register::scause::Interrupt;
 
-use crate::timer;
+use crate::{drivers::plic, sched, timer};
 
 use super::context::Context;
 
 pub fn handle_interrupt(ctx: &mut Context, i: Interrupt) {
     match i {
         Interrupt::SupervisorTimer => timer_interrupt(),
+        Interrupt::SupervisorExternal => plic::handle_interrupt(),
         _ => panic!("unhandled interrupt: {:?}!", i)
     }
 }
 
 fn timer_interrupt() {
     timer::tick();
+    sched::tick();
 }
\ No newline at end of file
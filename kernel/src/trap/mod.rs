@@ -1,15 +1,19 @@
 use core::arch::global_asm;
 
-use log::{debug, info};
-use riscv::register::{mcause::Exception, scause::{Interrupt, Scause, Trap}, sie, stvec::{self, TrapMode}};
+use log::info;
+use riscv::register::{satp, scause::{self, Scause, Trap}, sie, sstatus, stval, stvec::{self, TrapMode}};
 
-use self::context::Context;
+use crate::{arch, cpu, mm::memory_set, sched, task};
+
+pub use self::context::Context;
 
 mod context;
 mod kexception;
 mod kinterrupt;
+mod uexception;
 
 global_asm!(include_str!("ktrap.S"));
+global_asm!(include_str!("utrap.S"));
 
 pub fn init() {
     set_kernel_trap();
@@ -20,12 +24,46 @@ pub fn init() {
 pub extern "C" fn kernel_trap_handler(context: &mut Context, scause: Scause, stval: usize) {
     match scause.cause() {
         Trap::Interrupt(i) => kinterrupt::handle_interrupt(context, i),
-        Trap::Exception(e) => kexception::handle_exception(context, e),  
+        Trap::Exception(e) => kexception::handle_exception(context, e, stval),  
+    }
+}
+
+/// Entered from `_user_trap` on the kernel stack and page table.
+#[no_mangle]
+pub extern "C" fn user_trap_handler(context: &'static mut Context) -> ! {
+    set_kernel_trap();
+    let scause = scause::read();
+    let stval = stval::read();
+    match scause.cause() {
+        Trap::Interrupt(i) => kinterrupt::handle_interrupt(context, i),
+        Trap::Exception(e) => uexception::handle_exception(context, e, stval),
     }
+    sched::preempt_if_needed();
+    task::handle_signals();
+    user_return(context)
+}
+
+/// Return to U-mode with `context`, on the address space of the current hart.
+///
+/// `context.kernel_sp` must be the top of the kernel stack the next trap from
+/// U-mode runs on. Whatever is on the current stack is abandoned.
+pub fn user_return(context: &mut Context) -> ! {
+    let satp = memory_set::current().expect("Returning to U-mode without an address space").satp();
+    // A trap now would go to the user vector while still in S-mode.
+    unsafe { sstatus::clear_sie() };
+    set_user_trap();
+    context.set_user_mode();
+    context.kernel_satp = satp::read().bits();
+    context.trap_handler = user_trap_handler as usize;
+    context.hartid = cpu::hart_id();
+    context.kernel_tp = arch::tp();
+    unsafe { _user_return(context, satp) }
 }
 
 extern "C" {
     fn _kernel_trap();
+    fn _user_trap();
+    fn _user_return(context: &mut Context, satp: usize) -> !;
 }
 
 #[inline(always)]
@@ -34,5 +72,11 @@ fn set_kernel_trap() {
         stvec::write(_kernel_trap as usize, TrapMode::Direct);
         sie::set_sext();
     }
-    debug!("Kernel trap vector: 0x{:x}",stvec::read().address());
-}
\ No newline at end of file
+}
+
+#[inline(always)]
+fn set_user_trap() {
+    unsafe {
+        stvec::write(_user_trap as usize, TrapMode::Direct);
+    }
+}
@@ -0,0 +1,43 @@
+use log::info;
+use riscv::register::scause::Exception;
+
+use crate::{
+    mm::{addr::VirtAddr, memory_set::{self, Access}},
+    syscall,
+    task::{self, signal::{SIGBUS, SIGILL, SIGSEGV, SIGTRAP}},
+};
+
+use super::context::Context;
+
+pub fn handle_exception(ctx: &mut Context, e: Exception, stval: usize) {
+    match e {
+        Exception::UserEnvCall => {
+            // Past the `ecall`, before the call can replace the context.
+            ctx.sepc += 4;
+            let args = [ctx.regs[10], ctx.regs[11], ctx.regs[12], ctx.regs[13], ctx.regs[14], ctx.regs[15]];
+            ctx.regs[10] = syscall::syscall(ctx.regs[17], args) as usize;
+        }
+        Exception::LoadPageFault => page_fault(ctx, VirtAddr::from(stval), Access::Read),
+        Exception::StorePageFault => page_fault(ctx, VirtAddr::from(stval), Access::Write),
+        Exception::InstructionPageFault => page_fault(ctx, VirtAddr::from(stval), Access::Execute),
+        Exception::IllegalInstruction => user_fault(ctx, e, stval, SIGILL),
+        Exception::Breakpoint => user_fault(ctx, e, stval, SIGTRAP),
+        Exception::InstructionMisaligned | Exception::LoadMisaligned | Exception::StoreMisaligned => {
+            user_fault(ctx, e, stval, SIGBUS)
+        }
+        _ => user_fault(ctx, e, stval, SIGSEGV),
+    }
+}
+
+fn page_fault(ctx: &mut Context, va: VirtAddr, access: Access) {
+    if let Err(e) = memory_set::handle_page_fault(va, access) {
+        info!("{:?} page fault at {}, sepc: 0x{:x}: {}", access, va, ctx.sepc, e);
+        task::current().send_signal(SIGSEGV);
+    }
+}
+
+/// An exception the program cannot continue after, turned into `sig`.
+fn user_fault(ctx: &mut Context, e: Exception, stval: usize, sig: usize) {
+    info!("{:?} in user mode, stval: 0x{:x}, sepc: 0x{:x}", e, stval, ctx.sepc);
+    task::current().send_signal(sig);
+}